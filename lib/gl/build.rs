@@ -11,6 +11,9 @@ fn main() {
 
     Registry::new(Api::Gl, (4, 6), Profile::Core, Fallbacks::All, [
         "GL_EXT_texture_filter_anisotropic", // additional extension we want to use
+        "GL_EXT_texture_compression_s3tc", // BC1-BC3 formats in DDS and KTX2 files
+        "GL_EXT_texture_sRGB", // sRGB variants of the S3TC formats
+        "GL_KHR_texture_compression_astc_ldr",
     ])
         .write_bindings(
             GlobalGenerator,
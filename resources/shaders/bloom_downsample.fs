@@ -0,0 +1,51 @@
+#version 330 core
+
+// Outputs colors in RGBA
+out vec4 FragColor;
+
+
+in vec2 texCoord;
+
+uniform sampler2D source;
+uniform vec2 sourceTexelSize;
+// Only the first downsample reads the HDR scene and applies the threshold
+uniform bool prefilter;
+uniform float threshold;
+uniform float knee;
+
+vec3 sampleSource(vec2 offset)
+{
+	return texture(source, texCoord + offset * sourceTexelSize).rgb;
+}
+
+// Soft knee so bright pixels fade into the bloom instead of popping at the threshold
+vec3 applyThreshold(vec3 color)
+{
+	float brightness = max(color.r, max(color.g, color.b));
+	float soft = clamp(brightness - threshold + knee, 0.0, 2.0 * knee);
+	soft = soft * soft / (4.0 * knee + 0.0001);
+	return color * max(soft, brightness - threshold) / max(brightness, 0.0001);
+}
+
+void main()
+{
+	// 13 tap filter, overlapping 4x4 boxes keep the result stable as the camera moves
+	vec3 a = sampleSource(vec2(-2.0, 2.0));
+	vec3 b = sampleSource(vec2(0.0, 2.0));
+	vec3 c = sampleSource(vec2(2.0, 2.0));
+	vec3 d = sampleSource(vec2(-2.0, 0.0));
+	vec3 e = sampleSource(vec2(0.0, 0.0));
+	vec3 f = sampleSource(vec2(2.0, 0.0));
+	vec3 g = sampleSource(vec2(-2.0, -2.0));
+	vec3 h = sampleSource(vec2(0.0, -2.0));
+	vec3 i = sampleSource(vec2(2.0, -2.0));
+	vec3 j = sampleSource(vec2(-1.0, 1.0));
+	vec3 k = sampleSource(vec2(1.0, 1.0));
+	vec3 l = sampleSource(vec2(-1.0, -1.0));
+	vec3 m = sampleSource(vec2(1.0, -1.0));
+
+	vec3 color = e * 0.125 + (a + c + g + i) * 0.03125 + (b + d + f + h) * 0.0625 + (j + k + l + m) * 0.125;
+	if (prefilter)
+		color = applyThreshold(color);
+	FragColor = vec4(max(color, vec3(0.0)), 1.0);
+}
@@ -0,0 +1,23 @@
+#version 330 core
+
+// Outputs colors in RGBA, added onto the larger mip
+out vec4 FragColor;
+
+
+in vec2 texCoord;
+
+uniform sampler2D source;
+uniform vec2 sourceTexelSize;
+uniform float radius;
+
+void main()
+{
+	// 3x3 tent filter
+	vec2 r = radius * sourceTexelSize;
+	vec3 color = texture(source, texCoord).rgb * 4.0;
+	color += (texture(source, texCoord + vec2(-r.x, 0.0)).rgb + texture(source, texCoord + vec2(r.x, 0.0)).rgb
+		+ texture(source, texCoord + vec2(0.0, -r.y)).rgb + texture(source, texCoord + vec2(0.0, r.y)).rgb) * 2.0;
+	color += texture(source, texCoord + vec2(-r.x, -r.y)).rgb + texture(source, texCoord + vec2(r.x, -r.y)).rgb
+		+ texture(source, texCoord + vec2(-r.x, r.y)).rgb + texture(source, texCoord + vec2(r.x, r.y)).rgb;
+	FragColor = vec4(color / 16.0, 1.0);
+}
@@ -0,0 +1,51 @@
+#version 430 core
+
+#include "ibl.glsl"
+
+// Must match environment::GROUP_SIZE
+layout (local_size_x = 8, local_size_y = 8) in;
+
+layout (rg16f, binding = 0) uniform writeonly image2D brdfImage;
+
+uniform int size;
+
+#define SAMPLE_COUNT 1024u
+
+float geometrySchlickGGX(float NdotX, float roughness)
+{
+	// The IBL remapping of k, not the one used for analytic lights
+	float k = roughness * roughness * 0.5;
+	return NdotX / (NdotX * (1.0 - k) + k);
+}
+
+// Split sum lookup, x is NdotV and y is roughness. Stores the scale and bias applied to F0.
+void main()
+{
+	ivec2 texel = ivec2(gl_GlobalInvocationID.xy);
+	if (texel.x >= size || texel.y >= size)
+		return;
+
+	float NdotV = (float(texel.x) + 0.5) / float(size);
+	float roughness = (float(texel.y) + 0.5) / float(size);
+	vec3 V = vec3(sqrt(1.0 - NdotV * NdotV), 0.0, NdotV);
+	vec3 N = vec3(0.0, 0.0, 1.0);
+
+	float scale = 0.0;
+	float bias = 0.0;
+	for (uint i = 0u; i < SAMPLE_COUNT; i++)
+	{
+		vec3 H = importanceSampleGGX(hammersley(i, SAMPLE_COUNT), N, roughness);
+		vec3 L = normalize(2.0 * dot(V, H) * H - V);
+		float NdotL = max(L.z, 0.0);
+		if (NdotL <= 0.0)
+			continue;
+		float NdotH = max(H.z, 0.0);
+		float VdotH = max(dot(V, H), 0.0);
+		float G = geometrySchlickGGX(NdotV, roughness) * geometrySchlickGGX(NdotL, roughness);
+		float visibility = G * VdotH / (NdotH * NdotV);
+		float fresnel = pow(1.0 - VdotH, 5.0);
+		scale += (1.0 - fresnel) * visibility;
+		bias += fresnel * visibility;
+	}
+	imageStore(brdfImage, texel, vec4(scale, bias, 0.0, 0.0) / float(SAMPLE_COUNT));
+}
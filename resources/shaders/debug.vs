@@ -0,0 +1,19 @@
+#version 330 core
+
+// Positions/Coordinates
+layout (location = 0) in vec3 aPos;
+// Colors, alpha included
+layout (location = 1) in vec4 aColor;
+
+
+// Outputs the color for the Fragment Shader
+out vec4 color;
+
+uniform mat4 camMatrix;
+
+
+void main()
+{
+	gl_Position = camMatrix * vec4(aPos, 1.0);
+	color = aColor;
+}
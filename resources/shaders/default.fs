@@ -1,18 +1,109 @@
 #version 330 core
 
-// Outputs colors in RGBA
-out vec4 FragColor;
+#include "lighting.glsl"
+#include "dither.glsl"
+#include "splat.glsl"
+
+#define MAX_POINT_LIGHTS 16
+#define MAX_SPOT_LIGHTS 16
+
+// Outputs colors in RGBA, or the weighted color accumulation when drawing order-independent transparency
+layout (location = 0) out vec4 FragColor;
+// Outputs the alpha that multiplies into the revealage when drawing order-independent transparency
+layout (location = 1) out vec4 Revealage;
 
 
 // Inputs the color from the Vertex Shader
 in vec3 color;
 // Inputs the texture coordinates from the Vertex Shader
 in vec2 texCoord;
+// Inputs the world space position and normal from the Vertex Shader
+in vec3 fragPos;
+in vec3 normal;
+// Inputs the lightmap coordinates from the Vertex Shader
+in vec2 lightmapUV;
+// Inputs the LOD cross-fade from the Vertex Shader
+flat in float lodFade;
+
+struct PointLight {
+	bool baked;
+	vec3 position;
+	vec3 color;
+	float range;
+	int shadowView;
+};
+
+struct SpotLight {
+	bool baked;
+	vec3 position;
+	vec3 direction;
+	vec3 color;
+	float range;
+	float innerCos;
+	float outerCos;
+	int shadowView;
+};
 
 // Gets the Texture Unit from the main function
 uniform sampler2D tex0;
+uniform float roughness;
+// Pixels with a lower texture alpha are discarded
+uniform float alphaCutoff;
+// Premultiplied materials already scaled their color by alpha
+uniform bool premultipliedAlpha;
+uniform bool weightedOit;
+uniform vec2 oitWeightRange;
+
+uniform int pointLightCount;
+uniform PointLight pointLights[MAX_POINT_LIGHTS];
+uniform int spotLightCount;
+uniform SpotLight spotLights[MAX_SPOT_LIGHTS];
+
+// Static meshes with a baked lightmap skip the ambient term and every baked light
+uniform bool hasLightmap;
+uniform sampler2D lightmap;
+
+vec3 computeLighting(vec3 N, vec3 worldPos)
+{
+	vec3 result = hasLightmap ? texture(lightmap, lightmapUV).rgb : ambientLighting(N);
+	result += directionalLighting(N, hasLightmap);
+
+	for (int i = 0; i < pointLightCount; i++)
+	{
+		if (hasLightmap && pointLights[i].baked)
+			continue;
+		result += pointLighting(N, worldPos, pointLights[i].position, pointLights[i].color, pointLights[i].range, pointLights[i].shadowView);
+	}
+
+	for (int i = 0; i < spotLightCount; i++)
+	{
+		if (hasLightmap && spotLights[i].baked)
+			continue;
+		result += spotLighting(N, worldPos, spotLights[i].position, spotLights[i].direction, spotLights[i].color,
+			spotLights[i].range, spotLights[i].innerCos, spotLights[i].outerCos, spotLights[i].shadowView);
+	}
+
+	return result;
+}
 
 void main()
 {
-	FragColor = texture(tex0, texCoord);
-}
\ No newline at end of file
+	lodDither(lodFade);
+	vec4 albedo = materialAlbedo(tex0, texCoord);
+	if (albedo.a < alphaCutoff)
+		discard;
+	vec3 N = dot(normal, normal) < 0.0001 ? vec3(0.0) : normalize(normal);
+	// Environment reflections sit on top of the surface color rather than being tinted by it
+	vec4 result = vec4(albedo.rgb * computeLighting(N, fragPos) + environmentSpecular(N, fragPos, roughness), albedo.a);
+	if (!weightedOit)
+	{
+		FragColor = result;
+		return;
+	}
+
+	// Depth weighting from McGuire & Bavoil, nearer and more opaque layers dominate the average
+	vec3 premultiplied = premultipliedAlpha ? result.rgb : result.rgb * result.a;
+	float weight = clamp(pow(min(1.0, result.a * 10.0) + 0.01, 3.0) * 1e8 * pow(1.0 - gl_FragCoord.z * 0.9, 3.0), oitWeightRange.x, oitWeightRange.y);
+	FragColor = vec4(premultiplied, result.a) * weight;
+	Revealage = vec4(result.a);
+}
@@ -1,17 +1,35 @@
 #version 330 core
 
+#include "skinning.glsl"
+#include "morph.glsl"
+
 // Positions/Coordinates
 layout (location = 0) in vec3 aPos;
 // Colors
 layout (location = 1) in vec3 aColor;
 // Texture Coordinates
 layout (location = 2) in vec2 aTex;
+// Normals
+layout (location = 3) in vec3 aNormal;
+// Lightmap Coordinates
+layout (location = 4) in vec2 aLightmapUV;
+// Per instance model matrix, takes locations 5 to 8
+layout (location = 5) in mat4 aModel;
+// Per instance LOD cross-fade
+layout (location = 9) in float aLodFade;
 
 
 // Outputs the color for the Fragment Shader
 out vec3 color;
 // Outputs the texture coordinates to the fragment shader
 out vec2 texCoord;
+// Outputs the world space position and normal for lighting
+out vec3 fragPos;
+out vec3 normal;
+// Outputs the lightmap coordinates to the fragment shader
+out vec2 lightmapUV;
+// Outputs the LOD cross-fade to the fragment shader
+flat out float lodFade;
 
 // Imports the camera matrix from the main function
 uniform mat4 camMatrix;
@@ -19,10 +37,16 @@ uniform mat4 camMatrix;
 
 void main()
 {
+	mat4 model = aModel * skinMatrix();
+	vec4 worldPos = model * vec4(morphPosition(aPos), 1.0);
 	// Outputs the positions/coordinates of all vertices
-	gl_Position = camMatrix * vec4(aPos, 1.0);
+	gl_Position = camMatrix * worldPos;
 	// Assigns the colors from the Vertex Data to "color"
 	color = aColor;
 	// Assigns the texture coordinates from the Vertex Data to "texCoord"
 	texCoord = aTex;
-}
\ No newline at end of file
+	fragPos = worldPos.xyz;
+	normal = mat3(transpose(inverse(model))) * morphNormal(aNormal);
+	lightmapUV = aLightmapUV;
+	lodFade = aLodFade;
+}
@@ -0,0 +1,181 @@
+#version 430 core
+
+#include "lighting.glsl"
+
+// Must match deferred::TILE_SIZE
+#define TILE_SIZE 16
+#define MAX_TILE_LIGHTS 256
+
+layout (local_size_x = TILE_SIZE, local_size_y = TILE_SIZE) in;
+
+// Mirrors GpuPointLight and GpuSpotLight, every field is a vec4 so std430 adds no padding
+struct PointLight {
+	vec4 positionRange;
+	vec4 colorBaked;
+	vec4 shadowView;
+};
+
+struct SpotLight {
+	vec4 positionRange;
+	vec4 directionInnerCos;
+	vec4 colorBaked;
+	vec4 outerCosShadowView;
+};
+
+layout (std430, binding = 0) readonly buffer PointLights {
+	PointLight pointLights[];
+};
+
+layout (std430, binding = 1) readonly buffer SpotLights {
+	SpotLight spotLights[];
+};
+
+layout (rgba16f, binding = 0) uniform writeonly image2D litImage;
+
+uniform sampler2D gAlbedo;
+uniform sampler2D gNormal;
+uniform sampler2D gLighting;
+uniform sampler2D gDepth;
+
+uniform int pointLightCount;
+uniform int spotLightCount;
+
+uniform mat4 view;
+uniform mat4 invProjection;
+uniform mat4 invViewProjection;
+uniform ivec2 screenSize;
+
+// Depth bounds of the tile, non negative floats keep their order when compared as uints
+shared uint minDepthBits;
+shared uint maxDepthBits;
+// View space box around the tile between its closest and furthest depth
+shared vec3 tileMin;
+shared vec3 tileMax;
+// Spot light indices are offset by pointLightCount
+shared uint tileLightCount;
+shared uint tileLights[MAX_TILE_LIGHTS];
+
+vec3 viewPosition(vec2 ndc, float depth)
+{
+	vec4 position = invProjection * vec4(ndc, depth * 2.0 - 1.0, 1.0);
+	return position.xyz / position.w;
+}
+
+bool sphereInTile(vec3 center, float radius)
+{
+	vec3 offset = clamp(center, tileMin, tileMax) - center;
+	return dot(offset, offset) <= radius * radius;
+}
+
+void addLight(uint index)
+{
+	uint slot = atomicAdd(tileLightCount, 1u);
+	if (slot < MAX_TILE_LIGHTS)
+		tileLights[slot] = index;
+}
+
+void main()
+{
+	ivec2 pixel = ivec2(gl_GlobalInvocationID.xy);
+	bool inside = all(lessThan(pixel, screenSize));
+
+	if (gl_LocalInvocationIndex == 0u)
+	{
+		minDepthBits = 0xFFFFFFFFu;
+		maxDepthBits = 0u;
+		tileLightCount = 0u;
+	}
+	barrier();
+
+	// Sky pixels are left out of the depth bounds
+	float depth = inside ? texelFetch(gDepth, pixel, 0).r : 1.0;
+	if (depth < 1.0)
+	{
+		atomicMin(minDepthBits, floatBitsToUint(depth));
+		atomicMax(maxDepthBits, floatBitsToUint(depth));
+	}
+	barrier();
+
+	bool hasGeometry = minDepthBits <= maxDepthBits;
+	if (gl_LocalInvocationIndex == 0u && hasGeometry)
+	{
+		vec2 tileStart = vec2(gl_WorkGroupID.xy * TILE_SIZE) / vec2(screenSize) * 2.0 - 1.0;
+		vec2 tileEnd = vec2((gl_WorkGroupID.xy + 1u) * TILE_SIZE) / vec2(screenSize) * 2.0 - 1.0;
+		float depths[2] = float[2](uintBitsToFloat(minDepthBits), uintBitsToFloat(maxDepthBits));
+		vec3 boxMin = vec3(1e30);
+		vec3 boxMax = vec3(-1e30);
+		for (int i = 0; i < 8; i++)
+		{
+			vec2 ndc = vec2((i & 1) == 0 ? tileStart.x : tileEnd.x, (i & 2) == 0 ? tileStart.y : tileEnd.y);
+			vec3 corner = viewPosition(ndc, depths[i >> 2]);
+			boxMin = min(boxMin, corner);
+			boxMax = max(boxMax, corner);
+		}
+		tileMin = boxMin;
+		tileMax = boxMax;
+	}
+	barrier();
+
+	// Every thread in the tile tests a slice of the lights
+	if (hasGeometry)
+	{
+		uint threads = uint(TILE_SIZE * TILE_SIZE);
+		for (uint i = gl_LocalInvocationIndex; i < uint(pointLightCount); i += threads)
+		{
+			vec3 center = (view * vec4(pointLights[i].positionRange.xyz, 1.0)).xyz;
+			if (sphereInTile(center, pointLights[i].positionRange.w))
+				addLight(i);
+		}
+		for (uint i = gl_LocalInvocationIndex; i < uint(spotLightCount); i += threads)
+		{
+			vec3 center = (view * vec4(spotLights[i].positionRange.xyz, 1.0)).xyz;
+			if (sphereInTile(center, spotLights[i].positionRange.w))
+				addLight(uint(pointLightCount) + i);
+		}
+	}
+	barrier();
+
+	if (!inside)
+		return;
+
+	vec4 albedo = texelFetch(gAlbedo, pixel, 0);
+	if (depth >= 1.0)
+	{
+		imageStore(litImage, pixel, vec4(albedo.rgb, 1.0));
+		return;
+	}
+
+	vec4 normalRoughness = texelFetch(gNormal, pixel, 0);
+	vec3 N = normalRoughness.xyz;
+	vec4 baked = texelFetch(gLighting, pixel, 0);
+	bool lightmapped = baked.a > 0.5;
+
+	vec2 ndc = (vec2(pixel) + 0.5) / vec2(screenSize) * 2.0 - 1.0;
+	vec4 world = invViewProjection * vec4(ndc, depth * 2.0 - 1.0, 1.0);
+	vec3 worldPos = world.xyz / world.w;
+
+	vec3 result = baked.rgb + directionalLighting(N, lightmapped);
+	uint count = min(tileLightCount, uint(MAX_TILE_LIGHTS));
+	for (uint i = 0u; i < count; i++)
+	{
+		uint index = tileLights[i];
+		if (index < uint(pointLightCount))
+		{
+			PointLight light = pointLights[index];
+			if (lightmapped && light.colorBaked.w > 0.5)
+				continue;
+			result += pointLighting(N, worldPos, light.positionRange.xyz, light.colorBaked.rgb, light.positionRange.w, int(light.shadowView.x));
+		}
+		else
+		{
+			SpotLight light = spotLights[index - uint(pointLightCount)];
+			if (lightmapped && light.colorBaked.w > 0.5)
+				continue;
+			result += spotLighting(N, worldPos, light.positionRange.xyz, light.directionInnerCos.xyz, light.colorBaked.rgb,
+				light.positionRange.w, light.directionInnerCos.w, light.outerCosShadowView.x, int(light.outerCosShadowView.y));
+		}
+	}
+
+	vec3 specular = environmentSpecular(N, worldPos, normalRoughness.a);
+	imageStore(litImage, pixel, vec4(albedo.rgb * result + specular, 1.0));
+}
@@ -0,0 +1,17 @@
+#version 330 core
+
+// Outputs colors in RGBA
+out vec4 FragColor;
+
+
+in vec2 texCoord;
+
+uniform sampler2D litColor;
+uniform sampler2D litDepth;
+
+void main()
+{
+	FragColor = vec4(texture(litColor, texCoord).rgb, 1.0);
+	// Depth is carried over so later passes still depth test against the scene
+	gl_FragDepth = texture(litDepth, texCoord).r;
+}
@@ -0,0 +1,23 @@
+// LOD cross-fade dithering, included after the #version line
+
+// Ordered 4x4 Bayer threshold for the pixel, between 0 and 1
+float bayer4x4(vec2 fragCoord)
+{
+	const float pattern[16] = float[16](
+		0.0, 8.0, 2.0, 10.0,
+		12.0, 4.0, 14.0, 6.0,
+		3.0, 11.0, 1.0, 9.0,
+		15.0, 7.0, 13.0, 5.0
+	);
+	ivec2 cell = ivec2(mod(fragCoord, 4.0));
+	return (pattern[cell.y * 4 + cell.x] + 0.5) / 16.0;
+}
+
+// A fade of 0 keeps every pixel. A positive fade discards that fraction of the pattern from below and a
+// negative one from above, so the levels fading in and out together cover every pixel exactly once.
+void lodDither(float fade)
+{
+	float threshold = bayer4x4(gl_FragCoord.xy);
+	if ((fade > 0.0 && threshold < fade) || (fade < 0.0 && threshold >= 1.0 + fade))
+		discard;
+}
@@ -0,0 +1,13 @@
+#version 330 core
+
+// Outputs the texture coordinates to the fragment shader
+out vec2 texCoord;
+
+
+void main()
+{
+	// A single triangle covering the screen, no vertex buffers required
+	vec2 pos = vec2((gl_VertexID << 1) & 2, gl_VertexID & 2);
+	texCoord = pos;
+	gl_Position = vec4(pos * 2.0 - 1.0, 0.0, 1.0);
+}
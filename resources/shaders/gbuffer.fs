@@ -0,0 +1,42 @@
+#version 330 core
+
+#include "lighting.glsl"
+#include "dither.glsl"
+#include "splat.glsl"
+
+// Albedo in rgb
+layout (location = 0) out vec4 gAlbedo;
+// World space normal in rgb, material roughness in alpha
+layout (location = 1) out vec4 gNormal;
+// Lightmap irradiance or the ambient/environment irradiance in rgb, alpha flags lightmapped pixels
+layout (location = 2) out vec4 gLighting;
+
+
+// Inputs the texture coordinates from the Vertex Shader
+in vec2 texCoord;
+// Inputs the world space normal from the Vertex Shader
+in vec3 normal;
+// Inputs the lightmap coordinates from the Vertex Shader
+in vec2 lightmapUV;
+// Inputs the LOD cross-fade from the Vertex Shader
+flat in float lodFade;
+
+uniform sampler2D tex0;
+uniform float roughness;
+// Pixels with a lower texture alpha are discarded
+uniform float alphaCutoff;
+
+uniform bool hasLightmap;
+uniform sampler2D lightmap;
+
+void main()
+{
+	lodDither(lodFade);
+	vec4 albedo = materialAlbedo(tex0, texCoord);
+	if (albedo.a < alphaCutoff)
+		discard;
+	gAlbedo = vec4(albedo.rgb, 1.0);
+	// Zero normals are kept so the lighting pass lights them from every side
+	gNormal = vec4(dot(normal, normal) < 0.0001 ? vec3(0.0) : normalize(normal), roughness);
+	gLighting = hasLightmap ? vec4(texture(lightmap, lightmapUV).rgb, 1.0) : vec4(ambientLighting(gNormal.xyz), 0.0);
+}
@@ -0,0 +1,42 @@
+#version 330 core
+
+// Outputs the closest depth of the source texels
+out float Depth;
+
+
+// The depth buffer, only read when building level 0
+uniform sampler2D depthTexture;
+// The previous level of the pyramid, its base level is set to the level being read
+uniform sampler2D source;
+uniform bool copyDepth;
+uniform ivec2 sourceSize;
+
+float fetch(ivec2 coord)
+{
+	return texelFetch(source, min(coord, sourceSize - 1), 0).r;
+}
+
+void main()
+{
+	ivec2 coord = ivec2(gl_FragCoord.xy);
+	if (copyDepth)
+	{
+		Depth = texelFetch(depthTexture, coord, 0).r;
+		return;
+	}
+
+	ivec2 base = coord * 2;
+	float depth = min(min(fetch(base), fetch(base + ivec2(1, 0))), min(fetch(base + ivec2(0, 1)), fetch(base + ivec2(1, 1))));
+
+	// Odd sized levels fold the leftover row/column into the last texel
+	bool oddX = (sourceSize.x & 1) != 0 && coord.x == (sourceSize.x >> 1) - 1;
+	bool oddY = (sourceSize.y & 1) != 0 && coord.y == (sourceSize.y >> 1) - 1;
+	if (oddX)
+		depth = min(depth, min(fetch(base + ivec2(2, 0)), fetch(base + ivec2(2, 1))));
+	if (oddY)
+		depth = min(depth, min(fetch(base + ivec2(0, 2)), fetch(base + ivec2(1, 2))));
+	if (oddX && oddY)
+		depth = min(depth, fetch(base + ivec2(2, 2)));
+
+	Depth = depth;
+}
@@ -0,0 +1,54 @@
+// Shared by the environment convolution passes, included after the #version line
+
+const float PI = 3.14159265359;
+
+// Direction through texel (x, y) of a cubemap face, must match texture::cube_direction
+vec3 cubeDirection(int face, vec2 texel, int size)
+{
+	vec2 uv = (texel + 0.5) / float(size) * 2.0 - 1.0;
+	vec3 dir;
+	if (face == 0) dir = vec3(1.0, -uv.y, -uv.x);
+	else if (face == 1) dir = vec3(-1.0, -uv.y, uv.x);
+	else if (face == 2) dir = vec3(uv.x, 1.0, uv.y);
+	else if (face == 3) dir = vec3(uv.x, -1.0, -uv.y);
+	else if (face == 4) dir = vec3(uv.x, -uv.y, 1.0);
+	else dir = vec3(-uv.x, -uv.y, -1.0);
+	return normalize(dir);
+}
+
+// Low discrepancy 2D sequence, spreads samples more evenly than random numbers
+vec2 hammersley(uint i, uint count)
+{
+	uint bits = i;
+	bits = (bits << 16u) | (bits >> 16u);
+	bits = ((bits & 0x55555555u) << 1u) | ((bits & 0xAAAAAAAAu) >> 1u);
+	bits = ((bits & 0x33333333u) << 2u) | ((bits & 0xCCCCCCCCu) >> 2u);
+	bits = ((bits & 0x0F0F0F0Fu) << 4u) | ((bits & 0xF0F0F0F0u) >> 4u);
+	bits = ((bits & 0x00FF00FFu) << 8u) | ((bits & 0xFF00FF00u) >> 8u);
+	return vec2(float(i) / float(count), float(bits) * 2.3283064365386963e-10);
+}
+
+// Basis around N, used to move tangent space samples into world space
+mat3 tangentBasis(vec3 N)
+{
+	vec3 up = abs(N.z) < 0.999 ? vec3(0.0, 0.0, 1.0) : vec3(1.0, 0.0, 0.0);
+	vec3 tangent = normalize(cross(up, N));
+	return mat3(tangent, cross(N, tangent), N);
+}
+
+// Half vector distributed by the GGX lobe of the given roughness
+vec3 importanceSampleGGX(vec2 xi, vec3 N, float roughness)
+{
+	float a = roughness * roughness;
+	float phi = 2.0 * PI * xi.x;
+	float cosTheta = sqrt((1.0 - xi.y) / (1.0 + (a * a - 1.0) * xi.y));
+	float sinTheta = sqrt(1.0 - cosTheta * cosTheta);
+	return tangentBasis(N) * vec3(cos(phi) * sinTheta, sin(phi) * sinTheta, cosTheta);
+}
+
+float distributionGGX(float NdotH, float roughness)
+{
+	float a2 = roughness * roughness * roughness * roughness;
+	float d = NdotH * NdotH * (a2 - 1.0) + 1.0;
+	return a2 / (PI * d * d);
+}
@@ -0,0 +1,36 @@
+#version 430 core
+
+#include "ibl.glsl"
+
+// Must match environment::GROUP_SIZE
+layout (local_size_x = 8, local_size_y = 8) in;
+
+layout (rgba16f, binding = 0) uniform writeonly imageCube irradianceImage;
+
+uniform samplerCube environment;
+uniform int faceSize;
+
+// Cosine weighted integral over the hemisphere around each texel direction
+void main()
+{
+	ivec3 texel = ivec3(gl_GlobalInvocationID);
+	if (texel.x >= faceSize || texel.y >= faceSize)
+		return;
+
+	vec3 N = cubeDirection(texel.z, vec2(texel.xy), faceSize);
+	mat3 basis = tangentBasis(N);
+	vec3 sum = vec3(0.0);
+	float count = 0.0;
+	const float stepSize = 0.05;
+	for (float phi = 0.0; phi < 2.0 * PI; phi += stepSize)
+	{
+		for (float theta = 0.0; theta < 0.5 * PI; theta += stepSize)
+		{
+			vec3 dir = basis * vec3(sin(theta) * cos(phi), sin(theta) * sin(phi), cos(theta));
+			// A blurred mip stands in for the many texels each sample covers
+			sum += textureLod(environment, dir, 4.0).rgb * cos(theta) * sin(theta);
+			count += 1.0;
+		}
+	}
+	imageStore(irradianceImage, texel, vec4(PI * sum / count, 1.0));
+}
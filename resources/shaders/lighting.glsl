@@ -0,0 +1,141 @@
+// Shared by the forward and deferred lighting paths, included after the #version line
+
+#define MAX_DIRECTIONAL_LIGHTS 4
+#define MAX_SHADOW_VIEWS 48
+
+struct DirectionalLight {
+	bool baked;
+	vec3 direction;
+	vec3 color;
+};
+
+struct ShadowView {
+	mat4 matrix;
+	vec4 rect;
+};
+
+uniform int directionalLightCount;
+uniform DirectionalLight directionalLights[MAX_DIRECTIONAL_LIGHTS];
+
+uniform sampler2DShadow shadowAtlas;
+uniform float shadowTexelSize;
+uniform ShadowView shadowViews[MAX_SHADOW_VIEWS];
+
+// Image based lighting from environment::Skybox, the constant ambient stands in when there is no sky
+uniform bool hasEnvironment;
+uniform samplerCube irradianceMap;
+uniform samplerCube prefilteredMap;
+uniform sampler2D brdfLut;
+uniform float environmentIntensity;
+uniform float prefilteredLevels;
+uniform vec3 cameraPosition;
+
+const vec3 ambient = vec3(0.15);
+// Reflectance at normal incidence of dielectrics
+const vec3 F0 = vec3(0.04);
+
+vec3 ambientLighting(vec3 N)
+{
+	if (!hasEnvironment || dot(N, N) < 0.0001)
+		return ambient;
+	return texture(irradianceMap, N).rgb * environmentIntensity;
+}
+
+// Split sum approximation, rougher surfaces read blurrier mips of the prefiltered map
+vec3 environmentSpecular(vec3 N, vec3 worldPos, float roughness)
+{
+	if (!hasEnvironment || dot(N, N) < 0.0001)
+		return vec3(0.0);
+	vec3 V = normalize(cameraPosition - worldPos);
+	float NdotV = max(dot(N, V), 0.0);
+	vec3 R = reflect(-V, N);
+	vec3 radiance = textureLod(prefilteredMap, R, roughness * (prefilteredLevels - 1.0)).rgb;
+	vec2 brdf = texture(brdfLut, vec2(NdotV, roughness)).rg;
+	return radiance * (F0 * brdf.x + brdf.y) * environmentIntensity;
+}
+
+// Meshes without a normal buffer read a zero normal, light them from every side
+float lambert(vec3 N, vec3 L)
+{
+	return dot(N, N) < 0.0001 ? 1.0 : max(dot(N, L), 0.0);
+}
+
+float attenuation(float dist, float range)
+{
+	float falloff = clamp(1.0 - pow(dist / range, 4.0), 0.0, 1.0);
+	return falloff * falloff / (dist * dist + 1.0);
+}
+
+// 3x3 PCF inside a single atlas tile, clamped so filtering never reads a neighbouring tile
+float sampleShadow(int view, vec3 worldPos)
+{
+	vec4 clip = shadowViews[view].matrix * vec4(worldPos, 1.0);
+	vec3 coords = (clip.xyz / clip.w) * 0.5 + 0.5;
+	if (coords.z > 1.0)
+		return 1.0;
+
+	vec4 rect = shadowViews[view].rect;
+	vec2 minUV = rect.xy + vec2(shadowTexelSize);
+	vec2 maxUV = rect.xy + rect.zw - vec2(shadowTexelSize);
+	vec2 uv = rect.xy + coords.xy * rect.zw;
+
+	float lit = 0.0;
+	for (int x = -1; x <= 1; x++)
+	{
+		for (int y = -1; y <= 1; y++)
+		{
+			vec2 offset = vec2(x, y) * shadowTexelSize;
+			lit += texture(shadowAtlas, vec3(clamp(uv + offset, minUV, maxUV), coords.z));
+		}
+	}
+	return lit / 9.0;
+}
+
+// Face order matches shadow::view_projection, +X, -X, +Y, -Y, +Z, -Z
+int cubeFace(vec3 dir)
+{
+	vec3 a = abs(dir);
+	if (a.x >= a.y && a.x >= a.z)
+		return dir.x > 0.0 ? 0 : 1;
+	if (a.y >= a.z)
+		return dir.y > 0.0 ? 2 : 3;
+	return dir.z > 0.0 ? 4 : 5;
+}
+
+vec3 directionalLighting(vec3 N, bool skipBaked)
+{
+	vec3 result = vec3(0.0);
+	for (int i = 0; i < directionalLightCount; i++)
+	{
+		if (skipBaked && directionalLights[i].baked)
+			continue;
+		result += directionalLights[i].color * lambert(N, -directionalLights[i].direction);
+	}
+	return result;
+}
+
+vec3 pointLighting(vec3 N, vec3 worldPos, vec3 position, vec3 color, float range, int shadowView)
+{
+	vec3 toLight = position - worldPos;
+	float dist = length(toLight);
+	if (dist > range)
+		return vec3(0.0);
+	float shadow = 1.0;
+	if (shadowView >= 0)
+		shadow = sampleShadow(shadowView + cubeFace(-toLight), worldPos + N * 0.02);
+	return color * lambert(N, toLight / dist) * attenuation(dist, range) * shadow;
+}
+
+vec3 spotLighting(vec3 N, vec3 worldPos, vec3 position, vec3 direction, vec3 color, float range, float innerCos, float outerCos, int shadowView)
+{
+	vec3 toLight = position - worldPos;
+	float dist = length(toLight);
+	if (dist > range)
+		return vec3(0.0);
+	vec3 L = toLight / dist;
+	float cone = smoothstep(outerCos, innerCos, dot(-L, direction));
+	float shadow = 1.0;
+	if (shadowView >= 0 && cone > 0.0)
+		shadow = sampleShadow(shadowView, worldPos + N * 0.02);
+	return color * lambert(N, L) * attenuation(dist, range) * cone * shadow;
+}
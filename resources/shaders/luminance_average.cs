@@ -0,0 +1,46 @@
+#version 430 core
+
+// Must match postprocess::HISTOGRAM_BINS
+#define HISTOGRAM_BINS 256
+
+layout (local_size_x = HISTOGRAM_BINS) in;
+
+layout (std430, binding = 0) buffer Histogram {
+	uint histogram[HISTOGRAM_BINS];
+};
+
+layout (r32f, binding = 0) uniform image2D averageLuminance;
+
+uniform int pixelCount;
+uniform float minLogLuminance;
+uniform float logLuminanceRange;
+// Fraction of the way the stored luminance moves towards this frame's, depends on frame time
+uniform float adaptation;
+
+shared float weighted[HISTOGRAM_BINS];
+
+void main()
+{
+	uint index = gl_LocalInvocationIndex;
+	uint count = histogram[index];
+	weighted[index] = float(count) * float(index);
+	// Cleared here so the next frame starts from an empty histogram
+	histogram[index] = 0u;
+	barrier();
+
+	for (uint stride = HISTOGRAM_BINS / 2; stride > 0u; stride >>= 1)
+	{
+		if (index < stride)
+			weighted[index] += weighted[index + stride];
+		barrier();
+	}
+
+	// Thread 0 read bin 0, black pixels are left out of the average
+	if (index == 0u && pixelCount > int(count))
+	{
+		float meanBin = weighted[0] / float(pixelCount - int(count)) - 1.0;
+		float luminance = exp2(meanBin / 254.0 * logLuminanceRange + minLogLuminance);
+		float previous = imageLoad(averageLuminance, ivec2(0)).r;
+		imageStore(averageLuminance, ivec2(0), vec4(previous + (luminance - previous) * adaptation));
+	}
+}
@@ -0,0 +1,40 @@
+#version 430 core
+
+// Must match postprocess::HISTOGRAM_BINS and HISTOGRAM_TILE_SIZE
+#define HISTOGRAM_BINS 256
+
+layout (local_size_x = 16, local_size_y = 16) in;
+
+layout (std430, binding = 0) buffer Histogram {
+	uint histogram[HISTOGRAM_BINS];
+};
+
+uniform sampler2D hdrColor;
+uniform ivec2 screenSize;
+uniform float minLogLuminance;
+uniform float inverseLogLuminanceRange;
+
+shared uint localBins[HISTOGRAM_BINS];
+
+// Bin 0 holds black pixels, the rest split the log luminance range evenly
+uint luminanceBin(vec3 color)
+{
+	float luminance = dot(color, vec3(0.2126, 0.7152, 0.0722));
+	if (luminance < 0.0001)
+		return 0u;
+	float t = clamp((log2(luminance) - minLogLuminance) * inverseLogLuminanceRange, 0.0, 1.0);
+	return uint(t * 254.0 + 1.0);
+}
+
+void main()
+{
+	localBins[gl_LocalInvocationIndex] = 0u;
+	barrier();
+
+	ivec2 pixel = ivec2(gl_GlobalInvocationID.xy);
+	if (all(lessThan(pixel, screenSize)))
+		atomicAdd(localBins[luminanceBin(texelFetch(hdrColor, pixel, 0).rgb)], 1u);
+	barrier();
+
+	atomicAdd(histogram[gl_LocalInvocationIndex], localBins[gl_LocalInvocationIndex]);
+}
@@ -0,0 +1,37 @@
+// Morph targets, included after the #version line of vertex shaders that draw meshes
+
+// Per instance offset of the mesh's weights in the palette, negative for meshes that aren't morphed
+layout (location = 14) in float aMorphOffset;
+
+// Weights of every morphed instance, each list starting with its length
+uniform samplerBuffer morphWeights;
+// Targets of the mesh being drawn, a position and a normal offset per target of every vertex, vertex after vertex
+uniform samplerBuffer morphTargets;
+
+// Weighted sum of the offsets of every target, kind 0 for positions and 1 for normals
+vec3 morphOffset(int kind)
+{
+	if (aMorphOffset < 0.0)
+		return vec3(0.0);
+	int offset = int(aMorphOffset + 0.5);
+	int count = int(texelFetch(morphWeights, offset).r + 0.5);
+	int first = gl_VertexID * count * 2 + kind;
+	vec3 sum = vec3(0.0);
+	for (int i = 0; i < count; i++)
+	{
+		float weight = texelFetch(morphWeights, offset + 1 + i).r;
+		if (weight != 0.0)
+			sum += weight * texelFetch(morphTargets, first + i * 2).xyz;
+	}
+	return sum;
+}
+
+vec3 morphPosition(vec3 position)
+{
+	return position + morphOffset(0);
+}
+
+vec3 morphNormal(vec3 normal)
+{
+	return normal + morphOffset(1);
+}
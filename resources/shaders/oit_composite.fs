@@ -0,0 +1,21 @@
+#version 330 core
+
+// Outputs colors in RGBA
+out vec4 FragColor;
+
+
+in vec2 texCoord;
+
+uniform sampler2D accumTexture;
+uniform sampler2D revealageTexture;
+
+void main()
+{
+	float revealage = texture(revealageTexture, texCoord).r;
+	// Nothing transparent covers this pixel
+	if (revealage >= 0.9999)
+		discard;
+	vec4 accum = texture(accumTexture, texCoord);
+	// Blended over the scene with 1 - revealage as coverage
+	FragColor = vec4(accum.rgb / max(accum.a, 0.00001), 1.0 - revealage);
+}
@@ -0,0 +1,25 @@
+#version 330 core
+
+// Outputs colors in RGBA
+out vec4 FragColor;
+
+
+// Inputs the tint from the Vertex Shader
+in vec4 color;
+// Inputs the texture coordinates from the Vertex Shader
+in vec2 texCoord;
+// Inputs the position on the quad from the Vertex Shader
+in vec2 corner;
+
+uniform sampler2D particle;
+uniform bool hasTexture;
+
+void main()
+{
+	// Without a texture particles are soft discs
+	vec4 texel = hasTexture ? texture(particle, texCoord) : vec4(1.0, 1.0, 1.0, 1.0 - smoothstep(0.0, 0.5, length(corner)));
+	texel *= color;
+	if (texel.a <= 0.0)
+		discard;
+	FragColor = texel;
+}
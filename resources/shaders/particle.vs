@@ -0,0 +1,41 @@
+#version 330 core
+
+// Quad corner from -0.5 to 0.5
+layout (location = 0) in vec2 aCorner;
+// World space center in xyz, size in w
+layout (location = 5) in vec4 aCenter;
+// Tint, alpha included
+layout (location = 6) in vec4 aColor;
+// Rotation in radians and texture sheet frame
+layout (location = 7) in vec2 aRotation;
+
+
+// Outputs the tint for the Fragment Shader
+out vec4 color;
+// Outputs the texture coordinates to the fragment shader
+out vec2 texCoord;
+// Outputs the position on the quad for untextured particles
+out vec2 corner;
+
+uniform mat4 view;
+uniform mat4 projection;
+// Columns and rows of the texture sheet
+uniform ivec2 sheet;
+
+
+void main()
+{
+	// Billboarded in view space so the quad always faces the camera
+	float s = sin(aRotation.x);
+	float c = cos(aRotation.x);
+	vec2 offset = mat2(c, s, -s, c) * aCorner * aCenter.w;
+	vec4 center = view * vec4(aCenter.xyz, 1.0);
+	gl_Position = projection * vec4(center.xy + offset, center.zw);
+
+	// Sheet rows count from the top, textures are stored bottom row first
+	int frame = int(aRotation.y);
+	vec2 cell = vec2(frame % sheet.x, sheet.y - 1 - frame / sheet.x);
+	texCoord = (cell + aCorner + 0.5) / vec2(sheet);
+	color = aColor;
+	corner = aCorner;
+}
@@ -0,0 +1,51 @@
+#version 430 core
+
+#include "ibl.glsl"
+
+// Must match environment::GROUP_SIZE
+layout (local_size_x = 8, local_size_y = 8) in;
+
+layout (rgba16f, binding = 0) uniform writeonly imageCube prefilteredImage;
+
+uniform samplerCube environment;
+uniform int faceSize;
+uniform float roughness;
+
+#define SAMPLE_COUNT 512u
+
+// GGX importance sampled radiance, assuming the view direction equals the normal
+void main()
+{
+	ivec3 texel = ivec3(gl_GlobalInvocationID);
+	if (texel.x >= faceSize || texel.y >= faceSize)
+		return;
+
+	vec3 N = cubeDirection(texel.z, vec2(texel.xy), faceSize);
+	if (roughness == 0.0)
+	{
+		imageStore(prefilteredImage, texel, vec4(textureLod(environment, N, 0.0).rgb, 1.0));
+		return;
+	}
+
+	// Samples read a mip matching the solid angle they cover, which keeps bright spots from sparkling
+	float environmentSize = float(textureSize(environment, 0).x);
+	float texelSolidAngle = 4.0 * PI / (6.0 * environmentSize * environmentSize);
+
+	vec3 sum = vec3(0.0);
+	float weight = 0.0;
+	for (uint i = 0u; i < SAMPLE_COUNT; i++)
+	{
+		vec3 H = importanceSampleGGX(hammersley(i, SAMPLE_COUNT), N, roughness);
+		vec3 L = normalize(2.0 * dot(N, H) * H - N);
+		float NdotL = dot(N, L);
+		if (NdotL <= 0.0)
+			continue;
+		float NdotH = max(dot(N, H), 0.0);
+		float pdf = distributionGGX(NdotH, roughness) * 0.25 + 0.0001;
+		float sampleSolidAngle = 1.0 / (float(SAMPLE_COUNT) * pdf);
+		float level = 0.5 * log2(sampleSolidAngle / texelSolidAngle) + 1.0;
+		sum += textureLod(environment, L, max(level, 0.0)).rgb * NdotL;
+		weight += NdotL;
+	}
+	imageStore(prefilteredImage, texel, vec4(sum / max(weight, 0.0001), 1.0));
+}
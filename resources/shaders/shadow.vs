@@ -0,0 +1,18 @@
+#version 330 core
+
+#include "skinning.glsl"
+#include "morph.glsl"
+
+// Positions/Coordinates
+layout (location = 0) in vec3 aPos;
+// Per instance model matrix, takes locations 5 to 8
+layout (location = 5) in mat4 aModel;
+
+// Imports the view projection of the shadow tile being rendered
+uniform mat4 lightMatrix;
+
+
+void main()
+{
+	gl_Position = lightMatrix * aModel * skinMatrix() * vec4(morphPosition(aPos), 1.0);
+}
@@ -0,0 +1,29 @@
+// Skinning, included after the #version line of vertex shaders that draw meshes
+
+// Joints and weights of up to four influences per vertex
+layout (location = 11) in vec4 aJoints;
+layout (location = 12) in vec4 aWeights;
+// Per instance offset of the mesh's joint matrices in the palette, negative for meshes that aren't skinned
+layout (location = 13) in float aSkinOffset;
+
+// Every joint matrix takes four RGBA32F texels, one per column
+uniform samplerBuffer jointMatrices;
+
+mat4 jointMatrix(int joint)
+{
+	int texel = joint * 4;
+	return mat4(texelFetch(jointMatrices, texel), texelFetch(jointMatrices, texel + 1),
+		texelFetch(jointMatrices, texel + 2), texelFetch(jointMatrices, texel + 3));
+}
+
+// Bind pose to model space, identity for meshes that aren't skinned
+mat4 skinMatrix()
+{
+	if (aSkinOffset < 0.0)
+		return mat4(1.0);
+	int offset = int(aSkinOffset + 0.5);
+	return aWeights.x * jointMatrix(offset + int(aJoints.x + 0.5))
+		+ aWeights.y * jointMatrix(offset + int(aJoints.y + 0.5))
+		+ aWeights.z * jointMatrix(offset + int(aJoints.z + 0.5))
+		+ aWeights.w * jointMatrix(offset + int(aJoints.w + 0.5));
+}
@@ -0,0 +1,16 @@
+#version 330 core
+
+// Outputs colors in RGBA
+out vec4 FragColor;
+
+
+// Inputs the view direction from the Vertex Shader
+in vec3 direction;
+
+uniform samplerCube environment;
+uniform float intensity;
+
+void main()
+{
+	FragColor = vec4(textureLod(environment, normalize(direction), 0.0).rgb * intensity, 1.0);
+}
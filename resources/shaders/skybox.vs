@@ -0,0 +1,18 @@
+#version 330 core
+
+// Outputs the world space view direction to the fragment shader
+out vec3 direction;
+
+uniform mat4 view;
+uniform mat4 projection;
+
+
+void main()
+{
+	// Fullscreen triangle on the far plane, depth testing with LEQUAL keeps it behind every mesh
+	vec2 pos = vec2((gl_VertexID << 1) & 2, gl_VertexID & 2) * 2.0 - 1.0;
+	gl_Position = vec4(pos, 1.0, 1.0);
+	// Translation is dropped so the sky stays infinitely far away
+	vec4 world = inverse(projection * mat4(mat3(view))) * vec4(pos, 1.0, 1.0);
+	direction = world.xyz / world.w;
+}
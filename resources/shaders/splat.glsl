@@ -0,0 +1,24 @@
+// Splat mapping, included after the #version line
+
+// Splat mapped materials blend up to four layers of tex1 by the rgba weights their first texture holds
+uniform bool splatMapped;
+uniform sampler2DArray tex1;
+// Times the layers repeat across the splat map
+uniform float splatTiling;
+
+// The material's color at uv, straight from its first texture unless it is splat mapped
+vec4 materialAlbedo(sampler2D base, vec2 uv)
+{
+	vec4 color = texture(base, uv);
+	if (!splatMapped)
+		return color;
+	// Weights that don't add up to one are normalized, an all black splat map shows the first layer
+	float total = dot(color, vec4(1.0));
+	vec4 weights = total > 0.0001 ? color / total : vec4(1.0, 0.0, 0.0, 0.0);
+	vec2 layerUV = uv * splatTiling;
+	vec4 result = vec4(0.0);
+	// Sampled outside of any branch so mip selection has its derivatives
+	for (int i = 0; i < 4; i++)
+		result += weights[i] * texture(tex1, vec3(layerUV, float(i)));
+	return result;
+}
@@ -0,0 +1,21 @@
+#version 330 core
+
+// Outputs colors in RGBA
+out vec4 FragColor;
+
+
+// Inputs the tint from the Vertex Shader
+in vec4 color;
+// Inputs the texture coordinates from the Vertex Shader
+in vec2 texCoord;
+
+uniform sampler2D sprite;
+
+void main()
+{
+	vec4 texel = texture(sprite, texCoord) * color;
+	// Empty parts of the sheet skip blending entirely
+	if (texel.a <= 0.0)
+		discard;
+	FragColor = texel;
+}
@@ -0,0 +1,24 @@
+#version 330 core
+
+// View space positions, sprites are snapped and flipped on the CPU
+layout (location = 0) in vec3 aPos;
+// Tint, alpha included
+layout (location = 1) in vec4 aColor;
+// Texture Coordinates inside the atlas
+layout (location = 2) in vec2 aTex;
+
+
+// Outputs the tint for the Fragment Shader
+out vec4 color;
+// Outputs the texture coordinates to the fragment shader
+out vec2 texCoord;
+
+uniform mat4 projection;
+
+
+void main()
+{
+	gl_Position = projection * vec4(aPos, 1.0);
+	color = aColor;
+	texCoord = aTex;
+}
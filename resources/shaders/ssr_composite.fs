@@ -0,0 +1,43 @@
+#version 330 core
+
+// Outputs colors in RGBA
+out vec4 FragColor;
+
+
+in vec2 texCoord;
+
+uniform sampler2D sceneColor;
+uniform sampler2D sceneDepth;
+uniform sampler2D normals;
+uniform sampler2D reflections;
+
+uniform mat4 invProjection;
+uniform float maxLod;
+uniform float maxRoughness;
+
+void main()
+{
+	vec4 scene = texture(sceneColor, texCoord);
+	float depth = texture(sceneDepth, texCoord).r;
+	gl_FragDepth = depth;
+
+	vec4 normalRoughness = texture(normals, texCoord);
+	if (depth >= 1.0 || dot(normalRoughness.xyz, normalRoughness.xyz) < 0.0001)
+	{
+		FragColor = vec4(scene.rgb, 1.0);
+		return;
+	}
+
+	float roughness = normalRoughness.w;
+	// Rough surfaces read blurrier mips, the trace output is premultiplied so blurring keeps colors correct
+	vec4 reflection = textureLod(reflections, texCoord, roughness * maxLod);
+	vec3 reflectionColor = reflection.rgb / max(reflection.a, 0.0001);
+
+	vec4 view = invProjection * vec4(vec3(texCoord, depth) * 2.0 - 1.0, 1.0);
+	vec3 V = -normalize(view.xyz / view.w);
+	vec3 N = normalize(normalRoughness.xyz);
+	float fresnel = 0.04 + 0.96 * pow(1.0 - max(dot(N, V), 0.0), 5.0);
+
+	float weight = reflection.a * fresnel * (1.0 - clamp(roughness / maxRoughness, 0.0, 1.0));
+	FragColor = vec4(mix(scene.rgb, reflectionColor, weight), 1.0);
+}
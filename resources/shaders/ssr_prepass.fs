@@ -0,0 +1,18 @@
+#version 330 core
+
+// Outputs the view space normal and roughness
+out vec4 NormalRoughness;
+
+
+// Inputs the view space normal from the Vertex Shader
+in vec3 viewNormal;
+
+// Imports the roughness of the material being drawn
+uniform float roughness;
+
+void main()
+{
+	// Meshes without normals write a zero normal and never receive reflections
+	vec3 N = dot(viewNormal, viewNormal) < 0.0001 ? vec3(0.0) : normalize(viewNormal);
+	NormalRoughness = vec4(N, roughness);
+}
@@ -0,0 +1,28 @@
+#version 330 core
+
+#include "skinning.glsl"
+#include "morph.glsl"
+
+// Positions/Coordinates
+layout (location = 0) in vec3 aPos;
+// Normals
+layout (location = 3) in vec3 aNormal;
+// Per instance model matrix, takes locations 5 to 8
+layout (location = 5) in mat4 aModel;
+
+
+// Outputs the view space normal to the fragment shader
+out vec3 viewNormal;
+
+// Imports the camera matrix from the main function
+uniform mat4 camMatrix;
+// Imports the camera view matrix to move normals into view space
+uniform mat4 view;
+
+
+void main()
+{
+	mat4 model = aModel * skinMatrix();
+	gl_Position = camMatrix * model * vec4(morphPosition(aPos), 1.0);
+	viewNormal = mat3(view) * mat3(transpose(inverse(model))) * morphNormal(aNormal);
+}
@@ -0,0 +1,136 @@
+#version 330 core
+
+// Outputs the reflected color premultiplied by its confidence
+out vec4 FragColor;
+
+
+in vec2 texCoord;
+
+uniform sampler2D hiz;
+uniform sampler2D normals;
+uniform sampler2D sceneColor;
+uniform int hizLevels;
+
+uniform mat4 projection;
+uniform mat4 invProjection;
+
+uniform int maxSteps;
+uniform float maxDistance;
+uniform float thickness;
+uniform float edgeFade;
+uniform float maxRoughness;
+
+vec3 viewFromDepth(vec2 uv, float depth)
+{
+	vec4 view = invProjection * vec4(vec3(uv, depth) * 2.0 - 1.0, 1.0);
+	return view.xyz / view.w;
+}
+
+vec3 projectToScreen(vec3 viewPos)
+{
+	vec4 clip = projection * vec4(viewPos, 1.0);
+	return (clip.xyz / clip.w) * 0.5 + 0.5;
+}
+
+float linearDepth(float depth)
+{
+	vec4 view = invProjection * vec4(0.0, 0.0, depth * 2.0 - 1.0, 1.0);
+	return -view.z / view.w;
+}
+
+// Walks the ray through the depth pyramid, climbing a level whenever a whole cell is in front
+// of the closest surface inside it and descending whenever it might hit something.
+// Depth after projection is linear in screen space, so the ray is a straight line in (uv, depth).
+bool traceHiZ(vec3 origin, vec3 dir, out vec2 hitUV, out float steps)
+{
+	vec2 size = vec2(textureSize(hiz, 0));
+	float pixelLength = max(length(dir.xy * size), 0.0001);
+	float epsilon = 0.01 / pixelLength;
+	// Start a pixel and a half away so the ray does not hit the surface it leaves
+	float t = 1.5 / pixelLength;
+	int level = 0;
+
+	vec2 crossStep = vec2(dir.x >= 0.0 ? 1.0 : 0.0, dir.y >= 0.0 ? 1.0 : 0.0);
+	vec2 safeDir = vec2(abs(dir.x) < 1e-7 ? 1e-7 : dir.x, abs(dir.y) < 1e-7 ? 1e-7 : dir.y);
+
+	hitUV = vec2(0.0);
+	steps = 0.0;
+	for (int i = 0; i < maxSteps; i++)
+	{
+		steps = float(i);
+		if (t > 1.0)
+			return false;
+
+		vec3 p = origin + dir * t;
+		if (any(lessThan(p.xy, vec2(0.0))) || any(greaterThan(p.xy, vec2(1.0))))
+			return false;
+
+		vec2 cellCount = vec2(textureSize(hiz, level));
+		vec2 cell = floor(p.xy * cellCount);
+		vec2 tCross = ((cell + crossStep) / cellCount - origin.xy) / safeDir;
+		float tExit = min(min(tCross.x, tCross.y), 1.0);
+		float cellDepth = texelFetch(hiz, ivec2(cell), level).r;
+		float exitDepth = origin.z + dir.z * tExit;
+
+		if (max(p.z, exitDepth) < cellDepth)
+		{
+			t = tExit + epsilon;
+			level = min(level + 1, hizLevels - 1);
+		}
+		else if (level > 0)
+		{
+			level--;
+		}
+		else
+		{
+			float tHit = t;
+			if (dir.z > 0.0 && p.z < cellDepth)
+				tHit = clamp((cellDepth - origin.z) / dir.z, t, tExit);
+			vec3 hit = origin + dir * tHit;
+			if (linearDepth(hit.z) - linearDepth(cellDepth) < thickness)
+			{
+				hitUV = hit.xy;
+				return true;
+			}
+			// Passed behind a thin object, keep marching behind it
+			t = tExit + epsilon;
+		}
+	}
+	return false;
+}
+
+void main()
+{
+	vec4 normalRoughness = texture(normals, texCoord);
+	float depth = texelFetch(hiz, ivec2(texCoord * vec2(textureSize(hiz, 0))), 0).r;
+	if (depth >= 1.0 || dot(normalRoughness.xyz, normalRoughness.xyz) < 0.0001 || normalRoughness.w > maxRoughness)
+	{
+		FragColor = vec4(0.0);
+		return;
+	}
+
+	vec3 viewPos = viewFromDepth(texCoord, depth);
+	vec3 N = normalize(normalRoughness.xyz);
+	vec3 R = reflect(normalize(viewPos), N);
+	// Rays heading back towards the camera leave the screen almost immediately
+	if (R.z > 0.0)
+	{
+		FragColor = vec4(0.0);
+		return;
+	}
+
+	vec3 origin = projectToScreen(viewPos);
+	vec3 dir = projectToScreen(viewPos + R * maxDistance) - origin;
+
+	vec2 hitUV;
+	float steps;
+	if (!traceHiZ(origin, dir, hitUV, steps))
+	{
+		FragColor = vec4(0.0);
+		return;
+	}
+
+	vec2 edge = smoothstep(vec2(0.0), vec2(edgeFade), hitUV) * (1.0 - smoothstep(vec2(1.0 - edgeFade), vec2(1.0), hitUV));
+	float confidence = edge.x * edge.y * (1.0 - steps / float(maxSteps)) * clamp(-R.z * 4.0, 0.0, 1.0);
+	FragColor = vec4(texture(sceneColor, hitUV).rgb * confidence, confidence);
+}
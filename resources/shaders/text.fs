@@ -0,0 +1,24 @@
+#version 330 core
+
+// Outputs colors in RGBA
+out vec4 FragColor;
+
+
+// Inputs the color from the Vertex Shader
+in vec4 color;
+// Inputs the texture coordinates from the Vertex Shader
+in vec2 texCoord;
+
+// Signed distance to the glyph outline, 0.5 is the edge
+uniform sampler2D glyphs;
+
+void main()
+{
+	float distance = texture(glyphs, texCoord).r;
+	// Antialiases over about one screen pixel at any scale
+	float width = max(fwidth(distance) * 0.7, 0.0001);
+	float alpha = smoothstep(0.5 - width, 0.5 + width, distance) * color.a;
+	if (alpha <= 0.0)
+		discard;
+	FragColor = vec4(color.rgb, alpha);
+}
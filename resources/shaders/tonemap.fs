@@ -0,0 +1,99 @@
+#version 330 core
+
+// Outputs colors in RGBA
+out vec4 FragColor;
+
+
+in vec2 texCoord;
+
+uniform sampler2D hdrColor;
+uniform sampler2D hdrDepth;
+uniform sampler2D bloom;
+uniform sampler2D averageLuminance;
+uniform sampler3D lut;
+
+uniform bool bloomEnabled;
+uniform float bloomIntensity;
+uniform bool autoExposure;
+// In stops
+uniform float exposureCompensation;
+// 0 none, 1 Reinhard, 2 ACES, 3 AgX, matches postprocess::ToneMapping
+uniform int toneMapping;
+uniform bool lutEnabled;
+uniform float lutSize;
+uniform float gamma;
+
+vec3 reinhard(vec3 color)
+{
+	float luminance = dot(color, vec3(0.2126, 0.7152, 0.0722));
+	return color / (1.0 + luminance);
+}
+
+// Stephen Hill's fit of the ACES RRT and ODT
+vec3 aces(vec3 color)
+{
+	const mat3 inputMatrix = mat3(
+		0.59719, 0.07600, 0.02840,
+		0.35458, 0.90834, 0.13383,
+		0.04823, 0.01566, 0.83777);
+	const mat3 outputMatrix = mat3(
+		1.60475, -0.10208, -0.00327,
+		-0.53108, 1.10813, -0.07276,
+		-0.07367, -0.00605, 1.07602);
+	color = inputMatrix * color;
+	vec3 a = color * (color + 0.0245786) - 0.000090537;
+	vec3 b = color * (0.983729 * color + 0.4329510) + 0.238081;
+	return clamp(outputMatrix * (a / b), 0.0, 1.0);
+}
+
+// Minimal AgX with the default contrast curve fitted as a polynomial
+vec3 agx(vec3 color)
+{
+	const mat3 inset = mat3(
+		0.842479062253094, 0.0423282422610123, 0.0423756549057051,
+		0.0784335999999992, 0.878468636469772, 0.0784336,
+		0.0792237451477643, 0.0791661274605434, 0.879142973793104);
+	const mat3 outset = mat3(
+		1.19687900512017, -0.0528968517574562, -0.0529716355144438,
+		-0.0980208811401368, 1.15190312990417, -0.0980434501171241,
+		-0.0990297440797205, -0.0989611768448433, 1.15107367264116);
+	const float minEv = -12.47393;
+	const float maxEv = 4.026069;
+
+	color = clamp(log2(inset * max(color, vec3(1e-10))), minEv, maxEv);
+	color = (color - minEv) / (maxEv - minEv);
+	vec3 x2 = color * color;
+	vec3 x4 = x2 * x2;
+	color = 15.5 * x4 * x2 - 40.14 * x4 * color + 31.96 * x4 - 6.868 * x2 * color + 0.4298 * x2 + 0.1191 * color - 0.00232;
+	// The curve outputs display encoded values, decode so every operator shares the gamma step below
+	return pow(max(outset * color, vec3(0.0)), vec3(2.2));
+}
+
+void main()
+{
+	gl_FragDepth = texture(hdrDepth, texCoord).r;
+
+	vec3 color = texture(hdrColor, texCoord).rgb;
+	if (bloomEnabled)
+		color += texture(bloom, texCoord).rgb * bloomIntensity;
+
+	float exposure = exp2(exposureCompensation);
+	if (autoExposure)
+		exposure *= 0.18 / max(texelFetch(averageLuminance, ivec2(0), 0).r, 0.0001);
+	color *= exposure;
+
+	if (toneMapping == 1)
+		color = reinhard(color);
+	else if (toneMapping == 2)
+		color = aces(color);
+	else if (toneMapping == 3)
+		color = agx(color);
+
+	color = pow(clamp(color, 0.0, 1.0), vec3(1.0 / gamma));
+
+	// Sample texel centers so the LUT edges map exactly onto 0 and 1
+	if (lutEnabled)
+		color = texture(lut, color * ((lutSize - 1.0) / lutSize) + 0.5 / lutSize).rgb;
+
+	FragColor = vec4(color, 1.0);
+}
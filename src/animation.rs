@@ -0,0 +1,549 @@
+use std::{collections::HashMap, sync::Arc};
+
+use bevy_ecs::prelude::*;
+use glam::*;
+
+//Local transform of a joint, kept apart so poses can be blended component-wise
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Transform {
+    pub translation: Vec3,
+    pub rotation: Quat,
+    pub scale: Vec3,
+}
+
+impl Transform {
+    pub const IDENTITY: Transform = Transform { translation: Vec3::ZERO, rotation: Quat::IDENTITY, scale: Vec3::ONE };
+
+    pub fn from_matrix(matrix: Mat4) -> Transform {
+        let (scale, rotation, translation) = matrix.to_scale_rotation_translation();
+        Transform { translation, rotation, scale }
+    }
+    pub fn matrix(&self) -> Mat4 {
+        Mat4::from_scale_rotation_translation(self.scale, self.rotation, self.translation)
+    }
+    //Weight 0 keeps self, 1 gives other
+    pub fn blend(&self, other: &Transform, weight: f32) -> Transform {
+        Transform {
+            translation: self.translation.lerp(other.translation, weight),
+            rotation: self.rotation.slerp(other.rotation, weight),
+            scale: self.scale.lerp(other.scale, weight),
+        }
+    }
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Transform::IDENTITY
+    }
+}
+
+pub struct Joint {
+    pub name: String,
+    //Node of the file the joint came from, animation channels target nodes
+    pub node: usize,
+    pub parent: Option<usize>,
+    //Local transform when no clip animates the joint
+    pub rest: Transform,
+}
+
+//Joints can be listed in any order, they are posed parents first
+pub struct Skeleton {
+    pub joints: Vec<Joint>,
+    //Model space to the space of each joint when the mesh was bound
+    pub inverse_bind: Vec<Mat4>,
+    //Transform above the root joints, e.g. the nodes a file places the skeleton under
+    pub root: Mat4,
+    order: Vec<usize>,
+    by_node: HashMap<usize, usize>,
+}
+
+impl Skeleton {
+    pub fn new(joints: Vec<Joint>, inverse_bind: Vec<Mat4>, root: Mat4) -> Skeleton {
+        let depth = |mut joint: usize| {
+            let mut depth = 0;
+            while let Some(parent) = joints[joint].parent {
+                joint = parent;
+                depth += 1;
+                if depth > joints.len() {
+                    break;
+                }
+            }
+            depth
+        };
+        let mut order: Vec<usize> = (0..joints.len()).collect();
+        order.sort_by_key(|joint| depth(*joint));
+        let by_node = joints.iter().enumerate().map(|(index, joint)| (joint.node, index)).collect();
+        Skeleton { joints, inverse_bind, root, order, by_node }
+    }
+    pub fn joint(&self, name: &str) -> Option<usize> {
+        self.joints.iter().position(|joint| joint.name == name)
+    }
+    pub fn joint_for_node(&self, node: usize) -> Option<usize> {
+        self.by_node.get(&node).copied()
+    }
+    pub fn rest_pose(&self) -> Vec<Transform> {
+        self.joints.iter().map(|joint| joint.rest).collect()
+    }
+    //Model space matrix of every joint for a pose of local transforms
+    pub fn global_matrices(&self, pose: &[Transform]) -> Vec<Mat4> {
+        let mut globals = vec![Mat4::IDENTITY; self.joints.len()];
+        for &joint in &self.order {
+            let parent = self.joints[joint].parent.map_or(self.root, |parent| globals[parent]);
+            globals[joint] = parent * pose[joint].matrix();
+        }
+        globals
+    }
+    //What the vertex shader multiplies bind pose vertices by
+    pub fn joint_matrices(&self, pose: &[Transform]) -> Vec<Mat4> {
+        self.global_matrices(pose).iter().zip(&self.inverse_bind).map(|(global, inverse_bind)| *global * *inverse_bind).collect()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Interpolation {
+    Step,
+    Linear,
+    //Hermite spline, every keyframe stores an in-tangent, the value and an out-tangent
+    CubicSpline,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ChannelTarget {
+    Translation,
+    Rotation,
+    Scale,
+}
+
+//Keyframes of one property of one node, vectors in xyz and quaternions in xyzw
+pub struct Channel {
+    pub node: usize,
+    pub target: ChannelTarget,
+    pub interpolation: Interpolation,
+    pub times: Vec<f32>,
+    pub values: Vec<Vec4>,
+}
+
+//Keyframes on either side of time, how far between them it is and how long apart they are.
+//Before the first and after the last keyframe both are the same one.
+fn segment(times: &[f32], time: f32) -> (usize, usize, f32, f32) {
+    let last = times.len() - 1;
+    //Index of the first keyframe after time
+    let next = times.partition_point(|key| *key <= time);
+    if next == 0 {
+        return (0, 0, 0.0, 0.0);
+    }
+    if next > last {
+        return (last, last, 0.0, 0.0);
+    }
+    let previous = next - 1;
+    let duration = times[next] - times[previous];
+    let t = if duration > 0.0 { (time - times[previous]) / duration } else { 0.0 };
+    (previous, next, t, duration)
+}
+
+//Hermite basis for the previous value, its out-tangent, the next value and its in-tangent
+fn hermite(t: f32) -> [f32; 4] {
+    let (t2, t3) = (t * t, t * t * t);
+    [2.0 * t3 - 3.0 * t2 + 1.0, t3 - 2.0 * t2 + t, -2.0 * t3 + 3.0 * t2, t3 - t2]
+}
+
+impl Channel {
+    pub fn sample(&self, time: f32) -> Vec4 {
+        let value = |key: usize| match self.interpolation {
+            Interpolation::CubicSpline => self.values[key * 3 + 1],
+            _ => self.values[key],
+        };
+        let (previous, next, t, duration) = segment(&self.times, time);
+        match self.interpolation {
+            Interpolation::Step => value(previous),
+            Interpolation::Linear if self.target == ChannelTarget::Rotation => {
+                Vec4::from(Quat::from_vec4(value(previous)).slerp(Quat::from_vec4(value(next)), t))
+            }
+            Interpolation::Linear => value(previous).lerp(value(next), t),
+            Interpolation::CubicSpline => {
+                let out_tangent = self.values[previous * 3 + 2] * duration;
+                let in_tangent = self.values[next * 3] * duration;
+                let [h0, h1, h2, h3] = hermite(t);
+                let result = h0 * value(previous) + h1 * out_tangent + h2 * value(next) + h3 * in_tangent;
+                match self.target {
+                    ChannelTarget::Rotation => result.normalize_or_zero(),
+                    _ => result,
+                }
+            }
+        }
+    }
+    fn apply(&self, time: f32, transform: &mut Transform) {
+        let value = self.sample(time);
+        match self.target {
+            ChannelTarget::Translation => transform.translation = value.xyz(),
+            ChannelTarget::Rotation => transform.rotation = Quat::from_vec4(value).normalize(),
+            ChannelTarget::Scale => transform.scale = value.xyz(),
+        }
+    }
+}
+
+//Keyframes of the morph target weights of one node's mesh, every keyframe holds a weight for each target
+pub struct WeightsChannel {
+    pub node: usize,
+    pub interpolation: Interpolation,
+    pub times: Vec<f32>,
+    //Keyframe after keyframe, cubic splines store all in-tangents, then the weights, then the out-tangents
+    pub values: Vec<f32>,
+}
+
+impl WeightsChannel {
+    pub fn target_count(&self) -> usize {
+        let per_keyframe = if self.interpolation == Interpolation::CubicSpline { 3 } else { 1 };
+        self.values.len() / (self.times.len() * per_keyframe).max(1)
+    }
+    //Writes as many weights as both the channel and the slice have
+    pub fn sample(&self, time: f32, weights: &mut [f32]) {
+        if self.times.is_empty() {
+            return;
+        }
+        let count = self.target_count();
+        let (previous, next, t, duration) = segment(&self.times, time);
+        //Part 0 is the in-tangent, 1 the weight and 2 the out-tangent of a cubic spline keyframe
+        let value = |key: usize, part: usize, target: usize| match self.interpolation {
+            Interpolation::CubicSpline => self.values[(key * 3 + part) * count + target],
+            _ => self.values[key * count + target],
+        };
+        for (target, weight) in weights.iter_mut().enumerate().take(count) {
+            *weight = match self.interpolation {
+                Interpolation::Step => value(previous, 1, target),
+                Interpolation::Linear => value(previous, 1, target) + (value(next, 1, target) - value(previous, 1, target)) * t,
+                Interpolation::CubicSpline => {
+                    let [h0, h1, h2, h3] = hermite(t);
+                    h0 * value(previous, 1, target) + h1 * value(previous, 2, target) * duration
+                        + h2 * value(next, 1, target) + h3 * value(next, 0, target) * duration
+                }
+            };
+        }
+    }
+}
+
+pub struct AnimationClip {
+    pub name: String,
+    pub duration: f32,
+    pub channels: Vec<Channel>,
+    pub weights: Vec<WeightsChannel>,
+}
+
+impl AnimationClip {
+    //Overwrites the joints the clip animates, the rest of the pose is left alone
+    pub fn sample(&self, skeleton: &Skeleton, time: f32, pose: &mut [Transform]) {
+        for channel in &self.channels {
+            let joint = match skeleton.joint_for_node(channel.node) {
+                Some(joint) => joint,
+                None => continue,
+            };
+            if channel.times.is_empty() {
+                continue;
+            }
+            channel.apply(time, &mut pose[joint]);
+        }
+    }
+    //Local transform of one joint, its rest transform where the clip doesn't animate it
+    pub fn sample_joint(&self, skeleton: &Skeleton, joint: usize, time: f32) -> Transform {
+        let mut transform = skeleton.joints[joint].rest;
+        for channel in self.channels.iter().filter(|channel| channel.node == skeleton.joints[joint].node && !channel.times.is_empty()) {
+            channel.apply(time, &mut transform);
+        }
+        transform
+    }
+    //Overwrites the morph weights of the node's mesh where the clip animates them
+    pub fn sample_weights(&self, node: usize, time: f32, weights: &mut [f32]) {
+        for channel in self.weights.iter().filter(|channel| channel.node == node) {
+            channel.sample(time, weights);
+        }
+    }
+}
+
+#[derive(Clone)]
+struct PlayingClip {
+    clip: Arc<AnimationClip>,
+    time: f32,
+    looping: bool,
+}
+
+impl PlayingClip {
+    //Negative deltas play backwards, looping clips wrap around either end
+    fn advance(&mut self, delta: f32) {
+        let duration = self.clip.duration;
+        self.time = if self.looping && duration > 0.0 {
+            (self.time + delta).rem_euclid(duration)
+        } else {
+            (self.time + delta).clamp(0.0, duration)
+        };
+    }
+    fn is_finished(&self) -> bool {
+        !self.looping && self.time >= self.clip.duration
+    }
+}
+
+//Clip being faded out and how far along the fade is
+#[derive(Clone)]
+struct Crossfade {
+    from: PlayingClip,
+    duration: f32,
+    elapsed: f32,
+}
+
+//Clips the AnimationPlayer of the same entity is switched between, the first loops and the others play once.
+//See systems::update_animation_controls.
+#[derive(Component, Clone)]
+pub struct AnimationClips {
+    pub clips: Vec<Arc<AnimationClip>>,
+}
+
+//Plays clips on the SkinnedMesh and MorphWeights of the same entity, see systems::animate
+#[derive(Component, Clone)]
+pub struct AnimationPlayer {
+    current: Option<PlayingClip>,
+    fade: Option<Crossfade>,
+    //Scales time for every clip, negative plays backwards
+    pub speed: f32,
+    paused: bool,
+}
+
+impl Default for AnimationPlayer {
+    fn default() -> Self {
+        AnimationPlayer { current: None, fade: None, speed: 1.0, paused: false }
+    }
+}
+
+impl AnimationPlayer {
+    pub fn new() -> AnimationPlayer {
+        AnimationPlayer::default()
+    }
+    pub fn with_clip(mut self, clip: Arc<AnimationClip>, looping: bool) -> AnimationPlayer {
+        self.play(clip, looping);
+        self
+    }
+    //Switches straight to the clip, from its start
+    pub fn play(&mut self, clip: Arc<AnimationClip>, looping: bool) {
+        self.current = Some(PlayingClip { clip, time: 0.0, looping });
+        self.fade = None;
+    }
+    //Starts the clip while the current one keeps playing and fades out over duration seconds.
+    //A fade that is still running is cut short, its outgoing clip is dropped.
+    pub fn crossfade(&mut self, clip: Arc<AnimationClip>, duration: f32, looping: bool) {
+        let from = self.current.replace(PlayingClip { clip, time: 0.0, looping });
+        self.fade = match from {
+            Some(from) if duration > 0.0 => Some(Crossfade { from, duration, elapsed: 0.0 }),
+            _ => None,
+        };
+    }
+    pub fn stop(&mut self) {
+        self.current = None;
+        self.fade = None;
+    }
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+    pub fn resume(&mut self) {
+        self.paused = false;
+    }
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+    pub fn set_looping(&mut self, looping: bool) {
+        if let Some(current) = &mut self.current {
+            current.looping = looping;
+        }
+    }
+    pub fn seek(&mut self, time: f32) {
+        if let Some(current) = &mut self.current {
+            current.time = 0.0;
+            current.advance(time);
+        }
+    }
+    pub fn clip(&self) -> Option<&Arc<AnimationClip>> {
+        self.current.as_ref().map(|current| &current.clip)
+    }
+    pub fn time(&self) -> f32 {
+        self.current.as_ref().map_or(0.0, |current| current.time)
+    }
+    //A clip that doesn't loop stays on its last frame once it is done
+    pub fn is_finished(&self) -> bool {
+        self.current.as_ref().is_none_or(|current| current.is_finished())
+    }
+    pub fn advance(&mut self, delta_seconds: f32) {
+        if self.paused {
+            return;
+        }
+        let delta = delta_seconds * self.speed;
+        if let Some(current) = &mut self.current {
+            current.advance(delta);
+        }
+        if let Some(fade) = &mut self.fade {
+            fade.from.advance(delta);
+            fade.elapsed += delta.abs();
+            if fade.elapsed >= fade.duration {
+                self.fade = None;
+            }
+        }
+    }
+    //Local joint transforms for the current time, the rest pose where nothing is playing
+    pub fn pose(&self, skeleton: &Skeleton) -> Vec<Transform> {
+        let mut pose = skeleton.rest_pose();
+        if let Some(current) = &self.current {
+            current.clip.sample(skeleton, current.time, &mut pose);
+        }
+        if let Some(fade) = &self.fade {
+            let mut from = skeleton.rest_pose();
+            fade.from.clip.sample(skeleton, fade.from.time, &mut from);
+            let weight = (fade.elapsed / fade.duration).clamp(0.0, 1.0);
+            for (to, from) in pose.iter_mut().zip(&from) {
+                *to = from.blend(to, weight);
+            }
+        }
+        pose
+    }
+    //Morph weights of the node's mesh for the current time, the ones passed in are kept where nothing animates them
+    pub fn weights(&self, node: usize, weights: &mut [f32]) {
+        let from = self.fade.as_ref().map(|fade| {
+            let mut from = weights.to_vec();
+            fade.from.clip.sample_weights(node, fade.from.time, &mut from);
+            from
+        });
+        if let Some(current) = &self.current {
+            current.clip.sample_weights(node, current.time, weights);
+        }
+        if let (Some(from), Some(fade)) = (from, &self.fade) {
+            let weight = (fade.elapsed / fade.duration).clamp(0.0, 1.0);
+            for (to, from) in weights.iter_mut().zip(&from) {
+                *to = from + (*to - from) * weight;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn channel(target: ChannelTarget, interpolation: Interpolation, times: &[f32], values: &[Vec4]) -> Channel {
+        Channel { node: 1, target, interpolation, times: times.to_vec(), values: values.to_vec() }
+    }
+
+    //Moves from the origin to 2 along X over two seconds
+    fn ramp(interpolation: Interpolation) -> Channel {
+        channel(ChannelTarget::Translation, interpolation, &[0.0, 1.0, 2.0], &[Vec4::ZERO, Vec4::X, Vec4::X * 2.0])
+    }
+
+    //A tip listed before the root it hangs from, the test channels animate the tip's node
+    fn skeleton() -> Skeleton {
+        let joint = |name: &str, node: usize, parent: Option<usize>| Joint { name: name.to_string(), node, parent, rest: Transform::IDENTITY };
+        Skeleton::new(vec![joint("tip", 1, Some(1)), joint("root", 0, None)], vec![Mat4::IDENTITY; 2], Mat4::IDENTITY)
+    }
+
+    fn clip(name: &str, channels: Vec<Channel>) -> Arc<AnimationClip> {
+        Arc::new(AnimationClip { name: name.to_string(), duration: 2.0, channels, weights: Vec::new() })
+    }
+
+    #[test]
+    fn steps_hold_until_the_next_keyframe() {
+        let channel = ramp(Interpolation::Step);
+        assert_eq!(channel.sample(-1.0), Vec4::ZERO);
+        assert_eq!(channel.sample(0.99), Vec4::ZERO);
+        assert_eq!(channel.sample(1.0), Vec4::X);
+        assert_eq!(channel.sample(5.0), Vec4::X * 2.0);
+    }
+
+    #[test]
+    fn lerps_between_keyframes() {
+        let channel = ramp(Interpolation::Linear);
+        assert!(channel.sample(0.25).abs_diff_eq(Vec4::X * 0.25, 1e-6));
+        assert!(channel.sample(1.5).abs_diff_eq(Vec4::X * 1.5, 1e-6));
+        assert_eq!(channel.sample(3.0), Vec4::X * 2.0);
+    }
+
+    #[test]
+    fn slerps_rotations() {
+        let quarter = Quat::from_rotation_y(std::f32::consts::FRAC_PI_2);
+        let channel = channel(ChannelTarget::Rotation, Interpolation::Linear, &[0.0, 1.0], &[Vec4::from(Quat::IDENTITY), Vec4::from(quarter)]);
+        let half = Quat::from_vec4(channel.sample(0.5));
+        assert!(half.abs_diff_eq(Quat::from_rotation_y(std::f32::consts::FRAC_PI_4), 1e-5), "{:?}", half);
+    }
+
+    #[test]
+    fn follows_cubic_spline_tangents() {
+        //In-tangent, value and out-tangent per keyframe, tangents are per second and scaled by the two seconds apart
+        let values = [Vec4::ZERO, Vec4::ZERO, Vec4::X, Vec4::ZERO, Vec4::X, Vec4::ZERO];
+        let channel = channel(ChannelTarget::Translation, Interpolation::CubicSpline, &[0.0, 2.0], &values);
+        assert_eq!(channel.sample(0.0), Vec4::ZERO);
+        assert_eq!(channel.sample(2.0), Vec4::X);
+        assert!(channel.sample(1.0).abs_diff_eq(Vec4::X * 0.75, 1e-6), "{}", channel.sample(1.0));
+    }
+
+    #[test]
+    fn samples_morph_weights() {
+        let linear = WeightsChannel { node: 1, interpolation: Interpolation::Linear, times: vec![0.0, 1.0], values: vec![0.0, 1.0, 1.0, 0.0] };
+        assert_eq!(linear.target_count(), 2);
+        let mut weights = [9.0; 3];
+        linear.sample(0.25, &mut weights);
+        assert_eq!(weights, [0.25, 0.75, 9.0]);
+        let mut first = [9.0];
+        linear.sample(1.0, &mut first);
+        assert_eq!(first, [1.0]);
+
+        let mut values = vec![0.0; 12];
+        values[8..10].copy_from_slice(&[1.0, 0.5]);
+        let cubic = WeightsChannel { node: 1, interpolation: Interpolation::CubicSpline, times: vec![0.0, 1.0], values };
+        assert_eq!(cubic.target_count(), 2);
+        cubic.sample(0.5, &mut weights);
+        assert_eq!(weights[..2], [0.5, 0.25]);
+    }
+
+    #[test]
+    fn clips_only_pose_the_joints_they_animate() {
+        let skeleton = skeleton();
+        let clip = clip("slide", vec![ramp(Interpolation::Linear)]);
+        let mut pose = vec![Transform { scale: Vec3::splat(2.0), ..Transform::IDENTITY }; 2];
+        clip.sample(&skeleton, 0.5, &mut pose);
+        assert_eq!(pose[0].translation, Vec3::X * 0.5);
+        assert_eq!(pose[1].translation, Vec3::ZERO);
+        assert_eq!(clip.sample_joint(&skeleton, 1, 0.5), Transform::IDENTITY);
+        assert_eq!(clip.sample_joint(&skeleton, 0, 1.0).translation, Vec3::X);
+    }
+
+    #[test]
+    fn players_loop_seek_and_pause() {
+        let skeleton = skeleton();
+        let mut player = AnimationPlayer::new().with_clip(clip("slide", vec![ramp(Interpolation::Linear)]), true);
+        player.advance(2.5);
+        assert!((player.time() - 0.5).abs() < 1e-6);
+        player.seek(-0.5);
+        assert!((player.time() - 1.5).abs() < 1e-6);
+        assert!(player.pose(&skeleton)[0].translation.abs_diff_eq(Vec3::X * 1.5, 1e-6));
+
+        player.pause();
+        player.advance(1.0);
+        assert!(player.is_paused());
+        assert!((player.time() - 1.5).abs() < 1e-6);
+        player.resume();
+        player.set_looping(false);
+        player.advance(1.0);
+        assert_eq!(player.time(), 2.0);
+        assert!(player.is_finished());
+
+        player.stop();
+        assert!(player.clip().is_none());
+        assert_eq!(player.pose(&skeleton)[0], Transform::IDENTITY);
+    }
+
+    #[test]
+    fn crossfades_blend_out_the_previous_clip() {
+        let skeleton = skeleton();
+        let still = channel(ChannelTarget::Translation, Interpolation::Step, &[0.0], &[Vec4::Y]);
+        let mut player = AnimationPlayer::new().with_clip(clip("still", vec![still]), true);
+        player.crossfade(clip("slide", vec![ramp(Interpolation::Linear)]), 1.0, false);
+        player.advance(0.5);
+        //Halfway between Y and the slide's X * 0.5
+        assert!(player.pose(&skeleton)[0].translation.abs_diff_eq(Vec3::new(0.25, 0.5, 0.0), 1e-6));
+        player.advance(0.5);
+        assert!(player.pose(&skeleton)[0].translation.abs_diff_eq(Vec3::X, 1e-6));
+        assert_eq!(player.clip().unwrap().name, "slide");
+    }
+}
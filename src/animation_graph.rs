@@ -0,0 +1,702 @@
+use std::{collections::HashMap, error::Error, f32::consts::{PI, TAU}, fs, sync::Arc};
+
+use bevy_ecs::prelude::*;
+use glam::*;
+use serde::{Serialize, Deserialize};
+use simple_error::SimpleError;
+
+use crate::{animation::{AnimationClip, Skeleton, Transform}, gltf::Model};
+
+//Loaded from resources/animations/{name}.toml
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct AnimationGraphDescriptor {
+    //resources/models/{model}, whose clips the states play
+    pub model: String,
+    //Starting values, booleans are 0 or 1
+    pub parameters: HashMap<String, f32>,
+    //Joint whose horizontal movement and turning move the entity instead of the skeleton, empty for none.
+    //Only the first layer drives it.
+    pub root_motion: String,
+    //Evaluated in order, each on top of the ones before
+    pub layers: Vec<LayerDescriptor>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+pub enum LayerBlend {
+    //Blends towards the layer's pose by its weight
+    #[default]
+    Override,
+    //Adds the difference between the layer's pose and the first frame of its motion
+    Additive,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct LayerDescriptor {
+    pub name: String,
+    pub blend: LayerBlend,
+    pub weight: f32,
+    //Joints the layer affects along with everything below them, empty for the whole skeleton
+    pub mask: Vec<String>,
+    //Starting state, the first one when empty
+    pub initial: String,
+    pub states: Vec<StateDescriptor>,
+    //Checked in order every frame the layer isn't already transitioning, the first that passes is taken
+    pub transitions: Vec<TransitionDescriptor>,
+}
+
+impl Default for LayerDescriptor {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            blend: LayerBlend::Override,
+            weight: 1.0,
+            mask: Vec::new(),
+            initial: String::new(),
+            states: Vec::new(),
+            transitions: Vec::new(),
+        }
+    }
+}
+
+//Clips of a blend space play in sync, their times are scaled to a shared phase
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum Motion {
+    Clip(String),
+    //Clips placed along one parameter, the two on either side of its value are blended
+    Blend1D { parameter: String, clips: Vec<(String, f32)> },
+    //Clips placed on the plane of two parameters, weighted by gradient band interpolation
+    Blend2D { parameters: [String; 2], clips: Vec<(String, [f32; 2])> },
+}
+
+impl Default for Motion {
+    fn default() -> Self {
+        Motion::Clip(String::new())
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct StateDescriptor {
+    pub name: String,
+    pub motion: Motion,
+    pub speed: f32,
+    pub looping: bool,
+}
+
+impl Default for StateDescriptor {
+    fn default() -> Self {
+        Self { name: String::new(), motion: Motion::default(), speed: 1.0, looping: true }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct TransitionDescriptor {
+    //State to leave, "*" for any other state
+    pub from: String,
+    pub to: String,
+    //Seconds both states are blended for
+    pub duration: f32,
+    //Phase of the state being left, 0 to 1, before which the transition waits. None leaves right away.
+    pub exit_time: Option<f32>,
+    //All of them have to hold
+    pub conditions: Vec<Condition>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+pub enum Test {
+    #[default]
+    Greater,
+    Less,
+    Equal,
+    NotEqual,
+    //Non-zero, the value is ignored
+    True,
+    False,
+}
+
+#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+#[serde(default)]
+pub struct Condition {
+    pub parameter: String,
+    pub test: Test,
+    pub value: f32,
+}
+
+impl Condition {
+    fn holds(&self, parameter: f32) -> bool {
+        match self.test {
+            Test::Greater => parameter > self.value,
+            Test::Less => parameter < self.value,
+            Test::Equal => parameter == self.value,
+            Test::NotEqual => parameter != self.value,
+            Test::True => parameter != 0.0,
+            Test::False => parameter == 0.0,
+        }
+    }
+}
+
+enum ResolvedMotion {
+    Clip(Arc<AnimationClip>),
+    Blend1D { parameter: String, clips: Vec<(Arc<AnimationClip>, f32)> },
+    Blend2D { parameters: [String; 2], clips: Vec<(Arc<AnimationClip>, Vec2)> },
+}
+
+struct State {
+    motion: ResolvedMotion,
+    speed: f32,
+    looping: bool,
+}
+
+struct Transition {
+    from: Option<usize>,
+    to: usize,
+    duration: f32,
+    exit_time: Option<f32>,
+    conditions: Vec<Condition>,
+}
+
+struct Layer {
+    states: Vec<State>,
+    transitions: Vec<Transition>,
+    initial: usize,
+}
+
+//A descriptor with its names resolved to the model's clips and to state indices
+pub struct AnimationGraph {
+    pub descriptor: AnimationGraphDescriptor,
+    layers: Vec<Layer>,
+}
+
+impl AnimationGraph {
+    pub fn load(name: &str) -> Result<AnimationGraph, Box<dyn Error>> {
+        let descriptor: AnimationGraphDescriptor = toml::from_str(&fs::read_to_string(format!("resources/animations/{}.toml", name))?)?;
+        let model = Model::load(&descriptor.model)?;
+        AnimationGraph::new(descriptor, &model.clips)
+    }
+
+    pub fn new(descriptor: AnimationGraphDescriptor, clips: &[Arc<AnimationClip>]) -> Result<AnimationGraph, Box<dyn Error>> {
+        let clip = |name: &str| clips.iter().find(|clip| clip.name == name).cloned()
+            .ok_or_else(|| SimpleError::new(format!("Unknown clip {}", name)));
+        let mut layers = Vec::new();
+        for layer in &descriptor.layers {
+            if layer.states.is_empty() {
+                return Err(SimpleError::new(format!("Layer {} has no states", layer.name)).into());
+            }
+            let state = |name: &str| layer.states.iter().position(|state| state.name == name)
+                .ok_or_else(|| SimpleError::new(format!("Unknown state {} in layer {}", name, layer.name)));
+            let mut states = Vec::new();
+            for state in &layer.states {
+                let motion = match &state.motion {
+                    Motion::Clip(name) => ResolvedMotion::Clip(clip(name)?),
+                    Motion::Blend1D { parameter, clips } => {
+                        let mut clips = clips.iter().map(|(name, position)| Ok((clip(name)?, *position))).collect::<Result<Vec<_>, SimpleError>>()?;
+                        clips.sort_by(|a, b| a.1.total_cmp(&b.1));
+                        ResolvedMotion::Blend1D { parameter: parameter.clone(), clips }
+                    }
+                    Motion::Blend2D { parameters, clips } => {
+                        let clips = clips.iter().map(|(name, position)| Ok((clip(name)?, Vec2::from(*position)))).collect::<Result<Vec<_>, SimpleError>>()?;
+                        ResolvedMotion::Blend2D { parameters: parameters.clone(), clips }
+                    }
+                };
+                states.push(State { motion, speed: state.speed, looping: state.looping });
+            }
+            let mut transitions = Vec::new();
+            for transition in &layer.transitions {
+                transitions.push(Transition {
+                    from: if transition.from == "*" { None } else { Some(state(&transition.from)?) },
+                    to: state(&transition.to)?,
+                    duration: transition.duration,
+                    exit_time: transition.exit_time,
+                    conditions: transition.conditions.clone(),
+                });
+            }
+            let initial = if layer.initial.is_empty() { 0 } else { state(&layer.initial)? };
+            layers.push(Layer { states, transitions, initial });
+        }
+        Ok(AnimationGraph { descriptor, layers })
+    }
+
+    //Clips of a state with weights adding up to one
+    fn weights(&self, layer: usize, state: usize, parameters: &HashMap<String, f32>) -> Vec<(&Arc<AnimationClip>, f32)> {
+        let parameter = |name: &str| parameters.get(name).copied().unwrap_or(0.0);
+        match &self.layers[layer].states[state].motion {
+            ResolvedMotion::Clip(clip) => vec![(clip, 1.0)],
+            ResolvedMotion::Blend1D { parameter: name, clips } => {
+                let value = parameter(name);
+                let next = clips.partition_point(|(_, position)| *position <= value);
+                match next {
+                    0 => clips.first().map(|(clip, _)| (clip, 1.0)).into_iter().collect(),
+                    next if next == clips.len() => vec![(&clips[next - 1].0, 1.0)],
+                    next => {
+                        let ((previous, start), (clip, end)) = (&clips[next - 1], &clips[next]);
+                        let t = (value - start) / (end - start);
+                        vec![(previous, 1.0 - t), (clip, t)]
+                    }
+                }
+            }
+            ResolvedMotion::Blend2D { parameters: names, clips } => {
+                let value = Vec2::new(parameter(&names[0]), parameter(&names[1]));
+                let mut weights: Vec<(&Arc<AnimationClip>, f32)> = clips.iter().map(|(clip, position)| {
+                    //Each other point pulls the weight down the closer value gets to it
+                    let weight = clips.iter().filter(|(_, other)| other != position).fold(1.0f32, |weight, (_, other)| {
+                        let edge = *other - *position;
+                        weight.min(1.0 - (value - *position).dot(edge) / edge.length_squared())
+                    });
+                    (clip, weight.max(0.0))
+                }).collect();
+                let total: f32 = weights.iter().map(|(_, weight)| weight).sum();
+                if total > 0.0 {
+                    weights.iter_mut().for_each(|(_, weight)| *weight /= total);
+                }
+                weights
+            }
+        }
+    }
+}
+
+//Movement of the root motion joint since the last update, in the entity's space as it was facing before it
+#[derive(Clone, Copy, Default, Debug)]
+pub struct RootMotion {
+    pub translation: Vec3,
+    //Radians around +Y
+    pub yaw: f32,
+}
+
+#[derive(Clone)]
+struct Playback {
+    state: usize,
+    //0 to 1 through the state's motion
+    phase: f32,
+    previous_phase: f32,
+    //1 when the last update wrapped past the end, -1 past the start when playing backwards
+    wrapped: f32,
+}
+
+impl Playback {
+    fn new(state: usize) -> Playback {
+        Playback { state, phase: 0.0, previous_phase: 0.0, wrapped: 0.0 }
+    }
+    fn advance(&mut self, delta: f32, looping: bool) {
+        self.previous_phase = self.phase;
+        let phase = self.phase + delta;
+        self.wrapped = 0.0;
+        if looping {
+            if phase >= 1.0 {
+                self.wrapped = 1.0;
+            } else if phase < 0.0 {
+                self.wrapped = -1.0;
+            }
+            self.phase = phase.rem_euclid(1.0);
+        } else {
+            self.phase = phase.clamp(0.0, 1.0);
+        }
+    }
+    //Whether the phase is past exit_time, or went past it on the way around the last loop
+    fn reached(&self, exit_time: f32) -> bool {
+        self.phase >= exit_time || (self.wrapped > 0.0 && self.previous_phase <= exit_time)
+    }
+}
+
+#[derive(Clone)]
+struct LayerState {
+    current: Playback,
+    //State being blended out, with the blend's length and how far along it is
+    fade: Option<(Playback, f32, f32)>,
+    weight: f32,
+}
+
+//Evaluates an animation graph into the SkinnedMesh of the same entity, see systems::animate_graphs
+#[derive(Component, Clone)]
+pub struct AnimationGraphPlayer {
+    graph: Arc<AnimationGraph>,
+    parameters: HashMap<String, f32>,
+    layers: Vec<LayerState>,
+    //Scales time for every layer
+    pub speed: f32,
+    paused: bool,
+    //Per layer joint weights, resolved against the first skeleton evaluated
+    masks: Vec<Vec<f32>>,
+}
+
+impl AnimationGraphPlayer {
+    pub fn new(graph: Arc<AnimationGraph>) -> AnimationGraphPlayer {
+        let layers = graph.descriptor.layers.iter().zip(&graph.layers)
+            .map(|(descriptor, layer)| LayerState { current: Playback::new(layer.initial), fade: None, weight: descriptor.weight })
+            .collect();
+        let parameters = graph.descriptor.parameters.clone();
+        AnimationGraphPlayer { graph, parameters, layers, speed: 1.0, paused: false, masks: Vec::new() }
+    }
+    pub fn set_parameter(&mut self, name: &str, value: f32) {
+        self.parameters.insert(name.to_string(), value);
+    }
+    pub fn set_bool(&mut self, name: &str, value: bool) {
+        self.set_parameter(name, if value { 1.0 } else { 0.0 });
+    }
+    //Parameters that were never set are 0
+    pub fn parameter(&self, name: &str) -> f32 {
+        self.parameters.get(name).copied().unwrap_or(0.0)
+    }
+    pub fn set_layer_weight(&mut self, layer: &str, weight: f32) {
+        if let Some(index) = self.layer_index(layer) {
+            self.layers[index].weight = weight;
+        }
+    }
+    //Name of the state a layer is in, or blending into
+    pub fn state(&self, layer: &str) -> Option<&str> {
+        let index = self.layer_index(layer)?;
+        Some(&self.graph.descriptor.layers[index].states[self.layers[index].current.state].name)
+    }
+    //Moves a layer to a state regardless of its transitions
+    pub fn transition_to(&mut self, layer: &str, state: &str, duration: f32) {
+        let index = match self.layer_index(layer) {
+            Some(index) => index,
+            None => return,
+        };
+        if let Some(state) = self.graph.descriptor.layers[index].states.iter().position(|other| other.name == state) {
+            self.start_transition(index, state, duration);
+        }
+    }
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+    pub fn resume(&mut self) {
+        self.paused = false;
+    }
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    fn layer_index(&self, name: &str) -> Option<usize> {
+        self.graph.descriptor.layers.iter().position(|layer| layer.name == name)
+    }
+
+    fn start_transition(&mut self, layer: usize, state: usize, duration: f32) {
+        let layer = &mut self.layers[layer];
+        let from = std::mem::replace(&mut layer.current, Playback::new(state));
+        layer.fade = if duration > 0.0 { Some((from, duration, 0.0)) } else { None };
+    }
+
+    //Takes the transitions whose conditions hold, advances every layer and returns the blended pose
+    pub fn update(&mut self, delta_seconds: f32, skeleton: &Skeleton) -> (Vec<Transform>, RootMotion) {
+        if self.masks.len() != self.layers.len() || self.masks.iter().any(|mask| mask.len() != skeleton.joints.len()) {
+            self.masks = self.graph.descriptor.layers.iter().map(|layer| mask_weights(&layer.mask, skeleton)).collect();
+        }
+        if !self.paused {
+            for index in 0..self.layers.len() {
+                if self.layers[index].fade.is_none() {
+                    if let Some((to, duration)) = self.pending_transition(index) {
+                        self.start_transition(index, to, duration);
+                    }
+                }
+                self.advance_layer(index, delta_seconds * self.speed);
+            }
+        }
+
+        let mut pose = skeleton.rest_pose();
+        for (index, layer) in self.graph.descriptor.layers.iter().enumerate() {
+            let weight = self.layers[index].weight;
+            if weight <= 0.0 {
+                continue;
+            }
+            let target = self.sample_layer(index, skeleton, false);
+            let mask = &self.masks[index];
+            match layer.blend {
+                LayerBlend::Override => {
+                    for (joint, transform) in pose.iter_mut().enumerate() {
+                        *transform = transform.blend(&target[joint], weight * mask[joint]);
+                    }
+                }
+                LayerBlend::Additive => {
+                    let reference = self.sample_layer(index, skeleton, true);
+                    for (joint, transform) in pose.iter_mut().enumerate() {
+                        *transform = add_difference(transform, &reference[joint], &target[joint], weight * mask[joint]);
+                    }
+                }
+            }
+        }
+
+        let root_motion = self.extract_root_motion(skeleton, &mut pose);
+        (pose, root_motion)
+    }
+
+    fn pending_transition(&self, layer: usize) -> Option<(usize, f32)> {
+        let current = &self.layers[layer].current;
+        self.graph.layers[layer].transitions.iter().find(|transition| {
+            transition.from.is_none_or(|from| from == current.state)
+                && transition.to != current.state
+                && transition.exit_time.is_none_or(|exit_time| current.reached(exit_time))
+                && transition.conditions.iter().all(|condition| condition.holds(self.parameter(&condition.parameter)))
+        }).map(|transition| (transition.to, transition.duration))
+    }
+
+    //States of a blend space last as long as the weighted average of their clips
+    fn duration(&self, layer: usize, state: usize) -> f32 {
+        let duration: f32 = self.graph.weights(layer, state, &self.parameters).iter().map(|(clip, weight)| clip.duration * weight).sum();
+        if duration > 0.0 { duration } else { 1.0 }
+    }
+
+    fn advance_layer(&mut self, index: usize, delta: f32) {
+        let graph = self.graph.clone();
+        let states = &graph.layers[index].states;
+        let current = self.layers[index].current.state;
+        let phase_delta = delta * states[current].speed / self.duration(index, current);
+        self.layers[index].current.advance(phase_delta, states[current].looping);
+        let fading = self.layers[index].fade.as_ref().map(|(from, _, _)| from.state);
+        if let Some(from) = fading {
+            let phase_delta = delta * states[from].speed / self.duration(index, from);
+            let layer = &mut self.layers[index];
+            if let Some((playback, duration, elapsed)) = &mut layer.fade {
+                playback.advance(phase_delta, states[from].looping);
+                *elapsed += delta.abs();
+                if *elapsed >= *duration {
+                    layer.fade = None;
+                }
+            }
+        }
+    }
+
+    fn sample_state(&self, layer: usize, playback: &Playback, skeleton: &Skeleton, at_start: bool) -> Vec<Transform> {
+        let phase = if at_start { 0.0 } else { playback.phase };
+        let mut pose = skeleton.rest_pose();
+        let mut total = 0.0;
+        for (clip, weight) in self.graph.weights(layer, playback.state, &self.parameters) {
+            if weight <= 0.0 {
+                continue;
+            }
+            let mut sample = skeleton.rest_pose();
+            clip.sample(skeleton, phase * clip.duration, &mut sample);
+            //Running average, so the first clip is taken as is
+            total += weight;
+            for (transform, sample) in pose.iter_mut().zip(&sample) {
+                *transform = transform.blend(sample, weight / total);
+            }
+        }
+        pose
+    }
+
+    fn sample_layer(&self, index: usize, skeleton: &Skeleton, at_start: bool) -> Vec<Transform> {
+        let layer = &self.layers[index];
+        let mut pose = self.sample_state(index, &layer.current, skeleton, at_start);
+        if let Some((from, duration, elapsed)) = &layer.fade {
+            let from = self.sample_state(index, from, skeleton, at_start);
+            let weight = (elapsed / duration).clamp(0.0, 1.0);
+            for (transform, from) in pose.iter_mut().zip(&from) {
+                *transform = from.blend(transform, weight);
+            }
+        }
+        pose
+    }
+
+    //Takes the root joint's horizontal movement and turning out of the pose, the root keeps its rest position on
+    //X and Z and its rest heading
+    fn extract_root_motion(&self, skeleton: &Skeleton, pose: &mut [Transform]) -> RootMotion {
+        let joint = match skeleton.joint(&self.graph.descriptor.root_motion) {
+            Some(joint) if !self.layers.is_empty() => joint,
+            _ => return RootMotion::default(),
+        };
+        let layer = &self.layers[0];
+        let mut motion = self.playback_motion(skeleton, joint, &layer.current);
+        if let Some((from, duration, elapsed)) = &layer.fade {
+            let from = self.playback_motion(skeleton, joint, from);
+            let weight = (elapsed / duration).clamp(0.0, 1.0);
+            motion = RootMotion { translation: from.translation.lerp(motion.translation, weight), yaw: from.yaw + (motion.yaw - from.yaw) * weight };
+        }
+        let weight = layer.weight;
+        motion.translation = skeleton.root.transform_vector3(motion.translation) * weight;
+        motion.yaw *= weight;
+
+        let rest = skeleton.joints[joint].rest;
+        let root = &mut pose[joint];
+        root.translation.x = rest.translation.x;
+        root.translation.z = rest.translation.z;
+        root.rotation = (Quat::from_rotation_y(yaw(rest.rotation) - yaw(root.rotation)) * root.rotation).normalize();
+        motion
+    }
+
+    fn playback_motion(&self, skeleton: &Skeleton, joint: usize, playback: &Playback) -> RootMotion {
+        let mut motion = RootMotion::default();
+        for (clip, weight) in self.graph.weights(0, playback.state, &self.parameters) {
+            let at = |phase: f32| clip.sample_joint(skeleton, joint, phase * clip.duration);
+            let (start, end) = (at(playback.previous_phase), at(playback.phase));
+            //A wrap covers the rest of one loop and the beginning of the next
+            let (translation, turn) = if playback.wrapped != 0.0 {
+                let (leave, enter) = if playback.wrapped > 0.0 { (at(1.0), at(0.0)) } else { (at(0.0), at(1.0)) };
+                (leave.translation - start.translation + end.translation - enter.translation,
+                    angle_between(yaw(start.rotation), yaw(leave.rotation)) + angle_between(yaw(enter.rotation), yaw(end.rotation)))
+            } else {
+                (end.translation - start.translation, angle_between(yaw(start.rotation), yaw(end.rotation)))
+            };
+            //Relative to where the joint faced, the entity turns along with it
+            let facing = Quat::from_rotation_y(-yaw(start.rotation));
+            motion.translation += facing * Vec3::new(translation.x, 0.0, translation.z) * weight;
+            motion.yaw += turn * weight;
+        }
+        motion
+    }
+}
+
+fn yaw(rotation: Quat) -> f32 {
+    let forward = rotation * Vec3::Z;
+    forward.x.atan2(forward.z)
+}
+
+//Shortest signed turn from one yaw to another
+fn angle_between(from: f32, to: f32) -> f32 {
+    (to - from + PI).rem_euclid(TAU) - PI
+}
+
+//Base plus weight times the change from reference to target
+fn add_difference(base: &Transform, reference: &Transform, target: &Transform, weight: f32) -> Transform {
+    Transform {
+        translation: base.translation + (target.translation - reference.translation) * weight,
+        rotation: (base.rotation * Quat::IDENTITY.slerp(reference.rotation.inverse() * target.rotation, weight)).normalize(),
+        scale: base.scale * Vec3::ONE.lerp(target.scale / reference.scale, weight),
+    }
+}
+
+//1 for the named joints and their descendants, 0 for the rest. Names the skeleton doesn't have are ignored.
+fn mask_weights(mask: &[String], skeleton: &Skeleton) -> Vec<f32> {
+    if mask.is_empty() {
+        return vec![1.0; skeleton.joints.len()];
+    }
+    let roots: Vec<usize> = mask.iter().filter_map(|name| skeleton.joint(name)).collect();
+    (0..skeleton.joints.len()).map(|joint| {
+        let mut current = Some(joint);
+        let mut steps = 0;
+        while let Some(index) = current {
+            if roots.contains(&index) {
+                return 1.0;
+            }
+            current = skeleton.joints[index].parent;
+            steps += 1;
+            if steps > skeleton.joints.len() {
+                break;
+            }
+        }
+        0.0
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::animation::Joint;
+
+    const GRAPH: &str = r#"
+        [parameters]
+        speed = 0.25
+
+        [[layers]]
+        name = "base"
+
+        [[layers.states]]
+        name = "locomotion"
+        motion = { Blend1D = { parameter = "speed", clips = [["run", 1.0], ["idle", 0.0], ["walk", 0.5]] } }
+
+        [[layers.states]]
+        name = "strafe"
+        motion = { Blend2D = { parameters = ["x", "y"], clips = [["idle", [0.0, 0.0]], ["walk", [1.0, 0.0]], ["run", [0.0, 1.0]]] } }
+
+        [[layers.states]]
+        name = "jump"
+        motion = { Clip = "run" }
+        looping = false
+
+        [[layers.transitions]]
+        from = "locomotion"
+        to = "jump"
+        duration = 0.5
+        conditions = [{ parameter = "jumping", test = "True" }]
+
+        [[layers.transitions]]
+        from = "jump"
+        to = "locomotion"
+        duration = 0.25
+        exit_time = 0.5
+    "#;
+
+    //Clips without channels, only their lengths matter here
+    fn graph() -> Arc<AnimationGraph> {
+        let clips = [("idle", 2.0), ("walk", 1.0), ("run", 0.5)].map(|(name, duration)| {
+            Arc::new(AnimationClip { name: name.to_string(), duration, channels: Vec::new(), weights: Vec::new() })
+        });
+        Arc::new(AnimationGraph::new(toml::from_str(GRAPH).unwrap(), &clips).unwrap())
+    }
+
+    fn skeleton() -> Skeleton {
+        let root = Joint { name: "root".to_string(), node: 0, parent: None, rest: Transform::IDENTITY };
+        Skeleton::new(vec![root], vec![Mat4::IDENTITY], Mat4::IDENTITY)
+    }
+
+    fn weights(graph: &AnimationGraph, state: usize, parameters: &[(&str, f32)]) -> Vec<(String, f32)> {
+        let parameters = parameters.iter().map(|(name, value)| (name.to_string(), *value)).collect();
+        graph.weights(0, state, &parameters).into_iter().map(|(clip, weight)| (clip.name.clone(), weight)).collect()
+    }
+
+    #[test]
+    fn blends_the_two_nearest_clips_in_1d() {
+        let graph = graph();
+        let named = |pairs: &[(&str, f32)]| pairs.iter().map(|(name, weight)| (name.to_string(), *weight)).collect::<Vec<_>>();
+        assert_eq!(weights(&graph, 0, &[("speed", 0.25)]), named(&[("idle", 0.5), ("walk", 0.5)]));
+        assert_eq!(weights(&graph, 0, &[("speed", 0.875)]), named(&[("walk", 0.25), ("run", 0.75)]));
+        //Values outside the clips clamp to the ends
+        assert_eq!(weights(&graph, 0, &[("speed", -1.0)]), named(&[("idle", 1.0)]));
+        assert_eq!(weights(&graph, 0, &[("speed", 2.0)]), named(&[("run", 1.0)]));
+    }
+
+    #[test]
+    fn normalizes_2d_blend_weights() {
+        let graph = graph();
+        let at_walk = weights(&graph, 1, &[("x", 1.0), ("y", 0.0)]);
+        assert_eq!(at_walk.iter().map(|(_, weight)| *weight).collect::<Vec<_>>(), [0.0, 1.0, 0.0]);
+        for value in [(0.25, 0.25), (0.5, 0.1), (0.1, 0.8), (2.0, 2.0)] {
+            let blended = weights(&graph, 1, &[("x", value.0), ("y", value.1)]);
+            let total: f32 = blended.iter().map(|(_, weight)| weight).sum();
+            assert!((total - 1.0).abs() < 1e-5);
+            assert!(blended.iter().all(|(_, weight)| *weight >= 0.0));
+        }
+    }
+
+    #[test]
+    fn blend_spaces_last_their_weighted_length() {
+        let mut player = AnimationGraphPlayer::new(graph());
+        //Halfway between the 2 second idle and the 1 second walk
+        assert_eq!(player.duration(0, 0), 1.5);
+        player.update(0.75, &skeleton());
+        assert_eq!(player.layers[0].current.phase, 0.5);
+    }
+
+    #[test]
+    fn fades_between_states_for_the_transition_duration() {
+        let mut player = AnimationGraphPlayer::new(graph());
+        let skeleton = skeleton();
+        player.set_bool("jumping", true);
+        player.update(0.125, &skeleton);
+        assert_eq!(player.state("base"), Some("jump"));
+        assert_eq!(player.layers[0].fade.as_ref().map(|(from, duration, elapsed)| (from.state, *duration, *elapsed)), Some((0, 0.5, 0.125)));
+        player.update(0.25, &skeleton);
+        assert!(player.layers[0].fade.is_some());
+        player.update(0.125, &skeleton);
+        assert!(player.layers[0].fade.is_none());
+        //The 0.5 second jump has finished and was held at its end
+        assert_eq!(player.layers[0].current.phase, 1.0);
+    }
+
+    #[test]
+    fn waits_for_the_exit_time() {
+        let mut player = AnimationGraphPlayer::new(graph());
+        let skeleton = skeleton();
+        player.transition_to("base", "jump", 0.0);
+        player.update(0.125, &skeleton);
+        assert_eq!(player.state("base"), Some("jump"));
+        player.update(0.125, &skeleton);
+        //Half of the jump has played, the way back is taken on the next update
+        assert_eq!(player.state("base"), Some("jump"));
+        player.update(0.125, &skeleton);
+        assert_eq!(player.state("base"), Some("locomotion"));
+        assert_eq!(player.layers[0].fade.as_ref().map(|(from, duration, _)| (from.state, *duration)), Some((2, 0.25)));
+    }
+}
@@ -0,0 +1,209 @@
+use std::cell::RefCell;
+
+use glam::*;
+
+use crate::{material::{BlendMode, MagnificationFilter, MinificationFilter, WrapMode}, opengl_backend::OpenGlBackend, texture_formats::PixelFormat};
+
+//Names a resource created by a backend, only meaningful to the backend that created it
+pub type Handle = u32;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Resource {
+    VertexArray(Handle),
+    Buffer(Handle),
+    Texture(Handle),
+    Shader(Handle),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BufferKind {
+    Vertex,
+    Index,
+    Storage,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BufferUsage {
+    //Written once
+    Static,
+    //Rewritten now and then
+    Dynamic,
+    //Rewritten every draw
+    Stream,
+}
+
+//Float attribute read from a buffer, a divisor of 1 advances it once per instance instead of per vertex
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct VertexAttribute {
+    pub location: u32,
+    pub components: i32,
+    //In bytes, 0 for tightly packed
+    pub stride: i32,
+    pub offset: usize,
+    pub divisor: u32,
+}
+
+impl VertexAttribute {
+    pub fn new(location: u32, components: i32) -> VertexAttribute {
+        VertexAttribute { location, components, stride: 0, offset: 0, divisor: 0 }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TextureKind {
+    Texture2D,
+    Texture2DArray,
+    Cubemap,
+    Texture3D,
+}
+
+//Immutable storage, `depth` is the slice count of 3D textures and the layer count of arrays.
+//Formats are the sized internal formats the engine already describes textures with (see texture_formats.rs).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TextureDescriptor {
+    pub kind: TextureKind,
+    pub internal_format: u32,
+    pub width: i32,
+    pub height: i32,
+    pub depth: i32,
+    pub levels: i32,
+}
+
+//One mip level of a texture, of a single cubemap face or of every slice or layer at once
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TextureRegion {
+    pub level: i32,
+    pub face: Option<u32>,
+    pub width: i32,
+    pub height: i32,
+    pub depth: i32,
+    pub format: PixelFormat,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SamplerDescriptor {
+    pub mag_filter: MagnificationFilter,
+    pub min_filter: MinificationFilter,
+    pub wrap: WrapMode,
+    pub anisotropy: f32,
+}
+
+impl SamplerDescriptor {
+    //Linear filtering without mipmaps, clamped, what render targets and lookup tables use
+    pub fn clamped(min_filter: MinificationFilter) -> SamplerDescriptor {
+        SamplerDescriptor { mag_filter: MagnificationFilter::Linear, min_filter, wrap: WrapMode::ClampToEdge, anisotropy: 1.0 }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ShaderStage {
+    Vertex,
+    Fragment,
+    Compute,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Uniform {
+    Bool(bool),
+    Float(f32),
+    Vec2(Vec2),
+    Vec3(Vec3),
+    Vec4(Vec4),
+    Int(i32),
+    IVec2(IVec2),
+    IVec3(IVec3),
+    IVec4(IVec4),
+    UInt(u32),
+    UVec2(UVec2),
+    UVec3(UVec3),
+    UVec4(UVec4),
+    Double(f64),
+    DVec2(DVec2),
+    DVec3(DVec3),
+    DVec4(DVec4),
+    //Matrices come with the number of array elements to set
+    Mat2(Mat2, i32),
+    Mat3(Mat3, i32),
+    Mat4(Mat4, i32),
+    DMat2(DMat2, i32),
+    DMat3(DMat3, i32),
+    DMat4(DMat4, i32),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Primitive {
+    Triangles,
+    TriangleFan,
+    Lines,
+}
+
+//Indexed draws read 32 bit indices from the index buffer of the vertex array
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DrawCommand {
+    pub vertex_array: Handle,
+    pub primitive: Primitive,
+    pub first: i32,
+    pub count: i32,
+    pub instances: i32,
+    pub indexed: bool,
+}
+
+//Everything the engine asks of a graphics API. Calls happen in the order the engine makes them,
+//the shader and blend mode set last apply to the draws submitted after.
+pub trait RenderBackend {
+    fn name(&self) -> &'static str;
+
+    fn create_vertex_array(&mut self) -> Handle;
+    fn create_buffer(&mut self, kind: BufferKind) -> Handle;
+    fn upload_buffer(&mut self, buffer: Handle, usage: BufferUsage, data: &[u8]);
+    fn set_vertex_layout(&mut self, vertex_array: Handle, buffer: Handle, attributes: &[VertexAttribute]);
+    fn set_index_buffer(&mut self, vertex_array: Handle, buffer: Handle);
+
+    fn create_texture(&mut self, descriptor: &TextureDescriptor) -> Handle;
+    fn upload_texture(&mut self, texture: Handle, region: &TextureRegion, pixels: &[u8]);
+    fn set_sampler(&mut self, texture: Handle, sampler: &SamplerDescriptor);
+    fn generate_mipmaps(&mut self, texture: Handle);
+    //Texel buffer read with texelFetch through a samplerBuffer, the backend owns the buffer behind it
+    fn create_buffer_texture(&mut self, internal_format: u32) -> Handle;
+    //Replaces the buffer's contents, the texture grows and shrinks with the data
+    fn update_buffer_texture(&mut self, texture: Handle, usage: BufferUsage, data: &[u8]);
+
+    //Compile errors are reported by the backend, a broken shader still gets a handle
+    fn create_shader(&mut self, name: &str, stages: &[(ShaderStage, &str)]) -> Handle;
+    fn set_uniform(&mut self, shader: Handle, name: &str, value: Uniform);
+    fn set_blend_mode(&mut self, mode: BlendMode);
+
+    fn bind(&mut self, resource: Resource);
+    fn unbind(&mut self, resource: Resource);
+    fn submit(&mut self, draws: &[DrawCommand]);
+    fn dispatch(&mut self, shader: Handle, groups: UVec3);
+
+    fn label(&mut self, resource: Resource, name: &str);
+    fn destroy(&mut self, resource: Resource);
+}
+
+thread_local! {
+    static BACKEND: RefCell<Box<dyn RenderBackend>> = RefCell::new(Box::new(OpenGlBackend::default()));
+}
+
+//Replaces the backend of this thread, returning the previous one. Resources created by the previous backend
+//must not be used afterwards. Only tests swap in another backend so far.
+#[cfg(test)]
+pub fn install(backend: Box<dyn RenderBackend>) -> Box<dyn RenderBackend> {
+    BACKEND.with(|current| current.replace(backend))
+}
+
+//Backends must not call back into `with`
+pub fn with<R>(f: impl FnOnce(&mut dyn RenderBackend) -> R) -> R {
+    BACKEND.with(|backend| f(backend.borrow_mut().as_mut()))
+}
+
+//For Drop, which can run after the thread's backend is gone
+pub fn release(resource: Resource) {
+    let _ = BACKEND.try_with(|backend| backend.borrow_mut().destroy(resource));
+}
+
+//Reinterprets plain vertex, index and instance data as bytes for upload_buffer
+pub fn as_bytes<T: Copy>(data: &[T]) -> &[u8] {
+    unsafe { std::slice::from_raw_parts(data.as_ptr() as *const u8, std::mem::size_of_val(data)) }
+}
@@ -0,0 +1,554 @@
+use std::{error::Error, fs, sync::{atomic::{AtomicUsize, Ordering}, Mutex}, thread};
+
+use bevy_ecs::prelude::*;
+use glam::*;
+
+use crate::{components::*, lightmap::*, mesh::*, texture::HdrImage};
+
+const RAY_EPSILON: f32 = 0.001;
+const BVH_LEAF_SIZE: usize = 4;
+
+//Offline lightmap baking, everything here runs on the CPU so it works without a window or GPU
+
+struct Triangle {
+    vertices: [Vec3; 3],
+    normal: Vec3,
+    albedo: Vec3,
+}
+
+struct BvhNode {
+    min: Vec3,
+    max: Vec3,
+    //Children for inner nodes, the first triangle for leaves
+    first: usize,
+    count: usize,
+}
+
+pub struct Bvh {
+    nodes: Vec<BvhNode>,
+    triangles: Vec<Triangle>,
+}
+
+pub struct Hit {
+    pub distance: f32,
+    pub normal: Vec3,
+    pub albedo: Vec3,
+}
+
+impl Bvh {
+    fn new(mut triangles: Vec<Triangle>) -> Bvh {
+        let mut nodes = Vec::with_capacity(triangles.len() * 2);
+        nodes.push(BvhNode { min: Vec3::ZERO, max: Vec3::ZERO, first: 0, count: triangles.len() });
+        if !triangles.is_empty() {
+            Bvh::subdivide(&mut nodes, &mut triangles, 0);
+        }
+        Bvh { nodes, triangles }
+    }
+
+    fn subdivide(nodes: &mut Vec<BvhNode>, triangles: &mut [Triangle], index: usize) {
+        let (first, count) = (nodes[index].first, nodes[index].count);
+        let slice = &mut triangles[first..first + count];
+        let (mut min, mut max) = (Vec3::splat(f32::MAX), Vec3::splat(f32::MIN));
+        for triangle in slice.iter() {
+            for vertex in triangle.vertices {
+                min = min.min(vertex);
+                max = max.max(vertex);
+            }
+        }
+        nodes[index].min = min;
+        nodes[index].max = max;
+        if count <= BVH_LEAF_SIZE {
+            return;
+        }
+
+        //Median split along the longest axis of the node
+        let extent = max - min;
+        let axis = if extent.x >= extent.y && extent.x >= extent.z { 0 } else if extent.y >= extent.z { 1 } else { 2 };
+        let centroid = |t: &Triangle| (t.vertices[0] + t.vertices[1] + t.vertices[2])[axis];
+        let half = count / 2;
+        slice.select_nth_unstable_by(half, |a, b| centroid(a).total_cmp(&centroid(b)));
+
+        let left = nodes.len();
+        nodes.push(BvhNode { min: Vec3::ZERO, max: Vec3::ZERO, first, count: half });
+        nodes.push(BvhNode { min: Vec3::ZERO, max: Vec3::ZERO, first: first + half, count: count - half });
+        nodes[index].first = left;
+        nodes[index].count = 0;
+        Bvh::subdivide(nodes, triangles, left);
+        Bvh::subdivide(nodes, triangles, left + 1);
+    }
+
+    pub fn intersect(&self, origin: Vec3, direction: Vec3, max_distance: f32, any_hit: bool) -> Option<Hit> {
+        if self.triangles.is_empty() {
+            return None;
+        }
+        let inverse = direction.recip();
+        let mut closest: Option<(f32, usize)> = None;
+        let mut limit = max_distance;
+        let mut stack = vec![0usize];
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            if !ray_box(origin, inverse, node.min, node.max, limit) {
+                continue;
+            }
+            if node.count == 0 {
+                stack.push(node.first);
+                stack.push(node.first + 1);
+                continue;
+            }
+            for triangle in node.first..node.first + node.count {
+                if let Some(distance) = ray_triangle(origin, direction, &self.triangles[triangle].vertices) {
+                    if distance < limit {
+                        limit = distance;
+                        closest = Some((distance, triangle));
+                        if any_hit {
+                            stack.clear();
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+        closest.map(|(distance, triangle)| Hit {
+            distance,
+            normal: self.triangles[triangle].normal,
+            albedo: self.triangles[triangle].albedo,
+        })
+    }
+
+    pub fn occluded(&self, origin: Vec3, direction: Vec3, max_distance: f32) -> bool {
+        self.intersect(origin, direction, max_distance, true).is_some()
+    }
+}
+
+fn ray_box(origin: Vec3, inverse: Vec3, min: Vec3, max: Vec3, max_distance: f32) -> bool {
+    let t1 = (min - origin) * inverse;
+    let t2 = (max - origin) * inverse;
+    let near = t1.min(t2).max_element();
+    let far = t1.max(t2).min_element();
+    far >= near.max(0.0) && near < max_distance
+}
+
+//Möller-Trumbore, double sided
+fn ray_triangle(origin: Vec3, direction: Vec3, vertices: &[Vec3; 3]) -> Option<f32> {
+    let edge1 = vertices[1] - vertices[0];
+    let edge2 = vertices[2] - vertices[0];
+    let p = direction.cross(edge2);
+    let determinant = edge1.dot(p);
+    if determinant.abs() < 1e-9 {
+        return None;
+    }
+    let inverse = 1.0 / determinant;
+    let s = origin - vertices[0];
+    let u = s.dot(p) * inverse;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+    let q = s.cross(edge1);
+    let v = direction.dot(q) * inverse;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+    let distance = edge2.dot(q) * inverse;
+    if distance > RAY_EPSILON { Some(distance) } else { None }
+}
+
+enum BakeLight {
+    Directional { direction: Vec3, color: Vec3 },
+    Point { position: Vec3, color: Vec3, range: f32 },
+    Spot { position: Vec3, direction: Vec3, color: Vec3, range: f32, inner_cos: f32, outer_cos: f32 },
+}
+
+//Matches the falloff in the lit shaders so baked and dynamic lights look the same
+fn attenuation(distance: f32, range: f32) -> f32 {
+    let falloff = (1.0 - (distance / range).powi(4)).clamp(0.0, 1.0);
+    falloff * falloff / (distance * distance + 1.0)
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+struct BakeScene {
+    bvh: Bvh,
+    lights: Vec<BakeLight>,
+    sky: Vec3,
+    samples: u32,
+    bounces: u32,
+}
+
+impl BakeScene {
+    fn direct(&self, position: Vec3, normal: Vec3) -> Vec3 {
+        let origin = position + normal * RAY_EPSILON;
+        let mut result = Vec3::ZERO;
+        for light in &self.lights {
+            match *light {
+                BakeLight::Directional { direction, color } => {
+                    let l = -direction;
+                    let n_dot_l = normal.dot(l);
+                    if n_dot_l > 0.0 && !self.bvh.occluded(origin, l, f32::MAX) {
+                        result += color * n_dot_l;
+                    }
+                },
+                BakeLight::Point { position: light_position, color, range } => {
+                    let to_light = light_position - position;
+                    let distance = to_light.length();
+                    let l = to_light / distance;
+                    let n_dot_l = normal.dot(l);
+                    if distance < range && n_dot_l > 0.0 && !self.bvh.occluded(origin, l, distance) {
+                        result += color * n_dot_l * attenuation(distance, range);
+                    }
+                },
+                BakeLight::Spot { position: light_position, direction, color, range, inner_cos, outer_cos } => {
+                    let to_light = light_position - position;
+                    let distance = to_light.length();
+                    let l = to_light / distance;
+                    let n_dot_l = normal.dot(l);
+                    let cone = smoothstep(outer_cos, inner_cos, (-l).dot(direction));
+                    if distance < range && n_dot_l > 0.0 && cone > 0.0 && !self.bvh.occluded(origin, l, distance) {
+                        result += color * n_dot_l * attenuation(distance, range) * cone;
+                    }
+                },
+            }
+        }
+        result
+    }
+
+    //Irradiance at a texel, normalised the same way as the shader's ambient term
+    fn irradiance(&self, position: Vec3, normal: Vec3, rng: &mut Rng) -> Vec3 {
+        let direct = self.direct(position, normal);
+        if self.samples == 0 {
+            return direct + self.sky;
+        }
+
+        let mut indirect = Vec3::ZERO;
+        for _ in 0..self.samples {
+            let mut origin = position + normal * RAY_EPSILON;
+            let mut direction = rng.cosine_hemisphere(normal);
+            let mut throughput = Vec3::ONE;
+            for bounce in 0..=self.bounces {
+                match self.bvh.intersect(origin, direction, f32::MAX, false) {
+                    Some(hit) => {
+                        if bounce == self.bounces {
+                            break;
+                        }
+                        let hit_position = origin + direction * hit.distance;
+                        let hit_normal = if hit.normal.dot(direction) > 0.0 { -hit.normal } else { hit.normal };
+                        throughput *= hit.albedo;
+                        indirect += throughput * self.direct(hit_position, hit_normal);
+                        origin = hit_position + hit_normal * RAY_EPSILON;
+                        direction = rng.cosine_hemisphere(hit_normal);
+                    },
+                    None => {
+                        indirect += throughput * self.sky;
+                        break;
+                    },
+                }
+            }
+        }
+        direct + indirect / self.samples as f32
+    }
+}
+
+pub struct Rng(pub u64);
+
+impl Rng {
+    pub fn next(&mut self) -> f32 {
+        //xorshift64*
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        (self.0.wrapping_mul(0x2545F4914F6CDD1D) >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    fn cosine_hemisphere(&mut self, normal: Vec3) -> Vec3 {
+        let (u1, u2) = (self.next(), self.next());
+        let radius = u1.sqrt();
+        let theta = 2.0 * std::f32::consts::PI * u2;
+        let (tangent, bitangent) = normal.any_orthonormal_pair();
+        (tangent * radius * theta.cos() + bitangent * radius * theta.sin() + normal * (1.0 - u1).max(0.0).sqrt()).normalize()
+    }
+}
+
+//World space surface point behind every lightmap texel a triangle covers
+struct Texel {
+    position: Vec3,
+    normal: Vec3,
+}
+
+fn rasterize(data: &MeshData, layout: &LightmapLayout, model: &Mat4) -> Vec<Option<Texel>> {
+    let (width, height) = (layout.width as usize, layout.height as usize);
+    let mut texels: Vec<Option<Texel>> = (0..width * height).map(|_| None).collect();
+
+    let mut remapped = data.clone();
+    layout.apply(&mut remapped);
+    let positions = world_positions(&remapped, model);
+    let normal_matrix = Mat3::from_mat4(*model).inverse().transpose();
+    let normals: Option<Vec<Vec3>> = remapped.buffer(NORMAL_LOCATION)
+        .map(|(size, buffer)| buffer.chunks_exact(size as usize).map(|n| (normal_matrix * Vec3::new(n[0], n[1], n[2])).normalize_or_zero()).collect());
+    let scale = Vec2::new(width as f32, height as f32);
+    let uvs: Vec<Vec2> = layout.uvs.chunks_exact(2).map(|uv| Vec2::new(uv[0], uv[1]) * scale).collect();
+
+    for triangle in remapped.indices.chunks_exact(3) {
+        let [a, b, c] = [triangle[0] as usize, triangle[1] as usize, triangle[2] as usize];
+        let (ta, tb, tc) = (uvs[a], uvs[b], uvs[c]);
+        let area = (tb - ta).perp_dot(tc - ta);
+        if area.abs() < 1e-8 {
+            continue;
+        }
+        let face_normal = (positions[b] - positions[a]).cross(positions[c] - positions[a]).normalize_or_zero();
+        let min = ta.min(tb).min(tc).floor().max(Vec2::ZERO);
+        let max = ta.max(tb).max(tc).ceil().min(scale);
+        for y in min.y as usize..max.y as usize {
+            for x in min.x as usize..max.x as usize {
+                let p = Vec2::new(x as f32 + 0.5, y as f32 + 0.5);
+                let wa = (tb - p).perp_dot(tc - p) / area;
+                let wb = (tc - p).perp_dot(ta - p) / area;
+                let wc = 1.0 - wa - wb;
+                if wa < 0.0 || wb < 0.0 || wc < 0.0 {
+                    continue;
+                }
+                let normal = match &normals {
+                    Some(normals) => (normals[a] * wa + normals[b] * wb + normals[c] * wc).normalize_or(face_normal),
+                    None => face_normal,
+                };
+                texels[y * width + x] = Some(Texel {
+                    position: positions[a] * wa + positions[b] * wb + positions[c] * wc,
+                    normal,
+                });
+            }
+        }
+    }
+    texels
+}
+
+trait NormalizeOr {
+    fn normalize_or(self, fallback: Vec3) -> Vec3;
+}
+
+impl NormalizeOr for Vec3 {
+    fn normalize_or(self, fallback: Vec3) -> Vec3 {
+        let normalized = self.normalize_or_zero();
+        if normalized == Vec3::ZERO { fallback } else { normalized }
+    }
+}
+
+//Edge avoiding à-trous wavelet filter guided by normals and positions
+fn denoise(image: &mut HdrImage, texels: &[Option<Texel>], iterations: u32) {
+    const KERNEL: [f32; 3] = [3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+    let (width, height) = (image.width as i32, image.height as i32);
+    for iteration in 0..iterations {
+        let step = 1 << iteration;
+        let source = image.data.clone();
+        for y in 0..height {
+            for x in 0..width {
+                let center = match &texels[(y * width + x) as usize] {
+                    Some(texel) => texel,
+                    None => continue,
+                };
+                let index = ((y * width + x) * 3) as usize;
+                let center_color = Vec3::from_slice(&source[index..index + 3]);
+                let mut sum = Vec3::ZERO;
+                let mut weight_sum = 0.0;
+                for dy in -2i32..=2 {
+                    for dx in -2i32..=2 {
+                        let (sx, sy) = (x + dx * step, y + dy * step);
+                        if sx < 0 || sy < 0 || sx >= width || sy >= height {
+                            continue;
+                        }
+                        let sample = match &texels[(sy * width + sx) as usize] {
+                            Some(texel) => texel,
+                            None => continue,
+                        };
+                        let sample_index = ((sy * width + sx) * 3) as usize;
+                        let color = Vec3::from_slice(&source[sample_index..sample_index + 3]);
+                        let normal_weight = center.normal.dot(sample.normal).max(0.0).powi(32);
+                        let position_weight = (-(center.position - sample.position).length_squared() * 4.0).exp();
+                        let color_weight = (-(center_color - color).length_squared() / (0.5 * (iteration + 1) as f32)).exp();
+                        let weight = KERNEL[dx.unsigned_abs() as usize] * KERNEL[dy.unsigned_abs() as usize] * normal_weight * position_weight * color_weight;
+                        sum += color * weight;
+                        weight_sum += weight;
+                    }
+                }
+                if weight_sum > 0.0 {
+                    image.data[index..index + 3].copy_from_slice(&(sum / weight_sum).to_array());
+                }
+            }
+        }
+    }
+}
+
+//Bleeds covered texels into the gutter so bilinear filtering never reads black at chart edges
+fn dilate(image: &mut HdrImage, covered: &mut [bool], passes: u32) {
+    let (width, height) = (image.width as i32, image.height as i32);
+    for _ in 0..passes {
+        let source = image.data.clone();
+        let source_covered = covered.to_vec();
+        for y in 0..height {
+            for x in 0..width {
+                if source_covered[(y * width + x) as usize] {
+                    continue;
+                }
+                let mut sum = Vec3::ZERO;
+                let mut count = 0;
+                for (dx, dy) in [(-1, 0), (1, 0), (0, -1), (0, 1), (-1, -1), (1, -1), (-1, 1), (1, 1)] {
+                    let (sx, sy) = (x + dx, y + dy);
+                    if sx < 0 || sy < 0 || sx >= width || sy >= height || !source_covered[(sy * width + sx) as usize] {
+                        continue;
+                    }
+                    let index = ((sy * width + sx) * 3) as usize;
+                    sum += Vec3::from_slice(&source[index..index + 3]);
+                    count += 1;
+                }
+                if count > 0 {
+                    image.set(x as usize, y as usize, (sum / count as f32).to_array());
+                    covered[(y * width + x) as usize] = true;
+                }
+            }
+        }
+    }
+}
+
+fn bake_texels(scene: &BakeScene, texels: &[Option<Texel>], width: usize, height: usize) -> HdrImage {
+    let image = Mutex::new(HdrImage::new(width, height));
+    let next_row = AtomicUsize::new(0);
+    let threads = thread::available_parallelism().map_or(1, |n| n.get());
+
+    thread::scope(|scope| {
+        for _ in 0..threads {
+            scope.spawn(|| loop {
+                let y = next_row.fetch_add(1, Ordering::Relaxed);
+                if y >= height {
+                    break;
+                }
+                let mut row = vec![[0.0f32; 3]; width];
+                for x in 0..width {
+                    if let Some(texel) = &texels[y * width + x] {
+                        let mut rng = Rng(((y * width + x) as u64).wrapping_mul(0x9E3779B97F4A7C15) | 1);
+                        row[x] = scene.irradiance(texel.position, texel.normal, &mut rng).to_array();
+                    }
+                }
+                let mut image = image.lock().unwrap();
+                for (x, color) in row.into_iter().enumerate() {
+                    image.set(x, y, color);
+                }
+            });
+        }
+    });
+    image.into_inner().unwrap()
+}
+
+//Bakes every Static entity with a Name and MeshData, lit by every Static light, into
+//resources/levels/{level}/lightmaps. Needs no GL context. `progress` hears about each mesh before it is baked,
+//the number of lightmaps written is returned.
+pub fn bake_level(world: &mut World, level: &str, settings: &BakeSettings, mut progress: impl FnMut(&str, &LightmapLayout)) -> Result<usize, Box<dyn Error>> {
+    let albedo = Vec3::from_array(settings.albedo);
+    let mut meshes = Vec::new();
+    let mut query = world.query_filtered::<(&Name, &MeshData, Option<&Position>, Option<&Rotation>, Option<&Scale>), With<Static>>();
+    for (name, data, position, rotation, scale) in query.iter(world) {
+        meshes.push((name.d.clone(), data.clone(), model_matrix(position, rotation, scale)));
+    }
+
+    let mut triangles = Vec::new();
+    for (_, data, model) in &meshes {
+        let positions = world_positions(data, model);
+        for triangle in data.indices.chunks_exact(3) {
+            let vertices = [positions[triangle[0] as usize], positions[triangle[1] as usize], positions[triangle[2] as usize]];
+            let normal = (vertices[1] - vertices[0]).cross(vertices[2] - vertices[0]).normalize_or_zero();
+            triangles.push(Triangle { vertices, normal, albedo });
+        }
+    }
+
+    let mut lights = Vec::new();
+    for light in world.query_filtered::<&DirectionalLight, With<Static>>().iter(world) {
+        lights.push(BakeLight::Directional { direction: light.direction.normalize(), color: light.color * light.intensity });
+    }
+    for (position, light) in world.query_filtered::<(&Position, &PointLight), With<Static>>().iter(world) {
+        lights.push(BakeLight::Point { position: position.d, color: light.color * light.intensity, range: light.range });
+    }
+    for (position, light) in world.query_filtered::<(&Position, &SpotLight), With<Static>>().iter(world) {
+        lights.push(BakeLight::Spot {
+            position: position.d,
+            direction: light.direction.normalize(),
+            color: light.color * light.intensity,
+            range: light.range,
+            inner_cos: light.inner_angle.to_radians().cos(),
+            outer_cos: light.outer_angle.to_radians().cos(),
+        });
+    }
+
+    let scene = BakeScene {
+        bvh: Bvh::new(triangles),
+        lights,
+        sky: Vec3::from_array(settings.sky_color),
+        samples: settings.samples,
+        bounces: settings.bounces,
+    };
+
+    let directory = lightmap_directory(level);
+    fs::create_dir_all(&directory)?;
+    for (name, data, model) in &meshes {
+        let layout = match data.buffer(LIGHTMAP_UV_LOCATION) {
+            Some((_, uvs)) => LightmapLayout::from_existing(data, uvs, model, settings),
+            None => LightmapLayout::generate(data, model, settings),
+        };
+        progress(name, &layout);
+
+        let texels = rasterize(data, &layout, model);
+        let mut image = bake_texels(&scene, &texels, layout.width as usize, layout.height as usize);
+        if settings.denoise {
+            denoise(&mut image, &texels, 3);
+        }
+        let mut covered: Vec<bool> = texels.iter().map(|t| t.is_some()).collect();
+        dilate(&mut image, &mut covered, settings.padding.max(1));
+
+        layout.save(&format!("{}/{}.toml", directory, name))?;
+        image.save(&format!("{}/{}.hdr", directory, name))?;
+    }
+    Ok(meshes.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn triangle(vertices: [Vec3; 3]) -> Triangle {
+        let normal = (vertices[1] - vertices[0]).cross(vertices[2] - vertices[0]).normalize_or_zero();
+        Triangle { vertices, normal, albedo: Vec3::ONE }
+    }
+
+    #[test]
+    fn intersects_triangles_from_either_side() {
+        let vertices = [Vec3::new(-1.0, -1.0, 0.0), Vec3::new(1.0, -1.0, 0.0), Vec3::new(0.0, 1.0, 0.0)];
+        assert_eq!(ray_triangle(Vec3::new(0.0, 0.0, 2.0), Vec3::NEG_Z, &vertices), Some(2.0));
+        assert_eq!(ray_triangle(Vec3::new(0.0, 0.0, -3.0), Vec3::Z, &vertices), Some(3.0));
+        //Beside the triangle, pointing away, parallel to it and starting on it
+        assert_eq!(ray_triangle(Vec3::new(2.0, 0.0, 2.0), Vec3::NEG_Z, &vertices), None);
+        assert_eq!(ray_triangle(Vec3::new(0.0, 0.0, 2.0), Vec3::Z, &vertices), None);
+        assert_eq!(ray_triangle(Vec3::new(0.0, 0.0, 2.0), Vec3::X, &vertices), None);
+        assert_eq!(ray_triangle(Vec3::ZERO, Vec3::NEG_Z, &vertices), None);
+    }
+
+    #[test]
+    fn finds_the_same_hits_as_testing_every_triangle() {
+        let mut rng = Rng(7);
+        let mut point = |scale: f32| (Vec3::new(rng.next(), rng.next(), rng.next()) * 2.0 - 1.0) * scale;
+        let vertices: Vec<[Vec3; 3]> = (0..200).map(|_| {
+            let center = point(5.0);
+            [center + point(0.5), center + point(0.5), center + point(0.5)]
+        }).collect();
+        let rays: Vec<(Vec3, Vec3)> = (0..500).map(|_| (point(6.0), point(1.0).normalize_or(Vec3::X))).collect();
+        let bvh = Bvh::new(vertices.iter().map(|vertices| triangle(*vertices)).collect());
+
+        let mut hits = 0;
+        for (origin, direction) in rays {
+            let expected = vertices.iter().filter_map(|vertices| ray_triangle(origin, direction, vertices)).filter(|distance| *distance < 20.0).fold(None, |closest: Option<f32>, distance| Some(closest.map_or(distance, |closest| closest.min(distance))));
+            let hit = bvh.intersect(origin, direction, 20.0, false).map(|hit| hit.distance);
+            assert_eq!(hit, expected, "ray from {} towards {}", origin, direction);
+            assert_eq!(bvh.occluded(origin, direction, 20.0), expected.is_some());
+            hits += expected.is_some() as usize;
+        }
+        assert!(hits > 0);
+    }
+}
+
@@ -1,5 +1,5 @@
 use bevy_ecs::prelude::*;
-use glam::{Vec3, Mat4};
+use glam::{Vec3, Vec4, Mat4, Quat, EulerRot};
 
 #[derive(Default, Component)]
 pub struct Position { pub d: Vec3 }
@@ -20,6 +20,13 @@ pub struct Camera {
     pub first_mouse: bool,
     pub view: glam::Mat4,
     pub projection: glam::Mat4,
+    pub projection_mode: ProjectionMode,
+    //Part of the target drawn into, split screen and picture in picture cameras cover less than all of it
+    pub viewport: Viewport,
+    pub target: RenderTarget,
+    //Cameras render in ascending priority, later ones draw over earlier ones sharing a target
+    pub priority: i32,
+    pub clear: ClearSettings,
 }
 
 impl Camera {
@@ -30,4 +37,229 @@ impl Camera {
     pub fn set_projection(&mut self, fov: f32, ratio: f32, near_plane: f32, far_plane: f32) {
         self.projection = Mat4::perspective_rh_gl(fov, ratio, near_plane, far_plane);
     }
-}
\ No newline at end of file
+
+    //Box centered on the camera, sized by the scaling mode for a viewport of the given pixel size
+    pub fn set_orthographic(&mut self, scaling: OrthographicScaling, width: f32, height: f32, near_plane: f32, far_plane: f32) {
+        let half_height = match scaling {
+            OrthographicScaling::FixedHeight(units) => units * 0.5,
+            OrthographicScaling::PixelsPerUnit(pixels) => height * 0.5 / pixels,
+        };
+        let half_width = half_height * width / height;
+        self.projection = Mat4::orthographic_rh_gl(-half_width, half_width, -half_height, half_height, near_plane, far_plane);
+    }
+
+    pub fn is_orthographic(&self) -> bool {
+        matches!(self.projection_mode, ProjectionMode::Orthographic { .. })
+    }
+
+    //World units one pixel covers when the view is the given number of pixels tall, only constant for orthographic cameras
+    pub fn units_per_pixel(&self, viewport_height: f32) -> f32 {
+        2.0 / (self.projection.y_axis.y * viewport_height)
+    }
+}
+
+//Fov comes from the settings for perspective cameras
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum ProjectionMode {
+    #[default]
+    Perspective,
+    //Pixel perfect cameras snap sprites to whole pixels so pixel art doesn't shimmer while moving
+    Orthographic { scaling: OrthographicScaling, pixel_perfect: bool },
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum OrthographicScaling {
+    //World units visible vertically, resizing the window only shows more or less to the sides
+    FixedHeight(f32),
+    //Screen pixels per world unit, resizing the window shows more of the world
+    PixelsPerUnit(f32),
+}
+
+//Normalized rectangle of the render target, the origin is the bottom left corner
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Viewport {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl Viewport {
+    pub fn new(x: f32, y: f32, width: f32, height: f32) -> Viewport {
+        Viewport { x, y, width, height }
+    }
+
+    //x, y, width and height in pixels of a target of the given size
+    pub fn pixels(&self, width: u32, height: u32) -> [i32; 4] {
+        let (width, height) = (width as f32, height as f32);
+        let x = (self.x * width).round() as i32;
+        let y = (self.y * height).round() as i32;
+        let right = ((self.x + self.width) * width).round() as i32;
+        let top = ((self.y + self.height) * height).round() as i32;
+        [x, y, right - x, top - y]
+    }
+}
+
+impl Default for Viewport {
+    fn default() -> Self {
+        Self { x: 0.0, y: 0.0, width: 1.0, height: 1.0 }
+    }
+}
+
+//Cameras draw to the window unless they name a texture, which materials can then sample like any other
+#[derive(Clone, PartialEq, Debug, Default)]
+pub enum RenderTarget {
+    #[default]
+    Window,
+    Texture { name: String, width: u32, height: u32 },
+}
+
+//What is cleared inside the viewport before the camera draws, overlays such as weapon viewmodels keep the color
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct ClearSettings {
+    pub color: Option<Vec4>,
+    pub depth: bool,
+}
+
+impl ClearSettings {
+    pub const DEFAULT_COLOR: Vec4 = Vec4::new(0.2, 0.3, 0.3, 1.0);
+
+    //Keeps whatever earlier cameras drew, only depth starts over
+    pub fn depth_only() -> ClearSettings {
+        ClearSettings { color: None, depth: true }
+    }
+}
+
+impl Default for ClearSettings {
+    fn default() -> Self {
+        Self { color: Some(Self::DEFAULT_COLOR), depth: true }
+    }
+}
+
+//Bitmask of layers, cameras only draw meshes sharing at least one layer with them.
+//Entities without the component are on layer 0 only.
+#[derive(Component, Clone, Copy, PartialEq, Debug)]
+pub struct RenderLayers(pub u32);
+
+impl RenderLayers {
+    pub fn layer(layer: u32) -> RenderLayers {
+        RenderLayers(1 << layer)
+    }
+    pub fn with(self, layer: u32) -> RenderLayers {
+        RenderLayers(self.0 | 1 << layer)
+    }
+    pub fn intersects(&self, other: &RenderLayers) -> bool {
+        self.0 & other.0 != 0
+    }
+}
+
+impl Default for RenderLayers {
+    fn default() -> Self {
+        RenderLayers::layer(0)
+    }
+}
+
+//World entities that never move, static meshes receive baked lightmaps and static lights are baked into them
+#[derive(Default, Component)]
+pub struct Static;
+
+//Stable identifier for finding data saved alongside the level, such as lightmaps
+#[derive(Default, Component)]
+pub struct Name { pub d: String }
+
+//Sun-style light, lights every mesh from a single direction
+#[derive(Component)]
+pub struct DirectionalLight {
+    pub direction: Vec3,
+    pub color: Vec3,
+    pub intensity: f32,
+}
+
+impl Default for DirectionalLight {
+    fn default() -> Self {
+        Self { direction: Vec3::new(-0.3, -1.0, -0.2), color: Vec3::ONE, intensity: 1.0 }
+    }
+}
+
+//Lamp-style light placed at the entity's Position
+#[derive(Component)]
+pub struct PointLight {
+    pub color: Vec3,
+    pub intensity: f32,
+    pub range: f32,
+    pub casts_shadows: bool,
+}
+
+impl Default for PointLight {
+    fn default() -> Self {
+        Self { color: Vec3::ONE, intensity: 1.0, range: 10.0, casts_shadows: true }
+    }
+}
+
+//Cone light placed at the entity's Position, angles are in degrees
+#[derive(Component)]
+pub struct SpotLight {
+    pub direction: Vec3,
+    pub color: Vec3,
+    pub intensity: f32,
+    pub range: f32,
+    pub inner_angle: f32,
+    pub outer_angle: f32,
+    pub casts_shadows: bool,
+}
+
+impl Default for SpotLight {
+    fn default() -> Self {
+        Self {
+            direction: Vec3::NEG_Y,
+            color: Vec3::ONE,
+            intensity: 1.0,
+            range: 10.0,
+            inner_angle: 25.0,
+            outer_angle: 35.0,
+            casts_shadows: true,
+        }
+    }
+}
+
+//Meshes treat Rotation as euler angles in radians, missing components fall back to identity
+pub fn model_matrix(position: Option<&Position>, rotation: Option<&Rotation>, scale: Option<&Scale>) -> Mat4 {
+    let translation = position.map_or(Vec3::ZERO, |p| p.d);
+    let rotation = rotation.map_or(Quat::IDENTITY, |r| Quat::from_euler(EulerRot::XYZ, r.d.x, r.d.y, r.d.z));
+    let scale = scale.map_or(Vec3::ONE, |s| s.d);
+    Mat4::from_scale_rotation_translation(scale, rotation, translation)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn combines_render_layers() {
+        let map = RenderLayers::layer(0).with(2);
+        assert_eq!(map, RenderLayers(0b101));
+        assert!(map.intersects(&RenderLayers::default()));
+        assert!(map.intersects(&RenderLayers::layer(2)));
+        assert!(!map.intersects(&RenderLayers::layer(1)));
+    }
+
+    #[test]
+    fn rounds_viewports_to_pixels() {
+        assert_eq!(Viewport::default().pixels(1280, 720), [0, 0, 1280, 720]);
+        //Neighbouring viewports share their edge instead of leaving a gap
+        let (left, right) = (Viewport::new(0.0, 0.0, 1.0 / 3.0, 1.0), Viewport::new(1.0 / 3.0, 0.0, 2.0 / 3.0, 1.0));
+        let (left, right) = (left.pixels(100, 50), right.pixels(100, 50));
+        assert_eq!(left, [0, 0, 33, 50]);
+        assert_eq!(right, [33, 0, 67, 50]);
+        assert_eq!(Viewport::new(0.74, 0.04, 0.22, 0.22).pixels(800, 600), [592, 24, 176, 132]);
+    }
+
+    #[test]
+    fn sizes_orthographic_views() {
+        let mut camera = Camera::default();
+        camera.set_orthographic(OrthographicScaling::FixedHeight(16.0), 200.0, 100.0, 0.01, 100.0);
+        assert!((camera.units_per_pixel(100.0) - 0.16).abs() < 1e-5);
+        camera.set_orthographic(OrthographicScaling::PixelsPerUnit(2.0), 200.0, 100.0, 0.01, 100.0);
+        assert!((camera.units_per_pixel(100.0) - 0.5).abs() < 1e-5);
+    }
+}
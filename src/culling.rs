@@ -0,0 +1,198 @@
+use bevy_ecs::prelude::*;
+use glam::{Mat3, Mat4, Vec3, Vec4, Vec4Swizzles};
+
+//Local space bounding volume of a mesh, the sphere encloses the box
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Bounds {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Bounds {
+    //Positions are tightly packed with `size` components per vertex, only xyz is used
+    pub fn from_positions(data: &[f32], size: usize) -> Bounds {
+        if size < 3 || data.len() < size {
+            return Bounds::default();
+        }
+        let mut min = Vec3::splat(f32::MAX);
+        let mut max = Vec3::splat(f32::MIN);
+        for vertex in data.chunks_exact(size) {
+            let position = Vec3::new(vertex[0], vertex[1], vertex[2]);
+            min = min.min(position);
+            max = max.max(position);
+        }
+        Bounds { min, max }
+    }
+
+    pub fn center(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+    pub fn extents(&self) -> Vec3 {
+        (self.max - self.min) * 0.5
+    }
+    pub fn radius(&self) -> f32 {
+        self.extents().length()
+    }
+
+    //World space center and radius of the bounding sphere, non-uniform scale grows it by the largest axis
+    pub fn world_sphere(&self, model: &Mat4) -> (Vec3, f32) {
+        let scale = model.x_axis.xyz().length().max(model.y_axis.xyz().length()).max(model.z_axis.xyz().length());
+        (model.transform_point3(self.center()), self.radius() * scale)
+    }
+
+    //World space box enclosing the transformed box
+    pub fn transformed(&self, model: &Mat4) -> Bounds {
+        let center = model.transform_point3(self.center());
+        let axes = Mat3::from_mat4(*model);
+        let extents = Vec3::new(
+            axes.row(0).abs().dot(self.extents()),
+            axes.row(1).abs().dot(self.extents()),
+            axes.row(2).abs().dot(self.extents()),
+        );
+        Bounds { min: center - extents, max: center + extents }
+    }
+}
+
+//Planes point inwards, a point is inside when dot(plane.xyz, p) + plane.w >= 0 for all of them
+pub struct Frustum {
+    planes: [Vec4; 6],
+}
+
+impl Frustum {
+    //Gribb/Hartmann extraction, works for any projection * view matrix using GL clip space
+    pub fn from_matrix(matrix: &Mat4) -> Frustum {
+        let (r0, r1, r2, r3) = (matrix.row(0), matrix.row(1), matrix.row(2), matrix.row(3));
+        let planes = [r3 + r0, r3 - r0, r3 + r1, r3 - r1, r3 + r2, r3 - r2].map(|plane| plane / plane.xyz().length());
+        Frustum { planes }
+    }
+
+    pub fn intersects_sphere(&self, center: Vec3, radius: f32) -> bool {
+        self.planes.iter().all(|plane| plane.xyz().dot(center) + plane.w >= -radius)
+    }
+
+    pub fn intersects_bounds(&self, bounds: &Bounds) -> bool {
+        let (center, extents) = (bounds.center(), bounds.extents());
+        self.planes.iter().all(|plane| {
+            let normal = plane.xyz();
+            normal.dot(center) + plane.w >= -normal.abs().dot(extents)
+        })
+    }
+
+    //Sphere test first as a cheap reject, then the tighter box test
+    pub fn is_visible(&self, bounds: &Bounds, model: &Mat4) -> bool {
+        let (center, radius) = bounds.world_sphere(model);
+        if !self.intersects_sphere(center, radius) {
+            return false;
+        }
+        self.intersects_bounds(&bounds.transformed(model))
+    }
+}
+
+//Camera culling results for the last rendered frame, reset when the frame starts
+#[derive(Resource, Default, Debug)]
+pub struct CullingStats {
+    pub drawn: u32,
+    pub culled: u32,
+}
+
+impl CullingStats {
+    pub fn reset(&mut self) {
+        self.drawn = 0;
+        self.culled = 0;
+    }
+
+    pub fn record(&mut self, visible: bool) -> bool {
+        if visible {
+            self.drawn += 1;
+        } else {
+            self.culled += 1;
+        }
+        visible
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //Looking down -Z from the origin with a 90 degree field of view
+    fn frustum() -> Frustum {
+        let projection = Mat4::perspective_rh_gl(90f32.to_radians(), 1.0, 0.1, 100.0);
+        Frustum::from_matrix(&(projection * Mat4::look_at_rh(Vec3::ZERO, Vec3::NEG_Z, Vec3::Y)))
+    }
+
+    fn unit_box() -> Bounds {
+        Bounds { min: Vec3::splat(-0.5), max: Vec3::splat(0.5) }
+    }
+
+    #[test]
+    fn bounds_of_positions() {
+        let bounds = Bounds::from_positions(&[1.0, 2.0, 3.0, 0.5, -4.0, 1.0, 9.0, 0.0, 2.0, 5.0, 1.0], 3);
+        //The incomplete vertex at the end is ignored
+        assert_eq!(bounds, Bounds { min: Vec3::new(0.5, -4.0, 1.0), max: Vec3::new(9.0, 2.0, 3.0) });
+        //Extra components per vertex are skipped
+        let bounds = Bounds::from_positions(&[1.0, 1.0, 1.0, 7.0, -1.0, -1.0, -1.0, -7.0], 4);
+        assert_eq!(bounds, Bounds { min: Vec3::splat(-1.0), max: Vec3::ONE });
+        assert_eq!(Bounds::from_positions(&[1.0, 2.0], 2), Bounds::default());
+    }
+
+    #[test]
+    fn transformed_bounds_enclose_the_rotated_box() {
+        let model = Mat4::from_translation(Vec3::new(1.0, 2.0, 3.0)) * Mat4::from_rotation_y(45f32.to_radians()) * Mat4::from_scale(Vec3::new(2.0, 1.0, 1.0));
+        let bounds = unit_box().transformed(&model);
+        let half_diagonal = (1.0 + 0.5) * std::f32::consts::FRAC_1_SQRT_2;
+        assert!((bounds.max - Vec3::new(1.0 + half_diagonal, 2.5, 3.0 + half_diagonal)).abs().max_element() < 1e-5);
+        assert!((bounds.min - Vec3::new(1.0 - half_diagonal, 1.5, 3.0 - half_diagonal)).abs().max_element() < 1e-5);
+        let (center, radius) = unit_box().world_sphere(&model);
+        assert!((center - Vec3::new(1.0, 2.0, 3.0)).length() < 1e-5);
+        assert!((radius - unit_box().radius() * 2.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn keeps_what_is_in_front() {
+        let frustum = frustum();
+        for position in [Vec3::new(0.0, 0.0, -5.0), Vec3::new(4.0, 0.0, -5.0), Vec3::new(0.0, -4.0, -5.0), Vec3::new(0.0, 0.0, -99.0)] {
+            assert!(frustum.is_visible(&unit_box(), &Mat4::from_translation(position)), "{} culled", position);
+        }
+    }
+
+    #[test]
+    fn culls_what_is_outside() {
+        let frustum = frustum();
+        for position in [Vec3::new(0.0, 0.0, 5.0), Vec3::new(8.0, 0.0, -5.0), Vec3::new(0.0, 8.0, -5.0), Vec3::new(0.0, 0.0, -120.0)] {
+            assert!(!frustum.is_visible(&unit_box(), &Mat4::from_translation(position)), "{} visible", position);
+        }
+    }
+
+    #[test]
+    fn keeps_boxes_straddling_a_plane() {
+        let frustum = frustum();
+        //The centre is just outside the right plane (x = -z) but the box reaches into the frustum
+        assert!(frustum.is_visible(&unit_box(), &Mat4::from_translation(Vec3::new(5.6, 0.0, -5.0))));
+        assert!(!frustum.is_visible(&unit_box(), &Mat4::from_translation(Vec3::new(6.5, 0.0, -5.0))));
+        //Scaling the box up brings it back in
+        assert!(frustum.is_visible(&unit_box(), &(Mat4::from_translation(Vec3::new(6.5, 0.0, -5.0)) * Mat4::from_scale(Vec3::splat(4.0)))));
+    }
+
+    #[test]
+    fn box_test_is_tighter_than_the_sphere() {
+        let frustum = frustum();
+        //A long thin box along X whose sphere reaches past the near corner of the frustum but whose box doesn't
+        let bounds = Bounds { min: Vec3::new(-10.0, -0.01, -0.01), max: Vec3::new(10.0, 0.01, 0.01) };
+        let model = Mat4::from_translation(Vec3::new(0.0, 12.0, -5.0));
+        let (center, radius) = bounds.world_sphere(&model);
+        assert!(frustum.intersects_sphere(center, radius));
+        assert!(!frustum.is_visible(&bounds, &model));
+    }
+
+    #[test]
+    fn counts_culling_results() {
+        let mut stats = CullingStats::default();
+        assert!(stats.record(true));
+        assert!(!stats.record(false));
+        stats.record(true);
+        assert_eq!((stats.drawn, stats.culled), (2, 1));
+        stats.reset();
+        assert_eq!((stats.drawn, stats.culled), (0, 0));
+    }
+}
@@ -0,0 +1,355 @@
+use std::{error::Error, f32::consts::TAU, mem, os::raw::c_void, ptr};
+
+use bevy_ecs::prelude::*;
+use gl::types::*;
+use glam::*;
+
+use crate::{culling::Bounds, gl_state, mesh, renderer::{GPUObject, VAO}, shader::Shader};
+
+const SPHERE_SEGMENTS: usize = 32;
+//Glyph cell is one unit tall and this wide, plus the gap to the next character
+const GLYPH_WIDTH: f32 = 0.6;
+const GLYPH_ADVANCE: f32 = 0.8;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct DebugOptions {
+    //Seconds the shape stays, 0 draws it for the current frame only
+    pub duration: f32,
+    //Shapes without depth testing show through the scene
+    pub depth_test: bool,
+}
+
+impl DebugOptions {
+    pub fn duration(seconds: f32) -> DebugOptions {
+        DebugOptions { duration: seconds, ..Default::default() }
+    }
+    pub fn on_top(self) -> DebugOptions {
+        DebugOptions { depth_test: false, ..self }
+    }
+}
+
+impl Default for DebugOptions {
+    fn default() -> Self {
+        Self { duration: 0.0, depth_test: true }
+    }
+}
+
+struct DebugLine {
+    start: Vec3,
+    end: Vec3,
+    color: Vec4,
+    options: DebugOptions,
+}
+
+//Labels always face the camera, so they are only turned into lines when a view is drawn
+struct DebugLabel {
+    position: Vec3,
+    text: String,
+    height: f32,
+    color: Vec4,
+    options: DebugOptions,
+}
+
+//Immediate mode shapes any system can push, drawn over every view on layer 0 while Settings::debug_draw is on
+#[derive(Resource, Default)]
+pub struct DebugDraw {
+    lines: Vec<DebugLine>,
+    labels: Vec<DebugLabel>,
+}
+
+impl DebugDraw {
+    pub fn line(&mut self, start: Vec3, end: Vec3, color: Vec4, options: DebugOptions) {
+        self.lines.push(DebugLine { start, end, color, options });
+    }
+
+    //Head size follows the arrow length
+    pub fn arrow(&mut self, start: Vec3, end: Vec3, color: Vec4, options: DebugOptions) {
+        self.line(start, end, color, options);
+        let direction = end - start;
+        let length = direction.length();
+        if length <= f32::EPSILON {
+            return;
+        }
+        let (side, up) = (direction / length).any_orthonormal_pair();
+        let head = length * 0.2;
+        let base = end - direction / length * head;
+        for offset in [side, -side, up, -up] {
+            self.line(end, base + offset * head * 0.4, color, options);
+        }
+    }
+
+    pub fn aabb(&mut self, bounds: &Bounds, color: Vec4, options: DebugOptions) {
+        let corner = |i: usize| Vec3::select(BVec3::new(i & 1 != 0, i & 2 != 0, i & 4 != 0), bounds.max, bounds.min);
+        self.box_edges(corner, color, options);
+    }
+
+    //Three great circles, one around each axis
+    pub fn sphere(&mut self, center: Vec3, radius: f32, color: Vec4, options: DebugOptions) {
+        self.circle(center, Vec3::X * radius, Vec3::Y * radius, color, options);
+        self.circle(center, Vec3::Y * radius, Vec3::Z * radius, color, options);
+        self.circle(center, Vec3::Z * radius, Vec3::X * radius, color, options);
+    }
+
+    //Edges of the volume a view projection matrix sees, e.g. a camera or shadow view
+    pub fn frustum(&mut self, view_projection: &Mat4, color: Vec4, options: DebugOptions) {
+        let inverse = view_projection.inverse();
+        let corner = |i: usize| {
+            let ndc = Vec3::new(if i & 1 != 0 { 1.0 } else { -1.0 }, if i & 2 != 0 { 1.0 } else { -1.0 }, if i & 4 != 0 { 1.0 } else { -1.0 });
+            inverse.project_point3(ndc)
+        };
+        self.box_edges(corner, color, options);
+    }
+
+    //Square grid on the XZ plane, `cells` squares along each side
+    pub fn grid(&mut self, center: Vec3, size: f32, cells: u32, color: Vec4, options: DebugOptions) {
+        let half = size * 0.5;
+        let cells = cells.max(1);
+        for i in 0..=cells {
+            let offset = -half + size * i as f32 / cells as f32;
+            self.line(center + Vec3::new(offset, 0.0, -half), center + Vec3::new(offset, 0.0, half), color, options);
+            self.line(center + Vec3::new(-half, 0.0, offset), center + Vec3::new(half, 0.0, offset), color, options);
+        }
+    }
+
+    //Text centered above `position`, `height` in world units. Letters, digits and a few symbols, lowercase is drawn as uppercase.
+    pub fn text(&mut self, position: Vec3, text: &str, height: f32, color: Vec4, options: DebugOptions) {
+        self.labels.push(DebugLabel { position, text: text.to_string(), height, color, options });
+    }
+
+    //Drops shapes whose time is up, called once everything has been drawn for the frame
+    pub fn advance(&mut self, delta_seconds: f32) {
+        self.lines.retain_mut(|line| expire(&mut line.options, delta_seconds));
+        self.labels.retain_mut(|label| expire(&mut label.options, delta_seconds));
+    }
+
+    fn circle(&mut self, center: Vec3, x: Vec3, y: Vec3, color: Vec4, options: DebugOptions) {
+        let point = |i: usize| {
+            let angle = TAU * i as f32 / SPHERE_SEGMENTS as f32;
+            center + x * angle.cos() + y * angle.sin()
+        };
+        for i in 0..SPHERE_SEGMENTS {
+            self.line(point(i), point(i + 1), color, options);
+        }
+    }
+
+    //Corners are indexed by their x, y and z bits
+    fn box_edges(&mut self, corner: impl Fn(usize) -> Vec3, color: Vec4, options: DebugOptions) {
+        for i in 0..8 {
+            for axis in [1, 2, 4] {
+                if i & axis == 0 {
+                    self.line(corner(i), corner(i | axis), color, options);
+                }
+            }
+        }
+    }
+
+    //Vertices of either the depth tested or the always visible shapes, labels are turned towards the camera
+    fn vertices(&self, depth_test: bool, camera_right: Vec3, camera_up: Vec3) -> Vec<DebugVertex> {
+        let mut vertices = Vec::new();
+        let mut push = |start: Vec3, end: Vec3, color: Vec4| {
+            vertices.push(DebugVertex { position: start.to_array(), color: color.to_array() });
+            vertices.push(DebugVertex { position: end.to_array(), color: color.to_array() });
+        };
+        for line in self.lines.iter().filter(|line| line.options.depth_test == depth_test) {
+            push(line.start, line.end, line.color);
+        }
+        for label in self.labels.iter().filter(|label| label.options.depth_test == depth_test) {
+            let (right, up) = (camera_right * label.height, camera_up * label.height);
+            let width = label.text.chars().count() as f32 * GLYPH_ADVANCE - (GLYPH_ADVANCE - GLYPH_WIDTH);
+            let mut origin = label.position - right * width * 0.5;
+            for character in label.text.chars() {
+                for (start, end) in glyph_strokes(character) {
+                    let point = |p: Vec2| origin + right * p.x * GLYPH_WIDTH + up * p.y;
+                    push(point(start), point(end), label.color);
+                }
+                origin += right * GLYPH_ADVANCE;
+            }
+        }
+        vertices
+    }
+}
+
+fn expire(options: &mut DebugOptions, delta_seconds: f32) -> bool {
+    options.duration -= delta_seconds;
+    options.duration > 0.0
+}
+
+//Fourteen segment display, x and y go from 0 to 1:
+//a top, b/c right, d bottom, e/f left, g/h middle halves, j/n center verticals,
+//i/k diagonals from the top corners and l/o from the bottom corners, all meeting in the middle
+fn segment(name: char) -> Option<(Vec2, Vec2)> {
+    let (start, end) = match name {
+        'a' => ((0.0, 1.0), (1.0, 1.0)),
+        'b' => ((1.0, 1.0), (1.0, 0.5)),
+        'c' => ((1.0, 0.5), (1.0, 0.0)),
+        'd' => ((0.0, 0.0), (1.0, 0.0)),
+        'e' => ((0.0, 0.0), (0.0, 0.5)),
+        'f' => ((0.0, 0.5), (0.0, 1.0)),
+        'g' => ((0.0, 0.5), (0.5, 0.5)),
+        'h' => ((0.5, 0.5), (1.0, 0.5)),
+        'i' => ((0.0, 1.0), (0.5, 0.5)),
+        'j' => ((0.5, 1.0), (0.5, 0.5)),
+        'k' => ((1.0, 1.0), (0.5, 0.5)),
+        'l' => ((0.0, 0.0), (0.5, 0.5)),
+        'n' => ((0.5, 0.5), (0.5, 0.0)),
+        'o' => ((1.0, 0.0), (0.5, 0.5)),
+        _ => return None,
+    };
+    Some((Vec2::new(start.0, start.1), Vec2::new(end.0, end.1)))
+}
+
+fn glyph_strokes(character: char) -> impl Iterator<Item = (Vec2, Vec2)> {
+    let segments = match character.to_ascii_uppercase() {
+        '0' => "abcdefkl",
+        '1' => "bck",
+        '2' => "abdegh",
+        '3' => "abcdh",
+        '4' => "bcfgh",
+        '5' | 'S' => "acdfgh",
+        '6' => "acdefgh",
+        '7' => "abc",
+        '8' => "abcdefgh",
+        '9' => "abcdfgh",
+        'A' => "abcefgh",
+        'B' => "abcdhjn",
+        'C' => "adef",
+        'D' => "abcdjn",
+        'E' => "adefg",
+        'F' => "aefg",
+        'G' => "acdefh",
+        'H' => "bcefgh",
+        'I' => "adjn",
+        'J' => "bcde",
+        'K' => "efgko",
+        'L' => "def",
+        'M' => "bcefik",
+        'N' => "bcefio",
+        'O' => "abcdef",
+        'P' => "abefgh",
+        'Q' => "abcdefo",
+        'R' => "abefgho",
+        'T' => "ajn",
+        'U' => "bcdef",
+        'V' => "efkl",
+        'W' => "bceflo",
+        'X' => "iklo",
+        'Y' => "ikn",
+        'Z' => "adkl",
+        '-' => "gh",
+        '+' => "ghjn",
+        '=' => "dgh",
+        '_' => "d",
+        '/' => "kl",
+        '\\' => "io",
+        '(' => "ko",
+        ')' => "il",
+        '.' | ',' => "n",
+        ':' => "jn",
+        _ => "",
+    };
+    segments.chars().filter_map(segment)
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct DebugVertex {
+    position: [f32; 3],
+    color: [f32; 4],
+}
+
+//Uploads every debug line of a view into one stream buffer, drawn in two ranges with and without depth testing
+#[derive(Resource)]
+pub struct DebugRenderer {
+    shader: Shader,
+    vao: VAO,
+    vertex_buffer: u32,
+}
+
+impl DebugRenderer {
+    pub fn new() -> Result<DebugRenderer, Box<dyn Error>> {
+        let vao = VAO::new();
+        let mut vertex_buffer = 0;
+        unsafe {
+            gl::GenBuffers(1, &mut vertex_buffer);
+            vao.bind();
+            gl_state::bind_buffer(gl::ARRAY_BUFFER, vertex_buffer);
+            let stride = mem::size_of::<DebugVertex>() as GLsizei;
+            gl::VertexAttribPointer(mesh::POSITION_LOCATION, 3, gl::FLOAT, gl::FALSE, stride, ptr::null());
+            gl::EnableVertexAttribArray(mesh::POSITION_LOCATION);
+            gl::VertexAttribPointer(mesh::COLOR_LOCATION, 4, gl::FLOAT, gl::FALSE, stride, (3 * mem::size_of::<f32>()) as *const c_void);
+            gl::EnableVertexAttribArray(mesh::COLOR_LOCATION);
+            vao.label("debug draw");
+            gl_state::label(gl::BUFFER, vertex_buffer, "debug draw vertices");
+            vao.unbind();
+            gl_state::bind_buffer(gl::ARRAY_BUFFER, 0);
+        }
+        Ok(DebugRenderer { shader: Shader::new("debug")?, vao, vertex_buffer })
+    }
+
+    pub fn render(&self, debug: &DebugDraw, view: &Mat4, projection: &Mat4, is_wireframe: bool) {
+        let (right, up) = (view.row(0).truncate(), view.row(1).truncate());
+        let mut vertices = debug.vertices(true, right, up);
+        let depth_tested = vertices.len();
+        vertices.extend(debug.vertices(false, right, up));
+        if vertices.is_empty() {
+            return;
+        }
+        unsafe {
+            gl_state::bind_buffer(gl::ARRAY_BUFFER, self.vertex_buffer);
+            gl::BufferData(gl::ARRAY_BUFFER, (vertices.len() * mem::size_of::<DebugVertex>()) as GLsizeiptr, vertices.as_ptr() as *const c_void, gl::STREAM_DRAW);
+            gl_state::bind_buffer(gl::ARRAY_BUFFER, 0);
+            gl::PolygonMode(gl::FRONT_AND_BACK, gl::FILL);
+            gl_state::enable(gl::BLEND);
+            gl_state::blend_func(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+            gl_state::depth_mask(gl::FALSE);
+        }
+        self.shader.bind();
+        self.shader.set_uniform_4x4f("camMatrix".to_string(), None, &(*projection * *view));
+        self.vao.bind();
+        unsafe {
+            gl::DrawArrays(gl::LINES, 0, depth_tested as i32);
+            gl_state::disable(gl::DEPTH_TEST);
+            gl::DrawArrays(gl::LINES, depth_tested as i32, (vertices.len() - depth_tested) as i32);
+            gl_state::enable(gl::DEPTH_TEST);
+        }
+        self.vao.unbind();
+        self.shader.unbind();
+        unsafe {
+            gl_state::depth_mask(gl::TRUE);
+            gl_state::disable(gl::BLEND);
+        }
+        crate::renderer::update_wireframe(&is_wireframe);
+    }
+}
+
+impl Drop for DebugRenderer {
+    fn drop(&mut self) {
+        unsafe {
+            gl_state::delete_buffers(1, &self.vertex_buffer);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_shapes_for_their_duration() {
+        let mut debug = DebugDraw::default();
+        debug.line(Vec3::ZERO, Vec3::X, Vec4::ONE, DebugOptions::default());
+        debug.sphere(Vec3::ZERO, 1.0, Vec4::ONE, DebugOptions::duration(0.5));
+        debug.text(Vec3::ZERO, "hi", 1.0, Vec4::ONE, DebugOptions::duration(0.5).on_top());
+        assert_eq!(debug.lines.len(), 1 + 3 * SPHERE_SEGMENTS);
+
+        //Single frame shapes go after the frame they were drawn in
+        debug.advance(0.3);
+        assert_eq!(debug.lines.len(), 3 * SPHERE_SEGMENTS);
+        assert_eq!(debug.labels.len(), 1);
+        assert_eq!(debug.vertices(true, Vec3::X, Vec3::Y).len(), 6 * SPHERE_SEGMENTS);
+        assert!(!debug.vertices(false, Vec3::X, Vec3::Y).is_empty());
+
+        debug.advance(0.3);
+        assert!(debug.lines.is_empty() && debug.labels.is_empty());
+    }
+}
@@ -0,0 +1,285 @@
+use std::{error::Error, mem, ptr};
+
+use bevy_ecs::prelude::*;
+use glam::*;
+use serde::{Serialize, Deserialize};
+
+use crate::{components::{Camera, PointLight, SpotLight}, environment, framebuffer::{self, Framebuffer}, gl_state, lighting::{self, SceneLights}, renderer::{FullscreenTriangle, GPUObject}, shader::Shader, shadow::ShadowAtlas};
+
+#[derive(Serialize, Deserialize, Default, Clone, Copy, PartialEq)]
+pub enum RenderPath {
+    #[default]
+    Forward,
+    Deferred,
+}
+
+//Must match the work group size and buffer bindings in deferred_lighting.cs
+pub const TILE_SIZE: u32 = 16;
+const POINT_LIGHT_BINDING: u32 = 0;
+const SPOT_LIGHT_BINDING: u32 = 1;
+
+//G-buffer textures are read after the shadow atlas (1) and lightmap (2) units
+const ALBEDO_UNIT: u32 = 3;
+const NORMAL_UNIT: u32 = 4;
+const LIGHTING_UNIT: u32 = 5;
+const DEPTH_UNIT: u32 = 6;
+
+//std430 layouts, every field is padded out to a vec4
+#[repr(C)]
+struct GpuPointLight {
+    position_range: [f32; 4],
+    color_baked: [f32; 4],
+    shadow_view: [f32; 4],
+}
+
+#[repr(C)]
+struct GpuSpotLight {
+    position_range: [f32; 4],
+    direction_inner_cos: [f32; 4],
+    color_baked: [f32; 4],
+    outer_cos_shadow_view: [f32; 4],
+}
+
+//Lights without a shadow view get -1, baked ones 1 in the flag the shaders use to skip their direct light
+impl GpuPointLight {
+    fn new(position: Vec3, light: &PointLight, shadow_view: Option<usize>, baked: bool) -> GpuPointLight {
+        let color = light.color * light.intensity;
+        GpuPointLight {
+            position_range: position.extend(light.range).to_array(),
+            color_baked: color.extend(baked as u32 as f32).to_array(),
+            shadow_view: [shadow_view.map_or(-1.0, |view| view as f32), 0.0, 0.0, 0.0],
+        }
+    }
+}
+
+impl GpuSpotLight {
+    fn new(position: Vec3, light: &SpotLight, shadow_view: Option<usize>, baked: bool) -> GpuSpotLight {
+        let color = light.color * light.intensity;
+        GpuSpotLight {
+            position_range: position.extend(light.range).to_array(),
+            direction_inner_cos: light.direction.normalize().extend(light.inner_angle.to_radians().cos()).to_array(),
+            color_baked: color.extend(baked as u32 as f32).to_array(),
+            outer_cos_shadow_view: [light.outer_angle.to_radians().cos(), shadow_view.map_or(-1.0, |view| view as f32), 0.0, 0.0],
+        }
+    }
+}
+
+//Work groups covering a target, one per tile including the partial ones along the right and top edges
+pub fn tile_groups(width: i32, height: i32) -> (u32, u32) {
+    ((width.max(1) as u32).div_ceil(TILE_SIZE), (height.max(1) as u32).div_ceil(TILE_SIZE))
+}
+
+//Render graph attachments and framebuffers, all transient
+pub const GBUFFER: &str = "gbuffer";
+pub const GBUFFER_ALBEDO: &str = "gbuffer_albedo";
+//World normal + roughness
+pub const GBUFFER_NORMAL: &str = "gbuffer_normal";
+//Ambient or lightmap irradiance + lightmapped flag
+pub const GBUFFER_LIGHTING: &str = "gbuffer_lighting";
+pub const GBUFFER_DEPTH: &str = "gbuffer_depth";
+//Lit color written by the compute pass
+pub const LIT: &str = "deferred_lit";
+pub const LIT_COLOR: &str = "deferred_lit_color";
+
+#[derive(Resource)]
+pub struct DeferredRenderer {
+    triangle: FullscreenTriangle,
+    geometry_shader: Shader,
+    lighting_shader: Shader,
+    present_shader: Shader,
+    point_buffer: u32,
+    spot_buffer: u32,
+    //Framebuffer and viewport bound before the geometry pass, the result is presented back into them
+    previous_framebuffer: i32,
+    previous_viewport: [i32; 4],
+}
+
+impl DeferredRenderer {
+    pub fn new() -> Result<DeferredRenderer, Box<dyn Error>> {
+        let mut buffers = [0; 2];
+        unsafe {
+            gl::GenBuffers(2, buffers.as_mut_ptr());
+            //Bound once so the buffers exist before they are labeled
+            for buffer in buffers {
+                gl_state::bind_buffer(gl::SHADER_STORAGE_BUFFER, buffer);
+            }
+            gl_state::bind_buffer(gl::SHADER_STORAGE_BUFFER, 0);
+        }
+        gl_state::label(gl::BUFFER, buffers[0], "point lights");
+        gl_state::label(gl::BUFFER, buffers[1], "spot lights");
+        Ok(DeferredRenderer {
+            triangle: FullscreenTriangle::new(),
+            geometry_shader: Shader::from_files("default", "gbuffer")?,
+            lighting_shader: Shader::compute("deferred_lighting")?,
+            present_shader: Shader::from_files("fullscreen", "deferred_present")?,
+            point_buffer: buffers[0],
+            spot_buffer: buffers[1],
+            previous_framebuffer: 0,
+            previous_viewport: [0; 4],
+        })
+    }
+
+    pub fn geometry_shader(&self) -> &Shader {
+        &self.geometry_shader
+    }
+
+    pub fn begin_geometry(&mut self, geometry: &Framebuffer) {
+        unsafe {
+            gl::GetIntegerv(gl::DRAW_FRAMEBUFFER_BINDING, &mut self.previous_framebuffer);
+            gl::GetIntegerv(gl::VIEWPORT, self.previous_viewport.as_mut_ptr());
+        }
+        geometry.bind();
+        unsafe {
+            gl::Viewport(0, 0, geometry.width(), geometry.height());
+            //Every attachment takes the clear color, the lighting pass passes albedo through for the sky
+            gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
+        }
+        self.geometry_shader.bind();
+    }
+
+    pub fn end_geometry(&self, geometry: &Framebuffer) {
+        self.geometry_shader.unbind();
+        geometry.unbind();
+    }
+
+    //Culls point and spot lights per screen tile and shades every pixel in a single dispatch
+    pub fn light(&self, geometry: &Framebuffer, lit: &Framebuffer, camera: &Camera, lights: &SceneLights, shadows: &ShadowAtlas) {
+        self.upload_light_buffers(lights, shadows);
+
+        let shader = &self.lighting_shader;
+        shader.bind();
+        //Directional lights and shadow views stay plain uniforms, there are only ever a handful of them
+        lighting::upload_directional_lights(shader, &lights.directional);
+        shadows.upload(shader);
+        environment::upload(shader, lights.environment);
+        shader.set_uniform_3f("cameraPosition".to_string(), &camera.view.inverse().w_axis.xyz());
+        shader.set_uniform_i32("gAlbedo".to_string(), &(ALBEDO_UNIT as i32));
+        shader.set_uniform_i32("gNormal".to_string(), &(NORMAL_UNIT as i32));
+        shader.set_uniform_i32("gLighting".to_string(), &(LIGHTING_UNIT as i32));
+        shader.set_uniform_i32("gDepth".to_string(), &(DEPTH_UNIT as i32));
+        shader.set_uniform_i32("pointLightCount".to_string(), &(lights.point.len() as i32));
+        shader.set_uniform_i32("spotLightCount".to_string(), &(lights.spot.len() as i32));
+        shader.set_uniform_4x4f("view".to_string(), None, &camera.view);
+        shader.set_uniform_4x4f("invProjection".to_string(), None, &camera.projection.inverse());
+        shader.set_uniform_4x4f("invViewProjection".to_string(), None, &camera.get_calculation().inverse());
+        shader.set_uniform_2i("screenSize".to_string(), &IVec2::new(geometry.width(), geometry.height()));
+
+        framebuffer::bind_attachment(geometry.color_attachment(0).unwrap(), ALBEDO_UNIT);
+        framebuffer::bind_attachment(geometry.color_attachment(1).unwrap(), NORMAL_UNIT);
+        framebuffer::bind_attachment(geometry.color_attachment(2).unwrap(), LIGHTING_UNIT);
+        framebuffer::bind_attachment(geometry.depth_attachment().unwrap(), DEPTH_UNIT);
+        unsafe {
+            gl_state::bind_buffer_base(gl::SHADER_STORAGE_BUFFER, POINT_LIGHT_BINDING, self.point_buffer);
+            gl_state::bind_buffer_base(gl::SHADER_STORAGE_BUFFER, SPOT_LIGHT_BINDING, self.spot_buffer);
+            gl::BindImageTexture(0, lit.color_attachment(0).unwrap(), 0, gl::FALSE, 0, gl::WRITE_ONLY, gl::RGBA16F);
+        }
+
+        let (groups_x, groups_y) = tile_groups(geometry.width(), geometry.height());
+        shader.dispatch(groups_x, groups_y, 1);
+
+        unsafe {
+            gl::MemoryBarrier(gl::SHADER_IMAGE_ACCESS_BARRIER_BIT | gl::TEXTURE_FETCH_BARRIER_BIT | gl::FRAMEBUFFER_BARRIER_BIT);
+            gl::BindImageTexture(0, 0, 0, gl::FALSE, 0, gl::WRITE_ONLY, gl::RGBA16F);
+            gl_state::bind_buffer_base(gl::SHADER_STORAGE_BUFFER, POINT_LIGHT_BINDING, 0);
+            gl_state::bind_buffer_base(gl::SHADER_STORAGE_BUFFER, SPOT_LIGHT_BINDING, 0);
+        }
+        for unit in [ALBEDO_UNIT, NORMAL_UNIT, LIGHTING_UNIT, DEPTH_UNIT] {
+            framebuffer::unbind_attachment(unit);
+        }
+        shader.unbind();
+    }
+
+    //Draws the lit color and G-buffer depth into whatever target was bound before the geometry pass,
+    //transparent meshes are blended on top of it afterwards
+    pub fn present(&self, geometry: &Framebuffer, lit: &Framebuffer, is_wireframe: bool) {
+        let viewport = self.previous_viewport;
+        unsafe {
+            gl_state::bind_framebuffer(gl::FRAMEBUFFER, self.previous_framebuffer as u32);
+            gl::Viewport(viewport[0], viewport[1], viewport[2], viewport[3]);
+            gl::PolygonMode(gl::FRONT_AND_BACK, gl::FILL);
+            gl_state::depth_func(gl::ALWAYS);
+        }
+        let shader = &self.present_shader;
+        shader.bind();
+        shader.set_uniform_i32("litColor".to_string(), &0);
+        shader.set_uniform_i32("litDepth".to_string(), &1);
+        framebuffer::bind_attachment(lit.color_attachment(0).unwrap(), 0);
+        framebuffer::bind_attachment(geometry.depth_attachment().unwrap(), 1);
+        self.triangle.render();
+        framebuffer::unbind_attachment(1);
+        framebuffer::unbind_attachment(0);
+        shader.unbind();
+        unsafe {
+            gl_state::depth_func(gl::LESS);
+        }
+        crate::renderer::update_wireframe(&is_wireframe);
+    }
+
+    fn upload_light_buffers(&self, lights: &SceneLights, shadows: &ShadowAtlas) {
+        let shadow_view = |entity: Entity| shadows.first_view(entity);
+        let point_lights: Vec<GpuPointLight> = lights.point.iter()
+            .map(|(entity, position, light, is_baked)| GpuPointLight::new(position.d, light, shadow_view(*entity), *is_baked))
+            .collect();
+        let spot_lights: Vec<GpuSpotLight> = lights.spot.iter()
+            .map(|(entity, position, light, is_baked)| GpuSpotLight::new(position.d, light, shadow_view(*entity), *is_baked))
+            .collect();
+
+        upload_storage_buffer(self.point_buffer, &point_lights);
+        upload_storage_buffer(self.spot_buffer, &spot_lights);
+    }
+}
+
+impl Drop for DeferredRenderer {
+    fn drop(&mut self) {
+        unsafe {
+            gl_state::delete_buffers(1, &self.point_buffer);
+            gl_state::delete_buffers(1, &self.spot_buffer);
+        }
+    }
+}
+
+//Empty buffers still get a single element so the binding is always valid
+fn upload_storage_buffer<T>(buffer: u32, data: &[T]) {
+    let size = (data.len().max(1) * mem::size_of::<T>()) as isize;
+    unsafe {
+        gl_state::bind_buffer(gl::SHADER_STORAGE_BUFFER, buffer);
+        gl::BufferData(gl::SHADER_STORAGE_BUFFER, size, ptr::null(), gl::DYNAMIC_DRAW);
+        if !data.is_empty() {
+            gl::BufferSubData(gl::SHADER_STORAGE_BUFFER, 0, mem::size_of_val(data) as isize, data.as_ptr() as *const _);
+        }
+        gl_state::bind_buffer(gl::SHADER_STORAGE_BUFFER, 0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn covers_partial_tiles() {
+        assert_eq!(tile_groups(1280, 720), (80, 45));
+        assert_eq!(tile_groups(1281, 721), (81, 46));
+        assert_eq!(tile_groups(1, 1), (1, 1));
+        assert_eq!(tile_groups(0, 0), (1, 1));
+    }
+
+    #[test]
+    fn packs_lights_in_std430_layout() {
+        assert_eq!(mem::size_of::<GpuPointLight>(), 48);
+        assert_eq!(mem::size_of::<GpuSpotLight>(), 64);
+
+        let point = PointLight { color: Vec3::new(1.0, 0.5, 0.0), intensity: 2.0, range: 8.0, ..Default::default() };
+        let packed = GpuPointLight::new(Vec3::new(1.0, 2.0, 3.0), &point, None, true);
+        assert_eq!(packed.position_range, [1.0, 2.0, 3.0, 8.0]);
+        assert_eq!(packed.color_baked, [2.0, 1.0, 0.0, 1.0]);
+        assert_eq!(packed.shadow_view[0], -1.0);
+
+        let spot = SpotLight { direction: Vec3::NEG_Y * 3.0, inner_angle: 0.0, outer_angle: 60.0, ..Default::default() };
+        let packed = GpuSpotLight::new(Vec3::ZERO, &spot, Some(7), false);
+        assert_eq!(packed.direction_inner_cos, [0.0, -1.0, 0.0, 1.0]);
+        assert_eq!(packed.color_baked[3], 0.0);
+        assert!((packed.outer_cos_shadow_view[0] - 0.5).abs() < 1e-6);
+        assert_eq!(packed.outer_cos_shadow_view[1], 7.0);
+    }
+}
+
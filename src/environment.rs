@@ -0,0 +1,246 @@
+use std::{error::Error, path::Path};
+
+use bevy_ecs::prelude::*;
+use serde::{Serialize, Deserialize};
+
+use crate::{components::Camera, gl_state, renderer::{FullscreenTriangle, GPUObject}, shader::Shader, texture::{HdrImage, Texture}};
+
+//Sampled after the shadow atlas (1), lightmap (2) and G-buffer (3-6) units
+pub const IRRADIANCE_UNIT: u32 = 7;
+pub const PREFILTERED_UNIT: u32 = 8;
+pub const BRDF_UNIT: u32 = 9;
+
+//Must match the work group size in irradiance.cs, prefilter.cs and brdf.cs
+const GROUP_SIZE: u32 = 8;
+const IRRADIANCE_SIZE: i32 = 32;
+const PREFILTERED_SIZE: i32 = 128;
+//Roughness 0 to 1 is spread over the mip chain
+const PREFILTERED_LEVELS: i32 = 5;
+const BRDF_SIZE: i32 = 256;
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct SkyboxSettings {
+    //An equirectangular resources/textures/{texture}.hdr, or a folder of six faces when there is none
+    pub texture: String,
+    pub intensity: f32,
+    //Face size the equirectangular image is resampled to
+    pub cubemap_size: usize,
+}
+
+impl Default for SkyboxSettings {
+    fn default() -> Self {
+        Self {
+            texture: "skies/gradient".to_string(),
+            intensity: 1.0,
+            cubemap_size: 512,
+        }
+    }
+}
+
+//Environment cubemap drawn behind the scene, convolved once at load into the maps used for ambient lighting
+#[derive(Resource)]
+pub struct Skybox {
+    environment: Texture,
+    //Cosine weighted hemisphere integral, the diffuse ambient
+    irradiance: Texture,
+    //GGX prefiltered radiance, roughness picks the mip
+    prefiltered: Texture,
+    //Split sum scale and bias on F0
+    brdf: Texture,
+    shader: Shader,
+    triangle: FullscreenTriangle,
+    pub intensity: f32,
+}
+
+impl Skybox {
+    pub fn load(settings: &SkyboxSettings) -> Result<Skybox, Box<dyn Error>> {
+        let path = format!("resources/textures/{}.hdr", settings.texture);
+        let environment = if Path::new(&path).exists() {
+            Texture::cubemap_from_equirectangular(&HdrImage::load(&path)?, settings.cubemap_size)
+        } else {
+            Texture::cubemap(&settings.texture)?
+        };
+        environment.label(&settings.texture);
+        unsafe {
+            //Filtering across face edges, otherwise rough reflections show the cube seams
+            gl_state::enable(gl::TEXTURE_CUBE_MAP_SEAMLESS);
+        }
+
+        let irradiance = Texture::empty_cubemap(IRRADIANCE_SIZE, 1, gl::RGBA16F);
+        let prefiltered = Texture::empty_cubemap(PREFILTERED_SIZE, PREFILTERED_LEVELS, gl::RGBA16F);
+        let brdf = Texture::empty(BRDF_SIZE, BRDF_SIZE, gl::RG16F);
+        irradiance.label("irradiance");
+        prefiltered.label("prefiltered environment");
+        brdf.label("BRDF lookup");
+        convolve_irradiance(&environment, &irradiance)?;
+        prefilter(&environment, &prefiltered)?;
+        integrate_brdf(&brdf)?;
+
+        Ok(Skybox {
+            environment,
+            irradiance,
+            prefiltered,
+            brdf,
+            shader: Shader::from_files("skybox", "skybox")?,
+            triangle: FullscreenTriangle::new(),
+            intensity: settings.intensity,
+        })
+    }
+
+    pub fn bind_for_sampling(&self) {
+        bind_unit(&self.irradiance, IRRADIANCE_UNIT);
+        bind_unit(&self.prefiltered, PREFILTERED_UNIT);
+        bind_unit(&self.brdf, BRDF_UNIT);
+    }
+    pub fn unbind_for_sampling(&self) {
+        unbind_unit(&self.irradiance, IRRADIANCE_UNIT);
+        unbind_unit(&self.prefiltered, PREFILTERED_UNIT);
+        unbind_unit(&self.brdf, BRDF_UNIT);
+    }
+
+    //Fills every pixel the opaque passes left at the far plane
+    pub fn render(&self, camera: &Camera, is_wireframe: bool) {
+        unsafe {
+            gl::PolygonMode(gl::FRONT_AND_BACK, gl::FILL);
+            gl_state::depth_func(gl::LEQUAL);
+            gl_state::depth_mask(gl::FALSE);
+        }
+        let shader = &self.shader;
+        shader.bind();
+        shader.set_uniform_4x4f("view".to_string(), None, &camera.view);
+        shader.set_uniform_4x4f("projection".to_string(), None, &camera.projection);
+        shader.set_uniform_1f("intensity".to_string(), &self.intensity);
+        shader.set_uniform_i32("environment".to_string(), &0);
+        self.environment.bind();
+        self.triangle.render();
+        self.environment.unbind();
+        shader.unbind();
+        unsafe {
+            gl_state::depth_mask(gl::TRUE);
+            gl_state::depth_func(gl::LESS);
+        }
+        crate::renderer::update_wireframe(&is_wireframe);
+    }
+}
+
+//Lit shaders sample the environment only when there is one, the units are set either way so the samplers never share unit 0
+pub fn upload(shader: &Shader, skybox: Option<&Skybox>) {
+    shader.set_uniform_bool("hasEnvironment".to_string(), &skybox.is_some());
+    shader.set_uniform_i32("irradianceMap".to_string(), &(IRRADIANCE_UNIT as i32));
+    shader.set_uniform_i32("prefilteredMap".to_string(), &(PREFILTERED_UNIT as i32));
+    shader.set_uniform_i32("brdfLut".to_string(), &(BRDF_UNIT as i32));
+    shader.set_uniform_1f("prefilteredLevels".to_string(), &(PREFILTERED_LEVELS as f32));
+    shader.set_uniform_1f("environmentIntensity".to_string(), &skybox.map_or(0.0, |skybox| skybox.intensity));
+}
+
+fn bind_unit(texture: &Texture, unit: u32) {
+    unsafe {
+        gl_state::active_texture(gl::TEXTURE0 + unit);
+        texture.bind();
+        gl_state::active_texture(gl::TEXTURE0);
+    }
+}
+
+fn unbind_unit(texture: &Texture, unit: u32) {
+    unsafe {
+        gl_state::active_texture(gl::TEXTURE0 + unit);
+        texture.unbind();
+        gl_state::active_texture(gl::TEXTURE0);
+    }
+}
+
+fn groups(size: i32) -> u32 {
+    (size as u32).div_ceil(GROUP_SIZE)
+}
+
+fn convolve_irradiance(environment: &Texture, irradiance: &Texture) -> Result<(), Box<dyn Error>> {
+    let shader = Shader::compute("irradiance")?;
+    shader.bind();
+    shader.set_uniform_i32("environment".to_string(), &0);
+    shader.set_uniform_i32("faceSize".to_string(), &IRRADIANCE_SIZE);
+    environment.bind();
+    dispatch_cubemap(&shader, irradiance, 0, IRRADIANCE_SIZE);
+    environment.unbind();
+    shader.unbind();
+    Ok(())
+}
+
+//Each mip gets its own roughness, the base level stays a mirror reflection
+fn prefilter(environment: &Texture, prefiltered: &Texture) -> Result<(), Box<dyn Error>> {
+    let shader = Shader::compute("prefilter")?;
+    shader.bind();
+    shader.set_uniform_i32("environment".to_string(), &0);
+    environment.bind();
+    for level in 0..PREFILTERED_LEVELS {
+        let (size, roughness) = prefilter_level(level);
+        shader.set_uniform_i32("faceSize".to_string(), &size);
+        shader.set_uniform_1f("roughness".to_string(), &roughness);
+        dispatch_cubemap(&shader, prefiltered, level, size);
+    }
+    environment.unbind();
+    shader.unbind();
+    Ok(())
+}
+
+fn integrate_brdf(brdf: &Texture) -> Result<(), Box<dyn Error>> {
+    let shader = Shader::compute("brdf")?;
+    shader.bind();
+    shader.set_uniform_i32("size".to_string(), &BRDF_SIZE);
+    unsafe {
+        gl::BindImageTexture(0, brdf.handle(), 0, gl::FALSE, 0, gl::WRITE_ONLY, gl::RG16F);
+    }
+    shader.dispatch(groups(BRDF_SIZE), groups(BRDF_SIZE), 1);
+    finish_dispatch(gl::RG16F);
+    shader.unbind();
+    Ok(())
+}
+
+//Face size and GGX roughness of a prefiltered mip, lighting.glsl inverts this to pick the mip for a surface
+fn prefilter_level(level: i32) -> (i32, f32) {
+    ((PREFILTERED_SIZE >> level).max(1), level as f32 / (PREFILTERED_LEVELS - 1) as f32)
+}
+
+//All six faces are written at once through a layered image binding, z picks the face
+fn dispatch_cubemap(shader: &Shader, target: &Texture, level: i32, size: i32) {
+    unsafe {
+        gl::BindImageTexture(0, target.handle(), level, gl::TRUE, 0, gl::WRITE_ONLY, gl::RGBA16F);
+    }
+    shader.dispatch(groups(size), groups(size), 6);
+    finish_dispatch(gl::RGBA16F);
+}
+
+fn finish_dispatch(format: u32) {
+    unsafe {
+        gl::MemoryBarrier(gl::SHADER_IMAGE_ACCESS_BARRIER_BIT | gl::TEXTURE_FETCH_BARRIER_BIT);
+        gl::BindImageTexture(0, 0, 0, gl::FALSE, 0, gl::WRITE_ONLY, format);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spreads_roughness_over_the_prefiltered_mips() {
+        assert_eq!(prefilter_level(0), (PREFILTERED_SIZE, 0.0));
+        assert_eq!(prefilter_level(PREFILTERED_LEVELS - 1), (PREFILTERED_SIZE >> (PREFILTERED_LEVELS - 1), 1.0));
+        for level in 1..PREFILTERED_LEVELS {
+            let (size, roughness) = prefilter_level(level);
+            let (previous_size, previous_roughness) = prefilter_level(level - 1);
+            assert_eq!(size * 2, previous_size);
+            assert!(roughness > previous_roughness);
+            //The mip lighting.glsl samples for this roughness is the one it was filtered into
+            assert_eq!((roughness * (PREFILTERED_LEVELS - 1) as f32).round() as i32, level);
+        }
+    }
+
+    #[test]
+    fn covers_every_texel_with_work_groups() {
+        assert_eq!(groups(IRRADIANCE_SIZE), 4);
+        assert_eq!(groups(BRDF_SIZE), 32);
+        //The last prefiltered mips are smaller than one group and still need a dispatch
+        assert_eq!(groups(prefilter_level(PREFILTERED_LEVELS - 1).0), 1);
+        assert_eq!(groups(1), 1);
+    }
+}
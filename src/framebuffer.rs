@@ -0,0 +1,184 @@
+use std::ptr;
+
+use crate::{gl_state, renderer::GPUObject};
+
+pub struct Framebuffer {
+    id: u32,
+    width: i32,
+    height: i32,
+    color_attachments: Vec<u32>,
+    depth_attachment: Option<u32>,
+    //Framebuffers built from textures owned elsewhere (e.g. render graph transients) leave them alone on drop
+    owns_attachments: bool,
+}
+
+impl Framebuffer {
+    pub fn new(width: i32, height: i32) -> Framebuffer {
+        let mut framebuffer = Framebuffer { id: 0, width, height, color_attachments: Vec::new(), depth_attachment: None, owns_attachments: true };
+        unsafe {
+            gl::GenFramebuffers(1, &mut framebuffer.id);
+        }
+        framebuffer
+    }
+
+    //Wraps existing textures without taking ownership of them
+    pub fn from_textures(width: i32, height: i32, color_attachments: &[u32], depth_attachment: Option<u32>) -> Framebuffer {
+        let mut framebuffer = Framebuffer::new(width, height);
+        framebuffer.owns_attachments = false;
+        framebuffer.bind();
+        unsafe {
+            for (index, texture) in color_attachments.iter().enumerate() {
+                gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::COLOR_ATTACHMENT0 + index as u32, gl::TEXTURE_2D, *texture, 0);
+            }
+            if let Some(texture) = depth_attachment {
+                gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::DEPTH_ATTACHMENT, gl::TEXTURE_2D, texture, 0);
+            }
+        }
+        framebuffer.color_attachments = color_attachments.to_vec();
+        framebuffer.depth_attachment = depth_attachment;
+        framebuffer.update_draw_buffers();
+        framebuffer.unbind();
+        framebuffer
+    }
+
+    //Adds a texture to the next free color attachment point, returns its index
+    pub fn add_color_attachment(&mut self, internal_format: u32, format: u32, data_type: u32, filter: u32) -> usize {
+        let index = self.color_attachments.len();
+        let mut texture = 0;
+        self.bind();
+        unsafe {
+            gl::GenTextures(1, &mut texture);
+            gl_state::bind_texture(gl::TEXTURE_2D, texture);
+            gl::TexImage2D(gl::TEXTURE_2D, 0, internal_format as i32, self.width, self.height, 0, format, data_type, ptr::null());
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, filter as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, filter as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as i32);
+            gl_state::bind_texture(gl::TEXTURE_2D, 0);
+
+            gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::COLOR_ATTACHMENT0 + index as u32, gl::TEXTURE_2D, texture, 0);
+        }
+        self.color_attachments.push(texture);
+        self.update_draw_buffers();
+        self.unbind();
+
+        index
+    }
+
+    //Depth textures are set up for hardware comparison when used as a sampler2DShadow
+    pub fn add_depth_attachment(&mut self, compare: bool) {
+        let mut texture = 0;
+        self.bind();
+        unsafe {
+            gl::GenTextures(1, &mut texture);
+            gl_state::bind_texture(gl::TEXTURE_2D, texture);
+            gl::TexImage2D(gl::TEXTURE_2D, 0, gl::DEPTH_COMPONENT32F as i32, self.width, self.height, 0, gl::DEPTH_COMPONENT, gl::FLOAT, ptr::null());
+            let filter = if compare { gl::LINEAR } else { gl::NEAREST };
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, filter as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, filter as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as i32);
+            if compare {
+                gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_COMPARE_MODE, gl::COMPARE_REF_TO_TEXTURE as i32);
+                gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_COMPARE_FUNC, gl::LEQUAL as i32);
+            }
+            gl_state::bind_texture(gl::TEXTURE_2D, 0);
+
+            gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::DEPTH_ATTACHMENT, gl::TEXTURE_2D, texture, 0);
+        }
+        self.depth_attachment = Some(texture);
+        self.update_draw_buffers();
+        self.unbind();
+    }
+
+    fn update_draw_buffers(&self) {
+        unsafe {
+            if self.color_attachments.is_empty() {
+                gl::DrawBuffer(gl::NONE);
+                gl::ReadBuffer(gl::NONE);
+            } else {
+                let buffers: Vec<u32> = (0..self.color_attachments.len() as u32).map(|i| gl::COLOR_ATTACHMENT0 + i).collect();
+                gl::DrawBuffers(buffers.len() as i32, buffers.as_ptr());
+            }
+        }
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.bind();
+        let status = unsafe { gl::CheckFramebufferStatus(gl::FRAMEBUFFER) };
+        self.unbind();
+        if status != gl::FRAMEBUFFER_COMPLETE {
+            println!("ERROR::FRAMEBUFFER::INCOMPLETE\n{:#x}", status);
+        }
+        status == gl::FRAMEBUFFER_COMPLETE
+    }
+
+    pub fn color_attachment(&self, index: usize) -> Option<u32> {
+        self.color_attachments.get(index).copied()
+    }
+    pub fn depth_attachment(&self) -> Option<u32> {
+        self.depth_attachment
+    }
+    pub fn width(&self) -> i32 {
+        self.width
+    }
+    pub fn height(&self) -> i32 {
+        self.height
+    }
+    pub fn handle(&self) -> u32 {
+        self.id
+    }
+    //Names it and the attachments it owns in debuggers, a framebuffer only exists once it has been bound
+    pub fn label(&self, name: &str) {
+        gl_state::label(gl::FRAMEBUFFER, self.id, name);
+        if self.owns_attachments {
+            for (index, texture) in self.color_attachments.iter().enumerate() {
+                gl_state::label(gl::TEXTURE, *texture, &format!("{} color {}", name, index));
+            }
+            if let Some(texture) = self.depth_attachment {
+                gl_state::label(gl::TEXTURE, texture, &format!("{} depth", name));
+            }
+        }
+    }
+}
+
+impl GPUObject for Framebuffer {
+    fn bind(&self) {
+        unsafe {
+            gl_state::bind_framebuffer(gl::FRAMEBUFFER, self.id);
+        }
+    }
+
+    fn unbind(&self) {
+        unsafe {
+            gl_state::bind_framebuffer(gl::FRAMEBUFFER, 0);
+        }
+    }
+}
+
+impl Drop for Framebuffer {
+    fn drop(&mut self) {
+        unsafe {
+            if self.owns_attachments {
+                gl_state::delete_textures(self.color_attachments.len() as i32, self.color_attachments.as_ptr());
+                if let Some(depth) = self.depth_attachment {
+                    gl_state::delete_textures(1, &depth);
+                }
+            }
+            gl_state::delete_framebuffers(1, &self.id);
+        }
+    }
+}
+
+//Binds a raw attachment texture to a texture unit, leaving TEXTURE0 active afterwards
+pub fn bind_attachment(texture: u32, unit: u32) {
+    unsafe {
+        gl_state::active_texture(gl::TEXTURE0 + unit);
+        gl_state::bind_texture(gl::TEXTURE_2D, texture);
+        gl_state::active_texture(gl::TEXTURE0);
+    }
+}
+
+pub fn unbind_attachment(unit: u32) {
+    bind_attachment(0, unit);
+}
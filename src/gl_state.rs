@@ -0,0 +1,298 @@
+use std::{cell::RefCell, collections::HashMap, ffi::CStr, os::raw::c_void, ptr};
+
+use gl::types::*;
+
+//Last value set through this module for each piece of GL state, None when it is unknown.
+//Every bind and toggle in the engine goes through here, a raw gl call for the same state would leave the cache stale.
+#[derive(Default)]
+struct GlState {
+    program: Option<u32>,
+    vertex_array: Option<u32>,
+    //Buffer target to buffer, the element array binding belongs to the vertex array and is forgotten with it
+    buffers: HashMap<GLenum, u32>,
+    framebuffers: HashMap<GLenum, u32>,
+    active_texture: Option<GLenum>,
+    //Texture unit and target to texture
+    textures: HashMap<(GLenum, GLenum), u32>,
+    capabilities: HashMap<GLenum, bool>,
+    blend_func: Option<(GLenum, GLenum)>,
+    depth_mask: Option<GLboolean>,
+    depth_func: Option<GLenum>,
+}
+
+thread_local! {
+    static STATE: RefCell<GlState> = RefCell::new(GlState::default());
+}
+
+//Runs `apply` only when `value` differs from the cached one
+fn update<T: PartialEq + Copy>(field: impl FnOnce(&mut GlState) -> &mut Option<T>, value: T, apply: impl FnOnce()) {
+    let changed = STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let cached = field(&mut state);
+        let changed = *cached != Some(value);
+        *cached = Some(value);
+        changed
+    });
+    if changed {
+        apply();
+    }
+}
+
+fn update_map<K: Eq + std::hash::Hash, V: PartialEq + Copy>(field: impl FnOnce(&mut GlState) -> &mut HashMap<K, V>, key: K, value: V, apply: impl FnOnce()) {
+    let changed = STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        field(&mut state).insert(key, value) != Some(value)
+    });
+    if changed {
+        apply();
+    }
+}
+
+//Forgets everything, for code outside the engine that touches GL state
+pub fn invalidate() {
+    STATE.with(|state| *state.borrow_mut() = GlState::default());
+}
+
+//The wrappers are unsafe like the calls they replace, a context has to be current
+pub unsafe fn use_program(program: GLuint) {
+    update(|state| &mut state.program, program, || gl::UseProgram(program));
+}
+
+pub unsafe fn bind_vertex_array(array: GLuint) {
+    update(|state| &mut state.vertex_array, array, || {
+        gl::BindVertexArray(array);
+        STATE.with(|state| state.borrow_mut().buffers.remove(&gl::ELEMENT_ARRAY_BUFFER));
+    });
+}
+
+pub unsafe fn bind_buffer(target: GLenum, buffer: GLuint) {
+    update_map(|state| &mut state.buffers, target, buffer, || gl::BindBuffer(target, buffer));
+}
+
+//Binds the indexed binding point, which also replaces the generic binding of the target
+pub unsafe fn bind_buffer_base(target: GLenum, index: GLuint, buffer: GLuint) {
+    gl::BindBufferBase(target, index, buffer);
+    STATE.with(|state| state.borrow_mut().buffers.insert(target, buffer));
+}
+
+//FRAMEBUFFER sets both the draw and the read binding
+pub unsafe fn bind_framebuffer(target: GLenum, framebuffer: GLuint) {
+    if target == gl::FRAMEBUFFER {
+        let changed = STATE.with(|state| {
+            let mut state = state.borrow_mut();
+            let draw = state.framebuffers.insert(gl::DRAW_FRAMEBUFFER, framebuffer);
+            let read = state.framebuffers.insert(gl::READ_FRAMEBUFFER, framebuffer);
+            draw != Some(framebuffer) || read != Some(framebuffer)
+        });
+        if changed {
+            gl::BindFramebuffer(target, framebuffer);
+        }
+        return;
+    }
+    update_map(|state| &mut state.framebuffers, target, framebuffer, || gl::BindFramebuffer(target, framebuffer));
+}
+
+pub unsafe fn active_texture(unit: GLenum) {
+    update(|state| &mut state.active_texture, unit, || gl::ActiveTexture(unit));
+}
+
+//Binds to the active unit, an unknown active unit is made known first
+pub unsafe fn bind_texture(target: GLenum, texture: GLuint) {
+    let unit = STATE.with(|state| state.borrow().active_texture);
+    let unit = match unit {
+        Some(unit) => unit,
+        None => {
+            active_texture(gl::TEXTURE0);
+            gl::TEXTURE0
+        },
+    };
+    update_map(|state| &mut state.textures, (unit, target), texture, || gl::BindTexture(target, texture));
+}
+
+pub unsafe fn enable(capability: GLenum) {
+    update_map(|state| &mut state.capabilities, capability, true, || gl::Enable(capability));
+}
+
+pub unsafe fn disable(capability: GLenum) {
+    update_map(|state| &mut state.capabilities, capability, false, || gl::Disable(capability));
+}
+
+pub unsafe fn blend_func(source: GLenum, destination: GLenum) {
+    update(|state| &mut state.blend_func, (source, destination), || gl::BlendFunc(source, destination));
+}
+
+//Per draw buffer blending, the global function is unknown afterwards
+pub unsafe fn blend_func_indexed(buffer: GLuint, source: GLenum, destination: GLenum) {
+    gl::BlendFunci(buffer, source, destination);
+    STATE.with(|state| state.borrow_mut().blend_func = None);
+}
+
+pub unsafe fn depth_mask(flag: GLboolean) {
+    update(|state| &mut state.depth_mask, flag, || gl::DepthMask(flag));
+}
+
+pub unsafe fn depth_func(func: GLenum) {
+    update(|state| &mut state.depth_func, func, || gl::DepthFunc(func));
+}
+
+//Deleted objects are unbound by GL, so their cached bindings go back to 0
+pub unsafe fn delete_buffers(count: GLsizei, buffers: *const GLuint) {
+    let deleted = std::slice::from_raw_parts(buffers, count as usize);
+    STATE.with(|state| state.borrow_mut().buffers.values_mut().filter(|buffer| deleted.contains(buffer)).for_each(|buffer| *buffer = 0));
+    gl::DeleteBuffers(count, buffers);
+}
+
+pub unsafe fn delete_textures(count: GLsizei, textures: *const GLuint) {
+    let deleted = std::slice::from_raw_parts(textures, count as usize);
+    STATE.with(|state| state.borrow_mut().textures.values_mut().filter(|texture| deleted.contains(texture)).for_each(|texture| *texture = 0));
+    gl::DeleteTextures(count, textures);
+}
+
+pub unsafe fn delete_vertex_arrays(count: GLsizei, arrays: *const GLuint) {
+    let deleted = std::slice::from_raw_parts(arrays, count as usize);
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        if state.vertex_array.is_some_and(|array| deleted.contains(&array)) {
+            state.vertex_array = Some(0);
+            state.buffers.remove(&gl::ELEMENT_ARRAY_BUFFER);
+        }
+    });
+    gl::DeleteVertexArrays(count, arrays);
+}
+
+pub unsafe fn delete_framebuffers(count: GLsizei, framebuffers: *const GLuint) {
+    let deleted = std::slice::from_raw_parts(framebuffers, count as usize);
+    STATE.with(|state| state.borrow_mut().framebuffers.values_mut().filter(|framebuffer| deleted.contains(framebuffer)).for_each(|framebuffer| *framebuffer = 0));
+    gl::DeleteFramebuffers(count, framebuffers);
+}
+
+//A program in use is only deleted once something else is used, the next use_program always goes through
+pub unsafe fn delete_program(program: GLuint) {
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        if state.program == Some(program) {
+            state.program = None;
+        }
+    });
+    gl::DeleteProgram(program);
+}
+
+//Names objects for debuggers like RenderDoc, only in debug builds
+pub fn label(identifier: GLenum, name: GLuint, label: &str) {
+    if cfg!(debug_assertions) {
+        unsafe {
+            gl::ObjectLabel(identifier, name, label.len() as GLsizei, label.as_ptr() as *const GLchar);
+        }
+    }
+}
+
+//Groups the GL calls between push and pop under a name in captures, only in debug builds
+pub fn push_group(name: &str) {
+    if cfg!(debug_assertions) {
+        unsafe {
+            gl::PushDebugGroup(gl::DEBUG_SOURCE_APPLICATION, 0, name.len() as GLsizei, name.as_ptr() as *const GLchar);
+        }
+    }
+}
+
+pub fn pop_group() {
+    if cfg!(debug_assertions) {
+        unsafe {
+            gl::PopDebugGroup();
+        }
+    }
+}
+
+//KHR_debug messages are printed as they happen in debug builds. The context isn't created with the debug
+//flag, so how much drivers report varies, check_errors still catches what they don't.
+pub fn enable_debug_output() {
+    if !cfg!(debug_assertions) {
+        return;
+    }
+    unsafe {
+        enable(gl::DEBUG_OUTPUT);
+        //Messages arrive on the calling thread right after the call that caused them
+        enable(gl::DEBUG_OUTPUT_SYNCHRONOUS);
+        gl::DebugMessageCallback(Some(debug_message), ptr::null());
+        gl::DebugMessageControl(gl::DONT_CARE, gl::DONT_CARE, gl::DEBUG_SEVERITY_NOTIFICATION, 0, ptr::null(), gl::FALSE);
+        //Our own debug groups would be echoed back for every pass
+        gl::DebugMessageControl(gl::DEBUG_SOURCE_APPLICATION, gl::DONT_CARE, gl::DONT_CARE, 0, ptr::null(), gl::FALSE);
+    }
+}
+
+extern "system" fn debug_message(source: GLenum, kind: GLenum, id: GLuint, severity: GLenum, _length: GLsizei, message: *const GLchar, _user: *mut c_void) {
+    let source = match source {
+        gl::DEBUG_SOURCE_API => "API",
+        gl::DEBUG_SOURCE_WINDOW_SYSTEM => "window system",
+        gl::DEBUG_SOURCE_SHADER_COMPILER => "shader compiler",
+        gl::DEBUG_SOURCE_THIRD_PARTY => "third party",
+        gl::DEBUG_SOURCE_APPLICATION => "application",
+        _ => "other",
+    };
+    let kind = match kind {
+        gl::DEBUG_TYPE_ERROR => "error",
+        gl::DEBUG_TYPE_DEPRECATED_BEHAVIOR => "deprecated behavior",
+        gl::DEBUG_TYPE_UNDEFINED_BEHAVIOR => "undefined behavior",
+        gl::DEBUG_TYPE_PORTABILITY => "portability",
+        gl::DEBUG_TYPE_PERFORMANCE => "performance",
+        gl::DEBUG_TYPE_MARKER => "marker",
+        _ => "other",
+    };
+    let severity = match severity {
+        gl::DEBUG_SEVERITY_HIGH => "high",
+        gl::DEBUG_SEVERITY_MEDIUM => "medium",
+        gl::DEBUG_SEVERITY_LOW => "low",
+        _ => "notification",
+    };
+    let message = if message.is_null() { "".into() } else { unsafe { CStr::from_ptr(message) }.to_string_lossy() };
+    println!("GL {} {} ({} severity, {}): {}", source, kind, severity, id, message);
+}
+
+//Reports errors raised since the last check in debug builds, `context` says where it was checked
+pub fn check_errors(context: &str) {
+    if !cfg!(debug_assertions) {
+        return;
+    }
+    //A lost context keeps returning its error, so this gives up after a few
+    for _ in 0..16 {
+        let error = unsafe { gl::GetError() };
+        if error == gl::NO_ERROR {
+            break;
+        }
+        let name = match error {
+            gl::INVALID_ENUM => "GL_INVALID_ENUM",
+            gl::INVALID_VALUE => "GL_INVALID_VALUE",
+            gl::INVALID_OPERATION => "GL_INVALID_OPERATION",
+            gl::INVALID_FRAMEBUFFER_OPERATION => "GL_INVALID_FRAMEBUFFER_OPERATION",
+            gl::OUT_OF_MEMORY => "GL_OUT_OF_MEMORY",
+            gl::STACK_OVERFLOW => "GL_STACK_OVERFLOW",
+            gl::STACK_UNDERFLOW => "GL_STACK_UNDERFLOW",
+            _ => "unknown error",
+        };
+        println!("GL error {} (0x{:x}) during {}", name, error, context);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn skips_redundant_changes_until_invalidated() {
+        let mut applied = 0;
+        let mut set = |program: u32| update(|state| &mut state.program, program, || applied += 1);
+        set(3);
+        set(3);
+        set(4);
+        invalidate();
+        set(4);
+        assert_eq!(applied, 3);
+
+        let mut applied = 0;
+        for enabled in [true, true, false] {
+            update_map(|state| &mut state.capabilities, gl::BLEND, enabled, || applied += 1);
+        }
+        assert_eq!(applied, 2);
+    }
+}
+
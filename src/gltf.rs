@@ -0,0 +1,458 @@
+use std::{collections::HashMap, error::Error, fs, path::Path, sync::Arc};
+
+use glam::*;
+use simple_error::SimpleError;
+
+use crate::{animation::{AnimationClip, Channel, ChannelTarget, Interpolation, Joint, Skeleton, Transform, WeightsChannel}, json::Json, lod::{LodStep, MeshLodData}, mesh::{self, MeshData}, mesh_builder::MeshBuilder, morph::{MorphTarget, MorphWeights}};
+
+const GLB_MAGIC: u32 = 0x4654_6C67;
+const GLB_JSON_CHUNK: u32 = 0x4E4F_534A;
+const GLB_BIN_CHUNK: u32 = 0x004E_4942;
+
+//Meshes, skins and animations of a glTF 2.0 file
+pub struct Model {
+    pub meshes: Vec<ModelMesh>,
+    pub skeletons: Vec<Arc<Skeleton>>,
+    pub clips: Vec<Arc<AnimationClip>>,
+}
+
+//One triangle primitive. Skinned ones stay in bind pose space for their skeleton to move, the rest have their
+//node's transform applied so they can be placed with a single Position.
+pub struct ModelMesh {
+    pub name: String,
+    pub data: MeshData,
+    pub skin: Option<usize>,
+    //The file's default weights for meshes with morph targets
+    pub morph: Option<MorphWeights>,
+    //Simplified levels, see Model::generate_lods
+    pub lods: Option<MeshLodData>,
+}
+
+impl Model {
+    //resources/models/{name}.glb, or {name}.gltf with its buffers embedded or next to it
+    pub fn load(name: &str) -> Result<Model, Box<dyn Error>> {
+        let binary = format!("resources/models/{}.glb", name);
+        let document = if Path::new(&binary).exists() {
+            Document::from_glb(&fs::read(&binary)?, Path::new("resources/models"))?
+        } else {
+            let text = fs::read_to_string(format!("resources/models/{}.gltf", name))?;
+            Document::new(Json::parse(&text)?, None, Path::new("resources/models"))?
+        };
+        document.model(name)
+    }
+    //LOD chains for the meshes that can be simplified, morph targets don't survive simplification
+    pub fn generate_lods(&mut self, chain: &[LodStep]) {
+        for mesh in self.meshes.iter_mut().filter(|mesh| mesh.data.morph_targets.is_empty()) {
+            mesh.lods = Some(MeshLodData::generate(&mesh.data, chain));
+        }
+    }
+    pub fn clip(&self, name: &str) -> Option<Arc<AnimationClip>> {
+        self.clips.iter().find(|clip| clip.name == name).cloned()
+    }
+}
+
+struct Document {
+    json: Json,
+    buffers: Vec<Vec<u8>>,
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Result<u32, Box<dyn Error>> {
+    let bytes = bytes.get(offset..offset + 4).ok_or_else(|| SimpleError::new("Truncated glb"))?;
+    Ok(u32::from_le_bytes(bytes.try_into()?))
+}
+
+fn decode_base64(text: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut bytes = Vec::with_capacity(text.len() * 3 / 4);
+    let (mut bits, mut count) = (0u32, 0);
+    for character in text.bytes().filter(|character| !character.is_ascii_whitespace() && *character != b'=') {
+        let value = match character {
+            b'A'..=b'Z' => character - b'A',
+            b'a'..=b'z' => character - b'a' + 26,
+            b'0'..=b'9' => character - b'0' + 52,
+            b'+' | b'-' => 62,
+            b'/' | b'_' => 63,
+            _ => return Err(SimpleError::new("Invalid base64 data").into()),
+        };
+        bits = bits << 6 | value as u32;
+        count += 6;
+        if count >= 8 {
+            count -= 8;
+            bytes.push((bits >> count) as u8);
+        }
+    }
+    Ok(bytes)
+}
+
+impl Document {
+    //`binary` is the BIN chunk of a .glb, which a buffer without uri refers to
+    fn new(json: Json, mut binary: Option<Vec<u8>>, directory: &Path) -> Result<Document, Box<dyn Error>> {
+        let version = json["asset"]["version"].as_str().unwrap_or("");
+        if !version.starts_with('2') {
+            return Err(SimpleError::new(format!("Unsupported glTF version '{}'", version)).into());
+        }
+        let mut buffers = Vec::new();
+        for buffer in json["buffers"].as_array() {
+            let data = match buffer["uri"].as_str() {
+                Some(uri) if uri.starts_with("data:") => {
+                    let (_, data) = uri.split_once(";base64,").ok_or_else(|| SimpleError::new("Only base64 data URIs are supported"))?;
+                    decode_base64(data)?
+                }
+                Some(uri) => fs::read(directory.join(uri))?,
+                None => binary.take().ok_or_else(|| SimpleError::new("Buffer without uri or binary chunk"))?,
+            };
+            let length = buffer["byteLength"].as_usize().unwrap_or(data.len());
+            if data.len() < length {
+                return Err(SimpleError::new("Buffer shorter than its byteLength").into());
+            }
+            buffers.push(data);
+        }
+        Ok(Document { json, buffers })
+    }
+
+    fn from_glb(bytes: &[u8], directory: &Path) -> Result<Document, Box<dyn Error>> {
+        if read_u32(bytes, 0)? != GLB_MAGIC || read_u32(bytes, 4)? != 2 {
+            return Err(SimpleError::new("Not a glTF 2.0 binary").into());
+        }
+        let length = (read_u32(bytes, 8)? as usize).min(bytes.len());
+        let (mut json, mut binary) = (None, None);
+        let mut offset = 12;
+        while offset + 8 <= length {
+            let chunk_length = read_u32(bytes, offset)? as usize;
+            let kind = read_u32(bytes, offset + 4)?;
+            let chunk = bytes.get(offset + 8..offset + 8 + chunk_length).ok_or_else(|| SimpleError::new("Truncated glb chunk"))?;
+            match kind {
+                GLB_JSON_CHUNK => json = Some(Json::parse(std::str::from_utf8(chunk)?)?),
+                GLB_BIN_CHUNK => binary = Some(chunk.to_vec()),
+                _ => {}
+            }
+            //Chunks are padded to four bytes
+            offset += 8 + chunk_length.div_ceil(4) * 4;
+        }
+        let json = json.ok_or_else(|| SimpleError::new("glb without a JSON chunk"))?;
+        Document::new(json, binary, directory)
+    }
+
+    //Elements of an accessor flattened, with the number of components per element. Normalized integers are mapped
+    //to 0..1 or -1..1, others keep their value which f64 holds exactly. Sparse accessors are not supported.
+    fn accessor(&self, index: usize) -> Result<(usize, Vec<f64>), Box<dyn Error>> {
+        let accessor = &self.json["accessors"][index];
+        let count = accessor["count"].as_usize().ok_or_else(|| SimpleError::new(format!("Accessor {} has no count", index)))?;
+        let components = match accessor["type"].as_str() {
+            Some("SCALAR") => 1,
+            Some("VEC2") => 2,
+            Some("VEC3") => 3,
+            Some("VEC4") | Some("MAT2") => 4,
+            Some("MAT3") => 9,
+            Some("MAT4") => 16,
+            _ => return Err(SimpleError::new(format!("Accessor {} has an unknown type", index)).into()),
+        };
+        let component_type = accessor["componentType"].as_usize().unwrap_or(0);
+        let normalized = accessor["normalized"].as_bool().unwrap_or(false);
+        let size = match component_type {
+            5120 | 5121 => 1,
+            5122 | 5123 => 2,
+            5125 | 5126 => 4,
+            _ => return Err(SimpleError::new(format!("Accessor {} has an unknown component type", index)).into()),
+        };
+        if !accessor["sparse"].is_null() {
+            return Err(SimpleError::new(format!("Accessor {} is sparse", index)).into());
+        }
+        //Accessors without a buffer view are all zeros
+        let view = match accessor["bufferView"].as_usize() {
+            Some(view) => &self.json["bufferViews"][view],
+            None => return Ok((components, vec![0.0; count * components])),
+        };
+        let buffer = view["buffer"].as_usize().and_then(|buffer| self.buffers.get(buffer)).ok_or_else(|| SimpleError::new("Buffer view without a buffer"))?;
+        let start = view["byteOffset"].as_usize().unwrap_or(0) + accessor["byteOffset"].as_usize().unwrap_or(0);
+        let stride = view["byteStride"].as_usize().unwrap_or(components * size);
+
+        let mut values = Vec::with_capacity(count * components);
+        for element in 0..count {
+            let offset = start + element * stride;
+            let bytes = buffer.get(offset..offset + components * size).ok_or_else(|| SimpleError::new(format!("Accessor {} reads past its buffer", index)))?;
+            for component in bytes.chunks_exact(size) {
+                let value = match component_type {
+                    5120 if normalized => (component[0] as i8 as f64 / 127.0).max(-1.0),
+                    5120 => component[0] as i8 as f64,
+                    5121 if normalized => component[0] as f64 / 255.0,
+                    5121 => component[0] as f64,
+                    5122 if normalized => (i16::from_le_bytes([component[0], component[1]]) as f64 / 32767.0).max(-1.0),
+                    5122 => i16::from_le_bytes([component[0], component[1]]) as f64,
+                    5123 if normalized => u16::from_le_bytes([component[0], component[1]]) as f64 / 65535.0,
+                    5123 => u16::from_le_bytes([component[0], component[1]]) as f64,
+                    5125 => u32::from_le_bytes(component.try_into()?) as f64,
+                    _ => f32::from_le_bytes(component.try_into()?) as f64,
+                };
+                values.push(value);
+            }
+        }
+        Ok((components, values))
+    }
+
+    fn floats(&self, index: usize) -> Result<(usize, Vec<f32>), Box<dyn Error>> {
+        let (components, values) = self.accessor(index)?;
+        Ok((components, values.into_iter().map(|value| value as f32).collect()))
+    }
+
+    fn local_transform(node: &Json) -> Transform {
+        if let Some(matrix) = node["matrix"].as_floats().filter(|matrix| matrix.len() == 16) {
+            return Transform::from_matrix(Mat4::from_cols_slice(&matrix));
+        }
+        let vector = |key: &str, default: Vec3| node[key].as_floats().filter(|v| v.len() == 3).map_or(default, |v| Vec3::from_slice(&v));
+        let rotation = node["rotation"].as_floats().filter(|v| v.len() == 4).map_or(Quat::IDENTITY, |v| Quat::from_slice(&v).normalize());
+        Transform { translation: vector("translation", Vec3::ZERO), rotation, scale: vector("scale", Vec3::ONE) }
+    }
+
+    fn model(&self, name: &str) -> Result<Model, Box<dyn Error>> {
+        let nodes = self.json["nodes"].as_array();
+        let locals: Vec<Transform> = nodes.iter().map(Document::local_transform).collect();
+        let mut parents = vec![None; nodes.len()];
+        for (index, node) in nodes.iter().enumerate() {
+            for child in node["children"].as_array().iter().filter_map(|child| child.as_usize()) {
+                if let Some(parent) = parents.get_mut(child) {
+                    *parent = Some(index);
+                }
+            }
+        }
+        let global = |mut node: usize| {
+            let mut matrix = locals[node].matrix();
+            while let Some(parent) = parents[node] {
+                matrix = locals[parent].matrix() * matrix;
+                node = parent;
+            }
+            matrix
+        };
+
+        let mut skeletons = Vec::new();
+        for skin in self.json["skins"].as_array() {
+            let joint_nodes: Vec<usize> = skin["joints"].as_array().iter().filter_map(|joint| joint.as_usize()).collect();
+            let inverse_bind = match skin["inverseBindMatrices"].as_usize() {
+                Some(accessor) => self.floats(accessor)?.1.chunks_exact(16).map(Mat4::from_cols_slice).collect(),
+                None => vec![Mat4::IDENTITY; joint_nodes.len()],
+            };
+            if inverse_bind.len() != joint_nodes.len() {
+                return Err(SimpleError::new("Skin has a different number of joints and inverse bind matrices").into());
+            }
+            //Nodes between two joints that aren't joints themselves are skipped
+            let parent_joint = |node: usize| {
+                let mut parent = parents[node];
+                while let Some(candidate) = parent {
+                    if let Some(joint) = joint_nodes.iter().position(|joint| *joint == candidate) {
+                        return Some(joint);
+                    }
+                    parent = parents[candidate];
+                }
+                None
+            };
+            let joints: Vec<Joint> = joint_nodes.iter().map(|&node| Joint {
+                name: nodes[node]["name"].as_str().map_or_else(|| format!("joint {}", node), str::to_string),
+                node,
+                parent: parent_joint(node),
+                rest: locals[node],
+            }).collect();
+            let root = joints.iter().find(|joint| joint.parent.is_none())
+                .and_then(|joint| parents[joint.node]).map_or(Mat4::IDENTITY, global);
+            skeletons.push(Arc::new(Skeleton::new(joints, inverse_bind, root)));
+        }
+
+        let mut meshes = Vec::new();
+        for (index, node) in nodes.iter().enumerate() {
+            let mesh = match node["mesh"].as_usize() {
+                Some(mesh) => &self.json["meshes"][mesh],
+                None => continue,
+            };
+            let skin = node["skin"].as_usize().filter(|skin| *skin < skeletons.len());
+            let node_name = node["name"].as_str().or(mesh["name"].as_str()).unwrap_or(name);
+            for (primitive_index, primitive) in mesh["primitives"].as_array().iter().enumerate() {
+                if primitive["mode"].as_usize().unwrap_or(4) != 4 {
+                    println!("Skipping primitive {} of {} in model {}, only triangles are supported", primitive_index, node_name, name);
+                    continue;
+                }
+                let data = self.primitive(primitive, name)?;
+                let data = match skin {
+                    Some(_) => data,
+                    None => {
+                        let transform = global(index);
+                        let mut transformed = MeshBuilder::from_data(&data).transform(transform).build(&data.material);
+                        transformed.morph_targets = data.morph_targets.iter().map(|target| target.transformed(transform)).collect();
+                        transformed
+                    }
+                };
+                //Nodes can override their mesh's weights
+                let morph = (!data.morph_targets.is_empty()).then(|| {
+                    let weights = node["weights"].as_floats().filter(|weights| !weights.is_empty()).or_else(|| mesh["weights"].as_floats())
+                        .filter(|weights| weights.len() == data.morph_targets.len())
+                        .unwrap_or_else(|| vec![0.0; data.morph_targets.len()]);
+                    let names = mesh["extras"]["targetNames"].as_array().iter().filter_map(|name| name.as_str().map(str::to_string)).collect();
+                    MorphWeights::new(weights).with_names(names).with_node(index)
+                });
+                meshes.push(ModelMesh { name: node_name.to_string(), data, skin, morph, lods: None });
+            }
+        }
+
+        let mut clips = Vec::new();
+        for (index, animation) in self.json["animations"].as_array().iter().enumerate() {
+            clips.push(Arc::new(self.animation(animation, index)?));
+        }
+
+        Ok(Model { meshes, skeletons, clips })
+    }
+
+    //Materials are looked up by the glTF material's name, falling back to the model's
+    fn primitive(&self, primitive: &Json, name: &str) -> Result<MeshData, Box<dyn Error>> {
+        let attributes = &primitive["attributes"];
+        let position = attributes["POSITION"].as_usize().ok_or_else(|| SimpleError::new("Primitive without positions"))?;
+        let (_, positions) = self.floats(position)?;
+        let vertex_count = positions.len() / 3;
+        let indices = match primitive["indices"].as_usize() {
+            Some(indices) => self.accessor(indices)?.1.into_iter().map(|index| index as i32).collect(),
+            None => (0..vertex_count as i32).collect(),
+        };
+        let material = primitive["material"].as_usize()
+            .and_then(|material| self.json["materials"][material]["name"].as_str())
+            .unwrap_or(name);
+        let mut data = MeshData::new(indices, material).with_buffer(positions, mesh::POSITION_LOCATION, 3);
+
+        if let Some(normals) = attributes["NORMAL"].as_usize() {
+            data.set_buffer(self.floats(normals)?.1, mesh::NORMAL_LOCATION, 3);
+        }
+        //glTF puts the texture origin at the top left
+        if let Some(uvs) = attributes["TEXCOORD_0"].as_usize() {
+            let (_, mut uvs) = self.floats(uvs)?;
+            uvs.iter_mut().skip(1).step_by(2).for_each(|v| *v = 1.0 - *v);
+            data.set_buffer(uvs, mesh::TEXCOORD_LOCATION, 2);
+        }
+        if let Some(colors) = attributes["COLOR_0"].as_usize() {
+            let (components, colors) = self.floats(colors)?;
+            data.set_buffer(colors.chunks_exact(components).flat_map(|color| color[..3].to_vec()).collect(), mesh::COLOR_LOCATION, 3);
+        }
+        if let Some(tangents) = attributes["TANGENT"].as_usize() {
+            data.set_buffer(self.floats(tangents)?.1, mesh::TANGENT_LOCATION, 4);
+        }
+        if let (Some(joints), Some(weights)) = (attributes["JOINTS_0"].as_usize(), attributes["WEIGHTS_0"].as_usize()) {
+            let (_, mut weights) = self.floats(weights)?;
+            //Exporters don't always make the weights add up to one
+            for weights in weights.chunks_exact_mut(4) {
+                let total: f32 = weights.iter().sum();
+                if total > 0.0 {
+                    weights.iter_mut().for_each(|weight| *weight /= total);
+                }
+            }
+            data.set_buffer(self.floats(joints)?.1, mesh::JOINTS_LOCATION, 4);
+            data.set_buffer(weights, mesh::WEIGHTS_LOCATION, 4);
+        }
+
+        if data.buffer(mesh::NORMAL_LOCATION).is_none() {
+            data = MeshBuilder::from_data(&data).compute_smooth_normals().build(&data.material);
+        }
+        for target in primitive["targets"].as_array() {
+            let offsets = |attribute: &str| -> Result<Vec<f32>, Box<dyn Error>> {
+                match target[attribute].as_usize() {
+                    Some(accessor) => Ok(self.floats(accessor)?.1),
+                    None => Ok(Vec::new()),
+                }
+            };
+            let positions = offsets("POSITION")?;
+            let normals = offsets("NORMAL")?;
+            if (!positions.is_empty() && positions.len() != vertex_count * 3) || (!normals.is_empty() && normals.len() != vertex_count * 3) {
+                return Err(SimpleError::new("Morph target with a different number of vertices than its primitive").into());
+            }
+            data.morph_targets.push(MorphTarget { positions, normals });
+        }
+        Ok(data)
+    }
+
+    fn animation(&self, animation: &Json, index: usize) -> Result<AnimationClip, Box<dyn Error>> {
+        let name = animation["name"].as_str().map_or_else(|| format!("animation {}", index), str::to_string);
+        let samplers = animation["samplers"].as_array();
+        let mut channels = Vec::new();
+        let mut weights = Vec::new();
+        let mut duration: f32 = 0.0;
+        //Accessors are often shared between channels
+        let mut times_cache: HashMap<usize, Vec<f32>> = HashMap::new();
+        for channel in animation["channels"].as_array() {
+            //None for morph target weights
+            let target = match channel["target"]["path"].as_str() {
+                Some("translation") => Some(ChannelTarget::Translation),
+                Some("rotation") => Some(ChannelTarget::Rotation),
+                Some("scale") => Some(ChannelTarget::Scale),
+                Some("weights") => None,
+                _ => continue,
+            };
+            let node = match channel["target"]["node"].as_usize() {
+                Some(node) => node,
+                None => continue,
+            };
+            let sampler = channel["sampler"].as_usize().and_then(|sampler| samplers.get(sampler))
+                .ok_or_else(|| SimpleError::new(format!("Channel of {} without a sampler", name)))?;
+            let interpolation = match sampler["interpolation"].as_str() {
+                Some("STEP") => Interpolation::Step,
+                Some("CUBICSPLINE") => Interpolation::CubicSpline,
+                _ => Interpolation::Linear,
+            };
+            let input = sampler["input"].as_usize().ok_or_else(|| SimpleError::new("Sampler without input"))?;
+            let output = sampler["output"].as_usize().ok_or_else(|| SimpleError::new("Sampler without output"))?;
+            let times = match times_cache.get(&input) {
+                Some(times) => times.clone(),
+                None => {
+                    let times = self.floats(input)?.1;
+                    times_cache.insert(input, times.clone());
+                    times
+                }
+            };
+            let (components, values) = self.floats(output)?;
+            duration = duration.max(times.last().copied().unwrap_or(0.0));
+            let target = match target {
+                Some(target) => target,
+                None => {
+                    let keyframe_values = if interpolation == Interpolation::CubicSpline { times.len() * 3 } else { times.len() };
+                    if keyframe_values == 0 || values.len() % keyframe_values != 0 {
+                        return Err(SimpleError::new(format!("Weights channel of {} has {} values for {} keyframes", name, values.len(), times.len())).into());
+                    }
+                    weights.push(WeightsChannel { node, interpolation, times, values });
+                    continue;
+                }
+            };
+            let values: Vec<Vec4> = values.chunks_exact(components).map(|value| match value {
+                [x, y, z] => Vec4::new(*x, *y, *z, 0.0),
+                [x, y, z, w] => Vec4::new(*x, *y, *z, *w),
+                _ => Vec4::ZERO,
+            }).collect();
+            let expected = if interpolation == Interpolation::CubicSpline { times.len() * 3 } else { times.len() };
+            if values.len() != expected {
+                return Err(SimpleError::new(format!("Channel of {} has {} values for {} keyframes", name, values.len(), times.len())).into());
+            }
+            channels.push(Channel { node, target, interpolation, times, values });
+        }
+        Ok(AnimationClip { name, duration, channels, weights })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lod::LodSettings;
+
+    #[test]
+    fn generates_lods_for_imported_meshes() {
+        let mut model = Model::load("tentacle").unwrap();
+        let chain = LodSettings::default().chain;
+        model.generate_lods(&chain);
+        for mesh in &model.meshes {
+            let levels = &mesh.lods.as_ref().unwrap().levels;
+            assert_eq!(levels.len(), chain.len());
+            assert!(levels[0].0 == mesh.data);
+            let triangles: Vec<usize> = levels.iter().map(|(level, _)| level.indices.len() / 3).collect();
+            assert!(triangles.windows(2).all(|pair| pair[1] < pair[0]), "{} levels have {:?} triangles", mesh.name, triangles);
+            //Skinning attributes are carried over to every level
+            assert!(levels.iter().all(|(level, _)| level.buffer(mesh::JOINTS_LOCATION).is_some()));
+        }
+    }
+
+    #[test]
+    fn leaves_morphed_meshes_whole() {
+        let mut model = Model::load("blob").unwrap();
+        model.generate_lods(&LodSettings::default().chain);
+        assert!(model.meshes.iter().all(|mesh| mesh.morph.is_some() && mesh.lods.is_none()));
+    }
+}
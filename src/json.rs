@@ -0,0 +1,320 @@
+use std::{collections::HashMap, error::Error, ops::Index};
+
+use simple_error::SimpleError;
+
+//Just enough JSON to read glTF, numbers are kept as f64 which holds every 32 bit integer exactly
+#[derive(Clone, Debug, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(HashMap<String, Json>),
+}
+
+static NULL: Json = Json::Null;
+
+//Arrays and objects nested deeper than this are rejected instead of overflowing the stack
+const MAX_DEPTH: usize = 128;
+
+impl Json {
+    pub fn parse(text: &str) -> Result<Json, Box<dyn Error>> {
+        let mut parser = Parser { bytes: text.as_bytes(), position: 0, depth: 0 };
+        let value = parser.value()?;
+        parser.whitespace();
+        if parser.position != parser.bytes.len() {
+            return Err(parser.error("trailing characters"));
+        }
+        Ok(value)
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Json::Number(number) => Some(*number),
+            _ => None,
+        }
+    }
+    pub fn as_f32(&self) -> Option<f32> {
+        self.as_f64().map(|number| number as f32)
+    }
+    pub fn as_usize(&self) -> Option<usize> {
+        self.as_f64().filter(|number| *number >= 0.0 && number.fract() == 0.0).map(|number| number as usize)
+    }
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Json::Bool(value) => Some(*value),
+            _ => None,
+        }
+    }
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(string) => Some(string),
+            _ => None,
+        }
+    }
+    //Anything but an array reads as an empty one, so optional lists can be iterated directly
+    pub fn as_array(&self) -> &[Json] {
+        match self {
+            Json::Array(values) => values,
+            _ => &[],
+        }
+    }
+    pub fn is_null(&self) -> bool {
+        *self == Json::Null
+    }
+    //Numbers of an array, None if any element isn't one
+    pub fn as_floats(&self) -> Option<Vec<f32>> {
+        self.as_array().iter().map(|value| value.as_f32()).collect()
+    }
+}
+
+//Missing keys and indices give Null, so lookups can be chained like json["nodes"][0]["name"]
+impl Index<&str> for Json {
+    type Output = Json;
+
+    fn index(&self, key: &str) -> &Json {
+        match self {
+            Json::Object(members) => members.get(key).unwrap_or(&NULL),
+            _ => &NULL,
+        }
+    }
+}
+
+impl Index<usize> for Json {
+    type Output = Json;
+
+    fn index(&self, index: usize) -> &Json {
+        self.as_array().get(index).unwrap_or(&NULL)
+    }
+}
+
+struct Parser<'a> {
+    bytes: &'a [u8],
+    position: usize,
+    depth: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn error(&self, message: &str) -> Box<dyn Error> {
+        SimpleError::new(format!("Invalid JSON at byte {}: {}", self.position, message)).into()
+    }
+
+    fn whitespace(&mut self) {
+        while self.bytes.get(self.position).is_some_and(|byte| byte.is_ascii_whitespace()) {
+            self.position += 1;
+        }
+    }
+
+    fn peek(&mut self) -> Option<u8> {
+        self.whitespace();
+        self.bytes.get(self.position).copied()
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<(), Box<dyn Error>> {
+        if self.peek() != Some(byte) {
+            return Err(self.error(&format!("expected '{}'", byte as char)));
+        }
+        self.position += 1;
+        Ok(())
+    }
+
+    fn literal(&mut self, word: &str, value: Json) -> Result<Json, Box<dyn Error>> {
+        if !self.bytes[self.position..].starts_with(word.as_bytes()) {
+            return Err(self.error(&format!("expected {}", word)));
+        }
+        self.position += word.len();
+        Ok(value)
+    }
+
+    fn value(&mut self) -> Result<Json, Box<dyn Error>> {
+        match self.peek() {
+            Some(b'{') => self.nested(Self::object),
+            Some(b'[') => self.nested(Self::array),
+            Some(b'"') => Ok(Json::String(self.string()?)),
+            Some(b't') => self.literal("true", Json::Bool(true)),
+            Some(b'f') => self.literal("false", Json::Bool(false)),
+            Some(b'n') => self.literal("null", Json::Null),
+            Some(byte) if byte == b'-' || byte.is_ascii_digit() => self.number(),
+            _ => Err(self.error("expected a value")),
+        }
+    }
+
+    fn nested(&mut self, parse: fn(&mut Self) -> Result<Json, Box<dyn Error>>) -> Result<Json, Box<dyn Error>> {
+        if self.depth == MAX_DEPTH {
+            return Err(self.error("nested too deeply"));
+        }
+        self.depth += 1;
+        let value = parse(self);
+        self.depth -= 1;
+        value
+    }
+
+    fn object(&mut self) -> Result<Json, Box<dyn Error>> {
+        self.expect(b'{')?;
+        let mut members = HashMap::new();
+        if self.peek() == Some(b'}') {
+            self.position += 1;
+            return Ok(Json::Object(members));
+        }
+        loop {
+            if self.peek() != Some(b'"') {
+                return Err(self.error("expected a key"));
+            }
+            let key = self.string()?;
+            self.expect(b':')?;
+            members.insert(key, self.value()?);
+            match self.peek() {
+                Some(b',') => self.position += 1,
+                Some(b'}') => {
+                    self.position += 1;
+                    return Ok(Json::Object(members));
+                }
+                _ => return Err(self.error("expected ',' or '}'")),
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Json, Box<dyn Error>> {
+        self.expect(b'[')?;
+        let mut values = Vec::new();
+        if self.peek() == Some(b']') {
+            self.position += 1;
+            return Ok(Json::Array(values));
+        }
+        loop {
+            values.push(self.value()?);
+            match self.peek() {
+                Some(b',') => self.position += 1,
+                Some(b']') => {
+                    self.position += 1;
+                    return Ok(Json::Array(values));
+                }
+                _ => return Err(self.error("expected ',' or ']'")),
+            }
+        }
+    }
+
+    fn number(&mut self) -> Result<Json, Box<dyn Error>> {
+        let start = self.position;
+        while self.bytes.get(self.position).is_some_and(|byte| byte.is_ascii_digit() || b"+-.eE".contains(byte)) {
+            self.position += 1;
+        }
+        let text = std::str::from_utf8(&self.bytes[start..self.position])?;
+        text.parse().map(Json::Number).map_err(|_| self.error("malformed number"))
+    }
+
+    fn string(&mut self) -> Result<String, Box<dyn Error>> {
+        self.expect(b'"')?;
+        let mut string = String::new();
+        loop {
+            //Runs without escapes are copied in one go, they are valid UTF-8 because the input is a str
+            let start = self.position;
+            while self.bytes.get(self.position).is_some_and(|byte| *byte != b'"' && *byte != b'\\') {
+                self.position += 1;
+            }
+            string.push_str(std::str::from_utf8(&self.bytes[start..self.position])?);
+            match self.bytes.get(self.position) {
+                Some(b'"') => {
+                    self.position += 1;
+                    return Ok(string);
+                }
+                Some(b'\\') => {
+                    let escape = *self.bytes.get(self.position + 1).ok_or_else(|| self.error("unfinished escape"))?;
+                    self.position += 2;
+                    match escape {
+                        b'"' => string.push('"'),
+                        b'\\' => string.push('\\'),
+                        b'/' => string.push('/'),
+                        b'b' => string.push('\u{8}'),
+                        b'f' => string.push('\u{c}'),
+                        b'n' => string.push('\n'),
+                        b'r' => string.push('\r'),
+                        b't' => string.push('\t'),
+                        b'u' => string.push(self.unicode_escape()?),
+                        _ => return Err(self.error("unknown escape")),
+                    }
+                }
+                _ => return Err(self.error("unterminated string")),
+            }
+        }
+    }
+
+    //\uXXXX after the \u, joining surrogate pairs
+    fn unicode_escape(&mut self) -> Result<char, Box<dyn Error>> {
+        let first = self.hex4()?;
+        let code = if (0xD800..0xDC00).contains(&first) && self.bytes[self.position..].starts_with(b"\\u") {
+            self.position += 2;
+            let second = self.hex4()?;
+            0x10000 + ((first - 0xD800) << 10) + (second.wrapping_sub(0xDC00) & 0x3FF)
+        } else {
+            first
+        };
+        Ok(char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER))
+    }
+
+    fn hex4(&mut self) -> Result<u32, Box<dyn Error>> {
+        let digits = self.bytes.get(self.position..self.position + 4).ok_or_else(|| self.error("short unicode escape"))?;
+        let value = u32::from_str_radix(std::str::from_utf8(digits)?, 16).map_err(|_| self.error("malformed unicode escape"))?;
+        self.position += 4;
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_values() {
+        let json = Json::parse(r#" {"name": "cube", "count": 24, "scale": [1.5, -2e1, 0], "skinned": false, "skin": null} "#).unwrap();
+        assert_eq!(json["name"].as_str(), Some("cube"));
+        assert_eq!(json["count"].as_usize(), Some(24));
+        assert_eq!(json["scale"].as_floats(), Some(vec![1.5, -20.0, 0.0]));
+        assert_eq!(json["skinned"].as_bool(), Some(false));
+        assert!(json["skin"].is_null());
+    }
+
+    #[test]
+    fn missing_keys_and_indices_are_null() {
+        let json = Json::parse(r#"{"nodes": [{"name": "root"}]}"#).unwrap();
+        assert!(json["meshes"][0]["name"].is_null());
+        assert!(json["nodes"][1].is_null());
+        assert!(json["nodes"][0]["name"][0].is_null());
+        assert!(json["meshes"].as_array().is_empty());
+        assert_eq!(json["nodes"][0]["name"].as_usize(), None);
+    }
+
+    #[test]
+    fn only_whole_numbers_are_indices() {
+        let json = Json::parse("[3, 3.5, -1, 1e2]").unwrap();
+        assert_eq!(json[0].as_usize(), Some(3));
+        assert_eq!(json[1].as_usize(), None);
+        assert_eq!(json[2].as_usize(), None);
+        assert_eq!(json[3].as_usize(), Some(100));
+        assert_eq!(Json::parse("[1, \"2\"]").unwrap().as_floats(), None);
+    }
+
+    #[test]
+    fn unescapes_strings() {
+        let json = Json::parse(r#"["a\"b\\c\/d\n", "\u00e9\ud83d\ude00"]"#).unwrap();
+        assert_eq!(json[0].as_str(), Some("a\"b\\c/d\n"));
+        assert_eq!(json[1].as_str(), Some("\u{e9}\u{1F600}"));
+    }
+
+    #[test]
+    fn rejects_malformed_documents() {
+        for text in ["", "{", "[1,]", "{\"a\" 1}", "{\"a\": 1,}", "[1] 2", "\"open", "\"\\x\"", "\"\\u12\"", "tru", "-", "{1: 2}"] {
+            assert!(Json::parse(text).is_err(), "{:?} parsed", text);
+        }
+    }
+
+    #[test]
+    fn limits_nesting() {
+        let nested = |depth: usize| "[".repeat(depth) + &"]".repeat(depth);
+        assert!(Json::parse(&nested(MAX_DEPTH)).is_ok());
+        assert!(Json::parse(&nested(MAX_DEPTH + 1)).is_err());
+        assert!(Json::parse(&"{\"a\":".repeat(100_000)).is_err());
+        assert!(Json::parse(&"[".repeat(1_000_000)).is_err());
+    }
+}
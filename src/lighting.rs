@@ -0,0 +1,81 @@
+use bevy_ecs::prelude::*;
+use glam::*;
+
+use crate::{components::*, environment::{self, Skybox}, shader::Shader, shadow::ShadowAtlas};
+
+//Must match the array sizes in the lit shaders
+pub const MAX_DIRECTIONAL_LIGHTS: usize = 4;
+pub const MAX_POINT_LIGHTS: usize = 16;
+pub const MAX_SPOT_LIGHTS: usize = 16;
+
+//Static lights are flagged as baked, lightmapped meshes already contain their contribution
+pub fn upload_directional_lights(shader: &Shader, lights: &[(&DirectionalLight, bool)]) {
+    let count = lights.len().min(MAX_DIRECTIONAL_LIGHTS);
+    shader.set_uniform_i32("directionalLightCount".to_string(), &(count as i32));
+    for (index, (light, baked)) in lights.iter().take(count).enumerate() {
+        shader.set_uniform_bool(format!("directionalLights[{}].baked", index), baked);
+        shader.set_uniform_3f(format!("directionalLights[{}].direction", index), &light.direction.normalize());
+        shader.set_uniform_3f(format!("directionalLights[{}].color", index), &(light.color * light.intensity));
+    }
+}
+
+pub fn upload_point_lights(shader: &Shader, lights: &[(Entity, &Position, &PointLight, bool)], shadows: &ShadowAtlas) {
+    let count = lights.len().min(MAX_POINT_LIGHTS);
+    shader.set_uniform_i32("pointLightCount".to_string(), &(count as i32));
+    for (index, (entity, position, light, baked)) in lights.iter().take(count).enumerate() {
+        let shadow_view = shadows.first_view(*entity).map_or(-1, |view| view as i32);
+        shader.set_uniform_bool(format!("pointLights[{}].baked", index), baked);
+        shader.set_uniform_3f(format!("pointLights[{}].position", index), &position.d);
+        shader.set_uniform_3f(format!("pointLights[{}].color", index), &(light.color * light.intensity));
+        shader.set_uniform_1f(format!("pointLights[{}].range", index), &light.range);
+        shader.set_uniform_i32(format!("pointLights[{}].shadowView", index), &shadow_view);
+    }
+}
+
+pub fn upload_spot_lights(shader: &Shader, lights: &[(Entity, &Position, &SpotLight, bool)], shadows: &ShadowAtlas) {
+    let count = lights.len().min(MAX_SPOT_LIGHTS);
+    shader.set_uniform_i32("spotLightCount".to_string(), &(count as i32));
+    for (index, (entity, position, light, baked)) in lights.iter().take(count).enumerate() {
+        let shadow_view = shadows.first_view(*entity).map_or(-1, |view| view as i32);
+        shader.set_uniform_bool(format!("spotLights[{}].baked", index), baked);
+        shader.set_uniform_3f(format!("spotLights[{}].position", index), &position.d);
+        shader.set_uniform_3f(format!("spotLights[{}].direction", index), &light.direction.normalize());
+        shader.set_uniform_3f(format!("spotLights[{}].color", index), &(light.color * light.intensity));
+        shader.set_uniform_1f(format!("spotLights[{}].range", index), &light.range);
+        shader.set_uniform_1f(format!("spotLights[{}].innerCos", index), &light.inner_angle.to_radians().cos());
+        shader.set_uniform_1f(format!("spotLights[{}].outerCos", index), &light.outer_angle.to_radians().cos());
+        shader.set_uniform_i32(format!("spotLights[{}].shadowView", index), &shadow_view);
+    }
+}
+
+//Every light in the scene gathered once per frame, shared by the forward and deferred paths
+pub struct SceneLights<'a> {
+    pub directional: Vec<(&'a DirectionalLight, bool)>,
+    pub point: Vec<(Entity, &'a Position, &'a PointLight, bool)>,
+    pub spot: Vec<(Entity, &'a Position, &'a SpotLight, bool)>,
+    pub environment: Option<&'a Skybox>,
+}
+
+impl<'a> SceneLights<'a> {
+    pub fn upload(&self, shader: &Shader, shadows: &ShadowAtlas) {
+        upload_directional_lights(shader, &self.directional);
+        upload_point_lights(shader, &self.point, shadows);
+        upload_spot_lights(shader, &self.spot, shadows);
+        shadows.upload(shader);
+        environment::upload(shader, self.environment);
+    }
+
+    //Shadow atlas and environment maps stay bound for every lit draw in a pass
+    pub fn bind_for_sampling(&self, shadows: &ShadowAtlas) {
+        shadows.bind_for_sampling();
+        if let Some(skybox) = self.environment {
+            skybox.bind_for_sampling();
+        }
+    }
+    pub fn unbind_for_sampling(&self, shadows: &ShadowAtlas) {
+        shadows.unbind_for_sampling();
+        if let Some(skybox) = self.environment {
+            skybox.unbind_for_sampling();
+        }
+    }
+}
@@ -0,0 +1,390 @@
+use std::{collections::HashMap, error::Error, fs, path::Path};
+
+use bevy_ecs::prelude::*;
+use glam::*;
+use serde::{Serialize, Deserialize};
+
+use crate::{components::*, mesh::*, texture::{HdrImage, Texture}};
+
+pub const LIGHTMAP_UNIT: u32 = 2;
+
+//Charts bend at most this far away from the normal they are projected along
+const CHART_NORMAL_THRESHOLD: f32 = 0.7;
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct BakeSettings {
+    pub texels_per_unit: f32,
+    pub min_size: u32,
+    pub max_size: u32,
+    pub padding: u32,
+    pub samples: u32,
+    pub bounces: u32,
+    pub sky_color: [f32; 3],
+    //TODO: Read albedo from material textures
+    pub albedo: [f32; 3],
+    pub denoise: bool,
+}
+
+impl Default for BakeSettings {
+    fn default() -> Self {
+        Self {
+            texels_per_unit: 16.0,
+            min_size: 32,
+            max_size: 1024,
+            padding: 2,
+            samples: 128,
+            bounces: 2,
+            sky_color: [0.15, 0.15, 0.15],
+            albedo: [0.6, 0.6, 0.6],
+            denoise: true,
+        }
+    }
+}
+
+#[derive(Component)]
+pub struct Lightmap {
+    pub texture: Texture,
+}
+
+//How a mesh was unwrapped for its lightmap. Vertices split along chart seams are described by
+//remap, the original vertex every new vertex was copied from.
+#[derive(Serialize, Deserialize, Default)]
+pub struct LightmapLayout {
+    pub width: u32,
+    pub height: u32,
+    pub remap: Vec<u32>,
+    pub indices: Vec<i32>,
+    pub uvs: Vec<f32>,
+}
+
+impl LightmapLayout {
+    //Meshes that already have a second uv channel keep their vertices as they are
+    pub fn from_existing(data: &MeshData, uvs: &[f32], model: &Mat4, settings: &BakeSettings) -> LightmapLayout {
+        let positions = world_positions(data, model);
+        let area: f32 = data.indices.chunks_exact(3).map(|t| triangle_area(&positions, t)).sum();
+        let side = (area.sqrt() * settings.texels_per_unit).ceil() as u32;
+        let size = side.clamp(settings.min_size, settings.max_size);
+        LightmapLayout {
+            width: size,
+            height: size,
+            remap: (0..data.vertex_count() as u32).collect(),
+            indices: data.indices.clone(),
+            uvs: uvs.to_vec(),
+        }
+    }
+
+    //Splits the mesh into charts of triangles facing roughly the same way, flattens each chart
+    //along its normal and packs the charts into rows of one texture
+    pub fn generate(data: &MeshData, model: &Mat4, settings: &BakeSettings) -> LightmapLayout {
+        let positions = world_positions(data, model);
+        let charts = build_charts(&positions, &data.indices);
+
+        let mut texels_per_unit = settings.texels_per_unit;
+        let mut layout = LightmapLayout::default();
+        for _ in 0..8 {
+            layout = pack_charts(&positions, &data.indices, &charts, texels_per_unit, settings.padding);
+            let largest = layout.width.max(layout.height);
+            if largest > settings.max_size {
+                texels_per_unit *= settings.max_size as f32 / largest as f32 * 0.95;
+            } else if largest < settings.min_size {
+                texels_per_unit *= settings.min_size as f32 / largest.max(1) as f32;
+            } else {
+                break;
+            }
+        }
+        layout.width = layout.width.clamp(1, settings.max_size);
+        layout.height = layout.height.clamp(1, settings.max_size);
+        layout
+    }
+
+    pub fn apply(&self, data: &mut MeshData) {
+        for (_, size, buffer) in data.buffers.iter_mut() {
+            let size = *size as usize;
+            let mut remapped = Vec::with_capacity(self.remap.len() * size);
+            for vertex in &self.remap {
+                let start = *vertex as usize * size;
+                remapped.extend_from_slice(&buffer[start..start + size]);
+            }
+            *buffer = remapped;
+        }
+        data.indices = self.indices.clone();
+        data.set_buffer(self.uvs.clone(), LIGHTMAP_UV_LOCATION, 2);
+    }
+
+    pub fn load(path: &str) -> Result<LightmapLayout, Box<dyn Error>> {
+        Ok(toml::from_str(&fs::read_to_string(path)?)?)
+    }
+    pub fn save(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        fs::write(path, toml::to_string(self)?)?;
+        Ok(())
+    }
+}
+
+pub fn lightmap_directory(level: &str) -> String {
+    format!("resources/levels/{}/lightmaps", level)
+}
+
+//Applies saved layouts to static MeshData and attaches their lightmap textures.
+//Must run after the GL context exists and before the meshes are uploaded.
+pub fn load_lightmaps(world: &mut World, level: &str) {
+    let directory = lightmap_directory(level);
+    let mut query = world.query_filtered::<(Entity, &Name, &mut MeshData), With<Static>>();
+    let mut lightmaps = Vec::new();
+    for (entity, name, mut data) in query.iter_mut(world) {
+        let layout_path = format!("{}/{}.toml", directory, name.d);
+        let image_path = format!("{}/{}.hdr", directory, name.d);
+        if !Path::new(&layout_path).exists() || !Path::new(&image_path).exists() {
+            continue;
+        }
+
+        match (LightmapLayout::load(&layout_path), HdrImage::load(&image_path)) {
+            (Ok(layout), Ok(image)) => {
+                layout.apply(&mut data);
+                lightmaps.push((entity, Lightmap { texture: Texture::from_hdr_image(&image) }));
+            },
+            (Err(error), _) => println!("Unable to load lightmap layout {}: {}", layout_path, error),
+            (_, Err(error)) => println!("Unable to load lightmap {}: {}", image_path, error),
+        }
+    }
+    for (entity, lightmap) in lightmaps {
+        world.entity_mut(entity).insert(lightmap);
+    }
+}
+
+pub fn world_positions(data: &MeshData, model: &Mat4) -> Vec<Vec3> {
+    match data.buffer(POSITION_LOCATION) {
+        Some((size, buffer)) => buffer
+            .chunks_exact(size as usize)
+            .map(|p| model.transform_point3(Vec3::new(p[0], p[1], p.get(2).copied().unwrap_or(0.0))))
+            .collect(),
+        None => Vec::new(),
+    }
+}
+
+fn triangle_area(positions: &[Vec3], triangle: &[i32]) -> f32 {
+    let (a, b, c) = (positions[triangle[0] as usize], positions[triangle[1] as usize], positions[triangle[2] as usize]);
+    (b - a).cross(c - a).length() * 0.5
+}
+
+fn triangle_normal(positions: &[Vec3], triangle: &[i32]) -> Vec3 {
+    let (a, b, c) = (positions[triangle[0] as usize], positions[triangle[1] as usize], positions[triangle[2] as usize]);
+    (b - a).cross(c - a).normalize_or_zero()
+}
+
+struct Chart {
+    normal: Vec3,
+    triangles: Vec<usize>,
+}
+
+fn build_charts(positions: &[Vec3], indices: &[i32]) -> Vec<Chart> {
+    //Weld by position so triangles that only share a position (not an index) are still neighbours
+    let mut welded: HashMap<(i64, i64, i64), usize> = HashMap::new();
+    let ids: Vec<usize> = positions.iter().map(|p| {
+        let key = ((p.x * 1e4).round() as i64, (p.y * 1e4).round() as i64, (p.z * 1e4).round() as i64);
+        let next = welded.len();
+        *welded.entry(key).or_insert(next)
+    }).collect();
+
+    let triangle_count = indices.len() / 3;
+    let mut edges: HashMap<(usize, usize), Vec<usize>> = HashMap::new();
+    for triangle in 0..triangle_count {
+        for corner in 0..3 {
+            let a = ids[indices[triangle * 3 + corner] as usize];
+            let b = ids[indices[triangle * 3 + (corner + 1) % 3] as usize];
+            edges.entry((a.min(b), a.max(b))).or_default().push(triangle);
+        }
+    }
+
+    let normals: Vec<Vec3> = (0..triangle_count).map(|t| triangle_normal(positions, &indices[t * 3..t * 3 + 3])).collect();
+    let mut chart_of = vec![usize::MAX; triangle_count];
+    let mut charts: Vec<Chart> = Vec::new();
+    for seed in 0..triangle_count {
+        if chart_of[seed] != usize::MAX {
+            continue;
+        }
+        let chart_index = charts.len();
+        let normal = normals[seed];
+        let mut chart = Chart { normal, triangles: Vec::new() };
+        let mut open = vec![seed];
+        chart_of[seed] = chart_index;
+        while let Some(triangle) = open.pop() {
+            chart.triangles.push(triangle);
+            for corner in 0..3 {
+                let a = ids[indices[triangle * 3 + corner] as usize];
+                let b = ids[indices[triangle * 3 + (corner + 1) % 3] as usize];
+                for neighbour in &edges[&(a.min(b), a.max(b))] {
+                    let n = normals[*neighbour];
+                    let fits = n == Vec3::ZERO || normal == Vec3::ZERO || n.dot(normal) >= CHART_NORMAL_THRESHOLD;
+                    if chart_of[*neighbour] == usize::MAX && fits {
+                        chart_of[*neighbour] = chart_index;
+                        open.push(*neighbour);
+                    }
+                }
+            }
+        }
+        charts.push(chart);
+    }
+    charts
+}
+
+fn pack_charts(positions: &[Vec3], indices: &[i32], charts: &[Chart], texels_per_unit: f32, padding: u32) -> LightmapLayout {
+    struct Flattened {
+        vertices: Vec<(u32, Vec2)>, //original vertex, texel position relative to the chart origin
+        size: Vec2,
+        offset: Vec2,
+    }
+
+    let mut chart_vertex: HashMap<(usize, u32), u32> = HashMap::new();
+    let mut flattened: Vec<Flattened> = Vec::with_capacity(charts.len());
+    let mut new_indices = vec![0; indices.len()];
+    let mut next_vertex: u32 = 0;
+
+    for (chart_index, chart) in charts.iter().enumerate() {
+        let normal = if chart.normal == Vec3::ZERO { Vec3::Y } else { chart.normal };
+        let (tangent, bitangent) = normal.any_orthonormal_pair();
+        let mut vertices: Vec<(u32, Vec2)> = Vec::new();
+        for triangle in &chart.triangles {
+            for corner in 0..3 {
+                let original = indices[triangle * 3 + corner] as u32;
+                let vertex = *chart_vertex.entry((chart_index, original)).or_insert_with(|| {
+                    let p = positions[original as usize];
+                    vertices.push((original, Vec2::new(p.dot(tangent), p.dot(bitangent)) * texels_per_unit));
+                    next_vertex += 1;
+                    next_vertex - 1
+                });
+                new_indices[triangle * 3 + corner] = vertex as i32;
+            }
+        }
+        let min = vertices.iter().fold(Vec2::splat(f32::MAX), |m, v| m.min(v.1));
+        let max = vertices.iter().fold(Vec2::splat(f32::MIN), |m, v| m.max(v.1));
+        vertices.iter_mut().for_each(|v| v.1 -= min);
+        flattened.push(Flattened { vertices, size: (max - min).max(Vec2::ONE), offset: Vec2::ZERO });
+    }
+
+    //Shelf packing, tallest charts first, widening the atlas until everything fits in a square
+    let gap = (padding * 2) as f32;
+    let mut order: Vec<usize> = (0..flattened.len()).collect();
+    order.sort_by(|a, b| flattened[*b].size.y.total_cmp(&flattened[*a].size.y));
+    let total_area: f32 = flattened.iter().map(|f| (f.size.x + gap) * (f.size.y + gap)).sum();
+    let widest = flattened.iter().fold(0.0f32, |w, f| w.max(f.size.x + gap));
+    let mut side = total_area.sqrt().max(widest).ceil();
+    let (width, height) = loop {
+        let (mut x, mut y, mut row_height) = (0.0f32, 0.0f32, 0.0f32);
+        for index in &order {
+            let size = flattened[*index].size + Vec2::splat(gap);
+            if x + size.x > side {
+                x = 0.0;
+                y += row_height;
+                row_height = 0.0;
+            }
+            flattened[*index].offset = Vec2::new(x, y) + Vec2::splat(padding as f32);
+            x += size.x;
+            row_height = row_height.max(size.y);
+        }
+        let used_height = y + row_height;
+        if used_height <= side {
+            break (side, used_height);
+        }
+        side = (side * 1.1).ceil();
+    };
+
+    let width = ((width.ceil() as u32).div_ceil(4) * 4).max(4);
+    let height = ((height.ceil() as u32).div_ceil(4) * 4).max(4);
+    let mut remap = vec![0u32; next_vertex as usize];
+    let mut uvs = vec![0.0f32; next_vertex as usize * 2];
+    for (chart_index, chart) in flattened.iter().enumerate() {
+        for (original, texel) in &chart.vertices {
+            let vertex = chart_vertex[&(chart_index, *original)] as usize;
+            let uv = (chart.offset + *texel) / Vec2::new(width as f32, height as f32);
+            remap[vertex] = *original;
+            uvs[vertex * 2] = uv.x;
+            uvs[vertex * 2 + 1] = uv.y;
+        }
+    }
+
+    LightmapLayout { width, height, remap, indices: new_indices, uvs }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //Two unit quads facing up and one facing sideways, sharing no vertices
+    fn quads() -> (Vec<Vec3>, Vec<i32>) {
+        let mut positions = Vec::new();
+        let mut indices = Vec::new();
+        let faces = [(Vec3::ZERO, Vec3::X, Vec3::NEG_Z), (Vec3::new(3.0, 0.0, 0.0), Vec3::X * 2.0, Vec3::NEG_Z), (Vec3::ZERO, Vec3::Z, Vec3::Y)];
+        for (origin, u, v) in faces {
+            let first = positions.len() as i32;
+            positions.extend([origin, origin + u, origin + u + v, origin + v]);
+            indices.extend([first, first + 1, first + 2, first, first + 2, first + 3]);
+        }
+        (positions, indices)
+    }
+
+    #[test]
+    fn splits_charts_by_facing() {
+        let (positions, indices) = quads();
+        let charts = build_charts(&positions, &indices);
+        assert_eq!(charts.len(), 3);
+        assert!(charts.iter().all(|chart| chart.triangles.len() == 2));
+    }
+
+    #[test]
+    fn packs_charts_without_overlaps() {
+        let (positions, indices) = quads();
+        let charts = build_charts(&positions, &indices);
+        let padding = 2;
+        let layout = pack_charts(&positions, &indices, &charts, 8.0, padding);
+        assert!(layout.width.is_multiple_of(4) && layout.height.is_multiple_of(4));
+        assert_eq!(layout.indices.len(), indices.len());
+
+        //Texel rectangle of every chart, grown by the padding that has to stay free around it
+        let size = Vec2::new(layout.width as f32, layout.height as f32);
+        let rects: Vec<(Vec2, Vec2)> = charts.iter().map(|chart| {
+            let uvs = chart.triangles.iter().flat_map(|triangle| &layout.indices[triangle * 3..triangle * 3 + 3])
+                .map(|vertex| Vec2::new(layout.uvs[*vertex as usize * 2], layout.uvs[*vertex as usize * 2 + 1]) * size);
+            let (min, max) = uvs.fold((Vec2::splat(f32::MAX), Vec2::splat(f32::MIN)), |(min, max), uv| (min.min(uv), max.max(uv)));
+            (min - padding as f32, max + padding as f32)
+        }).collect();
+        for (index, (min, max)) in rects.iter().enumerate() {
+            assert!(min.cmpge(Vec2::splat(-0.01)).all() && max.cmple(size + 0.01).all(), "{} {} outside {}", min, max, size);
+            for (other_min, other_max) in &rects[index + 1..] {
+                let apart = max.x <= other_min.x + 0.01 || other_max.x <= min.x + 0.01 || max.y <= other_min.y + 0.01 || other_max.y <= min.y + 0.01;
+                assert!(apart, "{} {} overlaps {} {}", min, max, other_min, other_max);
+            }
+        }
+        //Every new vertex still points at the vertex it was split from
+        for (new, original) in layout.remap.iter().enumerate() {
+            let vertex = layout.indices.iter().position(|index| *index as usize == new).unwrap();
+            assert_eq!(indices[vertex], *original as i32);
+        }
+    }
+
+    #[test]
+    fn saves_and_loads_lightmaps() {
+        let directory = std::env::temp_dir().join(format!("butter_lightmap_{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let (layout_path, image_path) = (directory.join("quad.toml"), directory.join("quad.hdr"));
+        let (layout_path, image_path) = (layout_path.to_str().unwrap(), image_path.to_str().unwrap());
+
+        let layout = LightmapLayout { width: 4, height: 8, remap: vec![0, 1, 1], indices: vec![0, 1, 2], uvs: vec![0.0, 0.5, 0.25, 1.0, 0.75, 0.125] };
+        layout.save(layout_path).unwrap();
+        let loaded = LightmapLayout::load(layout_path).unwrap();
+        assert_eq!((loaded.width, loaded.height, &loaded.remap, &loaded.indices, &loaded.uvs), (4, 8, &layout.remap, &layout.indices, &layout.uvs));
+
+        let mut image = HdrImage::new(3, 2);
+        image.set(0, 0, [1.0, 0.5, 0.25]);
+        image.set(2, 1, [12.0, 0.0, 3.0]);
+        image.save(image_path).unwrap();
+        let loaded = HdrImage::load(image_path).unwrap();
+        assert_eq!((loaded.width, loaded.height), (3, 2));
+        //RGBE keeps eight bits of mantissa shared by the three channels
+        for (loaded, saved) in loaded.data.chunks_exact(3).zip(image.data.chunks_exact(3)) {
+            let step = saved.iter().fold(0.0f32, |max, value| max.max(*value)) / 64.0;
+            assert!(loaded.iter().zip(saved).all(|(loaded, saved)| (loaded - saved).abs() <= step), "{:?} became {:?}", saved, loaded);
+        }
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,125 @@
+use bevy_ecs::prelude::*;
+use glam::{Mat4, Vec3};
+use serde::{Serialize, Deserialize};
+
+use crate::{mesh::{Mesh, MeshCache, MeshData}, simplify::simplify};
+
+//One step of a generated LOD chain
+#[derive(Serialize, Deserialize, Clone)]
+pub struct LodStep {
+    //Fraction of the source triangles kept
+    pub triangle_ratio: f32,
+    //Smallest screen size the level is used at, as a fraction of the screen height covered by the bounding sphere
+    pub screen_size: f32,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct LodSettings {
+    //Seconds a dithered cross-fade between two levels lasts
+    pub fade_duration: f32,
+    pub chain: Vec<LodStep>,
+}
+
+impl Default for LodSettings {
+    fn default() -> Self {
+        Self {
+            fade_duration: 0.25,
+            chain: vec![
+                LodStep { triangle_ratio: 1.0, screen_size: 0.4 },
+                LodStep { triangle_ratio: 0.5, screen_size: 0.15 },
+                LodStep { triangle_ratio: 0.25, screen_size: 0.0 },
+            ],
+        }
+    }
+}
+
+//CPU side LOD chain, finest level first. Turned into a MeshLod once a GL context exists.
+#[derive(Component, Clone, Default)]
+pub struct MeshLodData {
+    pub levels: Vec<(MeshData, f32)>, //mesh, screen size
+}
+
+impl MeshLodData {
+    //Simplifies the source mesh once per step, a ratio of 1 keeps it untouched
+    pub fn generate(data: &MeshData, chain: &[LodStep]) -> MeshLodData {
+        let levels = chain.iter().map(|step| (simplify(data, step.triangle_ratio), step.screen_size)).collect();
+        MeshLodData { levels }
+    }
+}
+
+pub struct LodLevel {
+    pub mesh: Mesh,
+    pub screen_size: f32,
+}
+
+//The entity's Mesh always holds the selected level, so passes that don't care about LODs just draw that
+#[derive(Component)]
+pub struct MeshLod {
+    pub levels: Vec<LodLevel>,
+    current: usize,
+    //Level being faded out and how far the fade has got, 0 to 1
+    fading: Option<(usize, f32)>,
+}
+
+impl MeshLod {
+    pub fn new(levels: Vec<LodLevel>) -> MeshLod {
+        MeshLod { levels, current: 0, fading: None }
+    }
+
+    pub fn current(&self) -> &Mesh {
+        &self.levels[self.current].mesh
+    }
+
+    //Instance fades of the current level and the one fading out while a transition is running
+    pub fn fades(&self) -> (f32, Option<(&Mesh, f32)>) {
+        match self.fading {
+            Some((level, progress)) => (1.0 - progress, Some((&self.levels[level].mesh, -progress))),
+            None => (0.0, None),
+        }
+    }
+
+    //Finest level whose threshold the screen size still reaches, the coarsest one otherwise
+    fn select(&self, screen_size: f32) -> usize {
+        self.levels.iter().position(|level| screen_size >= level.screen_size).unwrap_or(self.levels.len() - 1)
+    }
+
+    //Returns true when the selected level changed and the entity's Mesh needs replacing
+    pub fn update(&mut self, screen_size: f32, delta_seconds: f32, fade_duration: f32) -> bool {
+        if let Some((level, progress)) = self.fading {
+            let progress = progress + delta_seconds / fade_duration.max(f32::EPSILON);
+            self.fading = if progress >= 1.0 { None } else { Some((level, progress)) };
+        }
+
+        let selected = self.select(screen_size);
+        if selected == self.current {
+            return false;
+        }
+        self.fading = if fade_duration > 0.0 { Some((self.current, 0.0)) } else { None };
+        self.current = selected;
+        true
+    }
+}
+
+//Fraction of the screen height covered by a sphere seen from `eye`
+pub fn screen_size(center: Vec3, radius: f32, eye: Vec3, projection: &Mat4) -> f32 {
+    let distance = center.distance(eye).max(f32::EPSILON);
+    radius * projection.y_axis.y / distance
+}
+
+//Turns every MeshLodData into a MeshLod, starting on the finest level
+pub fn upload_lods(world: &mut World) {
+    let mut query = world.query_filtered::<(Entity, &MeshLodData), Without<MeshLod>>();
+    let mut cache = MeshCache::default();
+    let uploads: Vec<(Entity, MeshLod)> = query.iter(world)
+        .filter(|(_, data)| !data.levels.is_empty())
+        .map(|(entity, data)| {
+            let levels = data.levels.iter().map(|(mesh, screen_size)| LodLevel { mesh: cache.upload(mesh), screen_size: *screen_size }).collect();
+            (entity, MeshLod::new(levels))
+        })
+        .collect();
+    for (entity, lod) in uploads {
+        let mesh = lod.current().clone();
+        world.entity_mut(entity).insert((lod, mesh)).remove::<MeshLodData>();
+    }
+}
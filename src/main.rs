@@ -8,25 +8,98 @@ mod settings;
 mod shader;
 mod systems;
 mod texture;
+mod texture_formats;
 mod window;
 mod mesh;
 mod material;
+mod framebuffer;
+mod lighting;
+mod shadow;
+mod ssr;
+mod lightmap;
+mod baker;
+mod deferred;
+mod postprocess;
+mod render_graph;
+mod culling;
+mod render_queue;
+mod lod;
+mod simplify;
+mod oit;
+mod environment;
+mod view;
+mod sprite;
+mod debug_draw;
+mod text;
+mod particles;
+mod profiler;
+mod gl_state;
+mod backend;
+mod opengl_backend;
+#[cfg(test)]
+mod null_backend;
+mod mesh_builder;
+mod terrain;
+mod json;
+mod gltf;
+mod animation;
+mod animation_graph;
+mod skinning;
+mod morph;
 
-use bevy_ecs::schedule::Schedule;
-use bevy_ecs::world::World;
+use std::{error::Error, sync::Arc};
+
+use animation::{AnimationClips, AnimationPlayer};
+use animation_graph::{AnimationGraph, AnimationGraphPlayer};
+use bevy_ecs::world::{Mut, World};
 use components::*;
+use culling::CullingStats;
+use debug_draw::{DebugDraw, DebugRenderer};
+use deferred::DeferredRenderer;
 use entities::*;
+use environment::Skybox;
+use gltf::Model;
 use glam::*;
-use mesh::Mesh;
+use lod::MeshLodData;
+use mesh::MeshData;
+use mesh_builder::MeshBuilder;
+use oit::OrderIndependentTransparency;
+use particles::{ParticleEmitter, ParticleRenderer, RestartWhenFinished};
+use postprocess::{PostProcess, PostProcessing};
+use profiler::{ProfiledSchedule, Profiler, ProfilerOverlay};
+use render_graph::RenderGraph;
 use resources::*;
 use settings::Settings;
+use shadow::ShadowAtlas;
+use skinning::{SkinnedMesh, SkinningPalette};
+use morph::MorphPalette;
+use sprite::{AtlasFrame, Sprite, SpriteRenderer};
+use ssr::ScreenSpaceReflections;
+use terrain::Terrain;
+use text::{GlyphAtlas, Text, TextAlignment, TextRenderer, TextSpace};
+use view::RenderTextures;
 use window::Window;
 use winit::event::{DeviceEvent, ElementState, Event, KeyEvent, WindowEvent};
 use winit::event_loop::{ControlFlow, EventLoop};
 use winit::keyboard::KeyCode;
 
+const DEMO_LEVEL: &str = "demo";
+
 fn main() {
     let settings: Settings = settings::load();
+    let mut world = World::new();
+    spawn_demo_level(&mut world, &settings);
+
+    //Baking only needs the CPU side of the level, so it runs before any window or GL context exists
+    let args: Vec<String> = std::env::args().collect();
+    if let Some(index) = args.iter().position(|arg| arg == "--bake") {
+        let level = args.get(index + 1).map_or(DEMO_LEVEL, |level| level.as_str());
+        let progress = |name: &str, layout: &lightmap::LightmapLayout| println!("Baking {} ({}x{})...", name, layout.width, layout.height);
+        let baked = baker::bake_level(&mut world, level, &settings.bake, progress).expect("Unable to bake lightmaps!");
+        println!("Baked {} lightmaps into {}", baked, lightmap::lightmap_directory(level));
+        return;
+    }
+
     let event_loop = EventLoop::new();
     let (window, gl_context) = Window::new(
         settings.width,
@@ -35,87 +108,90 @@ fn main() {
         &event_loop,
     );
     renderer::update_wireframe(&settings.is_wireframe);
+    println!("Rendering with {}", backend::with(|backend| backend.name()));
 
-    let vertices: [f32; 15] = [
-        -0.5, 0.0,  0.5,     	
-        -0.5, 0.0, -0.5,     	
-         0.5, 0.0, -0.5,     	
-         0.5, 0.0,  0.5,     	
-         0.0, 0.8,  0.0,     	
-    ];
-    let colors: [f32; 15] = [
-        0.83, 0.70, 0.44,
-        0.83, 0.70, 0.44,
-        0.83, 0.70, 0.44,
-        0.83, 0.70, 0.44,
-        0.92, 0.86, 0.76,
-    ];
-    let texture_coords: [f32; 10] = [
-        0.0, 0.0,
-        5.0, 0.0,
-        0.0, 0.0,
-        5.0, 0.0,
-        2.5, 5.0,
-    ];
-    let indices = [
-        0, 1, 2,
-        0, 2, 3,
-        0, 1, 4,
-        1, 2, 4,
-        2, 3, 4,
-        3, 0, 4,
-    ];
-
+    //Render textures go into the pool first so materials can sample them by name
     let mut asset_pool = AssetPool::default();
+    let mut render_textures = RenderTextures::default();
+    render_textures.update(world.query::<&Camera>().iter(&world), &mut asset_pool);
     let _ = asset_pool.load_material("wood", &settings);
+    let _ = asset_pool.load_shader("shadow");
 
-    let mut mesh: Mesh = Mesh::new(indices.to_vec(), "wood");
-    
-    mesh.add_buffer(vertices.to_vec(), 0, 3);
-    mesh.add_buffer(colors.to_vec(), 1, 3);
-    mesh.add_buffer(texture_coords.to_vec(), 2, 2);
-
-    let mut world = World::new();
-
-    let mut opengl_update = Schedule::default();
-    let mut update = Schedule::default();
-    let mut opengl_render = Schedule::default();
+    lightmap::load_lightmaps(&mut world, DEMO_LEVEL);
+    mesh::upload_meshes(&mut world);
+    lod::upload_lods(&mut world);
 
-    opengl_update.set_executor_kind(bevy_ecs::schedule::ExecutorKind::SingleThreaded);
-    opengl_render.set_executor_kind(bevy_ecs::schedule::ExecutorKind::SingleThreaded);
-
-    let _ = world.spawn(CameraBundle {
-        position: Position {
-            d: Vec3::new(0.0, 0.0, 3.0),
-        },
-        direction: Rotation::default(),
-        camera: Camera {
-            front: Vec3::new(0.0, 0.0, 0.0),
-            up: Vec3::new(0.0, 1.0, 0.0),
-            yaw: 0.0,
-            pitch: 0.0,
-            first_mouse: true,
-            view: Mat4::IDENTITY,
-            projection: Mat4::perspective_rh_gl(
-            90.0_f32.to_radians(), 
-            window.aspect_ratio(), 
-            0.01, 
-            100.0),
-        },
-    }).id();
-
-    world.spawn(mesh);
+    let mut opengl_update = ProfiledSchedule::new("opengl_update");
+    let mut update = ProfiledSchedule::new("update");
+    let mut overlay = ProfiledSchedule::new("overlay");
+    let mut render_graph = build_render_graph().expect("Unable to build render graph!");
+    if let Some(index) = args.iter().position(|arg| arg == "--dump-render-graph") {
+        render_graph.dump_to(args.get(index + 1).map_or("render_graph.dot", |path| path.as_str()));
+    }
 
     world.insert_resource(Input::new());
     world.insert_resource(Time::default());
+    world.insert_resource(CullingStats::default());
+    world.insert_resource(ShadowAtlas::new(settings.shadow_atlas_size));
+    world.insert_resource(ScreenSpaceReflections::new(settings.width, settings.height, settings.ssr_quality)
+        .expect("Unable to load screen space reflection shaders!"));
+    world.insert_resource(DeferredRenderer::new()
+        .expect("Unable to load deferred shaders!"));
+    world.insert_resource(OrderIndependentTransparency::new()
+        .expect("Unable to load transparency shaders!"));
+    world.insert_resource(PostProcessing::new(settings.width, settings.height)
+        .expect("Unable to load post processing shaders!"));
+    world.insert_resource(SpriteRenderer::new()
+        .expect("Unable to load sprite shaders!"));
+    world.insert_resource(DebugRenderer::new()
+        .expect("Unable to load debug draw shaders!"));
+    world.insert_resource(DebugDraw::default());
+    world.insert_resource(TextRenderer::new()
+        .expect("Unable to load text shaders!"));
+    world.insert_resource(GlyphAtlas::new(&settings.text));
+    world.insert_resource(ParticleRenderer::new()
+        .expect("Unable to load particle shaders!"));
+    world.insert_resource(ProfilerOverlay::new()
+        .expect("Unable to load profiler overlay shaders!"));
+    world.insert_resource(Profiler::new(&settings.profiler));
+    world.insert_resource(SkinningPalette::new());
+    world.insert_resource(MorphPalette::new());
+    //Scenes without a sky fall back to the constant ambient
+    match Skybox::load(&settings.skybox) {
+        Ok(skybox) => world.insert_resource(skybox),
+        Err(error) => println!("Unable to load skybox {}: {}", settings.skybox.texture, error),
+    }
     world.insert_resource(settings);
     world.insert_resource(window);
     world.insert_resource(asset_pool);
+    world.insert_resource(render_textures);
+    world.insert_resource(render_graph);
 
     update.add_system(systems::move_camera);
     update.add_system(systems::update_projection);
+    update.add_system(systems::update_render_textures);
+    update.add_system(systems::update_post_processing);
+    update.add_system(systems::update_lods);
+    update.add_system(systems::load_mesh_materials);
+    update.add_system(systems::load_terrain_materials);
+    update.add_system(systems::update_terrain);
+    update.add_system(systems::update_animation_parameters);
+    update.add_system(systems::update_animation_controls);
+    update.add_system(systems::animate);
+    update.add_system(systems::animate_graphs);
+    update.add_system(systems::load_sprites);
+    update.add_system(systems::layout_text);
+    update.add_system(systems::load_particle_effects);
+    update.add_system(systems::simulate_particles);
+    update.add_system(systems::update_profiler);
     opengl_update.add_system(systems::update_wireframe);
-    opengl_render.add_system(systems::render_scene);
+    opengl_update.add_system(systems::update_render_path);
+    opengl_update.add_system(systems::upload_skinning);
+    opengl_update.add_system(systems::upload_morph_weights);
+    opengl_update.add_system(systems::update_debug_draw);
+    opengl_update.add_system(systems::draw_debug_lights);
+    opengl_update.add_system(systems::draw_debug_bounds);
+    overlay.add_system(systems::render_profiler_overlay);
 
     event_loop.run(move |event, _, control_flow| {
         // ControlFlow::Poll continuously runs the event loop, even if the OS hasn't
@@ -128,6 +204,7 @@ fn main() {
             },
             Event::MainEventsCleared => {
                 let before = std::time::Instant::now();
+                world.resource_mut::<Profiler>().begin_frame();
 
                 // Application update code.
                 let mut input = world.get_resource_mut::<Input>().unwrap();
@@ -141,11 +218,16 @@ fn main() {
                 opengl_update.run(&mut world);
         
                 // Render
-                unsafe {
-                    gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
-                }
-                opengl_render.run(&mut world);
+                world.resource_scope(|world, mut render_graph: Mut<RenderGraph>| render_graph.run(world));
+                //Every view has drawn this frame's debug shapes, the next update pushes new ones
+                let delta_seconds = world.resource::<Time>().delta_seconds();
+                world.resource_mut::<DebugDraw>().advance(delta_seconds);
+                overlay.run(&mut world);
+                profiler::begin_scope(&mut world, "swap_buffers", false);
                 gl_context.handle.swap_buffers();
+                profiler::end_scope(&mut world, false);
+                world.resource_mut::<Profiler>().end_frame();
+                gl_state::check_errors("frame");
 
                 let after = std::time::Instant::now();
         
@@ -190,6 +272,348 @@ fn main() {
     });
 }
 
+fn build_render_graph() -> Result<RenderGraph, Box<dyn Error>> {
+    use render_graph::*;
+    let rgba8 = AttachmentDescriptor::new(AttachmentFormat::Rgba8);
+    let rgba16f = AttachmentDescriptor::new(AttachmentFormat::Rgba16F);
+    let depth = AttachmentDescriptor::new(AttachmentFormat::Depth32F);
+
+    let mut graph = RenderGraph::new();
+    graph.add_pass(RenderPass::new("clear", SETUP, systems::clear_backbuffer)
+        .once_per_frame()
+        .writes(BACKBUFFER))?;
+    graph.add_pass(RenderPass::new("begin_view", SETUP, systems::begin_view)
+        .writes(BACKBUFFER)
+        .writes(SCENE))?;
+    graph.add_pass(RenderPass::new("shadows", SHADOWS, systems::render_shadows)
+        .once_per_frame()
+        .writes(SHADOW_ATLAS))?;
+    graph.add_pass(RenderPass::new("begin_post_processing", PREPASS, systems::begin_post_processing)
+        .creates(postprocess::HDR_COLOR, rgba16f)
+        .creates(postprocess::HDR_DEPTH, depth)
+        .framebuffer(postprocess::HDR, &[postprocess::HDR_COLOR, postprocess::HDR_DEPTH])
+        .writes(SCENE))?;
+    graph.add_pass(RenderPass::new("ssr_prepass", PREPASS, systems::render_ssr_prepass)
+        .creates(ssr::NORMALS, rgba16f)
+        .creates(ssr::PREPASS_DEPTH, depth)
+        .creates(ssr::SCENE_COLOR, rgba16f)
+        .creates(ssr::SCENE_DEPTH, depth)
+        .framebuffer(ssr::PREPASS, &[ssr::NORMALS, ssr::PREPASS_DEPTH])
+        .framebuffer(ssr::SCENE, &[ssr::SCENE_COLOR, ssr::SCENE_DEPTH])
+        .writes(SCENE))?;
+    graph.add_pass(RenderPass::new("forward", OPAQUE, systems::render_scene)
+        .reads(SHADOW_ATLAS)
+        .writes(SCENE))?;
+    graph.add_pass(RenderPass::new("deferred", OPAQUE, systems::render_deferred)
+        .reads(SHADOW_ATLAS)
+        .creates(deferred::GBUFFER_ALBEDO, rgba8)
+        .creates(deferred::GBUFFER_NORMAL, rgba16f)
+        .creates(deferred::GBUFFER_LIGHTING, rgba16f)
+        .creates(deferred::GBUFFER_DEPTH, depth)
+        .creates(deferred::LIT_COLOR, rgba16f)
+        .framebuffer(deferred::GBUFFER, &[deferred::GBUFFER_ALBEDO, deferred::GBUFFER_NORMAL, deferred::GBUFFER_LIGHTING, deferred::GBUFFER_DEPTH])
+        .framebuffer(deferred::LIT, &[deferred::LIT_COLOR])
+        .writes(SCENE))?;
+    graph.add_pass(RenderPass::new("skybox", TRANSPARENT, systems::render_skybox)
+        .writes(SCENE))?;
+    graph.add_pass(RenderPass::new("transparent", TRANSPARENT, systems::render_transparent)
+        .reads(SHADOW_ATLAS)
+        .creates(oit::ACCUM, rgba16f)
+        .creates(oit::REVEALAGE, AttachmentDescriptor::new(AttachmentFormat::R32F))
+        .creates(oit::DEPTH, depth)
+        .framebuffer(oit::TARGET, &[oit::ACCUM, oit::REVEALAGE, oit::DEPTH])
+        .writes(SCENE))?;
+    graph.add_pass(RenderPass::new("sprites", TRANSPARENT, systems::render_sprites)
+        .writes(SCENE))?;
+    graph.add_pass(RenderPass::new("particles", TRANSPARENT, systems::render_particles)
+        .writes(SCENE))?;
+    //Reflections trace the finished scene, ahead of every other post process
+    graph.add_insertion_point("reflections", TRANSPARENT)?;
+    graph.add_pass(RenderPass::new("ssr", "reflections", systems::render_ssr)
+        .reads(ssr::NORMALS)
+        .reads(ssr::PREPASS_DEPTH)
+        .reads(ssr::SCENE_COLOR)
+        .reads(ssr::SCENE_DEPTH)
+        .writes(SCENE))?;
+    graph.add_pass(RenderPass::new("post_processing", POST_PROCESS, systems::render_post_processing)
+        .reads(SCENE)
+        .reads(postprocess::HDR_COLOR)
+        .reads(postprocess::HDR_DEPTH)
+        .writes(BACKBUFFER))?;
+    graph.add_pass(RenderPass::new("text", OVERLAY, systems::render_text)
+        .writes(BACKBUFFER))?;
+    graph.add_pass(RenderPass::new("debug_draw", OVERLAY, systems::render_debug_draw)
+        .writes(BACKBUFFER))?;
+    graph.compile()?;
+    Ok(graph)
+}
+
+fn spawn_demo_level(world: &mut World, settings: &Settings) {
+    let vertices: [f32; 15] = [
+        -0.5, 0.0,  0.5,     	
+        -0.5, 0.0, -0.5,     	
+         0.5, 0.0, -0.5,     	
+         0.5, 0.0,  0.5,     	
+         0.0, 0.8,  0.0,     	
+    ];
+    let colors: [f32; 15] = [
+        0.83, 0.70, 0.44,
+        0.83, 0.70, 0.44,
+        0.83, 0.70, 0.44,
+        0.83, 0.70, 0.44,
+        0.92, 0.86, 0.76,
+    ];
+    let texture_coords: [f32; 10] = [
+        0.0, 0.0,
+        5.0, 0.0,
+        0.0, 0.0,
+        5.0, 0.0,
+        2.5, 5.0,
+    ];
+    let indices = [
+        0, 1, 2,
+        0, 2, 3,
+        0, 1, 4,
+        1, 2, 4,
+        2, 3, 4,
+        3, 0, 4,
+    ];
+    let normals: [f32; 15] = [
+        -0.58, 0.58,  0.58,
+        -0.58, 0.58, -0.58,
+         0.58, 0.58, -0.58,
+         0.58, 0.58,  0.58,
+         0.0,  1.0,   0.0,
+    ];
+
+    let pyramid = MeshData::new(indices.to_vec(), "wood")
+        .with_buffer(vertices.to_vec(), mesh::POSITION_LOCATION, 3)
+        .with_buffer(colors.to_vec(), mesh::COLOR_LOCATION, 3)
+        .with_buffer(texture_coords.to_vec(), mesh::TEXCOORD_LOCATION, 2)
+        .with_buffer(normals.to_vec(), mesh::NORMAL_LOCATION, 3);
+
+    let floor = MeshBuilder::plane(Vec2::splat(10.0), UVec2::ONE).scale_uvs(Vec2::splat(10.0)).build("wood");
+
+    let _ = world.spawn((CameraBundle {
+        position: Position {
+            d: Vec3::new(0.0, 0.0, 3.0),
+        },
+        direction: Rotation::default(),
+        camera: Camera {
+            front: Vec3::new(0.0, 0.0, 0.0),
+            up: Vec3::new(0.0, 1.0, 0.0),
+            yaw: 0.0,
+            pitch: 0.0,
+            first_mouse: true,
+            view: Mat4::IDENTITY,
+            projection: Mat4::perspective_rh_gl(
+            90.0_f32.to_radians(), 
+            settings.width as f32 / settings.height as f32, 
+            0.01, 
+            100.0),
+            ..Default::default()
+        },
+    }, PostProcess::default())).id();
+
+    //HUD camera drawing only layer 1 over the scene, one unit is one screen pixel
+    let _ = world.spawn((CameraBundle {
+        position: Position { d: Vec3::new(0.0, 0.0, 10.0) },
+        direction: Rotation::default(),
+        camera: Camera {
+            front: Vec3::NEG_Z,
+            up: Vec3::Y,
+            projection_mode: ProjectionMode::Orthographic { scaling: OrthographicScaling::PixelsPerUnit(1.0), pixel_perfect: true },
+            priority: 2,
+            clear: ClearSettings::depth_only(),
+            ..Default::default()
+        },
+    }, RenderLayers::layer(1)));
+    //Top down minimap in the bottom right corner, it also sees the pins on layer 2 the main camera leaves out
+    let _ = world.spawn((CameraBundle {
+        position: Position { d: Vec3::new(0.0, 20.0, 0.0) },
+        direction: Rotation::default(),
+        camera: Camera {
+            front: Vec3::NEG_Y,
+            up: Vec3::NEG_Z,
+            projection_mode: ProjectionMode::Orthographic { scaling: OrthographicScaling::FixedHeight(16.0), pixel_perfect: false },
+            viewport: Viewport::new(0.74, 0.04, 0.22, 0.22),
+            priority: 1,
+            ..Default::default()
+        },
+    }, RenderLayers::layer(0).with(2)));
+    let pin = MeshBuilder::cone(0.6, 0.5, 12, 1).with_color(Vec3::new(1.0, 0.3, 0.2)).build("wood");
+    world.spawn((pin, Name { d: "map pin".to_string() }, Position { d: Vec3::new(-4.5, 3.0, -2.0) }, RenderLayers::layer(2)));
+    //Side view of the tentacles rendered into the "monitor" texture before the main camera draws the screen showing it
+    let _ = world.spawn(CameraBundle {
+        position: Position { d: Vec3::new(-4.5, 1.0, 1.0) },
+        direction: Rotation::default(),
+        camera: Camera {
+            front: Vec3::NEG_Z,
+            up: Vec3::Y,
+            projection_mode: ProjectionMode::Orthographic { scaling: OrthographicScaling::FixedHeight(3.0), pixel_perfect: false },
+            target: RenderTarget::Texture { name: "monitor".to_string(), width: 256, height: 256 },
+            priority: -1,
+            ..Default::default()
+        },
+    });
+    let screen = MeshBuilder::plane(Vec2::splat(1.6), UVec2::ONE).transform(Mat4::from_rotation_x(std::f32::consts::FRAC_PI_2)).build("monitor");
+    world.spawn((screen, Name { d: "monitor".to_string() }, Position { d: Vec3::new(-7.0, 1.2, -2.0) }));
+    world.spawn((
+        Sprite { size: Some(Vec2::splat(32.0)), color: Vec4::new(1.0, 1.0, 1.0, 0.8), ..Sprite::new("planks_oak") },
+        Position::default(),
+        RenderLayers::layer(1),
+    ));
+    //The middle of the same texture, picked by region name from resources/atlases/planks.toml
+    world.spawn((
+        Sprite { size: Some(Vec2::splat(32.0)), frame: AtlasFrame::Name("center".to_string()), ..Sprite::new("planks") },
+        Position { d: Vec3::new(40.0, 0.0, 0.0) },
+        RenderLayers::layer(1),
+    ));
+    world.spawn((
+        Text {
+            max_width: Some(360.0),
+            ..Text::new("Butter Engine ", "DejaVuSans", 24.0)
+        }.with_span("SDF text with wrapping, kerning and colored spans. ", Vec4::new(1.0, 0.8, 0.3, 1.0))
+            .with_span("Ünïcödé ✓ Привет", Vec4::new(0.5, 0.8, 1.0, 1.0)),
+        Position { d: Vec3::new(16.0, settings.height as f32 - 16.0, 0.0) },
+        RenderLayers::layer(1),
+    ));
+    world.spawn((
+        Text {
+            alignment: TextAlignment::Right,
+            anchor: Vec2::new(1.0, 1.0),
+            ..Text::new("F1 next clip  F2 pause  F3 loop  F7/F8 step  F12 stop\nF4 excite  F5 pause graph  F6 restart graph", "DejaVuSans", 16.0)
+        },
+        Position { d: Vec3::new(settings.width as f32 - 16.0, settings.height as f32 - 16.0, 0.0) },
+        RenderLayers::layer(1),
+    ));
+    world.spawn((
+        Text {
+            space: TextSpace::World,
+            alignment: TextAlignment::Center,
+            anchor: Vec2::new(0.5, 0.0),
+            ..Text::new("Pyramid", "DejaVuSans", 0.25)
+        },
+        Position { d: Vec3::new(0.0, 1.1, 0.0) },
+    ));
+
+    //Props further away switch to simplified meshes
+    let prop = MeshLodData::generate(&pyramid, &settings.lod.chain);
+    for i in 1..=4 {
+        world.spawn((prop.clone(), Position { d: Vec3::new(-2.0, 0.0, -4.0 * i as f32) }));
+    }
+
+    world.spawn((ParticleEmitter::new("magic").with_surface(&pyramid), Position::default()));
+    world.spawn((ParticleEmitter::new("sparks"), RestartWhenFinished, Position { d: Vec3::new(2.0, 0.0, -1.0) }));
+    world.spawn((ParticleEmitter::new("smoke"), Position { d: Vec3::new(-2.0, 0.0, -1.0) }));
+    world.spawn((pyramid, Static, Name { d: "pyramid".to_string() }));
+    world.spawn((floor, Static, Name { d: "floor".to_string() }, Position { d: Vec3::new(0.0, -0.01, 0.0) }));
+
+    //Generated shapes, with tangents for normal mapped materials
+    let shapes = [
+        ("icosphere", MeshBuilder::icosphere(0.5, 3), Vec3::new(1.5, 0.5, 1.5)),
+        ("torus", MeshBuilder::torus(0.4, 0.15, 32, 16), Vec3::new(-1.5, 0.15, 1.5)),
+        ("capsule", MeshBuilder::capsule(0.25, 0.5, 24, 8), Vec3::new(3.0, 0.5, 0.0)),
+        ("cube", MeshBuilder::cube(Vec3::splat(0.5), 1), Vec3::new(-3.0, 0.25, 0.0)),
+        ("uv sphere", MeshBuilder::uv_sphere(0.5, 24, 12), Vec3::new(1.5, 0.5, 3.0)),
+        ("cylinder", MeshBuilder::cylinder(0.3, 0.8, 24, 1, true), Vec3::new(-1.5, 0.4, 3.0)),
+        ("cone", MeshBuilder::cone(0.35, 0.7, 24, 1), Vec3::new(0.0, 0.35, 3.0)),
+    ];
+    for (name, shape, position) in shapes {
+        world.spawn((shape.compute_tangents().build("wood"), Static, Name { d: name.to_string() }, Position { d: position }));
+    }
+
+    //A marker put together from tinted shapes, with a sign fading from white to red hand built on the post
+    let mut marker = MeshBuilder::cylinder(0.05, 1.0, 12, 1, true).with_color(Vec3::splat(0.8));
+    marker.append(&MeshBuilder::cone(0.15, 0.3, 12, 1).with_color(Vec3::new(1.0, 0.3, 0.2)).transform(Mat4::from_translation(Vec3::Y * 0.65)));
+    let corners = [(-0.3, 0.1), (0.3, 0.1), (0.3, 0.4), (-0.3, 0.4)]
+        .map(|(x, y)| marker.add_vertex(Vec3::new(x, y, 0.06), Vec3::Z, Vec2::new(x + 0.5, y)));
+    marker.add_quad(corners[0], corners[1], corners[2], corners[3]);
+    marker.set_color(corners[1], Vec3::new(1.0, 0.3, 0.2));
+    marker.set_color(corners[2], Vec3::new(1.0, 0.3, 0.2));
+    world.spawn((marker.compute_tangents().build("wood"), Static, Name { d: "marker".to_string() }, Position { d: Vec3::new(3.0, 0.5, 3.0) }));
+
+    //Terrain past the props, with a boulder resting wherever the heightmap puts the ground and a crate dropped
+    //from above the highest point, tilted to the slope it lands on
+    match Terrain::load("demo") {
+        Ok(terrain) => {
+            let origin = Vec3::new(0.0, -4.0, -52.0);
+            if let Some(height) = terrain.height_at(4.0, 18.0) {
+                let boulder = MeshBuilder::icosphere(1.0, 2).compute_tangents().build("wood");
+                world.spawn((boulder, Static, Name { d: "boulder".to_string() }, Position { d: origin + Vec3::new(4.0, height + 0.8, 18.0) }));
+            }
+            let (low, high) = terrain.height_range();
+            let drop = Vec3::new(-10.0, high + 1.0, 12.0);
+            if let Some(distance) = terrain.raycast(drop, Vec3::NEG_Y, high - low + 2.0) {
+                let ground = drop + Vec3::NEG_Y * distance;
+                let normal = terrain.normal_at(ground.x, ground.z).unwrap_or(Vec3::Y);
+                let (x, y, z) = Quat::from_rotation_arc(Vec3::Y, normal).to_euler(EulerRot::XYZ);
+                let crate_mesh = MeshBuilder::cube(Vec3::splat(1.0), 1).compute_tangents().build("wood");
+                world.spawn((crate_mesh, Static, Name { d: "crate".to_string() }, Position { d: origin + ground + normal * 0.5 }, Rotation { d: Vec3::new(x, y, z) }));
+            }
+            world.spawn((terrain, Name { d: "terrain".to_string() }, Position { d: origin }));
+        }
+        Err(error) => println!("Unable to load terrain demo: {}", error),
+    }
+
+    //Skinned models, one playing its clips in turn with F1 to F3, F7, F8 and F12 and one driven by an animation graph that F4 switches
+    //between states and F5 and F6 pause and restart.
+    //The first switches to simplified meshes further away, the joint weights carry over to every level.
+    match Model::load("tentacle") {
+        Ok(mut model) => {
+            model.generate_lods(&settings.lod.chain);
+            let player = model.clip("sway").map_or_else(AnimationPlayer::new, |clip| AnimationPlayer::new().with_clip(clip, true));
+            let graph = match AnimationGraph::load("tentacle") {
+                Ok(graph) => Some(Arc::new(graph)),
+                Err(error) => {
+                    println!("Unable to load animation graph tentacle: {}", error);
+                    None
+                }
+            };
+            for mesh in model.meshes {
+                let skinned = mesh.skin.map(|skin| SkinnedMesh::new(model.skeletons[skin].clone()));
+                let mut entity = world.spawn((Name { d: mesh.name.clone() }, Position { d: Vec3::new(-4.0, 0.0, -2.0) }));
+                match &mesh.lods {
+                    Some(lods) => entity.insert(lods.clone()),
+                    None => entity.insert(mesh.data.clone()),
+                };
+                if let Some(skinned) = &skinned {
+                    entity.insert((skinned.clone(), player.clone(), AnimationClips { clips: model.clips.clone() }));
+                }
+                if let (Some(skinned), Some(graph)) = (skinned, &graph) {
+                    let player = AnimationGraphPlayer::new(graph.clone());
+                    world.spawn((mesh.data, Name { d: format!("{} graph", mesh.name) }, Position { d: Vec3::new(-5.0, 0.0, -2.0) }, skinned, player));
+                }
+            }
+        }
+        Err(error) => println!("Unable to load model tentacle: {}", error),
+    }
+
+    //Morph targets animated by the file's weights channels, next to a copy posed by target name
+    match Model::load("blob") {
+        Ok(model) => {
+            let player = model.clip("breathe").map_or_else(AnimationPlayer::new, |clip| AnimationPlayer::new().with_clip(clip, true));
+            for mesh in model.meshes {
+                if let Some(mut morph) = mesh.morph.clone() {
+                    morph.set("spikes", 1.0);
+                    world.spawn((mesh.data.clone(), morph, Name { d: format!("{} spiked", mesh.name) }, Position { d: Vec3::new(-2.5, 0.0, -3.5) }));
+                }
+                let mut entity = world.spawn((mesh.data, Name { d: mesh.name }, Position { d: Vec3::new(-2.5, 0.0, -2.0) }));
+                if let Some(morph) = mesh.morph {
+                    entity.insert((morph, player.clone()));
+                }
+            }
+        }
+        Err(error) => println!("Unable to load model blob: {}", error),
+    }
+
+    world.spawn((DirectionalLight { intensity: 0.3, ..Default::default() }, Static));
+    world.spawn((
+        Position { d: Vec3::new(1.2, 1.5, 1.0) },
+        PointLight { color: Vec3::new(1.0, 0.85, 0.6), intensity: 4.0, range: 8.0, ..Default::default() },
+    ));
+}
+
 fn exit(control_flow: &mut ControlFlow, world: &mut World) {
     settings::save(world.get_resource::<Settings>().unwrap()).expect("Unable to save settings!");
     println!("Stopping...");
@@ -1,15 +1,69 @@
-use std::{fs, io::Error};
+use std::{error::Error, fs};
 
-use serde::{Serialize, Deserialize};
+use serde::{Deserialize, Deserializer, Serialize};
 
-#[derive(Serialize, Deserialize, Default)]
+use crate::backend;
+
+//Textures after a material's first are bound to tex1, tex2 and so on from this unit, past the ones the renderer reserves
+pub const MATERIAL_TEXTURE_UNIT: u32 = 10;
+pub const MAX_MATERIAL_TEXTURES: usize = 4;
+
+#[derive(Serialize, Deserialize)]
 pub struct Material {
     pub name: String,
-    pub textures: Vec<(String, MagnificationFilter)>,
+    #[serde(deserialize_with = "deserialize_textures")]
+    pub textures: Vec<(String, TextureOptions)>,
     pub shader: String,
+    //0 is a perfect mirror, 1 never receives screen space reflections
+    #[serde(default = "default_roughness")]
+    pub roughness: f32,
+    #[serde(default)]
+    pub blend_mode: BlendMode,
+    //Pixels with a lower texture alpha are discarded, only used by BlendMode::AlphaTest
+    #[serde(default = "default_alpha_cutoff")]
+    pub alpha_cutoff: f32,
+    //Splat mapped materials blend the layers of their second texture, a 2D array, by the RGBA weights of the first.
+    //The layers repeat this many times across the first texture.
+    #[serde(default)]
+    pub splat_tiling: Option<f32>,
+}
+
+fn default_roughness() -> f32 {
+    1.0
+}
+
+fn default_alpha_cutoff() -> f32 {
+    0.5
+}
+
+//Blended modes skip the G-buffer and are drawn after the opaque pass, furthest first
+#[derive(Serialize, Deserialize, Default, Clone, Copy, PartialEq, Debug)]
+pub enum BlendMode {
+    #[default]
+    Opaque,
+    AlphaTest,
+    AlphaBlend,
+    Additive,
+    Premultiplied,
+}
+
+impl BlendMode {
+    pub fn is_transparent(&self) -> bool {
+        !matches!(self, BlendMode::Opaque | BlendMode::AlphaTest)
+    }
+
+    pub fn apply(&self) {
+        backend::with(|backend| backend.set_blend_mode(*self));
+    }
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Self { name: String::new(), textures: Vec::new(), shader: String::new(), roughness: default_roughness(), blend_mode: BlendMode::Opaque, alpha_cutoff: default_alpha_cutoff(), splat_tiling: None }
+    }
 }
 
-#[derive(Serialize, Deserialize, Default, Clone, Copy)]
+#[derive(Serialize, Deserialize, Default, Clone, Copy, PartialEq, Debug)]
 pub enum MagnificationFilter {
     #[default]
     Linear,
@@ -23,13 +77,98 @@ pub fn to_gl_filter(filter: &MagnificationFilter) -> u32{
     }
 }
 
+#[derive(Serialize, Deserialize, Default, Clone, Copy, PartialEq, Debug)]
+pub enum MinificationFilter {
+    Nearest,
+    Linear,
+    NearestMipmapNearest,
+    LinearMipmapNearest,
+    NearestMipmapLinear,
+    #[default]
+    LinearMipmapLinear,
+}
+
+impl MinificationFilter {
+    pub fn uses_mipmaps(&self) -> bool {
+        !matches!(self, MinificationFilter::Nearest | MinificationFilter::Linear)
+    }
+}
+
+pub fn to_gl_min_filter(filter: &MinificationFilter) -> u32 {
+    match filter {
+        MinificationFilter::Nearest => gl::NEAREST,
+        MinificationFilter::Linear => gl::LINEAR,
+        MinificationFilter::NearestMipmapNearest => gl::NEAREST_MIPMAP_NEAREST,
+        MinificationFilter::LinearMipmapNearest => gl::LINEAR_MIPMAP_NEAREST,
+        MinificationFilter::NearestMipmapLinear => gl::NEAREST_MIPMAP_LINEAR,
+        MinificationFilter::LinearMipmapLinear => gl::LINEAR_MIPMAP_LINEAR,
+    }
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, Copy, PartialEq, Debug)]
+pub enum WrapMode {
+    #[default]
+    Repeat,
+    MirroredRepeat,
+    ClampToEdge,
+}
+
+pub fn to_gl_wrap(wrap: &WrapMode) -> u32 {
+    match wrap {
+        WrapMode::Repeat => gl::REPEAT,
+        WrapMode::MirroredRepeat => gl::MIRRORED_REPEAT,
+        WrapMode::ClampToEdge => gl::CLAMP_TO_EDGE,
+    }
+}
+
+//Color textures authored in sRGB are decoded to linear when sampled, data textures like normal maps stay linear.
+//Float formats are always linear.
+#[derive(Serialize, Deserialize, Default, Clone, Copy, PartialEq)]
+pub enum ColorSpace {
+    #[default]
+    Linear,
+    Srgb,
+}
+
+//How a material samples one of its textures
+#[derive(Serialize, Deserialize, Default, Clone)]
+#[serde(default)]
+pub struct TextureOptions {
+    pub mag_filter: MagnificationFilter,
+    pub min_filter: MinificationFilter,
+    pub wrap: WrapMode,
+    pub color_space: ColorSpace,
+    //Further textures stacked after the named one into a 2D array
+    pub layers: Vec<String>,
+}
+
+//Materials written before TextureOptions only name the magnification filter, ["planks_oak", "Nearest"]
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum TextureEntry {
+    Options(TextureOptions),
+    Filter(MagnificationFilter),
+}
+
+fn deserialize_textures<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<(String, TextureOptions)>, D::Error> {
+    let textures: Vec<(String, TextureEntry)> = Vec::deserialize(deserializer)?;
+    Ok(textures.into_iter().map(|(name, entry)| match entry {
+        TextureEntry::Options(options) => (name, options),
+        TextureEntry::Filter(mag_filter) => (name, TextureOptions { mag_filter, ..Default::default() }),
+    }).collect())
+}
+
 impl Material {
-    pub fn new(name: &str) -> Result<Material, Error> {
-        let file = fs::read_to_string(format!("resources/materials/{}.toml", &name));
-        match file {
-            Ok(file_string) => Ok(toml::from_str(&file_string).unwrap_or_default()),
-            Err(error) => Err(error),
-        }
+    //Alpha below which pixels are discarded, 0 keeps every pixel
+    pub fn cutoff(&self) -> f32 {
+        if self.blend_mode == BlendMode::AlphaTest { self.alpha_cutoff } else { 0.0 }
+    }
+    pub fn new(name: &str) -> Result<Material, Box<dyn Error>> {
+        let file_string = fs::read_to_string(format!("resources/materials/{}.toml", &name))?;
+        Ok(Self::parse(&file_string)?)
+    }
+    pub fn parse(toml: &str) -> Result<Material, toml::de::Error> {
+        toml::from_str(toml)
     }
     // pub fn save(&self) {
     //     match fs::write(format!("resources/materials/{}.toml", self.name), toml::to_string(&self).expect("Failed to serialize settings!")) {
@@ -37,4 +176,50 @@ impl Material {
     //         Err(_) => Err("Failed to save settings!"),
     //     };
     // } //TODO: Make a UI material editor
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_texture_options() {
+        let material = Material::parse(r#"
+            name = "wood"
+            textures = [["planks_oak", { mag_filter = "Nearest", wrap = "ClampToEdge" }]]
+            shader = "default"
+        "#).unwrap();
+        let (name, options) = &material.textures[0];
+        assert_eq!(name, "planks_oak");
+        assert_eq!(options.mag_filter, MagnificationFilter::Nearest);
+        assert_eq!(options.wrap, WrapMode::ClampToEdge);
+        assert_eq!(options.min_filter, MinificationFilter::LinearMipmapLinear);
+    }
+
+    #[test]
+    fn reads_the_filter_only_form() {
+        let material = Material::parse(r#"
+            name = "wood"
+            textures = [["planks_oak", "Nearest"], ["grass", "Linear"]]
+            shader = "default"
+        "#).unwrap();
+        assert_eq!(material.textures.len(), 2);
+        assert_eq!(material.textures[0].1.mag_filter, MagnificationFilter::Nearest);
+        assert_eq!(material.textures[1].1.mag_filter, MagnificationFilter::Linear);
+        assert!(material.textures[0].1.layers.is_empty());
+    }
+
+    #[test]
+    fn reports_invalid_files() {
+        assert!(Material::parse("name = \"wood\"\ntextures = [[\"planks_oak\", \"Blurry\"]]\nshader = \"default\"").is_err());
+        assert!(Material::parse("name = \"wood\"").is_err());
+    }
+
+    #[test]
+    fn reads_every_material() {
+        for entry in fs::read_dir("resources/materials").unwrap() {
+            let path = entry.unwrap().path();
+            Material::parse(&fs::read_to_string(&path).unwrap()).unwrap_or_else(|error| panic!("{}: {}", path.display(), error));
+        }
+    }
+}
@@ -1,38 +1,171 @@
-use std::ptr;
+use std::sync::Arc;
 
-use bevy_ecs::prelude::Component;
+use bevy_ecs::prelude::*;
+use glam::*;
 
-use crate::{renderer::{VAO, VBO, IBO, GPUObject}};
+use crate::{backend::{self, DrawCommand, Primitive}, renderer::{VAO, VBO, IBO, Instance, InstanceBuffer}, culling::Bounds, morph::{MorphTarget, MorphTargetBuffer}};
 
-#[derive(Component)]
-pub struct Mesh {
+//Attribute locations shared by every mesh and shader
+pub const POSITION_LOCATION: u32 = 0;
+pub const COLOR_LOCATION: u32 = 1;
+pub const TEXCOORD_LOCATION: u32 = 2;
+pub const NORMAL_LOCATION: u32 = 3;
+pub const LIGHTMAP_UV_LOCATION: u32 = 4;
+
+//Per-instance model matrix, a mat4 attribute so it takes locations 5 to 8
+pub const MODEL_LOCATION: u32 = 5;
+pub const LOD_FADE_LOCATION: u32 = 9;
+//vec4, w is the handedness of the bitangent
+pub const TANGENT_LOCATION: u32 = 10;
+//Skinning influences, joint indices are stored as floats
+pub const JOINTS_LOCATION: u32 = 11;
+pub const WEIGHTS_LOCATION: u32 = 12;
+//Per-instance offset into the skinning palette
+pub const SKIN_OFFSET_LOCATION: u32 = 13;
+//Per-instance offset into the morph weight palette
+pub const MORPH_OFFSET_LOCATION: u32 = 14;
+
+//GPU buffers shared by every Mesh cloned from the same upload
+struct Geometry {
     vao: VAO,
     ibo: IBO,
-    buffers: Vec<VBO>,
+    //Only kept alive for the VAO, which references them
+    _buffers: Vec<VBO>,
+    instances: InstanceBuffer,
+    bounds: Bounds,
+    morph_targets: Option<MorphTargetBuffer>,
+}
+
+//Cloning a mesh is cheap and keeps pointing at the same GPU buffers, which lets the render queue instance it
+#[derive(Component, Clone)]
+pub struct Mesh {
+    geometry: Arc<Geometry>,
     pub material: String,
 }
 
 impl Mesh {
-    pub fn new(indices: Vec<i32>, material: &str) -> Mesh {
+    pub fn from_data(data: &MeshData) -> Mesh {
         let vao: VAO = VAO::new();
-        let ibo: IBO = IBO::new(indices, &vao);
-        let buffers: Vec<VBO> = Vec::new();
-
-        return Mesh { vao, ibo, buffers, material: material.to_string()};
-    }
-    pub fn add_buffer(&mut self, data: Vec<f32>, index: u32, size: i32) {
-        self.buffers.push(VBO::new(data, index, size, &self.vao));
-    }
-    pub fn render(&self) {
-        self.vao.bind();
-        unsafe {
-            gl::DrawElements(
-                gl::TRIANGLES,
-                self.ibo.get_indices().len() as i32,
-                gl::UNSIGNED_INT,
-                ptr::null(),
-            );
+        let ibo: IBO = IBO::new(data.indices.clone(), &vao);
+        let instances = InstanceBuffer::new(MODEL_LOCATION, LOD_FADE_LOCATION, SKIN_OFFSET_LOCATION, MORPH_OFFSET_LOCATION, &vao);
+        let mut bounds = Bounds::default();
+        let mut buffers: Vec<VBO> = Vec::new();
+        for (location, size, buffer) in &data.buffers {
+            if *location == POSITION_LOCATION {
+                bounds = Bounds::from_positions(buffer, *size as usize);
+            }
+            buffers.push(VBO::new(buffer.clone(), *location, *size, &vao));
+        }
+        //Fully applied targets can move vertices outside the rest shape
+        let morph_targets = if data.morph_targets.is_empty() {
+            None
+        } else {
+            let reach = Vec3::splat(data.morph_targets.iter().map(MorphTarget::max_offset).sum());
+            bounds = Bounds { min: bounds.min - reach, max: bounds.max + reach };
+            Some(MorphTargetBuffer::new(&data.morph_targets, data.vertex_count(), &data.material))
+        };
+
+        vao.label(&format!("{} vertex array", data.material));
+        ibo.label(&format!("{} indices", data.material));
+        instances.label(&format!("{} instances", data.material));
+        for (buffer, (location, _, _)) in buffers.iter().zip(&data.buffers) {
+            buffer.label(&format!("{} attribute {}", data.material, location));
+        }
+
+        let geometry = Geometry { vao, ibo, _buffers: buffers, instances, bounds, morph_targets };
+        Mesh { geometry: Arc::new(geometry), material: data.material.clone() }
+    }
+    //Same geometry drawn with another material
+    pub fn with_material(&self, material: &str) -> Mesh {
+        Mesh { geometry: self.geometry.clone(), material: material.to_string() }
+    }
+    //Identifies the shared geometry, equal for clones
+    pub fn id(&self) -> usize {
+        Arc::as_ptr(&self.geometry) as usize
+    }
+    pub fn bounds(&self) -> &Bounds {
+        &self.geometry.bounds
+    }
+    pub fn morph_target_count(&self) -> usize {
+        self.geometry.morph_targets.as_ref().map_or(0, MorphTargetBuffer::count)
+    }
+    pub fn render(&self, instances: &[Instance]) {
+        if instances.is_empty() {
+            return;
+        }
+        let geometry = &self.geometry;
+        geometry.instances.upload(instances);
+        if let Some(morph_targets) = &geometry.morph_targets {
+            morph_targets.bind();
+        }
+        let draw = DrawCommand {
+            vertex_array: geometry.vao.handle(),
+            primitive: Primitive::Triangles,
+            first: 0,
+            count: geometry.ibo.get_indices().len() as i32,
+            instances: instances.len() as i32,
+            indexed: true,
+        };
+        backend::with(|backend| backend.submit(&[draw]));
+    }
+}
+
+//CPU side copy of a mesh, can be built and inspected without a GL context (e.g. when baking)
+#[derive(Component, Clone, Default, PartialEq)]
+pub struct MeshData {
+    pub indices: Vec<i32>,
+    pub buffers: Vec<(u32, i32, Vec<f32>)>, //location, components per vertex, data
+    pub material: String,
+    //Offsets for the vertices in POSITION_LOCATION order, see morph.glsl
+    pub morph_targets: Vec<MorphTarget>,
+}
+
+impl MeshData {
+    pub fn new(indices: Vec<i32>, material: &str) -> MeshData {
+        MeshData { indices, buffers: Vec::new(), material: material.to_string(), morph_targets: Vec::new() }
+    }
+    pub fn with_buffer(mut self, data: Vec<f32>, location: u32, size: i32) -> MeshData {
+        self.set_buffer(data, location, size);
+        self
+    }
+    pub fn set_buffer(&mut self, data: Vec<f32>, location: u32, size: i32) {
+        self.buffers.retain(|(l, _, _)| *l != location);
+        self.buffers.push((location, size, data));
+    }
+    pub fn buffer(&self, location: u32) -> Option<(i32, &Vec<f32>)> {
+        self.buffers.iter().find(|(l, _, _)| *l == location).map(|(_, size, data)| (*size, data))
+    }
+    pub fn vertex_count(&self) -> usize {
+        self.buffer(POSITION_LOCATION).map_or(0, |(size, data)| data.len() / size as usize)
+    }
+}
+
+//Uploads MeshData, handing out clones of an earlier upload when the geometry is identical so they can be instanced
+#[derive(Default)]
+pub struct MeshCache<'a> {
+    uploaded: Vec<(&'a MeshData, Mesh)>,
+}
+
+impl<'a> MeshCache<'a> {
+    pub fn upload(&mut self, data: &'a MeshData) -> Mesh {
+        let shared = self.uploaded.iter().find(|(other, _)| other.indices == data.indices && other.buffers == data.buffers && other.morph_targets == data.morph_targets);
+        match shared {
+            Some((_, mesh)) => mesh.with_material(&data.material),
+            None => {
+                let mesh = Mesh::from_data(data);
+                self.uploaded.push((data, mesh.clone()));
+                mesh
+            }
         }
-        self.vao.unbind();
     }
-}
\ No newline at end of file
+}
+
+//Turns every MeshData into a GPU Mesh, must run once a GL context exists
+pub fn upload_meshes(world: &mut World) {
+    let mut query = world.query_filtered::<(Entity, &MeshData), Without<Mesh>>();
+    let mut cache = MeshCache::default();
+    let uploads: Vec<(Entity, Mesh)> = query.iter(world).map(|(entity, data)| (entity, cache.upload(data))).collect();
+    for (entity, mesh) in uploads {
+        world.entity_mut(entity).insert(mesh).remove::<MeshData>();
+    }
+}
@@ -0,0 +1,553 @@
+use std::{collections::HashMap, f32::consts::{PI, TAU}};
+
+use glam::*;
+
+use crate::mesh::{self, Mesh, MeshData};
+
+//Collects vertices and triangles, then turns them into MeshData. Front faces wind counterclockwise, so a triangle
+//a, b, c faces along (b - a) x (c - a). Colors and tangents are only emitted once something sets them.
+#[derive(Clone, Default)]
+pub struct MeshBuilder {
+    positions: Vec<Vec3>,
+    normals: Vec<Vec3>,
+    uvs: Vec<Vec2>,
+    //xyz along +u, w is the handedness of the bitangent, which is w * cross(normal, tangent)
+    tangents: Vec<Vec4>,
+    colors: Vec<Vec3>,
+    //Buffers at other locations, e.g. lightmap UVs, carried along untouched
+    extra: Vec<(u32, i32, Vec<f32>)>,
+    indices: Vec<i32>,
+}
+
+impl MeshBuilder {
+    pub fn new() -> MeshBuilder {
+        MeshBuilder::default()
+    }
+
+    //Picks up the attributes it knows from existing data, e.g. to add tangents to a hand written mesh
+    pub fn from_data(data: &MeshData) -> MeshBuilder {
+        let mut builder = MeshBuilder { indices: data.indices.clone(), ..Default::default() };
+        for (location, size, buffer) in &data.buffers {
+            match (*location, *size) {
+                (mesh::POSITION_LOCATION, 3) => builder.positions = buffer.chunks_exact(3).map(Vec3::from_slice).collect(),
+                (mesh::NORMAL_LOCATION, 3) => builder.normals = buffer.chunks_exact(3).map(Vec3::from_slice).collect(),
+                (mesh::TEXCOORD_LOCATION, 2) => builder.uvs = buffer.chunks_exact(2).map(Vec2::from_slice).collect(),
+                (mesh::TANGENT_LOCATION, 4) => builder.tangents = buffer.chunks_exact(4).map(Vec4::from_slice).collect(),
+                (mesh::COLOR_LOCATION, 3) => builder.colors = buffer.chunks_exact(3).map(Vec3::from_slice).collect(),
+                _ => builder.extra.push((*location, *size, buffer.clone())),
+            }
+        }
+        let count = builder.positions.len();
+        builder.normals.resize(count, Vec3::Y);
+        builder.uvs.resize(count, Vec2::ZERO);
+        builder
+    }
+
+    pub fn vertex_count(&self) -> usize {
+        self.positions.len()
+    }
+
+    //Returns the index of the new vertex
+    pub fn add_vertex(&mut self, position: Vec3, normal: Vec3, uv: Vec2) -> u32 {
+        self.positions.push(position);
+        self.normals.push(normal);
+        self.uvs.push(uv);
+        if !self.tangents.is_empty() {
+            self.tangents.push(Vec4::new(1.0, 0.0, 0.0, 1.0));
+        }
+        if !self.colors.is_empty() {
+            self.colors.push(Vec3::ONE);
+        }
+        for (_, size, buffer) in &mut self.extra {
+            buffer.extend(std::iter::repeat_n(0.0, *size as usize));
+        }
+        self.vertex_count() as u32 - 1
+    }
+
+    pub fn add_triangle(&mut self, a: u32, b: u32, c: u32) {
+        self.indices.extend([a as i32, b as i32, c as i32]);
+    }
+
+    //Corners in counterclockwise order
+    pub fn add_quad(&mut self, a: u32, b: u32, c: u32, d: u32) {
+        self.add_triangle(a, b, c);
+        self.add_triangle(a, c, d);
+    }
+
+    //Vertices without a color are white
+    pub fn set_color(&mut self, vertex: u32, color: Vec3) {
+        self.colors.resize(self.positions.len(), Vec3::ONE);
+        self.colors[vertex as usize] = color;
+    }
+
+    pub fn with_color(mut self, color: Vec3) -> MeshBuilder {
+        self.colors = vec![color; self.positions.len()];
+        self
+    }
+
+    //Tiles the texture, e.g. 10 repeats it ten times across
+    pub fn scale_uvs(mut self, scale: Vec2) -> MeshBuilder {
+        self.uvs.iter_mut().for_each(|uv| *uv *= scale);
+        self
+    }
+
+    //Moves every vertex, normals and tangents follow the inverse transpose so non uniform scaling keeps them right
+    pub fn transform(mut self, transform: Mat4) -> MeshBuilder {
+        let normal_matrix = Mat3::from_mat4(transform).inverse().transpose();
+        let tangent_matrix = Mat3::from_mat4(transform);
+        for position in &mut self.positions {
+            *position = transform.transform_point3(*position);
+        }
+        for normal in &mut self.normals {
+            *normal = (normal_matrix * *normal).normalize_or_zero();
+        }
+        for tangent in &mut self.tangents {
+            *tangent = (tangent_matrix * tangent.xyz()).normalize_or_zero().extend(tangent.w);
+        }
+        //A mirroring transform flips the winding as well
+        if transform.determinant() < 0.0 {
+            self.indices.chunks_exact_mut(3).for_each(|triangle| triangle.swap(1, 2));
+        }
+        self
+    }
+
+    //Adds the other mesh's vertices and triangles, attributes only one side has are filled with defaults
+    pub fn append(&mut self, other: &MeshBuilder) {
+        let offset = self.vertex_count() as i32;
+        if !other.colors.is_empty() {
+            self.colors.resize(self.positions.len(), Vec3::ONE);
+        }
+        if !other.tangents.is_empty() {
+            self.tangents.resize(self.positions.len(), Vec4::new(1.0, 0.0, 0.0, 1.0));
+        }
+        for index in 0..other.positions.len() {
+            let vertex = self.add_vertex(other.positions[index], other.normals[index], other.uvs[index]);
+            if let Some(color) = other.colors.get(index) {
+                self.colors[vertex as usize] = *color;
+            }
+            if let Some(tangent) = other.tangents.get(index) {
+                self.tangents[vertex as usize] = *tangent;
+            }
+        }
+        self.indices.extend(other.indices.iter().map(|index| index + offset));
+    }
+
+    pub fn build(&self, material: &str) -> MeshData {
+        let flatten3 = |values: &[Vec3]| values.iter().flat_map(|value| value.to_array()).collect::<Vec<f32>>();
+        let mut data = MeshData::new(self.indices.clone(), material)
+            .with_buffer(flatten3(&self.positions), mesh::POSITION_LOCATION, 3)
+            .with_buffer(flatten3(&self.normals), mesh::NORMAL_LOCATION, 3)
+            .with_buffer(self.uvs.iter().flat_map(|uv| uv.to_array()).collect(), mesh::TEXCOORD_LOCATION, 2);
+        if !self.colors.is_empty() {
+            data.set_buffer(flatten3(&self.colors), mesh::COLOR_LOCATION, 3);
+        }
+        if !self.tangents.is_empty() {
+            data.set_buffer(self.tangents.iter().flat_map(|tangent| tangent.to_array()).collect(), mesh::TANGENT_LOCATION, 4);
+        }
+        for (location, size, buffer) in &self.extra {
+            data.set_buffer(buffer.clone(), *location, *size);
+        }
+        data
+    }
+
+    //Needs a GL context, MeshData from build can be spawned instead and is uploaded with the rest of the scene
+    pub fn upload(&self, material: &str) -> Mesh {
+        Mesh::from_data(&self.build(material))
+    }
+}
+
+//Shapes, all centered on the origin with Y up
+impl MeshBuilder {
+    //Flat on the XZ plane facing +Y, UVs run along +X and -Z
+    pub fn plane(size: Vec2, subdivisions: UVec2) -> MeshBuilder {
+        let mut builder = MeshBuilder::new();
+        builder.add_patch(Vec3::new(-size.x / 2.0, 0.0, size.y / 2.0), Vec3::X * size.x, Vec3::NEG_Z * size.y, subdivisions);
+        builder
+    }
+
+    //Six separately textured faces, each split into subdivisions x subdivisions quads
+    pub fn cube(size: Vec3, subdivisions: u32) -> MeshBuilder {
+        let mut builder = MeshBuilder::new();
+        let half = size / 2.0;
+        //Face normal and the two edge directions, chosen so that u x v is the normal
+        let faces = [
+            (Vec3::X, Vec3::NEG_Z, Vec3::Y),
+            (Vec3::NEG_X, Vec3::Z, Vec3::Y),
+            (Vec3::Y, Vec3::X, Vec3::NEG_Z),
+            (Vec3::NEG_Y, Vec3::X, Vec3::Z),
+            (Vec3::Z, Vec3::X, Vec3::Y),
+            (Vec3::NEG_Z, Vec3::NEG_X, Vec3::Y),
+        ];
+        for (normal, u, v) in faces {
+            let (u, v) = (u * size, v * size);
+            let origin = normal * half - u / 2.0 - v / 2.0;
+            builder.add_patch(origin, u, v, UVec2::splat(subdivisions.max(1)));
+        }
+        builder
+    }
+
+    //Sectors around Y, stacks from pole to pole
+    pub fn uv_sphere(radius: f32, sectors: u32, stacks: u32) -> MeshBuilder {
+        let (sectors, stacks) = (sectors.max(3), stacks.max(2));
+        let mut builder = MeshBuilder::new();
+        builder.add_grid(sectors, stacks, |i, j| {
+            let (u, v) = (i as f32 / sectors as f32, j as f32 / stacks as f32);
+            let normal = around_y(u * TAU, -PI / 2.0 + v * PI);
+            (normal * radius, normal, Vec2::new(u, v))
+        });
+        builder
+    }
+
+    //Even triangles all over, from an icosahedron split `subdivisions` times. UVs are mapped like a UV sphere,
+    //vertices on the seam are doubled so no triangle wraps around the texture.
+    pub fn icosphere(radius: f32, subdivisions: u32) -> MeshBuilder {
+        let t = (1.0 + 5.0f32.sqrt()) / 2.0;
+        let mut positions: Vec<Vec3> = [
+            (-1.0, t, 0.0), (1.0, t, 0.0), (-1.0, -t, 0.0), (1.0, -t, 0.0),
+            (0.0, -1.0, t), (0.0, 1.0, t), (0.0, -1.0, -t), (0.0, 1.0, -t),
+            (t, 0.0, -1.0), (t, 0.0, 1.0), (-t, 0.0, -1.0), (-t, 0.0, 1.0),
+        ].iter().map(|(x, y, z)| Vec3::new(*x, *y, *z).normalize()).collect();
+        let mut triangles: Vec<[u32; 3]> = vec![
+            [0, 11, 5], [0, 5, 1], [0, 1, 7], [0, 7, 10], [0, 10, 11],
+            [1, 5, 9], [5, 11, 4], [11, 10, 2], [10, 7, 6], [7, 1, 8],
+            [3, 9, 4], [3, 4, 2], [3, 2, 6], [3, 6, 8], [3, 8, 9],
+            [4, 9, 5], [2, 4, 11], [6, 2, 10], [8, 6, 7], [9, 8, 1],
+        ];
+        for _ in 0..subdivisions {
+            let mut midpoints: HashMap<(u32, u32), u32> = HashMap::new();
+            let mut midpoint = |a: u32, b: u32, positions: &mut Vec<Vec3>| {
+                *midpoints.entry((a.min(b), a.max(b))).or_insert_with(|| {
+                    positions.push((positions[a as usize] + positions[b as usize]).normalize());
+                    positions.len() as u32 - 1
+                })
+            };
+            let mut split = Vec::with_capacity(triangles.len() * 4);
+            for [a, b, c] in triangles {
+                let (ab, bc, ca) = (midpoint(a, b, &mut positions), midpoint(b, c, &mut positions), midpoint(c, a, &mut positions));
+                split.extend([[a, ab, ca], [b, bc, ab], [c, ca, bc], [ab, bc, ca]]);
+            }
+            triangles = split;
+        }
+
+        let mut builder = MeshBuilder::new();
+        for position in &positions {
+            builder.add_vertex(*position * radius, *position, sphere_uv(*position));
+        }
+        //Copies of seam vertices with u past 1
+        let mut wrapped: HashMap<u32, u32> = HashMap::new();
+        for mut triangle in triangles {
+            //Wound outwards regardless of how the table above is ordered
+            let [a, b, c] = triangle.map(|index| positions[index as usize]);
+            if (b - a).cross(c - a).dot(a + b + c) < 0.0 {
+                triangle.swap(1, 2);
+            }
+            let us = triangle.map(|index| builder.uvs[index as usize].x);
+            if us.iter().cloned().fold(f32::MIN, f32::max) - us.iter().cloned().fold(f32::MAX, f32::min) > 0.5 {
+                for corner in &mut triangle {
+                    if builder.uvs[*corner as usize].x < 0.5 {
+                        *corner = *wrapped.entry(*corner).or_insert_with(|| {
+                            let index = *corner as usize;
+                            let uv = builder.uvs[index] + Vec2::X;
+                            builder.add_vertex(builder.positions[index], builder.normals[index], uv)
+                        });
+                    }
+                }
+            }
+            builder.add_triangle(triangle[0], triangle[1], triangle[2]);
+        }
+        builder
+    }
+
+    //Axis along Y, caps are separate vertices so their edges stay sharp
+    pub fn cylinder(radius: f32, height: f32, sectors: u32, stacks: u32, caps: bool) -> MeshBuilder {
+        let (sectors, stacks) = (sectors.max(3), stacks.max(1));
+        let mut builder = MeshBuilder::new();
+        builder.add_grid(sectors, stacks, |i, j| {
+            let (u, v) = (i as f32 / sectors as f32, j as f32 / stacks as f32);
+            let normal = around_y(u * TAU, 0.0);
+            (normal * radius + Vec3::Y * (v - 0.5) * height, normal, Vec2::new(u, v))
+        });
+        if caps {
+            builder.add_disc(radius, height / 2.0, sectors, true);
+            builder.add_disc(radius, -height / 2.0, sectors, false);
+        }
+        builder
+    }
+
+    //Base at -height / 2, tip at +height / 2
+    pub fn cone(radius: f32, height: f32, sectors: u32, stacks: u32) -> MeshBuilder {
+        let (sectors, stacks) = (sectors.max(3), stacks.max(1));
+        let mut builder = MeshBuilder::new();
+        //Perpendicular to the slope, which runs radius inwards for every height upwards
+        let slope = Vec2::new(height, radius).normalize();
+        builder.add_grid(sectors, stacks, |i, j| {
+            let (u, v) = (i as f32 / sectors as f32, j as f32 / stacks as f32);
+            let radial = around_y(u * TAU, 0.0);
+            let position = radial * radius * (1.0 - v) + Vec3::Y * (v - 0.5) * height;
+            (position, radial * slope.x + Vec3::Y * slope.y, Vec2::new(u, v))
+        });
+        builder.add_disc(radius, -height / 2.0, sectors, false);
+        builder
+    }
+
+    //A cylinder of `height` between two hemispheres, `rings` per hemisphere. V runs evenly along the whole length.
+    pub fn capsule(radius: f32, height: f32, sectors: u32, rings: u32) -> MeshBuilder {
+        let (sectors, rings) = (sectors.max(3), rings.max(1));
+        let length = height + 2.0 * radius;
+        let mut builder = MeshBuilder::new();
+        //Rows 0..=rings are the bottom hemisphere up to the equator, the rest start at the equator of the top one
+        builder.add_grid(sectors, 2 * rings + 1, |i, j| {
+            let (half, ring) = if j <= rings { (-0.5, j) } else { (0.5, j - 1) };
+            let latitude = -PI / 2.0 + ring as f32 / rings as f32 * PI / 2.0;
+            let u = i as f32 / sectors as f32;
+            let normal = around_y(u * TAU, latitude.min(PI / 2.0));
+            let position = normal * radius + Vec3::Y * half * height;
+            (position, normal, Vec2::new(u, (position.y + length / 2.0) / length))
+        });
+        builder
+    }
+
+    //Ring in the XZ plane, `major_radius` to the middle of the tube
+    pub fn torus(major_radius: f32, minor_radius: f32, major_segments: u32, minor_segments: u32) -> MeshBuilder {
+        let (major_segments, minor_segments) = (major_segments.max(3), minor_segments.max(3));
+        let mut builder = MeshBuilder::new();
+        builder.add_grid(major_segments, minor_segments, |i, j| {
+            let (u, v) = (i as f32 / major_segments as f32, j as f32 / minor_segments as f32);
+            let radial = around_y(u * TAU, 0.0);
+            let normal = around_y(u * TAU, v * TAU);
+            (radial * major_radius + normal * minor_radius, normal, Vec2::new(u, v))
+        });
+        builder
+    }
+
+    //Flat grid from `origin` along u and v, facing u x v
+    fn add_patch(&mut self, origin: Vec3, u: Vec3, v: Vec3, subdivisions: UVec2) {
+        let normal = u.cross(v).normalize();
+        let (columns, rows) = (subdivisions.x.max(1), subdivisions.y.max(1));
+        self.add_grid(columns, rows, |i, j| {
+            let uv = Vec2::new(i as f32 / columns as f32, j as f32 / rows as f32);
+            (origin + u * uv.x + v * uv.y, normal, uv)
+        });
+    }
+
+    //(columns + 1) x (rows + 1) vertices from `vertex`, quads wound so that increasing column then row is
+    //counterclockwise. Triangles collapsed to a point or line, like the ones at a sphere's poles, are left out.
+    fn add_grid(&mut self, columns: u32, rows: u32, vertex: impl Fn(u32, u32) -> (Vec3, Vec3, Vec2)) {
+        let first = self.positions.len() as u32;
+        for j in 0..=rows {
+            for i in 0..=columns {
+                let (position, normal, uv) = vertex(i, j);
+                self.add_vertex(position, normal, uv);
+            }
+        }
+        let index = |i: u32, j: u32| first + j * (columns + 1) + i;
+        for j in 0..rows {
+            for i in 0..columns {
+                let (a, b, c, d) = (index(i, j), index(i + 1, j), index(i + 1, j + 1), index(i, j + 1));
+                for [a, b, c] in [[a, b, c], [a, c, d]] {
+                    let [pa, pb, pc] = [a, b, c].map(|index| self.positions[index as usize]);
+                    if (pb - pa).cross(pc - pa).length_squared() > 1e-12 {
+                        self.add_triangle(a, b, c);
+                    }
+                }
+            }
+        }
+    }
+
+    //Flat disc at height y facing up or down, UVs map the unit square onto it
+    fn add_disc(&mut self, radius: f32, y: f32, sectors: u32, up: bool) {
+        let normal = if up { Vec3::Y } else { Vec3::NEG_Y };
+        let center = self.add_vertex(Vec3::Y * y, normal, Vec2::splat(0.5));
+        let first = self.positions.len() as u32;
+        for i in 0..=sectors {
+            let direction = around_y(i as f32 / sectors as f32 * TAU, 0.0);
+            self.add_vertex(direction * radius + Vec3::Y * y, normal, Vec2::new(0.5 + direction.x * 0.5, 0.5 - direction.z * 0.5));
+        }
+        for i in 0..sectors {
+            if up {
+                self.add_triangle(center, first + i, first + i + 1);
+            } else {
+                self.add_triangle(center, first + i + 1, first + i);
+            }
+        }
+    }
+}
+
+//Unit vector `angle` around Y counterclockwise seen from above starting at +X, raised by `latitude`
+fn around_y(angle: f32, latitude: f32) -> Vec3 {
+    Vec3::new(latitude.cos() * angle.cos(), latitude.sin(), -latitude.cos() * angle.sin())
+}
+
+//Inverse of around_y mapped to 0..1, matching uv_sphere
+fn sphere_uv(direction: Vec3) -> Vec2 {
+    let u = (-direction.z).atan2(direction.x).rem_euclid(TAU) / TAU;
+    let v = direction.y.clamp(-1.0, 1.0).asin() / PI + 0.5;
+    Vec2::new(u, v)
+}
+
+//Normal and tangent generation
+impl MeshBuilder {
+    //Area weighted average of the faces around each position. Vertices at the same position share the result,
+    //so UV seams don't show up as creases.
+    pub fn compute_smooth_normals(mut self) -> MeshBuilder {
+        let key = |position: Vec3| (position * 1e4).round().as_ivec3().to_array();
+        let mut sums: HashMap<[i32; 3], Vec3> = HashMap::new();
+        for triangle in self.indices.chunks_exact(3) {
+            let [a, b, c] = [triangle[0], triangle[1], triangle[2]].map(|index| self.positions[index as usize]);
+            //Not normalized, so larger faces count more
+            let face = (b - a).cross(c - a);
+            for position in [a, b, c] {
+                *sums.entry(key(position)).or_insert(Vec3::ZERO) += face;
+            }
+        }
+        for (position, normal) in self.positions.iter().zip(&mut self.normals) {
+            *normal = sums.get(&key(*position)).map_or(Vec3::Y, |sum| sum.normalize_or_zero());
+        }
+        self
+    }
+
+    //Per vertex tangents the way MikkTSpace builds them: each corner contributes its face tangent projected onto
+    //the vertex normal and weighted by the corner angle, and corners whose UVs are mirrored never average with
+    //unmirrored ones, the vertex is split instead. MikkTSpace's handling of degenerate UVs is simplified to
+    //picking any direction perpendicular to the normal.
+    pub fn compute_tangents(mut self) -> MeshBuilder {
+        //Sums per vertex and handedness
+        let mut sums: HashMap<(u32, bool), Vec3> = HashMap::new();
+        for triangle in self.indices.chunks_exact(3) {
+            let corners = [triangle[0] as u32, triangle[1] as u32, triangle[2] as u32];
+            let [p0, p1, p2] = corners.map(|index| self.positions[index as usize]);
+            let [t0, t1, t2] = corners.map(|index| self.uvs[index as usize]);
+            let (e1, e2) = (p1 - p0, p2 - p0);
+            let (d1, d2) = (t1 - t0, t2 - t0);
+            let signed_area = d1.x * d2.y - d1.y * d2.x;
+            let tangent = e1 * d2.y - e2 * d1.y;
+            let preserves_orientation = signed_area > 0.0;
+            let tangent = if signed_area.abs() > f32::EPSILON { tangent / signed_area.abs() } else { Vec3::ZERO };
+            for corner in 0..3 {
+                let vertex = corners[corner];
+                let normal = self.normals[vertex as usize];
+                let projected = (tangent - normal * normal.dot(tangent)).normalize_or_zero();
+                let (previous, next) = (self.positions[corners[(corner + 2) % 3] as usize], self.positions[corners[(corner + 1) % 3] as usize]);
+                let position = self.positions[vertex as usize];
+                let edge = |other: Vec3| { let edge = other - position; (edge - normal * normal.dot(edge)).normalize_or_zero() };
+                let angle = edge(next).dot(edge(previous)).clamp(-1.0, 1.0).acos();
+                *sums.entry((vertex, preserves_orientation)).or_insert(Vec3::ZERO) += projected * angle;
+            }
+        }
+
+        self.tangents = vec![Vec4::new(1.0, 0.0, 0.0, 1.0); self.positions.len()];
+        let mut mirrored_copies: HashMap<u32, u32> = HashMap::new();
+        let mut keys: Vec<(u32, bool)> = sums.keys().copied().collect();
+        //Unmirrored corners keep the original vertex, so the result doesn't depend on hash order
+        keys.sort_by_key(|(vertex, preserves)| (*vertex, !*preserves));
+        for (vertex, preserves_orientation) in keys {
+            let normal = self.normals[vertex as usize];
+            let tangent = sums[&(vertex, preserves_orientation)].try_normalize().unwrap_or_else(|| normal.any_orthonormal_vector());
+            let tangent = tangent.extend(if preserves_orientation { 1.0 } else { -1.0 });
+            let target = if preserves_orientation || !sums.contains_key(&(vertex, true)) {
+                vertex
+            } else {
+                *mirrored_copies.entry(vertex).or_insert_with(|| self.duplicate_vertex(vertex))
+            };
+            self.tangents[target as usize] = tangent;
+        }
+        //Mirrored corners of split vertices point at the copy
+        if !mirrored_copies.is_empty() {
+            for triangle in 0..self.indices.len() / 3 {
+                let corners = &self.indices[triangle * 3..triangle * 3 + 3];
+                let [t0, t1, t2] = [corners[0], corners[1], corners[2]].map(|index| self.uvs[index as usize]);
+                let (d1, d2) = (t1 - t0, t2 - t0);
+                if d1.x * d2.y - d1.y * d2.x > 0.0 {
+                    continue;
+                }
+                for corner in triangle * 3..triangle * 3 + 3 {
+                    if let Some(copy) = mirrored_copies.get(&(self.indices[corner] as u32)) {
+                        self.indices[corner] = *copy as i32;
+                    }
+                }
+            }
+        }
+        self
+    }
+
+    fn duplicate_vertex(&mut self, vertex: u32) -> u32 {
+        let index = vertex as usize;
+        let copy = self.add_vertex(self.positions[index], self.normals[index], self.uvs[index]);
+        if !self.colors.is_empty() {
+            self.colors[copy as usize] = self.colors[index];
+        }
+        for (_, size, buffer) in &mut self.extra {
+            let size = *size as usize;
+            let start = buffer.len() - size;
+            buffer.copy_within(index * size..index * size + size, start);
+        }
+        copy
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn positions(data: &MeshData) -> Vec<Vec3> {
+        data.buffers.iter().find(|(location, _, _)| *location == mesh::POSITION_LOCATION).unwrap().2.chunks_exact(3).map(Vec3::from_slice).collect()
+    }
+
+    //Every triangle faces away from the origin
+    fn faces_outwards(builder: &MeshBuilder) -> bool {
+        let data = builder.build("wood");
+        let positions = positions(&data);
+        data.indices.chunks_exact(3).all(|triangle| {
+            let [a, b, c] = [triangle[0], triangle[1], triangle[2]].map(|index| positions[index as usize]);
+            (b - a).cross(c - a).dot(a + b + c) > 0.0
+        })
+    }
+
+    #[test]
+    fn winds_shapes_outwards() {
+        for (name, shape) in [
+            ("cube", MeshBuilder::cube(Vec3::ONE, 2)),
+            ("uv sphere", MeshBuilder::uv_sphere(1.0, 12, 6)),
+            ("icosphere", MeshBuilder::icosphere(1.0, 2)),
+            ("cylinder", MeshBuilder::cylinder(0.5, 1.0, 12, 2, true)),
+            ("cone", MeshBuilder::cone(0.5, 1.0, 12, 2)),
+            ("capsule", MeshBuilder::capsule(0.5, 1.0, 12, 4)),
+        ] {
+            assert!(faces_outwards(&shape), "{}", name);
+        }
+    }
+
+    #[test]
+    fn leaves_out_degenerate_pole_triangles() {
+        //Each pole row collapses to a point, so it only gets one triangle per sector
+        let sphere = MeshBuilder::uv_sphere(1.0, 8, 4);
+        assert_eq!(sphere.vertex_count(), 9 * 5);
+        assert_eq!(sphere.indices.len() / 3, 8 * 2 * 4 - 8 * 2);
+    }
+
+    #[test]
+    fn caps_cylinders_on_request() {
+        let open = MeshBuilder::cylinder(0.5, 1.0, 8, 1, false);
+        let closed = MeshBuilder::cylinder(0.5, 1.0, 8, 1, true);
+        assert_eq!(closed.vertex_count() - open.vertex_count(), 2 * (1 + 9));
+        assert_eq!((closed.indices.len() - open.indices.len()) / 3, 2 * 8);
+        assert!(closed.normals[open.vertex_count()..].iter().all(|normal| normal.y.abs() == 1.0));
+    }
+
+    #[test]
+    fn appends_with_offset_indices_and_default_colors() {
+        let mut builder = MeshBuilder::new();
+        let corners = [Vec3::ZERO, Vec3::X, Vec3::ONE, Vec3::Y].map(|position| builder.add_vertex(position, Vec3::Z, Vec2::ZERO));
+        builder.add_quad(corners[0], corners[1], corners[2], corners[3]);
+        builder.set_color(corners[2], Vec3::X);
+        assert_eq!(builder.colors, [Vec3::ONE, Vec3::ONE, Vec3::X, Vec3::ONE]);
+
+        builder.append(&MeshBuilder::cube(Vec3::ONE, 1).with_color(Vec3::Y));
+        assert_eq!(builder.vertex_count(), 4 + 24);
+        assert_eq!(builder.indices[..6], [0, 1, 2, 0, 2, 3]);
+        assert_eq!(builder.indices[6], 4);
+        assert_eq!(builder.colors.len(), builder.vertex_count());
+        assert!(builder.colors[4..].iter().all(|color| *color == Vec3::Y));
+        assert!(builder.tangents.is_empty());
+    }
+}
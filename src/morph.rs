@@ -0,0 +1,169 @@
+use bevy_ecs::prelude::*;
+use glam::*;
+
+use crate::{backend::BufferUsage, mesh::Mesh, shader::Shader, texture::BufferTexture};
+
+//Texture unit of the weight buffer sampled by morph.glsl
+pub const MORPH_WEIGHTS_UNIT: u32 = 14;
+//Texture unit the targets of the mesh being drawn are bound to
+pub const MORPH_TARGETS_UNIT: u32 = 15;
+
+//Blend shape of a mesh, offsets added to its vertices in proportion to the target's weight.
+//Both are tightly packed xyz per vertex, normals are empty for targets that don't change them.
+#[derive(Clone, Default, PartialEq, Debug)]
+pub struct MorphTarget {
+    pub positions: Vec<f32>,
+    pub normals: Vec<f32>,
+}
+
+impl MorphTarget {
+    //The offsets for a mesh the transform was applied to, translation doesn't move them
+    pub fn transformed(&self, transform: Mat4) -> MorphTarget {
+        let linear = Mat3::from_mat4(transform);
+        let normal_matrix = linear.inverse().transpose();
+        let apply = |data: &[f32], matrix: Mat3| data.chunks_exact(3).flat_map(|offset| (matrix * Vec3::from_slice(offset)).to_array()).collect();
+        MorphTarget { positions: apply(&self.positions, linear), normals: apply(&self.normals, normal_matrix) }
+    }
+    //Largest distance the target moves a vertex, for growing bounds
+    pub fn max_offset(&self) -> f32 {
+        self.positions.chunks_exact(3).map(|offset| Vec3::from_slice(offset).length()).fold(0.0, f32::max)
+    }
+}
+
+//Targets of one mesh in a texture buffer, two RGBA32F texels per target of every vertex (position then normal
+//offset), vertex after vertex. Bound to MORPH_TARGETS_UNIT by Mesh::render.
+pub struct MorphTargetBuffer {
+    texture: BufferTexture,
+    count: usize,
+}
+
+impl MorphTargetBuffer {
+    pub fn new(targets: &[MorphTarget], vertex_count: usize, name: &str) -> MorphTargetBuffer {
+        let offset = |data: &[f32], vertex: usize| data.get(vertex * 3..vertex * 3 + 3).map_or(Vec4::ZERO, |offset| Vec3::from_slice(offset).extend(0.0));
+        let mut texels = Vec::with_capacity(vertex_count * targets.len() * 2);
+        for vertex in 0..vertex_count {
+            for target in targets {
+                texels.push(offset(&target.positions, vertex));
+                texels.push(offset(&target.normals, vertex));
+            }
+        }
+        let texture = BufferTexture::new(gl::RGBA32F, &format!("{} morph targets", name));
+        texture.update(BufferUsage::Static, &texels);
+        MorphTargetBuffer { texture, count: targets.len() }
+    }
+    pub fn count(&self) -> usize {
+        self.count
+    }
+    pub fn bind(&self) {
+        self.texture.bind_to_unit(MORPH_TARGETS_UNIT);
+    }
+}
+
+//How far each morph target of the Mesh on the same entity is applied, in the mesh's target order. Set directly or
+//by the weights channels of an AnimationPlayer's clips, see systems::animate.
+#[derive(Component, Clone, Default)]
+pub struct MorphWeights {
+    pub weights: Vec<f32>,
+    //Target names from the file, empty when it has none
+    pub names: Vec<String>,
+    //Node of the file the mesh came from, weights channels target nodes
+    pub node: Option<usize>,
+    //Where weights start in this frame's palette, None until it is first uploaded
+    palette_offset: Option<u32>,
+}
+
+impl MorphWeights {
+    pub fn new(weights: Vec<f32>) -> MorphWeights {
+        MorphWeights { weights, ..Default::default() }
+    }
+    pub fn with_names(mut self, names: Vec<String>) -> MorphWeights {
+        self.names = names;
+        self
+    }
+    pub fn with_node(mut self, node: usize) -> MorphWeights {
+        self.node = Some(node);
+        self
+    }
+    pub fn target(&self, name: &str) -> Option<usize> {
+        self.names.iter().position(|target| target == name)
+    }
+    //Unknown names are ignored
+    pub fn set(&mut self, name: &str, weight: f32) {
+        let target = self.target(name);
+        if let Some(current) = target.and_then(|target| self.weights.get_mut(target)) {
+            *current = weight;
+        }
+    }
+    //Per-instance value telling the vertex shader where the weights are, negative when there is nothing to morph.
+    //Weights that don't match the mesh's targets one to one leave it unmorphed.
+    pub fn instance_offset(morph: Option<&MorphWeights>, mesh: &Mesh) -> f32 {
+        morph.filter(|morph| morph.weights.len() == mesh.morph_target_count() && !morph.weights.is_empty())
+            .and_then(|morph| morph.palette_offset).map_or(-1.0, |offset| offset as f32)
+    }
+}
+
+//Morph weights of every instance, packed into one texture buffer each frame like the skinning palette. Every list
+//is led by its length, one R32F texel each.
+#[derive(Resource)]
+pub struct MorphPalette {
+    texture: BufferTexture,
+    weights: Vec<f32>,
+}
+
+impl MorphPalette {
+    pub fn new() -> MorphPalette {
+        let texture = BufferTexture::new(gl::R32F, "morph weights");
+        texture.update(BufferUsage::Stream, &[0.0f32]);
+        MorphPalette { texture, weights: Vec::new() }
+    }
+    //Appends the weights to the frame being built and remembers where they went
+    pub fn push(&mut self, morph: &mut MorphWeights) {
+        morph.palette_offset = Some(self.weights.len() as u32);
+        self.weights.push(morph.weights.len() as f32);
+        self.weights.extend_from_slice(&morph.weights);
+    }
+    //Replaces last frame's weights with the ones pushed since, the texture stays bound to MORPH_WEIGHTS_UNIT
+    pub fn upload(&mut self) {
+        if !self.weights.is_empty() {
+            self.texture.update(BufferUsage::Stream, &self.weights);
+        }
+        self.texture.bind_to_unit(MORPH_WEIGHTS_UNIT);
+        self.weights.clear();
+    }
+}
+
+//Points the samplers of a shader that includes morph.glsl at the weight palette and the bound mesh's targets
+pub fn bind_targets(shader: &Shader) {
+    shader.set_uniform_i32("morphWeights".to_string(), &(MORPH_WEIGHTS_UNIT as i32));
+    shader.set_uniform_i32("morphTargets".to_string(), &(MORPH_TARGETS_UNIT as i32));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sets_weights_by_target_name() {
+        let mut morph = MorphWeights::new(vec![0.0, 0.0]).with_names(vec!["squash".to_string(), "spikes".to_string()]);
+        assert_eq!(morph.target("spikes"), Some(1));
+        assert_eq!(morph.target("missing"), None);
+        morph.set("spikes", 0.75);
+        morph.set("missing", 1.0);
+        assert_eq!(morph.weights, [0.0, 0.75]);
+
+        //Weights without names can only be set by index
+        let mut unnamed = MorphWeights::new(vec![0.5]);
+        unnamed.set("squash", 1.0);
+        assert_eq!(unnamed.weights, [0.5]);
+    }
+
+    #[test]
+    fn transforms_target_offsets() {
+        let target = MorphTarget { positions: vec![1.0, 0.0, 0.0], normals: vec![0.0, 1.0, 0.0] };
+        let transform = Mat4::from_scale_rotation_translation(Vec3::new(2.0, 1.0, 1.0), Quat::from_rotation_z(std::f32::consts::FRAC_PI_2), Vec3::splat(5.0));
+        let moved = target.transformed(transform);
+        assert!(Vec3::from_slice(&moved.positions).abs_diff_eq(Vec3::new(0.0, 2.0, 0.0), 1e-5));
+        assert!(Vec3::from_slice(&moved.normals).normalize().abs_diff_eq(Vec3::NEG_X, 1e-5));
+        assert!((moved.max_offset() - 2.0).abs() < 1e-5);
+    }
+}
@@ -0,0 +1,269 @@
+use std::{cell::RefCell, rc::Rc};
+
+use glam::UVec3;
+
+use crate::{backend::*, material::BlendMode};
+
+//What a NullBackend was asked to do, data uploads are recorded by size
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+    CreateVertexArray(Handle),
+    CreateBuffer(Handle, BufferKind),
+    UploadBuffer { buffer: Handle, usage: BufferUsage, size: usize },
+    SetVertexLayout { vertex_array: Handle, buffer: Handle, attributes: Vec<VertexAttribute> },
+    SetIndexBuffer { vertex_array: Handle, buffer: Handle },
+    CreateTexture(Handle, TextureDescriptor),
+    UploadTexture { texture: Handle, region: TextureRegion, size: usize },
+    SetSampler(Handle, SamplerDescriptor),
+    GenerateMipmaps(Handle),
+    CreateBufferTexture(Handle, u32),
+    UpdateBufferTexture { texture: Handle, usage: BufferUsage, size: usize },
+    CreateShader { shader: Handle, name: String, stages: Vec<ShaderStage> },
+    SetUniform { shader: Handle, name: String, value: Uniform },
+    SetBlendMode(BlendMode),
+    Bind(Resource),
+    Unbind(Resource),
+    Draw(DrawCommand),
+    Dispatch { shader: Handle, groups: UVec3 },
+    Label(Resource, String),
+    Destroy(Resource),
+}
+
+//Shared with whoever installed the backend, so the commands can be inspected while it is installed
+pub type CommandLog = Rc<RefCell<Vec<Command>>>;
+
+//Needs no GPU or context, it only records commands, e.g. to check what a renderer submits in a test
+#[derive(Default)]
+pub struct NullBackend {
+    next_handle: Handle,
+    log: CommandLog,
+}
+
+impl NullBackend {
+    pub fn new() -> NullBackend {
+        NullBackend::default()
+    }
+    pub fn log(&self) -> CommandLog {
+        self.log.clone()
+    }
+
+    //Starts at 1, 0 means "nothing" to the engine like it does to GL
+    fn allocate(&mut self) -> Handle {
+        self.next_handle += 1;
+        self.next_handle
+    }
+    fn record(&mut self, command: Command) {
+        self.log.borrow_mut().push(command);
+    }
+}
+
+impl RenderBackend for NullBackend {
+    fn name(&self) -> &'static str {
+        "null"
+    }
+
+    fn create_vertex_array(&mut self) -> Handle {
+        let vertex_array = self.allocate();
+        self.record(Command::CreateVertexArray(vertex_array));
+        vertex_array
+    }
+
+    fn create_buffer(&mut self, kind: BufferKind) -> Handle {
+        let buffer = self.allocate();
+        self.record(Command::CreateBuffer(buffer, kind));
+        buffer
+    }
+
+    fn upload_buffer(&mut self, buffer: Handle, usage: BufferUsage, data: &[u8]) {
+        self.record(Command::UploadBuffer { buffer, usage, size: data.len() });
+    }
+
+    fn set_vertex_layout(&mut self, vertex_array: Handle, buffer: Handle, attributes: &[VertexAttribute]) {
+        self.record(Command::SetVertexLayout { vertex_array, buffer, attributes: attributes.to_vec() });
+    }
+
+    fn set_index_buffer(&mut self, vertex_array: Handle, buffer: Handle) {
+        self.record(Command::SetIndexBuffer { vertex_array, buffer });
+    }
+
+    fn create_texture(&mut self, descriptor: &TextureDescriptor) -> Handle {
+        let texture = self.allocate();
+        self.record(Command::CreateTexture(texture, *descriptor));
+        texture
+    }
+
+    fn upload_texture(&mut self, texture: Handle, region: &TextureRegion, pixels: &[u8]) {
+        self.record(Command::UploadTexture { texture, region: *region, size: pixels.len() });
+    }
+
+    fn set_sampler(&mut self, texture: Handle, sampler: &SamplerDescriptor) {
+        self.record(Command::SetSampler(texture, *sampler));
+    }
+
+    fn generate_mipmaps(&mut self, texture: Handle) {
+        self.record(Command::GenerateMipmaps(texture));
+    }
+
+    fn create_buffer_texture(&mut self, internal_format: u32) -> Handle {
+        let texture = self.allocate();
+        self.record(Command::CreateBufferTexture(texture, internal_format));
+        texture
+    }
+
+    fn update_buffer_texture(&mut self, texture: Handle, usage: BufferUsage, data: &[u8]) {
+        self.record(Command::UpdateBufferTexture { texture, usage, size: data.len() });
+    }
+
+    fn create_shader(&mut self, name: &str, stages: &[(ShaderStage, &str)]) -> Handle {
+        let shader = self.allocate();
+        let stages = stages.iter().map(|(stage, _)| *stage).collect();
+        self.record(Command::CreateShader { shader, name: name.to_string(), stages });
+        shader
+    }
+
+    fn set_uniform(&mut self, shader: Handle, name: &str, value: Uniform) {
+        self.record(Command::SetUniform { shader, name: name.to_string(), value });
+    }
+
+    fn set_blend_mode(&mut self, mode: BlendMode) {
+        self.record(Command::SetBlendMode(mode));
+    }
+
+    fn bind(&mut self, resource: Resource) {
+        self.record(Command::Bind(resource));
+    }
+
+    fn unbind(&mut self, resource: Resource) {
+        self.record(Command::Unbind(resource));
+    }
+
+    fn submit(&mut self, draws: &[DrawCommand]) {
+        for draw in draws {
+            self.record(Command::Draw(*draw));
+        }
+    }
+
+    fn dispatch(&mut self, shader: Handle, groups: UVec3) {
+        self.record(Command::Dispatch { shader, groups });
+    }
+
+    fn label(&mut self, resource: Resource, name: &str) {
+        self.record(Command::Label(resource, name.to_string()));
+    }
+
+    fn destroy(&mut self, resource: Resource) {
+        self.record(Command::Destroy(resource));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Mat4;
+
+    use super::*;
+    use crate::{backend, material::TextureOptions, mesh::{self, Mesh, MeshData}, renderer::{GPUObject, Instance}, shader::Shader, skinning::SkinningPalette, texture::{BufferTexture, Texture}, texture_formats::{PixelFormat, TextureData}};
+
+    fn install() -> CommandLog {
+        let backend = NullBackend::new();
+        let log = backend.log();
+        backend::install(Box::new(backend));
+        log
+    }
+
+    fn triangle() -> MeshData {
+        MeshData::new(vec![0, 1, 2], "wood")
+            .with_buffer(vec![0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0], mesh::POSITION_LOCATION, 3)
+            .with_buffer(vec![0.0, 0.0, 1.0, 0.0, 0.0, 1.0], mesh::TEXCOORD_LOCATION, 2)
+    }
+
+    fn checker() -> TextureData {
+        TextureData {
+            format: PixelFormat::Uncompressed { internal_format: gl::RGBA8, format: gl::RGBA, data_type: gl::UNSIGNED_BYTE, bytes_per_pixel: 4 },
+            width: 2,
+            height: 2,
+            depth: 1,
+            layers: 0,
+            faces: 1,
+            levels: vec![vec![255; 16]],
+        }
+    }
+
+    #[test]
+    fn installs_for_this_thread() {
+        install();
+        assert_eq!(backend::with(|backend| backend.name()), "null");
+    }
+
+    #[test]
+    fn records_a_textured_draw() {
+        let log = install();
+        let mesh = Mesh::from_data(&triangle());
+        let texture = Texture::from_data(&checker(), &TextureOptions::default(), 1.0).unwrap();
+        let shader = Shader::new("default").unwrap();
+        log.borrow_mut().clear();
+
+        shader.bind();
+        texture.bind();
+        shader.set_uniform_i32("tex0".to_string(), &0);
+        mesh.render(&[Instance::new(Mat4::IDENTITY), Instance::new(Mat4::from_translation(glam::Vec3::X))]);
+
+        let commands = log.borrow();
+        let draw = match commands.last() {
+            Some(Command::Draw(draw)) => *draw,
+            other => panic!("expected a draw, got {:?}", other),
+        };
+        assert_eq!((draw.primitive, draw.first, draw.count, draw.instances, draw.indexed), (Primitive::Triangles, 0, 3, 2, true));
+        assert!(matches!(commands[0], Command::Bind(Resource::Shader(_))));
+        assert_eq!(commands[1], Command::Bind(Resource::Texture(texture.handle())));
+        assert!(matches!(&commands[2], Command::SetUniform { name, value: Uniform::Int(0), .. } if name == "tex0"));
+        //Both instance matrices are streamed before the draw
+        assert!(commands.iter().any(|command| matches!(command, Command::UploadBuffer { usage: BufferUsage::Stream, size, .. } if *size == 2 * std::mem::size_of::<Instance>())));
+    }
+
+    #[test]
+    fn records_creation_and_destruction() {
+        let log = install();
+        let texture = Texture::from_data(&checker(), &TextureOptions::default(), 1.0).unwrap();
+        let shader = Shader::new("default").unwrap();
+        let mesh = Mesh::from_data(&triangle());
+        {
+            let commands = log.borrow();
+            //Uncompressed data without mips gets them generated for the default trilinear filter
+            assert!(matches!(commands[0], Command::CreateTexture(handle, TextureDescriptor { width: 2, height: 2, levels: 2, .. }) if handle == texture.handle()));
+            assert!(matches!(commands[1], Command::UploadTexture { size: 16, .. }));
+            assert_eq!(commands[2], Command::GenerateMipmaps(texture.handle()));
+            assert!(commands.contains(&Command::CreateShader { shader: 2, name: "default".to_string(), stages: vec![ShaderStage::Vertex, ShaderStage::Fragment] }));
+            //Index buffer and one vertex buffer per attribute
+            assert_eq!(commands.iter().filter(|command| matches!(command, Command::CreateBuffer(_, BufferKind::Index))).count(), 1);
+            assert_eq!(commands.iter().filter(|command| matches!(command, Command::CreateBuffer(_, BufferKind::Vertex))).count(), 3);
+            assert!(commands.iter().any(|command| matches!(command, Command::UploadBuffer { size: 36, usage: BufferUsage::Static, .. })));
+        }
+
+        log.borrow_mut().clear();
+        let copy = mesh.clone();
+        drop(mesh);
+        assert!(log.borrow().is_empty());
+        drop((copy, texture, shader));
+        let commands = log.borrow();
+        assert!(commands.contains(&Command::Destroy(Resource::Texture(1))));
+        assert!(commands.contains(&Command::Destroy(Resource::Shader(2))));
+        assert_eq!(commands.iter().filter(|command| matches!(command, Command::Destroy(Resource::Buffer(_)))).count(), 4);
+        assert_eq!(commands.iter().filter(|command| matches!(command, Command::Destroy(Resource::VertexArray(_)))).count(), 1);
+    }
+
+    #[test]
+    fn records_buffer_texture_updates() {
+        let log = install();
+        let palette = SkinningPalette::new();
+        //The palette starts out holding one identity matrix
+        assert_eq!(log.borrow()[0], Command::CreateBufferTexture(1, gl::RGBA32F));
+        assert_eq!(log.borrow()[1], Command::Label(Resource::Texture(1), "joint matrices".to_string()));
+        assert_eq!(log.borrow()[2], Command::UpdateBufferTexture { texture: 1, usage: BufferUsage::Stream, size: 64 });
+
+        let texture = BufferTexture::new(gl::R32F, "weights");
+        texture.update(BufferUsage::Static, &[0.5f32, 1.0, 0.25]);
+        assert_eq!(log.borrow().last(), Some(&Command::UpdateBufferTexture { texture: 2, usage: BufferUsage::Static, size: 12 }));
+        drop((palette, texture));
+        assert_eq!(log.borrow()[log.borrow().len() - 2..], [Command::Destroy(Resource::Texture(1)), Command::Destroy(Resource::Texture(2))]);
+    }
+}
@@ -0,0 +1,123 @@
+use std::error::Error;
+
+use bevy_ecs::prelude::*;
+use glam::Vec2;
+
+use crate::{framebuffer::{self, Framebuffer}, gl_state, renderer::{FullscreenTriangle, GPUObject}, shader::Shader};
+
+//Render graph attachments and framebuffer, all transient
+pub const TARGET: &str = "oit";
+//Weighted premultiplied color in rgb, weighted alpha in a
+pub const ACCUM: &str = "oit_accum";
+//Product of (1 - alpha) over every transparent layer
+pub const REVEALAGE: &str = "oit_revealage";
+pub const DEPTH: &str = "oit_depth";
+//Bounds of the per layer weight written by default.fs. The upper one keeps a stack of near opaque layers inside
+//the half float accumulation target, the lower one stops distant layers from vanishing from the average.
+pub const WEIGHT_RANGE: Vec2 = Vec2::new(0.01, 3000.0);
+
+//Weighted blended order-independent transparency (McGuire & Bavoil). Layers are accumulated in any
+//order and resolved on top of the scene in one fullscreen pass, so no per-mesh sorting is needed.
+#[derive(Resource)]
+pub struct OrderIndependentTransparency {
+    triangle: FullscreenTriangle,
+    composite_shader: Shader,
+    //Scene target and viewport the layers are composited back into
+    previous_framebuffer: i32,
+    previous_viewport: [i32; 4],
+}
+
+impl OrderIndependentTransparency {
+    pub fn new() -> Result<OrderIndependentTransparency, Box<dyn Error>> {
+        Ok(OrderIndependentTransparency {
+            triangle: FullscreenTriangle::new(),
+            composite_shader: Shader::from_files("fullscreen", "oit_composite")?,
+            previous_framebuffer: 0,
+            previous_viewport: [0; 4],
+        })
+    }
+
+    //Copies the scene depth so layers behind opaque meshes are rejected. Returns false when drawing straight
+    //to the window, its depth buffer format can't be blitted into our attachment.
+    pub fn begin(&mut self, target: &Framebuffer) -> bool {
+        unsafe {
+            gl::GetIntegerv(gl::DRAW_FRAMEBUFFER_BINDING, &mut self.previous_framebuffer);
+            gl::GetIntegerv(gl::VIEWPORT, self.previous_viewport.as_mut_ptr());
+        }
+        if self.previous_framebuffer == 0 {
+            return false;
+        }
+        //The scene may only cover part of its target, e.g. a split screen view of a render texture
+        let [x, y, width, height] = self.previous_viewport;
+        unsafe {
+            gl_state::bind_framebuffer(gl::READ_FRAMEBUFFER, self.previous_framebuffer as u32);
+            gl_state::bind_framebuffer(gl::DRAW_FRAMEBUFFER, target.handle());
+            gl::BlitFramebuffer(x, y, x + width, y + height, 0, 0, target.width(), target.height(), gl::DEPTH_BUFFER_BIT, gl::NEAREST);
+            gl_state::bind_framebuffer(gl::FRAMEBUFFER, target.handle());
+            gl::Viewport(0, 0, target.width(), target.height());
+            gl::ClearBufferfv(gl::COLOR, 0, [0.0f32; 4].as_ptr());
+            gl::ClearBufferfv(gl::COLOR, 1, [1.0f32; 4].as_ptr());
+
+            gl_state::enable(gl::BLEND);
+            gl_state::blend_func_indexed(0, gl::ONE, gl::ONE);
+            gl_state::blend_func_indexed(1, gl::ZERO, gl::ONE_MINUS_SRC_COLOR);
+            gl_state::depth_mask(gl::FALSE);
+        }
+        true
+    }
+
+    pub fn resolve(&self, target: &Framebuffer, is_wireframe: bool) {
+        unsafe {
+            let [x, y, width, height] = self.previous_viewport;
+            gl_state::bind_framebuffer(gl::FRAMEBUFFER, self.previous_framebuffer as u32);
+            gl::Viewport(x, y, width, height);
+            gl::PolygonMode(gl::FRONT_AND_BACK, gl::FILL);
+            gl_state::disable(gl::DEPTH_TEST);
+            gl_state::blend_func(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+        }
+        let shader = &self.composite_shader;
+        shader.bind();
+        shader.set_uniform_i32("accumTexture".to_string(), &0);
+        shader.set_uniform_i32("revealageTexture".to_string(), &1);
+        framebuffer::bind_attachment(target.color_attachment(0).unwrap(), 0);
+        framebuffer::bind_attachment(target.color_attachment(1).unwrap(), 1);
+        self.triangle.render();
+        framebuffer::unbind_attachment(1);
+        framebuffer::unbind_attachment(0);
+        shader.unbind();
+        unsafe {
+            gl_state::enable(gl::DEPTH_TEST);
+            gl_state::disable(gl::BLEND);
+            gl_state::depth_mask(gl::TRUE);
+        }
+        crate::renderer::update_wireframe(&is_wireframe);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //Mirrors the weight in default.fs, depth is the window space depth of the fragment
+    fn weight(alpha: f32, depth: f32) -> f32 {
+        let weight = ((alpha * 10.0).min(1.0) + 0.01).powi(3) * 1e8 * (1.0 - depth * 0.9).powi(3);
+        weight.clamp(WEIGHT_RANGE.x, WEIGHT_RANGE.y)
+    }
+
+    #[test]
+    fn weighs_near_opaque_layers_more() {
+        //Most layers saturate the upper bound, faint ones still order by depth and coverage
+        assert!(weight(0.001, 0.5) > weight(0.001, 0.99));
+        assert!(weight(0.002, 0.99) > weight(0.001, 0.99));
+        assert!(weight(0.0, 1.0) >= WEIGHT_RANGE.x);
+    }
+
+    #[test]
+    fn keeps_stacked_layers_inside_half_floats() {
+        const HALF_MAX: f32 = 65504.0;
+        let nearest = weight(1.0, 0.0);
+        assert_eq!(nearest, WEIGHT_RANGE.y);
+        //Sixteen fully opaque layers right at the near plane still accumulate without overflowing
+        assert!(nearest * 16.0 < HALF_MAX);
+    }
+}
@@ -0,0 +1,420 @@
+use std::{collections::HashMap, ffi::CString, os::raw::c_void, ptr, str};
+
+use gl::types::*;
+use glam::UVec3;
+use raw_gl_context::{GlConfig, GlContext, Profile};
+
+use crate::{backend::*, gl_state, material::{self, BlendMode}, texture_formats::PixelFormat};
+
+//Handles are GL object names. Buffers and textures remember the target they were created for.
+#[derive(Default)]
+pub struct OpenGlBackend {
+    buffer_targets: HashMap<Handle, GLenum>,
+    texture_targets: HashMap<Handle, GLenum>,
+    //Buffer behind each buffer texture
+    texture_buffers: HashMap<Handle, Handle>,
+}
+
+impl OpenGlBackend {
+    //The context every shader in resources/shaders is written against
+    pub fn context_config() -> GlConfig {
+        GlConfig {
+            version: (4, 6),
+            profile: Profile::Core,
+            red_bits: 8,
+            blue_bits: 8,
+            green_bits: 8,
+            alpha_bits: 8,
+            depth_bits: 24,
+            stencil_bits: 8,
+            samples: None,
+            srgb: true,
+            double_buffer: true,
+            vsync: false,
+        }
+    }
+
+    //Must run once the context is current, before any other GL call
+    pub fn load_functions(context: &GlContext) {
+        gl::load_with(|symbol| context.get_proc_address(symbol) as *const _);
+    }
+
+    fn buffer_target(&self, buffer: Handle) -> GLenum {
+        self.buffer_targets.get(&buffer).copied().unwrap_or(gl::ARRAY_BUFFER)
+    }
+    fn texture_target(&self, texture: Handle) -> GLenum {
+        self.texture_targets.get(&texture).copied().unwrap_or(gl::TEXTURE_2D)
+    }
+}
+
+fn texture_target(kind: TextureKind) -> GLenum {
+    match kind {
+        TextureKind::Texture2D => gl::TEXTURE_2D,
+        TextureKind::Texture2DArray => gl::TEXTURE_2D_ARRAY,
+        TextureKind::Cubemap => gl::TEXTURE_CUBE_MAP,
+        TextureKind::Texture3D => gl::TEXTURE_3D,
+    }
+}
+
+fn primitive_mode(primitive: Primitive) -> GLenum {
+    match primitive {
+        Primitive::Triangles => gl::TRIANGLES,
+        Primitive::TriangleFan => gl::TRIANGLE_FAN,
+        Primitive::Lines => gl::LINES,
+    }
+}
+
+impl RenderBackend for OpenGlBackend {
+    fn name(&self) -> &'static str {
+        "OpenGL 4.6"
+    }
+
+    fn create_vertex_array(&mut self) -> Handle {
+        let mut vertex_array = 0;
+        unsafe {
+            gl::GenVertexArrays(1, &mut vertex_array);
+        }
+        vertex_array
+    }
+
+    fn create_buffer(&mut self, kind: BufferKind) -> Handle {
+        let mut buffer = 0;
+        unsafe {
+            gl::GenBuffers(1, &mut buffer);
+        }
+        let target = match kind {
+            BufferKind::Vertex => gl::ARRAY_BUFFER,
+            BufferKind::Index => gl::ELEMENT_ARRAY_BUFFER,
+            BufferKind::Storage => gl::SHADER_STORAGE_BUFFER,
+        };
+        self.buffer_targets.insert(buffer, target);
+        buffer
+    }
+
+    //Uploads go through COPY_WRITE_BUFFER, binding an index buffer would attach it to whatever vertex array is bound
+    fn upload_buffer(&mut self, buffer: Handle, usage: BufferUsage, data: &[u8]) {
+        let usage = match usage {
+            BufferUsage::Static => gl::STATIC_DRAW,
+            BufferUsage::Dynamic => gl::DYNAMIC_DRAW,
+            BufferUsage::Stream => gl::STREAM_DRAW,
+        };
+        unsafe {
+            gl_state::bind_buffer(gl::COPY_WRITE_BUFFER, buffer);
+            let pointer = if data.is_empty() { ptr::null() } else { data.as_ptr() as *const c_void };
+            gl::BufferData(gl::COPY_WRITE_BUFFER, data.len() as GLsizeiptr, pointer, usage);
+            gl_state::bind_buffer(gl::COPY_WRITE_BUFFER, 0);
+        }
+    }
+
+    fn set_vertex_layout(&mut self, vertex_array: Handle, buffer: Handle, attributes: &[VertexAttribute]) {
+        unsafe {
+            gl_state::bind_vertex_array(vertex_array);
+            gl_state::bind_buffer(gl::ARRAY_BUFFER, buffer);
+            for attribute in attributes {
+                gl::VertexAttribPointer(attribute.location, attribute.components, gl::FLOAT, gl::FALSE, attribute.stride, attribute.offset as *const c_void);
+                gl::EnableVertexAttribArray(attribute.location);
+                gl::VertexAttribDivisor(attribute.location, attribute.divisor);
+            }
+            gl_state::bind_vertex_array(0);
+            gl_state::bind_buffer(gl::ARRAY_BUFFER, 0);
+        }
+    }
+
+    //The element array binding is part of the vertex array, so it is never unbound
+    fn set_index_buffer(&mut self, vertex_array: Handle, buffer: Handle) {
+        unsafe {
+            gl_state::bind_vertex_array(vertex_array);
+            gl_state::bind_buffer(gl::ELEMENT_ARRAY_BUFFER, buffer);
+            gl_state::bind_vertex_array(0);
+        }
+    }
+
+    fn create_texture(&mut self, descriptor: &TextureDescriptor) -> Handle {
+        let target = texture_target(descriptor.kind);
+        let mut texture = 0;
+        unsafe {
+            gl::GenTextures(1, &mut texture);
+            gl_state::bind_texture(target, texture);
+            let (width, height) = (descriptor.width, descriptor.height);
+            match descriptor.kind {
+                TextureKind::Texture3D | TextureKind::Texture2DArray => {
+                    gl::TexStorage3D(target, descriptor.levels, descriptor.internal_format, width, height, descriptor.depth);
+                }
+                TextureKind::Texture2D | TextureKind::Cubemap => gl::TexStorage2D(target, descriptor.levels, descriptor.internal_format, width, height),
+            }
+            gl_state::bind_texture(target, 0);
+        }
+        self.texture_targets.insert(texture, target);
+        texture
+    }
+
+    fn upload_texture(&mut self, texture: Handle, region: &TextureRegion, pixels: &[u8]) {
+        let target = self.texture_target(texture);
+        let image_target = region.face.map_or(target, |face| gl::TEXTURE_CUBE_MAP_POSITIVE_X + face);
+        let layered = target == gl::TEXTURE_3D || target == gl::TEXTURE_2D_ARRAY;
+        let (level, width, height, depth) = (region.level, region.width, region.height, region.depth);
+        let data = pixels.as_ptr() as *const c_void;
+        unsafe {
+            gl_state::bind_texture(target, texture);
+            gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
+            match region.format {
+                PixelFormat::Compressed { internal_format, .. } if layered => {
+                    gl::CompressedTexSubImage3D(image_target, level, 0, 0, 0, width, height, depth, internal_format, pixels.len() as i32, data);
+                }
+                PixelFormat::Compressed { internal_format, .. } => {
+                    gl::CompressedTexSubImage2D(image_target, level, 0, 0, width, height, internal_format, pixels.len() as i32, data);
+                }
+                PixelFormat::Uncompressed { format, data_type, .. } if layered => {
+                    gl::TexSubImage3D(image_target, level, 0, 0, 0, width, height, depth, format, data_type, data);
+                }
+                PixelFormat::Uncompressed { format, data_type, .. } => {
+                    gl::TexSubImage2D(image_target, level, 0, 0, width, height, format, data_type, data);
+                }
+            }
+            gl::PixelStorei(gl::UNPACK_ALIGNMENT, 4);
+            gl_state::bind_texture(target, 0);
+        }
+    }
+
+    fn set_sampler(&mut self, texture: Handle, sampler: &SamplerDescriptor) {
+        let target = self.texture_target(texture);
+        unsafe {
+            gl_state::bind_texture(target, texture);
+            let wrap = material::to_gl_wrap(&sampler.wrap) as i32;
+            for axis in [gl::TEXTURE_WRAP_S, gl::TEXTURE_WRAP_T, gl::TEXTURE_WRAP_R] {
+                gl::TexParameteri(target, axis, wrap);
+            }
+            gl::TexParameteri(target, gl::TEXTURE_MIN_FILTER, material::to_gl_min_filter(&sampler.min_filter) as i32);
+            gl::TexParameteri(target, gl::TEXTURE_MAG_FILTER, material::to_gl_filter(&sampler.mag_filter) as i32);
+            if sampler.anisotropy > 1.0 {
+                gl::TexParameterf(target, gl::TEXTURE_MAX_ANISOTROPY_EXT, sampler.anisotropy);
+            }
+            gl_state::bind_texture(target, 0);
+        }
+    }
+
+    fn generate_mipmaps(&mut self, texture: Handle) {
+        let target = self.texture_target(texture);
+        unsafe {
+            gl_state::bind_texture(target, texture);
+            gl::GenerateMipmap(target);
+            gl_state::bind_texture(target, 0);
+        }
+    }
+
+    fn create_buffer_texture(&mut self, internal_format: u32) -> Handle {
+        let (mut buffer, mut texture) = (0, 0);
+        unsafe {
+            gl::GenBuffers(1, &mut buffer);
+            gl::GenTextures(1, &mut texture);
+            //Binding creates the buffer object TexBuffer needs
+            gl_state::bind_buffer(gl::TEXTURE_BUFFER, buffer);
+            gl_state::bind_buffer(gl::TEXTURE_BUFFER, 0);
+            gl_state::bind_texture(gl::TEXTURE_BUFFER, texture);
+            gl::TexBuffer(gl::TEXTURE_BUFFER, internal_format, buffer);
+            gl_state::bind_texture(gl::TEXTURE_BUFFER, 0);
+        }
+        self.texture_targets.insert(texture, gl::TEXTURE_BUFFER);
+        self.texture_buffers.insert(texture, buffer);
+        texture
+    }
+
+    fn update_buffer_texture(&mut self, texture: Handle, usage: BufferUsage, data: &[u8]) {
+        if let Some(buffer) = self.texture_buffers.get(&texture).copied() {
+            self.upload_buffer(buffer, usage, data);
+        }
+    }
+
+    fn create_shader(&mut self, name: &str, stages: &[(ShaderStage, &str)]) -> Handle {
+        unsafe {
+            let compiled: Vec<u32> = stages.iter().map(|(stage, source)| compile_stage(*stage, source)).collect();
+            let program = link_program(&compiled);
+            gl_state::label(gl::PROGRAM, program, name);
+            program
+        }
+    }
+
+    fn set_uniform(&mut self, shader: Handle, name: &str, value: Uniform) {
+        let c_name = CString::new(name).unwrap();
+        unsafe {
+            let location = gl::GetUniformLocation(shader, c_name.as_ptr());
+            match value {
+                Uniform::Bool(value) => gl::Uniform1i(location, value as i32),
+                Uniform::Float(value) => gl::Uniform1f(location, value),
+                Uniform::Vec2(value) => gl::Uniform2f(location, value.x, value.y),
+                Uniform::Vec3(value) => gl::Uniform3f(location, value.x, value.y, value.z),
+                Uniform::Vec4(value) => gl::Uniform4f(location, value.x, value.y, value.z, value.w),
+                Uniform::Int(value) => gl::Uniform1i(location, value),
+                Uniform::IVec2(value) => gl::Uniform2i(location, value.x, value.y),
+                Uniform::IVec3(value) => gl::Uniform3i(location, value.x, value.y, value.z),
+                Uniform::IVec4(value) => gl::Uniform4i(location, value.x, value.y, value.z, value.w),
+                Uniform::UInt(value) => gl::Uniform1ui(location, value),
+                Uniform::UVec2(value) => gl::Uniform2ui(location, value.x, value.y),
+                Uniform::UVec3(value) => gl::Uniform3ui(location, value.x, value.y, value.z),
+                Uniform::UVec4(value) => gl::Uniform4ui(location, value.x, value.y, value.z, value.w),
+                Uniform::Double(value) => gl::Uniform1d(location, value),
+                Uniform::DVec2(value) => gl::Uniform2d(location, value.x, value.y),
+                Uniform::DVec3(value) => gl::Uniform3d(location, value.x, value.y, value.z),
+                Uniform::DVec4(value) => gl::Uniform4d(location, value.x, value.y, value.z, value.w),
+                Uniform::Mat2(value, count) => gl::UniformMatrix2fv(location, count, gl::FALSE, value.as_ref().as_ptr()),
+                Uniform::Mat3(value, count) => gl::UniformMatrix3fv(location, count, gl::FALSE, value.as_ref().as_ptr()),
+                Uniform::Mat4(value, count) => gl::UniformMatrix4fv(location, count, gl::FALSE, value.as_ref().as_ptr()),
+                Uniform::DMat2(value, count) => gl::UniformMatrix2dv(location, count, gl::FALSE, value.as_ref().as_ptr()),
+                Uniform::DMat3(value, count) => gl::UniformMatrix3dv(location, count, gl::FALSE, value.as_ref().as_ptr()),
+                Uniform::DMat4(value, count) => gl::UniformMatrix4dv(location, count, gl::FALSE, value.as_ref().as_ptr()),
+            }
+        }
+    }
+
+    fn set_blend_mode(&mut self, mode: BlendMode) {
+        unsafe {
+            match mode {
+                BlendMode::Opaque | BlendMode::AlphaTest => gl_state::disable(gl::BLEND),
+                BlendMode::AlphaBlend => {
+                    gl_state::enable(gl::BLEND);
+                    gl_state::blend_func(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+                }
+                BlendMode::Additive => {
+                    gl_state::enable(gl::BLEND);
+                    gl_state::blend_func(gl::SRC_ALPHA, gl::ONE);
+                }
+                BlendMode::Premultiplied => {
+                    gl_state::enable(gl::BLEND);
+                    gl_state::blend_func(gl::ONE, gl::ONE_MINUS_SRC_ALPHA);
+                }
+            }
+        }
+    }
+
+    fn bind(&mut self, resource: Resource) {
+        unsafe {
+            match resource {
+                Resource::VertexArray(vertex_array) => gl_state::bind_vertex_array(vertex_array),
+                Resource::Buffer(buffer) => gl_state::bind_buffer(self.buffer_target(buffer), buffer),
+                Resource::Texture(texture) => gl_state::bind_texture(self.texture_target(texture), texture),
+                Resource::Shader(program) => gl_state::use_program(program),
+            }
+        }
+    }
+
+    fn unbind(&mut self, resource: Resource) {
+        unsafe {
+            match resource {
+                Resource::VertexArray(_) => gl_state::bind_vertex_array(0),
+                Resource::Buffer(buffer) => gl_state::bind_buffer(self.buffer_target(buffer), 0),
+                Resource::Texture(texture) => gl_state::bind_texture(self.texture_target(texture), 0),
+                Resource::Shader(_) => gl_state::use_program(0),
+            }
+        }
+    }
+
+    fn submit(&mut self, draws: &[DrawCommand]) {
+        unsafe {
+            for draw in draws {
+                gl_state::bind_vertex_array(draw.vertex_array);
+                let mode = primitive_mode(draw.primitive);
+                if draw.indexed {
+                    let offset = (draw.first as usize * std::mem::size_of::<u32>()) as *const c_void;
+                    gl::DrawElementsInstanced(mode, draw.count, gl::UNSIGNED_INT, offset, draw.instances);
+                } else {
+                    gl::DrawArraysInstanced(mode, draw.first, draw.count, draw.instances);
+                }
+            }
+            gl_state::bind_vertex_array(0);
+        }
+    }
+
+    fn dispatch(&mut self, shader: Handle, groups: UVec3) {
+        unsafe {
+            gl_state::use_program(shader);
+            gl::DispatchCompute(groups.x, groups.y, groups.z);
+        }
+    }
+
+    fn label(&mut self, resource: Resource, name: &str) {
+        match resource {
+            Resource::VertexArray(vertex_array) => gl_state::label(gl::VERTEX_ARRAY, vertex_array, name),
+            Resource::Buffer(buffer) => gl_state::label(gl::BUFFER, buffer, name),
+            Resource::Texture(texture) => {
+                gl_state::label(gl::TEXTURE, texture, name);
+                if let Some(buffer) = self.texture_buffers.get(&texture) {
+                    gl_state::label(gl::BUFFER, *buffer, name);
+                }
+            }
+            Resource::Shader(program) => gl_state::label(gl::PROGRAM, program, name),
+        }
+    }
+
+    fn destroy(&mut self, resource: Resource) {
+        unsafe {
+            match resource {
+                Resource::VertexArray(vertex_array) => gl_state::delete_vertex_arrays(1, &vertex_array),
+                Resource::Buffer(buffer) => {
+                    self.buffer_targets.remove(&buffer);
+                    gl_state::delete_buffers(1, &buffer);
+                }
+                Resource::Texture(texture) => {
+                    self.texture_targets.remove(&texture);
+                    gl_state::delete_textures(1, &texture);
+                    if let Some(buffer) = self.texture_buffers.remove(&texture) {
+                        gl_state::delete_buffers(1, &buffer);
+                    }
+                }
+                Resource::Shader(program) => gl_state::delete_program(program),
+            }
+        }
+    }
+}
+
+unsafe fn compile_stage(stage: ShaderStage, source: &str) -> u32 {
+    let (kind, stage_name) = match stage {
+        ShaderStage::Vertex => (gl::VERTEX_SHADER, "VERTEX"),
+        ShaderStage::Fragment => (gl::FRAGMENT_SHADER, "FRAGMENT"),
+        ShaderStage::Compute => (gl::COMPUTE_SHADER, "COMPUTE"),
+    };
+    let shader = gl::CreateShader(kind);
+    let c_str = CString::new(source.as_bytes()).unwrap();
+    gl::ShaderSource(shader, 1, &c_str.as_ptr(), ptr::null());
+    gl::CompileShader(shader);
+
+    // check for shader compile errors
+    let mut success = gl::FALSE as GLint;
+    let mut info_log = vec![0u8; 512];
+    gl::GetShaderiv(shader, gl::COMPILE_STATUS, &mut success);
+    if success != gl::TRUE as GLint {
+        let mut length = 0;
+        gl::GetShaderInfoLog(shader, info_log.len() as i32, &mut length, info_log.as_mut_ptr() as *mut GLchar);
+        println!(
+            "ERROR::SHADER::{}::COMPILATION_FAILED\n{}",
+            stage_name,
+            str::from_utf8(&info_log[..length as usize]).unwrap_or_default()
+        );
+    }
+    shader
+}
+
+//Links the stages into a program and deletes them
+unsafe fn link_program(stages: &[u32]) -> u32 {
+    let shader_program = gl::CreateProgram();
+    for stage in stages {
+        gl::AttachShader(shader_program, *stage);
+    }
+    gl::LinkProgram(shader_program);
+
+    // check for linking errors
+    let mut success = gl::FALSE as GLint;
+    let mut info_log = vec![0u8; 512];
+    gl::GetProgramiv(shader_program, gl::LINK_STATUS, &mut success);
+    if success != gl::TRUE as GLint {
+        let mut length = 0;
+        gl::GetProgramInfoLog(shader_program, info_log.len() as i32, &mut length, info_log.as_mut_ptr() as *mut GLchar);
+        println!(
+            "ERROR::SHADER::PROGRAM::COMPILATION_FAILED\n{}",
+            str::from_utf8(&info_log[..length as usize]).unwrap_or_default()
+        );
+    }
+    for stage in stages {
+        gl::DeleteShader(*stage);
+    }
+    shader_program
+}
@@ -0,0 +1,567 @@
+use std::{error::Error, f32::consts::PI, fs, mem, os::raw::c_void, sync::{Arc, atomic::{AtomicU64, Ordering}}};
+
+use bevy_ecs::prelude::*;
+use gl::types::*;
+use glam::*;
+use serde::{Serialize, Deserialize};
+
+use crate::{baker::Rng, components::Camera, gl_state, material::{BlendMode, TextureOptions, WrapMode}, mesh::{self, MeshData}, renderer::{GPUObject, VAO}, shader::Shader};
+
+//Per-particle attributes, they reuse the instanced model matrix locations
+const CENTER_LOCATION: u32 = mesh::MODEL_LOCATION;
+const TINT_LOCATION: u32 = mesh::MODEL_LOCATION + 1;
+const ROTATION_LOCATION: u32 = mesh::MODEL_LOCATION + 2;
+
+//Keyframes of (normalized age, value), sampled linearly and clamped at both ends
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(transparent)]
+pub struct Curve<T>(pub Vec<(f32, T)>);
+
+pub trait Interpolate: Copy {
+    fn interpolate(a: Self, b: Self, t: f32) -> Self;
+}
+
+impl Interpolate for f32 {
+    fn interpolate(a: f32, b: f32, t: f32) -> f32 {
+        a + (b - a) * t
+    }
+}
+
+impl Interpolate for [f32; 4] {
+    fn interpolate(a: [f32; 4], b: [f32; 4], t: f32) -> [f32; 4] {
+        Vec4::from_array(a).lerp(Vec4::from_array(b), t).to_array()
+    }
+}
+
+impl<T: Interpolate> Curve<T> {
+    pub fn constant(value: T) -> Curve<T> {
+        Curve(vec![(0.0, value)])
+    }
+
+    pub fn sample(&self, t: f32) -> Option<T> {
+        let keys = &self.0;
+        let next = keys.iter().position(|(time, _)| *time > t);
+        match next {
+            Some(0) => keys.first().map(|(_, value)| *value),
+            Some(index) => {
+                let ((start, a), (end, b)) = (keys[index - 1], keys[index]);
+                Some(T::interpolate(a, b, (t - start) / (end - start).max(f32::EPSILON)))
+            },
+            None => keys.last().map(|(_, value)| *value),
+        }
+    }
+}
+
+//Where particles start and which way they leave, in the emitter's local space
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub enum EmissionShape {
+    #[default]
+    Point,
+    //Anywhere inside, moving away from the center
+    Sphere { radius: f32 },
+    //Disc of `radius` around the emitter, moving up +Y within `angle` degrees
+    Cone { angle: f32, radius: f32 },
+    //Surface of the emitter's mesh, moving along the face normals, see ParticleEmitter::with_surface
+    Mesh,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+pub enum SimulationSpace {
+    //Particles stay where they were spawned when the emitter moves
+    #[default]
+    World,
+    Local,
+}
+
+//Texture sheet cells played row by row from the top left
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct SheetAnimation {
+    pub columns: u32,
+    pub rows: u32,
+    //0 plays the sheet once over each particle's lifetime
+    pub frames_per_second: f32,
+    pub random_start: bool,
+}
+
+impl Default for SheetAnimation {
+    fn default() -> Self {
+        Self { columns: 1, rows: 1, frames_per_second: 0.0, random_start: false }
+    }
+}
+
+impl SheetAnimation {
+    fn frames(&self) -> u32 {
+        (self.columns * self.rows).max(1)
+    }
+}
+
+//Layout of resources/particles/{name}.toml, ranges are picked uniformly per particle
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct ParticleEffect {
+    //Empty draws soft round particles
+    pub texture: String,
+    pub texture_options: TextureOptions,
+    pub sheet: SheetAnimation,
+    pub blend_mode: BlendMode,
+    pub shape: EmissionShape,
+    pub space: SimulationSpace,
+    //Particles per second
+    pub rate: f32,
+    //(seconds into the cycle, count)
+    pub bursts: Vec<(f32, u32)>,
+    //Length of one emission cycle in seconds
+    pub duration: f32,
+    pub looping: bool,
+    pub max_particles: usize,
+    pub lifetime: [f32; 2],
+    pub speed: [f32; 2],
+    //Added to every particle's start velocity, emitter space
+    pub velocity: [f32; 3],
+    //World units per second squared
+    pub gravity: [f32; 3],
+    //Fraction of the velocity lost per second
+    pub drag: f32,
+    //Degrees and degrees per second
+    pub rotation: [f32; 2],
+    pub angular_velocity: [f32; 2],
+    pub size: [f32; 2],
+    pub color: [f32; 4],
+    //Multiply `size` and `color` over each particle's normalized age
+    pub size_over_lifetime: Curve<f32>,
+    pub color_over_lifetime: Curve<[f32; 4]>,
+}
+
+impl Default for ParticleEffect {
+    fn default() -> Self {
+        Self {
+            texture: String::new(),
+            texture_options: TextureOptions { wrap: WrapMode::ClampToEdge, ..Default::default() },
+            sheet: SheetAnimation::default(),
+            blend_mode: BlendMode::AlphaBlend,
+            shape: EmissionShape::Point,
+            space: SimulationSpace::World,
+            rate: 10.0,
+            bursts: Vec::new(),
+            duration: 5.0,
+            looping: true,
+            max_particles: 1000,
+            lifetime: [1.0, 1.0],
+            speed: [1.0, 1.0],
+            velocity: [0.0; 3],
+            gravity: [0.0; 3],
+            drag: 0.0,
+            rotation: [0.0, 0.0],
+            angular_velocity: [0.0, 0.0],
+            size: [0.1, 0.1],
+            color: [1.0; 4],
+            size_over_lifetime: Curve::constant(1.0),
+            color_over_lifetime: Curve::constant([1.0; 4]),
+        }
+    }
+}
+
+impl ParticleEffect {
+    pub fn load(name: &str) -> Result<ParticleEffect, Box<dyn Error>> {
+        Ok(toml::from_str(&fs::read_to_string(format!("resources/particles/{}.toml", name))?)?)
+    }
+}
+
+//Triangles of a mesh with their running area total, so surface points are picked evenly
+pub struct EmissionSurface {
+    triangles: Vec<[Vec3; 3]>,
+    areas: Vec<f32>,
+}
+
+impl EmissionSurface {
+    pub fn from_mesh(data: &MeshData) -> EmissionSurface {
+        let mut surface = EmissionSurface { triangles: Vec::new(), areas: Vec::new() };
+        let (size, positions) = match data.buffer(mesh::POSITION_LOCATION) {
+            Some((size, positions)) if size >= 3 => (size as usize, positions),
+            _ => return surface,
+        };
+        let vertex = |index: i32| Vec3::from_slice(&positions[index as usize * size..]);
+        let mut total = 0.0;
+        for triangle in data.indices.chunks_exact(3) {
+            let corners = [vertex(triangle[0]), vertex(triangle[1]), vertex(triangle[2])];
+            total += (corners[1] - corners[0]).cross(corners[2] - corners[0]).length() * 0.5;
+            surface.triangles.push(corners);
+            surface.areas.push(total);
+        }
+        surface
+    }
+
+    fn sample(&self, rng: &mut Rng) -> Option<(Vec3, Vec3)> {
+        let total = *self.areas.last()?;
+        let target = rng.next() * total;
+        let index = self.areas.partition_point(|area| *area < target).min(self.triangles.len() - 1);
+        let [a, b, c] = self.triangles[index];
+        let (mut u, mut v) = (rng.next(), rng.next());
+        if u + v > 1.0 {
+            (u, v) = (1.0 - u, 1.0 - v);
+        }
+        let normal = (b - a).cross(c - a).try_normalize().unwrap_or(Vec3::Y);
+        Some((a + (b - a) * u + (c - a) * v, normal))
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Particle {
+    //World or emitter space depending on the effect
+    position: Vec3,
+    velocity: Vec3,
+    age: f32,
+    lifetime: f32,
+    size: f32,
+    rotation: f32,
+    angular_velocity: f32,
+    start_frame: u32,
+}
+
+static NEXT_SEED: AtomicU64 = AtomicU64::new(1);
+
+//Simulated on the CPU every frame from the effect in resources/particles/{effect}.toml
+//Starts the effect of the ParticleEmitter on the same entity over once it finished, for replaying one-shot effects
+#[derive(Default, Component)]
+pub struct RestartWhenFinished;
+
+#[derive(Component)]
+pub struct ParticleEmitter {
+    pub effect: String,
+    //Stops spawning, particles already alive finish their lifetime
+    pub emitting: bool,
+    surface: Option<Arc<EmissionSurface>>,
+    particles: Vec<Particle>,
+    time: f32,
+    spawn_accumulator: f32,
+    next_burst: usize,
+    //Emitter model matrix of the last simulated frame, local space particles are drawn with it
+    transform: Mat4,
+    rng: Rng,
+}
+
+impl ParticleEmitter {
+    pub fn new(effect: &str) -> ParticleEmitter {
+        ParticleEmitter {
+            effect: effect.to_string(),
+            emitting: true,
+            surface: None,
+            particles: Vec::new(),
+            time: 0.0,
+            spawn_accumulator: 0.0,
+            next_burst: 0,
+            transform: Mat4::IDENTITY,
+            rng: Rng(NEXT_SEED.fetch_add(1, Ordering::Relaxed).wrapping_mul(0x9E3779B97F4A7C15) | 1),
+        }
+    }
+
+    //Mesh EmissionShape spawns on this mesh, emitters without one use a point
+    pub fn with_surface(mut self, data: &MeshData) -> ParticleEmitter {
+        self.surface = Some(Arc::new(EmissionSurface::from_mesh(data)));
+        self
+    }
+
+    pub fn particle_count(&self) -> usize {
+        self.particles.len()
+    }
+
+    //Non-looping effects are done once their cycle ended and the last particle died
+    pub fn is_finished(&self, effect: &ParticleEffect) -> bool {
+        !effect.looping && self.time >= effect.duration && self.particles.is_empty()
+    }
+
+    //Restarts the cycle, bursts fire again
+    pub fn restart(&mut self) {
+        self.time = 0.0;
+        self.spawn_accumulator = 0.0;
+        self.next_burst = 0;
+        self.emitting = true;
+    }
+
+    pub fn update(&mut self, effect: &ParticleEffect, transform: Mat4, delta: f32) {
+        self.transform = transform;
+
+        //Ages and moves the living particles, gravity is always world space
+        let gravity = match effect.space {
+            SimulationSpace::World => Vec3::from_array(effect.gravity),
+            SimulationSpace::Local => transform.inverse().transform_vector3(Vec3::from_array(effect.gravity)),
+        };
+        let damping = (1.0 - effect.drag * delta).max(0.0);
+        self.particles.retain_mut(|particle| {
+            particle.age += delta;
+            particle.velocity = (particle.velocity + gravity * delta) * damping;
+            particle.position += particle.velocity * delta;
+            particle.rotation += particle.angular_velocity * delta;
+            particle.age < particle.lifetime
+        });
+
+        if !self.emitting || (!effect.looping && self.time >= effect.duration) {
+            return;
+        }
+        self.time += delta;
+        let mut count = 0;
+        //Bursts are fired in cycle order, wrapping around when a looping cycle ends
+        let mut cycle_time = self.time;
+        if effect.looping && effect.duration > 0.0 && self.time >= effect.duration {
+            count += self.bursts_until(effect, f32::MAX);
+            self.next_burst = 0;
+            cycle_time = self.time % effect.duration;
+            self.time = cycle_time;
+        }
+        count += self.bursts_until(effect, cycle_time);
+        self.spawn_accumulator += effect.rate * delta;
+        count += self.spawn_accumulator as u32;
+        self.spawn_accumulator = self.spawn_accumulator.fract();
+        for _ in 0..count {
+            if self.particles.len() >= effect.max_particles {
+                break;
+            }
+            let particle = self.spawn(effect);
+            self.particles.push(particle);
+        }
+    }
+
+    fn bursts_until(&mut self, effect: &ParticleEffect, time: f32) -> u32 {
+        let mut count = 0;
+        while let Some((at, burst)) = effect.bursts.get(self.next_burst) {
+            if *at > time {
+                break;
+            }
+            count += burst;
+            self.next_burst += 1;
+        }
+        count
+    }
+
+    fn spawn(&mut self, effect: &ParticleEffect) -> Particle {
+        let rng = &mut self.rng;
+        let (position, direction) = match &effect.shape {
+            EmissionShape::Point => (Vec3::ZERO, random_direction(rng)),
+            EmissionShape::Sphere { radius } => {
+                let direction = random_direction(rng);
+                (direction * *radius * rng.next().cbrt(), direction)
+            },
+            EmissionShape::Cone { angle, radius } => {
+                let (distance, around) = (radius * rng.next().sqrt(), 2.0 * PI * rng.next());
+                let spread = angle.to_radians() * rng.next();
+                let direction = Vec3::new(spread.sin() * around.cos(), spread.cos(), spread.sin() * around.sin());
+                (Vec3::new(around.cos(), 0.0, around.sin()) * distance, direction)
+            },
+            EmissionShape::Mesh => self.surface.as_ref().and_then(|surface| surface.sample(rng)).unwrap_or((Vec3::ZERO, Vec3::Y)),
+        };
+        let velocity = direction * range(rng, effect.speed) + Vec3::from_array(effect.velocity);
+        let (position, velocity) = match effect.space {
+            SimulationSpace::World => (self.transform.transform_point3(position), self.transform.transform_vector3(velocity)),
+            SimulationSpace::Local => (position, velocity),
+        };
+        Particle {
+            position,
+            velocity,
+            age: 0.0,
+            lifetime: range(rng, effect.lifetime).max(f32::EPSILON),
+            size: range(rng, effect.size),
+            rotation: range(rng, effect.rotation).to_radians(),
+            angular_velocity: range(rng, effect.angular_velocity).to_radians(),
+            start_frame: if effect.sheet.random_start { (rng.next() * effect.sheet.frames() as f32) as u32 } else { 0 },
+        }
+    }
+
+    //World space position used to sort emitters against each other
+    pub fn origin(&self) -> Vec3 {
+        self.transform.transform_point3(Vec3::ZERO)
+    }
+}
+
+fn range(rng: &mut Rng, [min, max]: [f32; 2]) -> f32 {
+    min + (max - min) * rng.next()
+}
+
+fn random_direction(rng: &mut Rng) -> Vec3 {
+    let z = rng.next() * 2.0 - 1.0;
+    let around = 2.0 * PI * rng.next();
+    let radius = (1.0 - z * z).max(0.0).sqrt();
+    Vec3::new(radius * around.cos(), radius * around.sin(), z)
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct ParticleInstance {
+    //World space center and size
+    center: [f32; 4],
+    color: [f32; 4],
+    //Radians and sheet frame
+    rotation: [f32; 2],
+}
+
+//Camera facing quads, one instanced draw call per emitter
+#[derive(Resource)]
+pub struct ParticleRenderer {
+    shader: Shader,
+    vao: VAO,
+    quad_buffer: u32,
+    instance_buffer: u32,
+    instances: Vec<ParticleInstance>,
+}
+
+impl ParticleRenderer {
+    pub fn new() -> Result<ParticleRenderer, Box<dyn Error>> {
+        let vao = VAO::new();
+        let (mut quad_buffer, mut instance_buffer) = (0, 0);
+        let corners: [f32; 8] = [-0.5, -0.5, 0.5, -0.5, 0.5, 0.5, -0.5, 0.5];
+        unsafe {
+            gl::GenBuffers(1, &mut quad_buffer);
+            gl::GenBuffers(1, &mut instance_buffer);
+            vao.bind();
+            gl_state::bind_buffer(gl::ARRAY_BUFFER, quad_buffer);
+            gl::BufferData(gl::ARRAY_BUFFER, mem::size_of_val(&corners) as GLsizeiptr, corners.as_ptr() as *const c_void, gl::STATIC_DRAW);
+            gl::VertexAttribPointer(mesh::POSITION_LOCATION, 2, gl::FLOAT, gl::FALSE, 0, std::ptr::null());
+            gl::EnableVertexAttribArray(mesh::POSITION_LOCATION);
+
+            gl_state::bind_buffer(gl::ARRAY_BUFFER, instance_buffer);
+            let stride = mem::size_of::<ParticleInstance>() as GLsizei;
+            let attributes = [(CENTER_LOCATION, 4, 0), (TINT_LOCATION, 4, 4), (ROTATION_LOCATION, 2, 8)];
+            for (location, size, offset) in attributes {
+                gl::VertexAttribPointer(location, size, gl::FLOAT, gl::FALSE, stride, (offset * mem::size_of::<f32>()) as *const c_void);
+                gl::EnableVertexAttribArray(location);
+                gl::VertexAttribDivisor(location, 1);
+            }
+            vao.label("particles");
+            gl_state::label(gl::BUFFER, quad_buffer, "particle quad");
+            gl_state::label(gl::BUFFER, instance_buffer, "particle instances");
+            vao.unbind();
+            gl_state::bind_buffer(gl::ARRAY_BUFFER, 0);
+        }
+        Ok(ParticleRenderer {
+            shader: Shader::new("particle")?,
+            vao,
+            quad_buffer,
+            instance_buffer,
+            instances: Vec::new(),
+        })
+    }
+
+    //Blended over the bound target and depth tested against the scene without writing depth, alpha blended particles are sorted far to near
+    pub fn render(&mut self, emitter: &ParticleEmitter, effect: &ParticleEffect, camera: &Camera, texture: Option<u32>, is_wireframe: bool) {
+        if emitter.particles.is_empty() {
+            return;
+        }
+        let to_world = match effect.space {
+            SimulationSpace::World => Mat4::IDENTITY,
+            SimulationSpace::Local => emitter.transform,
+        };
+        let sheet = &effect.sheet;
+        let frames = sheet.frames();
+        self.instances.clear();
+        self.instances.extend(emitter.particles.iter().map(|particle| {
+            let t = particle.age / particle.lifetime;
+            let size = particle.size * effect.size_over_lifetime.sample(t).unwrap_or(1.0);
+            let color = Vec4::from_array(effect.color) * Vec4::from_array(effect.color_over_lifetime.sample(t).unwrap_or([1.0; 4]));
+            let played = if sheet.frames_per_second > 0.0 { particle.age * sheet.frames_per_second } else { t * frames as f32 };
+            let frame = (particle.start_frame + played as u32) % frames;
+            ParticleInstance {
+                center: to_world.transform_point3(particle.position).extend(size).to_array(),
+                color: color.to_array(),
+                rotation: [particle.rotation, frame as f32],
+            }
+        }));
+        if effect.blend_mode == BlendMode::AlphaBlend {
+            let depth = |instance: &ParticleInstance| camera.view.transform_point3(Vec4::from_array(instance.center).truncate()).z;
+            self.instances.sort_by(|a, b| depth(a).total_cmp(&depth(b)));
+        }
+
+        unsafe {
+            gl_state::bind_buffer(gl::ARRAY_BUFFER, self.instance_buffer);
+            gl::BufferData(gl::ARRAY_BUFFER, (self.instances.len() * mem::size_of::<ParticleInstance>()) as GLsizeiptr, self.instances.as_ptr() as *const c_void, gl::STREAM_DRAW);
+            gl_state::bind_buffer(gl::ARRAY_BUFFER, 0);
+
+            gl::PolygonMode(gl::FRONT_AND_BACK, gl::FILL);
+            gl_state::depth_mask(gl::FALSE);
+            gl_state::bind_texture(gl::TEXTURE_2D, texture.unwrap_or(0));
+        }
+        effect.blend_mode.apply();
+        let shader = &self.shader;
+        shader.bind();
+        shader.set_uniform_4x4f("view".to_string(), None, &camera.view);
+        shader.set_uniform_4x4f("projection".to_string(), None, &camera.projection);
+        shader.set_uniform_i32("particle".to_string(), &0);
+        shader.set_uniform_bool("hasTexture".to_string(), &texture.is_some());
+        shader.set_uniform_2i("sheet".to_string(), &IVec2::new(sheet.columns.max(1) as i32, sheet.rows.max(1) as i32));
+        self.vao.bind();
+        unsafe {
+            gl::DrawArraysInstanced(gl::TRIANGLE_FAN, 0, 4, self.instances.len() as i32);
+        }
+        self.vao.unbind();
+        shader.unbind();
+        unsafe {
+            gl_state::bind_texture(gl::TEXTURE_2D, 0);
+            gl_state::disable(gl::BLEND);
+            gl_state::depth_mask(gl::TRUE);
+        }
+        crate::renderer::update_wireframe(&is_wireframe);
+    }
+}
+
+impl Drop for ParticleRenderer {
+    fn drop(&mut self) {
+        unsafe {
+            gl_state::delete_buffers(1, &self.quad_buffer);
+            gl_state::delete_buffers(1, &self.instance_buffer);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //Ten particles at the start of a one second cycle, each living half a second
+    fn burst(looping: bool) -> ParticleEffect {
+        ParticleEffect { rate: 0.0, bursts: vec![(0.0, 10)], duration: 1.0, looping, lifetime: [0.5, 0.5], ..Default::default() }
+    }
+
+    #[test]
+    fn finishes_one_shot_effects_and_restarts_them() {
+        let effect = burst(false);
+        let mut emitter = ParticleEmitter::new("burst");
+        emitter.update(&effect, Mat4::IDENTITY, 0.1);
+        assert_eq!(emitter.particle_count(), 10);
+        for _ in 0..12 {
+            emitter.update(&effect, Mat4::IDENTITY, 0.1);
+        }
+        assert_eq!(emitter.particle_count(), 0);
+        assert!(emitter.is_finished(&effect));
+
+        emitter.restart();
+        assert!(!emitter.is_finished(&effect));
+        emitter.update(&effect, Mat4::IDENTITY, 0.1);
+        assert_eq!(emitter.particle_count(), 10);
+    }
+
+    #[test]
+    fn fires_bursts_again_every_cycle() {
+        let effect = burst(true);
+        let mut emitter = ParticleEmitter::new("burst");
+        emitter.update(&effect, Mat4::IDENTITY, 0.1);
+        emitter.update(&effect, Mat4::IDENTITY, 0.6);
+        assert_eq!(emitter.particle_count(), 0);
+        emitter.update(&effect, Mat4::IDENTITY, 0.4);
+        assert_eq!(emitter.particle_count(), 10);
+        assert!(!emitter.is_finished(&effect));
+    }
+
+    #[test]
+    fn caps_particles_and_stops_emitting_on_request() {
+        let effect = ParticleEffect { rate: 100.0, max_particles: 20, lifetime: [10.0, 10.0], ..Default::default() };
+        let mut emitter = ParticleEmitter::new("stream");
+        emitter.update(&effect, Mat4::IDENTITY, 0.1);
+        assert_eq!(emitter.particle_count(), 10);
+        emitter.update(&effect, Mat4::IDENTITY, 0.5);
+        assert_eq!(emitter.particle_count(), 20);
+
+        let mut emitter = ParticleEmitter::new("stream");
+        emitter.emitting = false;
+        emitter.update(&effect, Mat4::IDENTITY, 0.1);
+        assert_eq!(emitter.particle_count(), 0);
+    }
+}
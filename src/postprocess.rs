@@ -0,0 +1,479 @@
+use std::{collections::HashMap, error::Error, mem, os::raw::c_void};
+
+use bevy_ecs::prelude::*;
+use glam::*;
+
+use crate::{framebuffer::{self, Framebuffer}, gl_state, renderer::{FullscreenTriangle, GPUObject}, shader::Shader, texture::Texture};
+
+//Must match the bin count and work group sizes in the luminance shaders
+const HISTOGRAM_BINS: usize = 256;
+const HISTOGRAM_TILE_SIZE: u32 = 16;
+const HISTOGRAM_BINDING: u32 = 0;
+
+#[derive(Clone, Copy, PartialEq, Default)]
+pub enum ToneMapping {
+    None,
+    Reinhard,
+    #[default]
+    Aces,
+    AgX,
+}
+
+impl ToneMapping {
+    pub fn next(&self) -> ToneMapping {
+        match self {
+            ToneMapping::None => ToneMapping::Reinhard,
+            ToneMapping::Reinhard => ToneMapping::Aces,
+            ToneMapping::Aces => ToneMapping::AgX,
+            ToneMapping::AgX => ToneMapping::None,
+        }
+    }
+
+    //Must match the operator ids in tonemap.fs
+    fn id(&self) -> i32 {
+        match self {
+            ToneMapping::None => 0,
+            ToneMapping::Reinhard => 1,
+            ToneMapping::Aces => 2,
+            ToneMapping::AgX => 3,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Bloom {
+    pub enabled: bool,
+    //Brightness where bloom starts, knee softens the cut off
+    pub threshold: f32,
+    pub knee: f32,
+    pub intensity: f32,
+    //Upsample filter radius in texels of the smaller mip
+    pub radius: f32,
+    pub mip_count: u32,
+}
+
+impl Default for Bloom {
+    fn default() -> Self {
+        Self { enabled: true, threshold: 1.0, knee: 0.5, intensity: 0.3, radius: 1.0, mip_count: 6 }
+    }
+}
+
+#[derive(Clone)]
+pub struct Exposure {
+    //Adapts to the average scene luminance, otherwise only the compensation is applied
+    pub auto: bool,
+    //In stops, applied on top of auto exposure
+    pub compensation: f32,
+    //Luminance range covered by the histogram, in log2 units
+    pub min_log_luminance: f32,
+    pub max_log_luminance: f32,
+    pub adaptation_speed: f32,
+}
+
+impl Exposure {
+    //Never zero, the histogram shader divides by it
+    fn log_luminance_range(&self) -> f32 {
+        (self.max_log_luminance - self.min_log_luminance).max(0.0001)
+    }
+}
+
+impl Default for Exposure {
+    fn default() -> Self {
+        Self { auto: true, compensation: 0.0, min_log_luminance: -8.0, max_log_luminance: 4.0, adaptation_speed: 1.5 }
+    }
+}
+
+#[derive(Clone)]
+pub struct ColorGrading {
+    //Strip LUT from resources/textures, e.g. "luts/neutral"
+    pub lut: Option<String>,
+    pub gamma: f32,
+}
+
+impl Default for ColorGrading {
+    fn default() -> Self {
+        Self { lut: None, gamma: 2.2 }
+    }
+}
+
+//Cameras with this component render into an HDR target that is resolved by the post stack
+#[derive(Component, Clone, Default)]
+pub struct PostProcess {
+    pub bloom: Bloom,
+    pub exposure: Exposure,
+    pub tone_mapping: ToneMapping,
+    pub color_grading: ColorGrading,
+}
+
+//Render graph attachments and framebuffer the scene is drawn into
+pub const HDR: &str = "hdr";
+pub const HDR_COLOR: &str = "hdr_color";
+pub const HDR_DEPTH: &str = "hdr_depth";
+
+struct PostTargets {
+    width: u32,
+    height: u32,
+    mip_count: u32,
+    bloom_mips: Vec<Framebuffer>,
+}
+
+impl PostTargets {
+    fn new(width: u32, height: u32, mip_count: u32) -> PostTargets {
+        let bloom_mips = bloom_mip_sizes(width, height, mip_count).into_iter().enumerate().map(|(index, (mip_width, mip_height))| {
+            let mut mip = Framebuffer::new(mip_width, mip_height);
+            mip.add_color_attachment(gl::RGBA16F, gl::RGBA, gl::FLOAT, gl::LINEAR);
+            mip.is_complete();
+            mip.label(&format!("bloom mip {}", index));
+            mip
+        }).collect();
+
+        PostTargets { width, height, mip_count, bloom_mips }
+    }
+}
+
+//Each mip is half the size of the one before, stopping before anything collapses to a single pixel
+fn bloom_mip_sizes(width: u32, height: u32, mip_count: u32) -> Vec<(i32, i32)> {
+    let mut sizes = Vec::new();
+    let (mut mip_width, mut mip_height) = (width.max(1) as i32 / 2, height.max(1) as i32 / 2);
+    while (sizes.len() as u32) < mip_count && mip_width > 1 && mip_height > 1 {
+        sizes.push((mip_width, mip_height));
+        mip_width /= 2;
+        mip_height /= 2;
+    }
+    sizes
+}
+
+//How far the stored average moves towards this frame's in delta_seconds, independent of the frame rate
+fn adaptation(exposure: &Exposure, delta_seconds: f32) -> f32 {
+    (1.0 - (-delta_seconds * exposure.adaptation_speed).exp()).clamp(0.0, 1.0)
+}
+
+//Targets of recently used view sizes are kept, so split screen views don't reallocate every frame
+const CACHED_TARGETS: usize = 4;
+
+#[derive(Resource)]
+pub struct PostProcessing {
+    targets: PostTargets,
+    cached_targets: Vec<PostTargets>,
+    triangle: FullscreenTriangle,
+    downsample_shader: Shader,
+    upsample_shader: Shader,
+    histogram_shader: Shader,
+    exposure_shader: Shader,
+    tonemap_shader: Shader,
+    histogram_buffer: u32,
+    //1x1 R32F holding the adapted average luminance between frames
+    exposure_texture: u32,
+    //Failed loads are kept as None so a missing LUT is only reported once
+    luts: HashMap<String, Option<(Texture, i32)>>,
+    active: bool,
+    //View target and rectangle the tone mapped result is written back into
+    previous_framebuffer: i32,
+    previous_viewport: [i32; 4],
+}
+
+impl PostProcessing {
+    pub fn new(width: u32, height: u32) -> Result<PostProcessing, Box<dyn Error>> {
+        let bloom_mip_count = Bloom::default().mip_count;
+        let mut histogram_buffer = 0;
+        let mut exposure_texture = 0;
+        unsafe {
+            let bins = [0u32; HISTOGRAM_BINS];
+            gl::GenBuffers(1, &mut histogram_buffer);
+            gl_state::bind_buffer(gl::SHADER_STORAGE_BUFFER, histogram_buffer);
+            gl::BufferData(gl::SHADER_STORAGE_BUFFER, mem::size_of_val(&bins) as isize, bins.as_ptr() as *const c_void, gl::DYNAMIC_COPY);
+            gl_state::bind_buffer(gl::SHADER_STORAGE_BUFFER, 0);
+
+            //Starts at middle grey so the first frames are not wildly over or under exposed
+            let luminance = 0.18f32;
+            gl::GenTextures(1, &mut exposure_texture);
+            gl_state::bind_texture(gl::TEXTURE_2D, exposure_texture);
+            gl::TexStorage2D(gl::TEXTURE_2D, 1, gl::R32F, 1, 1);
+            gl::TexSubImage2D(gl::TEXTURE_2D, 0, 0, 0, 1, 1, gl::RED, gl::FLOAT, &luminance as *const f32 as *const c_void);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as i32);
+            gl_state::bind_texture(gl::TEXTURE_2D, 0);
+        }
+        gl_state::label(gl::BUFFER, histogram_buffer, "luminance histogram");
+        gl_state::label(gl::TEXTURE, exposure_texture, "exposure");
+
+        Ok(PostProcessing {
+            targets: PostTargets::new(width, height, bloom_mip_count),
+            cached_targets: Vec::new(),
+            triangle: FullscreenTriangle::new(),
+            downsample_shader: Shader::from_files("fullscreen", "bloom_downsample")?,
+            upsample_shader: Shader::from_files("fullscreen", "bloom_upsample")?,
+            histogram_shader: Shader::compute("luminance_histogram")?,
+            exposure_shader: Shader::compute("luminance_average")?,
+            tonemap_shader: Shader::from_files("fullscreen", "tonemap")?,
+            histogram_buffer,
+            exposure_texture,
+            luts: HashMap::new(),
+            active: false,
+            previous_framebuffer: 0,
+            previous_viewport: [0; 4],
+        })
+    }
+
+    pub fn resize(&mut self, width: u32, height: u32, bloom_mip_count: u32) {
+        let matches = |targets: &PostTargets| targets.width == width && targets.height == height && targets.mip_count == bloom_mip_count;
+        if matches(&self.targets) {
+            return;
+        }
+        let targets = match self.cached_targets.iter().position(matches) {
+            Some(index) => self.cached_targets.remove(index),
+            None => PostTargets::new(width, height, bloom_mip_count),
+        };
+        self.cached_targets.push(mem::replace(&mut self.targets, targets));
+        if self.cached_targets.len() > CACHED_TARGETS {
+            self.cached_targets.remove(0);
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    //Everything drawn until resolve() lands in the HDR target
+    pub fn begin(&mut self, hdr: &Framebuffer) {
+        unsafe {
+            gl::GetIntegerv(gl::DRAW_FRAMEBUFFER_BINDING, &mut self.previous_framebuffer);
+            gl::GetIntegerv(gl::VIEWPORT, self.previous_viewport.as_mut_ptr());
+        }
+        hdr.bind();
+        unsafe {
+            gl::Viewport(0, 0, hdr.width(), hdr.height());
+            gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
+        }
+        self.active = true;
+    }
+
+    //Runs the enabled passes and writes the tone mapped result and scene depth to the view's target
+    pub fn resolve(&mut self, hdr: &Framebuffer, settings: &PostProcess, delta_seconds: f32, is_wireframe: bool) {
+        self.active = false;
+        hdr.unbind();
+        unsafe {
+            gl::PolygonMode(gl::FRONT_AND_BACK, gl::FILL);
+            gl_state::disable(gl::DEPTH_TEST);
+        }
+
+        let bloom = settings.bloom.enabled && !self.targets.bloom_mips.is_empty();
+        if bloom {
+            self.render_bloom(hdr, &settings.bloom);
+        }
+        if settings.exposure.auto {
+            self.measure_luminance(hdr, &settings.exposure, delta_seconds);
+        }
+        let lut = settings.color_grading.lut.as_ref().and_then(|name| self.load_lut(name));
+
+        let targets = &self.targets;
+        let viewport = self.previous_viewport;
+        unsafe {
+            gl_state::bind_framebuffer(gl::FRAMEBUFFER, self.previous_framebuffer as u32);
+            gl::Viewport(viewport[0], viewport[1], viewport[2], viewport[3]);
+            gl_state::enable(gl::DEPTH_TEST);
+            gl_state::depth_func(gl::ALWAYS);
+        }
+        let shader = &self.tonemap_shader;
+        shader.bind();
+        shader.set_uniform_i32("hdrColor".to_string(), &0);
+        shader.set_uniform_i32("hdrDepth".to_string(), &1);
+        shader.set_uniform_i32("bloom".to_string(), &2);
+        shader.set_uniform_i32("averageLuminance".to_string(), &3);
+        shader.set_uniform_i32("lut".to_string(), &4);
+        shader.set_uniform_bool("bloomEnabled".to_string(), &bloom);
+        shader.set_uniform_1f("bloomIntensity".to_string(), &settings.bloom.intensity);
+        shader.set_uniform_bool("autoExposure".to_string(), &settings.exposure.auto);
+        shader.set_uniform_1f("exposureCompensation".to_string(), &settings.exposure.compensation);
+        shader.set_uniform_i32("toneMapping".to_string(), &settings.tone_mapping.id());
+        shader.set_uniform_bool("lutEnabled".to_string(), &lut.is_some());
+        shader.set_uniform_1f("lutSize".to_string(), &lut.map_or(1.0, |(_, size)| size as f32));
+        shader.set_uniform_1f("gamma".to_string(), &settings.color_grading.gamma);
+        framebuffer::bind_attachment(hdr.color_attachment(0).unwrap(), 0);
+        framebuffer::bind_attachment(hdr.depth_attachment().unwrap(), 1);
+        if bloom {
+            framebuffer::bind_attachment(targets.bloom_mips[0].color_attachment(0).unwrap(), 2);
+        }
+        framebuffer::bind_attachment(self.exposure_texture, 3);
+        unsafe {
+            gl_state::active_texture(gl::TEXTURE4);
+            gl_state::bind_texture(gl::TEXTURE_3D, lut.map_or(0, |(handle, _)| handle));
+            gl_state::active_texture(gl::TEXTURE0);
+        }
+        self.triangle.render();
+        unsafe {
+            gl_state::active_texture(gl::TEXTURE4);
+            gl_state::bind_texture(gl::TEXTURE_3D, 0);
+            gl_state::active_texture(gl::TEXTURE0);
+        }
+        for unit in 0..4 {
+            framebuffer::unbind_attachment(unit);
+        }
+        shader.unbind();
+
+        unsafe {
+            gl_state::depth_func(gl::LESS);
+        }
+        crate::renderer::update_wireframe(&is_wireframe);
+    }
+
+    //Thresholded downsample chain, then each mip is blurred and added onto the next larger one
+    fn render_bloom(&self, hdr: &Framebuffer, bloom: &Bloom) {
+        let targets = &self.targets;
+
+        let shader = &self.downsample_shader;
+        shader.bind();
+        shader.set_uniform_i32("source".to_string(), &0);
+        shader.set_uniform_1f("threshold".to_string(), &bloom.threshold);
+        shader.set_uniform_1f("knee".to_string(), &bloom.knee);
+        let mut source = hdr;
+        for (index, mip) in targets.bloom_mips.iter().enumerate() {
+            mip.bind();
+            unsafe {
+                gl::Viewport(0, 0, mip.width(), mip.height());
+            }
+            shader.set_uniform_bool("prefilter".to_string(), &(index == 0));
+            shader.set_uniform_2f("sourceTexelSize".to_string(), &(Vec2::ONE / Vec2::new(source.width() as f32, source.height() as f32)));
+            framebuffer::bind_attachment(source.color_attachment(0).unwrap(), 0);
+            self.triangle.render();
+            source = mip;
+        }
+        shader.unbind();
+
+        let shader = &self.upsample_shader;
+        shader.bind();
+        shader.set_uniform_i32("source".to_string(), &0);
+        shader.set_uniform_1f("radius".to_string(), &bloom.radius);
+        unsafe {
+            gl_state::enable(gl::BLEND);
+            gl_state::blend_func(gl::ONE, gl::ONE);
+        }
+        for index in (1..targets.bloom_mips.len()).rev() {
+            let (source, destination) = (&targets.bloom_mips[index], &targets.bloom_mips[index - 1]);
+            destination.bind();
+            unsafe {
+                gl::Viewport(0, 0, destination.width(), destination.height());
+            }
+            shader.set_uniform_2f("sourceTexelSize".to_string(), &(Vec2::ONE / Vec2::new(source.width() as f32, source.height() as f32)));
+            framebuffer::bind_attachment(source.color_attachment(0).unwrap(), 0);
+            self.triangle.render();
+        }
+        unsafe {
+            gl_state::disable(gl::BLEND);
+        }
+        framebuffer::unbind_attachment(0);
+        shader.unbind();
+        targets.bloom_mips[0].unbind();
+    }
+
+    //Builds a log luminance histogram of the HDR target and eases the stored average towards its mean
+    fn measure_luminance(&self, hdr: &Framebuffer, exposure: &Exposure, delta_seconds: f32) {
+        let range = exposure.log_luminance_range();
+        unsafe {
+            gl_state::bind_buffer_base(gl::SHADER_STORAGE_BUFFER, HISTOGRAM_BINDING, self.histogram_buffer);
+        }
+
+        let shader = &self.histogram_shader;
+        shader.bind();
+        shader.set_uniform_i32("hdrColor".to_string(), &0);
+        shader.set_uniform_2i("screenSize".to_string(), &IVec2::new(hdr.width(), hdr.height()));
+        shader.set_uniform_1f("minLogLuminance".to_string(), &exposure.min_log_luminance);
+        shader.set_uniform_1f("inverseLogLuminanceRange".to_string(), &(1.0 / range));
+        framebuffer::bind_attachment(hdr.color_attachment(0).unwrap(), 0);
+        let groups_x = (hdr.width() as u32).div_ceil(HISTOGRAM_TILE_SIZE);
+        let groups_y = (hdr.height() as u32).div_ceil(HISTOGRAM_TILE_SIZE);
+        shader.dispatch(groups_x, groups_y, 1);
+        framebuffer::unbind_attachment(0);
+        unsafe {
+            gl::MemoryBarrier(gl::SHADER_STORAGE_BARRIER_BIT);
+        }
+
+        let shader = &self.exposure_shader;
+        shader.bind();
+        shader.set_uniform_i32("pixelCount".to_string(), &(hdr.width() * hdr.height()));
+        shader.set_uniform_1f("minLogLuminance".to_string(), &exposure.min_log_luminance);
+        shader.set_uniform_1f("logLuminanceRange".to_string(), &range);
+        shader.set_uniform_1f("adaptation".to_string(), &adaptation(exposure, delta_seconds));
+        unsafe {
+            gl::BindImageTexture(0, self.exposure_texture, 0, gl::FALSE, 0, gl::READ_WRITE, gl::R32F);
+        }
+        shader.dispatch(1, 1, 1);
+        unsafe {
+            gl::MemoryBarrier(gl::TEXTURE_FETCH_BARRIER_BIT | gl::SHADER_IMAGE_ACCESS_BARRIER_BIT | gl::SHADER_STORAGE_BARRIER_BIT);
+            gl::BindImageTexture(0, 0, 0, gl::FALSE, 0, gl::READ_WRITE, gl::R32F);
+            gl_state::bind_buffer_base(gl::SHADER_STORAGE_BUFFER, HISTOGRAM_BINDING, 0);
+        }
+        shader.unbind();
+    }
+
+    //Returns the 3D texture handle and its edge length
+    fn load_lut(&mut self, name: &str) -> Option<(u32, i32)> {
+        let lut = self.luts.entry(name.to_string()).or_insert_with(|| match Texture::lut(name) {
+            Ok(texture) => {
+                let mut size = 0;
+                unsafe {
+                    gl_state::bind_texture(gl::TEXTURE_3D, texture.handle());
+                    gl::GetTexLevelParameteriv(gl::TEXTURE_3D, 0, gl::TEXTURE_WIDTH, &mut size);
+                    gl_state::bind_texture(gl::TEXTURE_3D, 0);
+                }
+                Some((texture, size))
+            }
+            Err(error) => {
+                println!("Unable to load LUT {}: {}", name, error);
+                None
+            }
+        });
+        lut.as_ref().map(|(texture, size)| (texture.handle(), *size))
+    }
+}
+
+impl Drop for PostProcessing {
+    fn drop(&mut self) {
+        unsafe {
+            gl_state::delete_buffers(1, &self.histogram_buffer);
+            gl_state::delete_textures(1, &self.exposure_texture);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn halves_bloom_mips_until_they_collapse() {
+        assert_eq!(bloom_mip_sizes(1280, 720, 3), [(640, 360), (320, 180), (160, 90)]);
+        assert_eq!(bloom_mip_sizes(64, 16, 6), [(32, 8), (16, 4), (8, 2)]);
+        assert!(bloom_mip_sizes(2, 2, 6).is_empty());
+        assert!(bloom_mip_sizes(0, 0, 6).is_empty());
+    }
+
+    #[test]
+    fn cycles_through_every_tone_mapper() {
+        let mut tone_mapping = ToneMapping::default();
+        let mut ids = Vec::new();
+        for _ in 0..4 {
+            ids.push(tone_mapping.id());
+            tone_mapping = tone_mapping.next();
+        }
+        assert!(tone_mapping == ToneMapping::default());
+        ids.sort();
+        assert_eq!(ids, [0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn adapts_exposure_independent_of_frame_rate() {
+        let exposure = Exposure::default();
+        assert_eq!(adaptation(&exposure, 0.0), 0.0);
+        //Two half steps end where one whole step does
+        let half = adaptation(&exposure, 0.05);
+        let whole = adaptation(&exposure, 0.1);
+        assert!((1.0 - (1.0 - half) * (1.0 - half) - whole).abs() < 1e-6);
+        assert!(adaptation(&exposure, 1000.0) <= 1.0);
+
+        assert_eq!(exposure.log_luminance_range(), 12.0);
+        let flat = Exposure { min_log_luminance: 2.0, max_log_luminance: 1.0, ..Default::default() };
+        assert!(flat.log_luminance_range() > 0.0);
+    }
+}
+
@@ -0,0 +1,574 @@
+use std::{collections::VecDeque, error::Error, fmt::Write, fs, mem, os::raw::c_void, time::Instant};
+
+use bevy_ecs::prelude::*;
+use bevy_ecs::system::BoxedSystem;
+use gl::types::*;
+use glam::*;
+use serde::{Serialize, Deserialize};
+
+use crate::{gl_state, mesh, renderer::{GPUObject, VAO}, settings::Settings, shader::Shader, text::{Font, GlyphAtlas, Text, TextRenderer}};
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct ProfilerSettings {
+    pub enabled: bool,
+    //Frames kept for the overlay and the trace export
+    pub history: usize,
+    //Flame graph of the last finished frame, toggled with F10
+    pub overlay: bool,
+    pub overlay_font: String,
+    //Written with F11 in Chrome's trace event format, open it in chrome://tracing or Perfetto
+    pub trace_path: String,
+}
+
+impl Default for ProfilerSettings {
+    fn default() -> Self {
+        Self { enabled: true, history: 240, overlay: false, overlay_font: "DejaVuSans".to_string(), trace_path: "profile.json".to_string() }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Scope {
+    pub name: String,
+    //Nesting level, 0 for the outermost scopes
+    pub depth: u32,
+    //Milliseconds from the start of the frame
+    pub start: f32,
+    pub duration: f32,
+}
+
+#[derive(Clone, Default)]
+pub struct FrameProfile {
+    pub index: u64,
+    //Milliseconds since the profiler was created
+    pub start: f64,
+    pub duration: f32,
+    pub cpu: Vec<Scope>,
+    //Filled in a few frames later, once the timer queries are available
+    pub gpu: Vec<Scope>,
+    pub gpu_ready: bool,
+}
+
+impl FrameProfile {
+    //Time the GPU was busy with the outermost scopes
+    pub fn gpu_duration(&self) -> f32 {
+        self.gpu.iter().filter(|scope| scope.depth == 0).map(|scope| scope.duration).sum()
+    }
+}
+
+//Frames the GPU results may lag behind, queries are only read once they are available so reading never stalls
+const GPU_LATENCY: usize = 4;
+
+#[derive(Default)]
+struct GpuFrame {
+    index: u64,
+    queries: Vec<u32>,
+    used: usize,
+    //Name, depth and the timestamp queries at both ends
+    scopes: Vec<(String, u32, u32, u32)>,
+    pending: bool,
+}
+
+impl GpuFrame {
+    fn timestamp(&mut self) -> u32 {
+        if self.used == self.queries.len() {
+            let mut query = 0;
+            unsafe {
+                gl::GenQueries(1, &mut query);
+            }
+            self.queries.push(query);
+        }
+        let query = self.queries[self.used];
+        self.used += 1;
+        unsafe {
+            gl::QueryCounter(query, gl::TIMESTAMP);
+        }
+        query
+    }
+
+    //Scopes relative to the first timestamp, None when the GPU hasn't finished the frame yet
+    fn collect(&self) -> Option<Vec<Scope>> {
+        let last = *self.queries[..self.used].last()?;
+        let mut available = 0;
+        unsafe {
+            gl::GetQueryObjectiv(last, gl::QUERY_RESULT_AVAILABLE, &mut available);
+        }
+        if available == 0 {
+            return None;
+        }
+        let read = |query: u32| {
+            let mut nanoseconds = 0;
+            unsafe {
+                gl::GetQueryObjectui64v(query, gl::QUERY_RESULT, &mut nanoseconds);
+            }
+            nanoseconds
+        };
+        let origin = read(self.queries[0]);
+        let milliseconds = |nanoseconds: u64| nanoseconds.saturating_sub(origin) as f32 / 1_000_000.0;
+        Some(self.scopes.iter().map(|(name, depth, start, end)| {
+            let (start, end) = (milliseconds(read(*start)), milliseconds(read(*end)));
+            Scope { name: name.clone(), depth: *depth, start, duration: (end - start).max(0.0) }
+        }).collect())
+    }
+}
+
+impl Drop for GpuFrame {
+    fn drop(&mut self) {
+        //Frames that never timed a GPU scope have nothing to delete, e.g. when profiling only the CPU
+        if self.queries.is_empty() {
+            return;
+        }
+        unsafe {
+            gl::DeleteQueries(self.queries.len() as i32, self.queries.as_ptr());
+        }
+    }
+}
+
+//CPU scopes of the current frame plus GPU timestamp queries, finished frames are kept in a ring buffer
+#[derive(Resource)]
+pub struct Profiler {
+    enabled: bool,
+    history: usize,
+    epoch: Instant,
+    frames: VecDeque<FrameProfile>,
+    current: FrameProfile,
+    frame_start: Instant,
+    //Index into current.cpu and when the scope began
+    cpu_stack: Vec<(usize, Instant)>,
+    gpu_frames: Vec<GpuFrame>,
+    gpu_current: usize,
+    gpu_stack: Vec<usize>,
+    in_frame: bool,
+}
+
+impl Profiler {
+    pub fn new(settings: &ProfilerSettings) -> Profiler {
+        let now = Instant::now();
+        Profiler {
+            enabled: settings.enabled,
+            history: settings.history.max(1),
+            epoch: now,
+            frames: VecDeque::new(),
+            current: FrameProfile::default(),
+            frame_start: now,
+            cpu_stack: Vec::new(),
+            gpu_frames: (0..GPU_LATENCY).map(|_| GpuFrame::default()).collect(),
+            gpu_current: 0,
+            gpu_stack: Vec::new(),
+            in_frame: false,
+        }
+    }
+
+    pub fn frames(&self) -> &VecDeque<FrameProfile> {
+        &self.frames
+    }
+
+    pub fn begin_frame(&mut self) {
+        if !self.enabled {
+            return;
+        }
+        self.gpu_current = (self.gpu_current + 1) % GPU_LATENCY;
+        let gpu_frame = &mut self.gpu_frames[self.gpu_current];
+        //Results still not available after GPU_LATENCY frames are dropped instead of waited on
+        if gpu_frame.pending {
+            if let Some(scopes) = gpu_frame.collect() {
+                if let Some(frame) = self.frames.iter_mut().find(|frame| frame.index == gpu_frame.index) {
+                    frame.gpu = scopes;
+                    frame.gpu_ready = true;
+                }
+            }
+        }
+        let index = self.frames.back().map_or(0, |frame| frame.index + 1);
+        gpu_frame.index = index;
+        gpu_frame.used = 0;
+        gpu_frame.scopes.clear();
+        gpu_frame.pending = false;
+
+        self.frame_start = Instant::now();
+        self.current = FrameProfile { index, start: self.frame_start.duration_since(self.epoch).as_secs_f64() * 1000.0, ..Default::default() };
+        self.cpu_stack.clear();
+        self.gpu_stack.clear();
+        self.in_frame = true;
+    }
+
+    pub fn end_frame(&mut self) {
+        if !self.enabled || !self.in_frame {
+            return;
+        }
+        while !self.cpu_stack.is_empty() {
+            self.end_scope();
+        }
+        while !self.gpu_stack.is_empty() {
+            self.end_gpu_scope();
+        }
+        self.gpu_frames[self.gpu_current].pending = self.gpu_frames[self.gpu_current].used > 0;
+        self.current.duration = self.frame_start.elapsed().as_secs_f32() * 1000.0;
+        self.frames.push_back(mem::take(&mut self.current));
+        while self.frames.len() > self.history {
+            self.frames.pop_front();
+        }
+        self.in_frame = false;
+    }
+
+    pub fn begin_scope(&mut self, name: &str) {
+        if !self.enabled || !self.in_frame {
+            return;
+        }
+        let now = Instant::now();
+        let start = now.duration_since(self.frame_start).as_secs_f32() * 1000.0;
+        self.cpu_stack.push((self.current.cpu.len(), now));
+        self.current.cpu.push(Scope { name: name.to_string(), depth: self.cpu_stack.len() as u32 - 1, start, duration: 0.0 });
+    }
+
+    pub fn end_scope(&mut self) {
+        if let Some((index, start)) = self.cpu_stack.pop() {
+            self.current.cpu[index].duration = start.elapsed().as_secs_f32() * 1000.0;
+        }
+    }
+
+    //Timestamps are written when the GPU reaches them, so scopes measure GPU time even though the CPU returns right away
+    pub fn begin_gpu_scope(&mut self, name: &str) {
+        if !self.enabled || !self.in_frame {
+            return;
+        }
+        let frame = &mut self.gpu_frames[self.gpu_current];
+        let query = frame.timestamp();
+        self.gpu_stack.push(frame.scopes.len());
+        frame.scopes.push((name.to_string(), self.gpu_stack.len() as u32 - 1, query, query));
+    }
+
+    pub fn end_gpu_scope(&mut self) {
+        if let Some(index) = self.gpu_stack.pop() {
+            let frame = &mut self.gpu_frames[self.gpu_current];
+            frame.scopes[index].3 = frame.timestamp();
+        }
+    }
+
+    pub fn export_chrome_trace(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        fs::write(path, self.chrome_trace())?;
+        Ok(())
+    }
+
+    //Complete events on a CPU and a GPU track, GPU scopes are placed relative to the CPU start of their frame
+    fn chrome_trace(&self) -> String {
+        let mut json = String::new();
+        json.push_str("{\"traceEvents\":[\n");
+        json.push_str("{\"name\":\"thread_name\",\"ph\":\"M\",\"pid\":1,\"tid\":1,\"args\":{\"name\":\"CPU\"}},\n");
+        json.push_str("{\"name\":\"thread_name\",\"ph\":\"M\",\"pid\":1,\"tid\":2,\"args\":{\"name\":\"GPU\"}}");
+        for frame in &self.frames {
+            for (track, scopes) in [(1, &frame.cpu), (2, &frame.gpu)] {
+                for scope in scopes {
+                    let _ = write!(json, ",\n{{\"name\":\"{}\",\"cat\":\"{}\",\"ph\":\"X\",\"ts\":{:.3},\"dur\":{:.3},\"pid\":1,\"tid\":{},\"args\":{{\"frame\":{}}}}}",
+                        escape_json(&scope.name), if track == 1 { "cpu" } else { "gpu" },
+                        (frame.start + scope.start as f64) * 1000.0, scope.duration * 1000.0, track, frame.index);
+                }
+            }
+        }
+        json.push_str("\n]}\n");
+        json
+    }
+}
+
+fn escape_json(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for character in text.chars() {
+        match character {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            character if character.is_control() => {
+                let _ = write!(escaped, "\\u{:04x}", character as u32);
+            },
+            character => escaped.push(character),
+        }
+    }
+    escaped
+}
+
+//Opens a CPU scope, and a GPU one too for work that issues GL commands
+pub fn begin_scope(world: &mut World, name: &str, gpu: bool) {
+    if let Some(mut profiler) = world.get_resource_mut::<Profiler>() {
+        profiler.begin_scope(name);
+        if gpu {
+            profiler.begin_gpu_scope(name);
+        }
+    }
+}
+
+pub fn end_scope(world: &mut World, gpu: bool) {
+    if let Some(mut profiler) = world.get_resource_mut::<Profiler>() {
+        if gpu {
+            profiler.end_gpu_scope();
+        }
+        profiler.end_scope();
+    }
+}
+
+//Runs its systems one after another in the order they were added, each inside its own profiler scope.
+//Used instead of a bevy Schedule so every system shows up in the profile.
+pub struct ProfiledSchedule {
+    name: String,
+    systems: Vec<(String, BoxedSystem, bool)>,
+}
+
+impl ProfiledSchedule {
+    pub fn new(name: &str) -> ProfiledSchedule {
+        ProfiledSchedule { name: name.to_string(), systems: Vec::new() }
+    }
+
+    pub fn add_system<M>(&mut self, system: impl IntoSystem<(), (), M>) -> &mut ProfiledSchedule {
+        let system: BoxedSystem = Box::new(IntoSystem::into_system(system));
+        //Only the function name, the module path makes the overlay unreadable
+        let name = system.name();
+        let name = name.split('<').next().unwrap_or(&name).rsplit("::").next().unwrap_or(&name).to_string();
+        self.systems.push((name, system, false));
+        self
+    }
+
+    pub fn run(&mut self, world: &mut World) {
+        world.check_change_ticks();
+        begin_scope(world, &self.name, false);
+        let change_tick = world.change_tick();
+        for (name, system, initialized) in &mut self.systems {
+            if !*initialized {
+                system.initialize(world);
+                *initialized = true;
+            }
+            begin_scope(world, name, false);
+            system.check_change_tick(change_tick);
+            system.run((), world);
+            system.apply_buffers(world);
+            end_scope(world, false);
+        }
+        end_scope(world, false);
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct OverlayVertex {
+    position: [f32; 3],
+    color: [f32; 4],
+}
+
+const PANEL_MARGIN: f32 = 10.0;
+const ROW_HEIGHT: f32 = 16.0;
+const GRAPH_HEIGHT: f32 = 60.0;
+const LABEL_SIZE: f32 = 12.0;
+
+//Frame time history and the flame graph of the last frame along the top of the window
+#[derive(Resource)]
+pub struct ProfilerOverlay {
+    shader: Shader,
+    vao: VAO,
+    vertex_buffer: u32,
+    vertices: Vec<OverlayVertex>,
+    labels: Vec<(String, Vec2)>,
+}
+
+impl ProfilerOverlay {
+    pub fn new() -> Result<ProfilerOverlay, Box<dyn Error>> {
+        let vao = VAO::new();
+        let mut vertex_buffer = 0;
+        unsafe {
+            gl::GenBuffers(1, &mut vertex_buffer);
+            vao.bind();
+            gl_state::bind_buffer(gl::ARRAY_BUFFER, vertex_buffer);
+            let stride = mem::size_of::<OverlayVertex>() as GLsizei;
+            let attributes = [(mesh::POSITION_LOCATION, 3, 0), (mesh::COLOR_LOCATION, 4, 3)];
+            for (location, size, offset) in attributes {
+                gl::VertexAttribPointer(location, size, gl::FLOAT, gl::FALSE, stride, (offset * mem::size_of::<f32>()) as *const c_void);
+                gl::EnableVertexAttribArray(location);
+            }
+            vao.label("profiler overlay");
+            gl_state::label(gl::BUFFER, vertex_buffer, "profiler overlay vertices");
+            vao.unbind();
+            gl_state::bind_buffer(gl::ARRAY_BUFFER, 0);
+        }
+        //Flat colored triangles need nothing the debug line shader doesn't have
+        Ok(ProfilerOverlay { shader: Shader::new("debug")?, vao, vertex_buffer, vertices: Vec::new(), labels: Vec::new() })
+    }
+
+    fn rect(&mut self, min: Vec2, max: Vec2, color: Vec4) {
+        let color = color.to_array();
+        let corners = [Vec2::new(min.x, min.y), Vec2::new(max.x, min.y), Vec2::new(max.x, max.y), Vec2::new(min.x, min.y), Vec2::new(max.x, max.y), Vec2::new(min.x, max.y)];
+        self.vertices.extend(corners.map(|corner| OverlayVertex { position: corner.extend(0.0).to_array(), color }));
+    }
+
+    //One row per nesting level going down from `top`, returns the y below the last row
+    fn flame_graph(&mut self, scopes: &[Scope], left: f32, top: f32, pixels_per_millisecond: f32) -> f32 {
+        let rows = scopes.iter().map(|scope| scope.depth + 1).max().unwrap_or(0);
+        for scope in scopes {
+            let min = Vec2::new(left + scope.start * pixels_per_millisecond, top - (scope.depth + 1) as f32 * ROW_HEIGHT);
+            let width = (scope.duration * pixels_per_millisecond).max(1.0);
+            self.rect(min, min + Vec2::new(width, ROW_HEIGHT - 1.0), scope_color(&scope.name));
+            if width > 40.0 {
+                self.labels.push((format!("{} {:.2}", scope.name, scope.duration), min + Vec2::new(2.0, ROW_HEIGHT - 2.0)));
+            }
+        }
+        top - rows as f32 * ROW_HEIGHT
+    }
+
+    //Draws straight into the window over every view, the budget is the frame time the swap interval allows
+    pub fn render(&mut self, profiler: &Profiler, window_size: (u32, u32), text: &mut TextRenderer, atlas: &mut GlyphAtlas, font: Option<(&str, &Font)>, settings: &Settings) {
+        let budget = 1000.0 / settings.swap_interval.max(1) as f32;
+        let is_wireframe = settings.is_wireframe;
+        let frames = profiler.frames();
+        let latest = match frames.back() {
+            Some(frame) => frame,
+            None => return,
+        };
+        let gpu = frames.iter().rev().find(|frame| frame.gpu_ready);
+        let (width, height) = (window_size.0 as f32, window_size.1 as f32);
+        let (left, right) = (PANEL_MARGIN, width - PANEL_MARGIN);
+        let panel_width = right - left;
+        let top = height - PANEL_MARGIN;
+        self.vertices.clear();
+        self.labels.clear();
+
+        //Frame times, the line marks the budget and bars over it turn red
+        let graph_bottom = top - ROW_HEIGHT - GRAPH_HEIGHT;
+        let scale = GRAPH_HEIGHT / (budget * 2.0);
+        let bar_width = panel_width / frames.len().max(1) as f32;
+        for (index, frame) in frames.iter().enumerate() {
+            let color = if frame.duration > budget { Vec4::new(0.9, 0.2, 0.2, 0.9) } else { Vec4::new(0.3, 0.8, 0.3, 0.9) };
+            let min = Vec2::new(left + index as f32 * bar_width, graph_bottom);
+            self.rect(min, min + Vec2::new(bar_width.max(1.0), (frame.duration * scale).min(GRAPH_HEIGHT)), color);
+        }
+        self.rect(Vec2::new(left, graph_bottom + budget * scale), Vec2::new(right, graph_bottom + budget * scale + 1.0), Vec4::new(1.0, 1.0, 1.0, 0.6));
+        let gpu_time = gpu.map_or("-".to_string(), |frame| format!("{:.2} ms", frame.gpu_duration()));
+        self.labels.push((format!("Frame {} CPU {:.2} ms GPU {} budget {:.2} ms", latest.index, latest.duration, gpu_time, budget), Vec2::new(left, top)));
+
+        //The flame graphs span the budget or the frame when it ran over
+        let pixels_per_millisecond = panel_width / budget.max(latest.duration);
+        let mut y = graph_bottom - 4.0;
+        self.labels.push(("CPU".to_string(), Vec2::new(left, y)));
+        y = self.flame_graph(&latest.cpu, left, y - ROW_HEIGHT, pixels_per_millisecond) - 4.0;
+        if let Some(frame) = gpu {
+            self.labels.push(("GPU".to_string(), Vec2::new(left, y)));
+            y = self.flame_graph(&frame.gpu, left, y - ROW_HEIGHT, pixels_per_millisecond);
+        }
+        let background = Vec4::new(0.05, 0.05, 0.05, 0.75);
+        let mut vertices = mem::take(&mut self.vertices);
+        self.rect(Vec2::new(left - 4.0, y - 4.0), Vec2::new(right + 4.0, top + 4.0), background);
+        //Background goes first so everything else is drawn over it
+        self.vertices.append(&mut vertices);
+
+        let projection = Mat4::orthographic_rh_gl(0.0, width, 0.0, height, -1.0, 1.0);
+        unsafe {
+            gl_state::bind_framebuffer(gl::FRAMEBUFFER, 0);
+            gl::Viewport(0, 0, window_size.0 as i32, window_size.1 as i32);
+            gl_state::bind_buffer(gl::ARRAY_BUFFER, self.vertex_buffer);
+            gl::BufferData(gl::ARRAY_BUFFER, (self.vertices.len() * mem::size_of::<OverlayVertex>()) as GLsizeiptr, self.vertices.as_ptr() as *const c_void, gl::STREAM_DRAW);
+            gl_state::bind_buffer(gl::ARRAY_BUFFER, 0);
+            gl::PolygonMode(gl::FRONT_AND_BACK, gl::FILL);
+            gl_state::enable(gl::BLEND);
+            gl_state::blend_func(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+            gl_state::disable(gl::DEPTH_TEST);
+        }
+        self.shader.bind();
+        self.shader.set_uniform_4x4f("camMatrix".to_string(), None, &projection);
+        self.vao.bind();
+        unsafe {
+            gl::DrawArrays(gl::TRIANGLES, 0, self.vertices.len() as i32);
+        }
+        self.vao.unbind();
+        self.shader.unbind();
+        unsafe {
+            gl_state::disable(gl::BLEND);
+            gl_state::enable(gl::DEPTH_TEST);
+        }
+
+        if let Some(font) = font {
+            for (label, position) in &self.labels {
+                let layout = atlas.layout(&Text::new(label, font.0, LABEL_SIZE), &[font]);
+                text.push(&layout, &Mat4::from_translation(position.extend(0.0)));
+            }
+            text.flush(&projection, atlas, false, is_wireframe);
+        }
+        crate::renderer::update_wireframe(&is_wireframe);
+    }
+}
+
+//Stable color per scope name so the same system keeps its color between frames
+fn scope_color(name: &str) -> Vec4 {
+    let hash = name.bytes().fold(2166136261u32, |hash, byte| (hash ^ byte as u32).wrapping_mul(16777619));
+    let hue = (hash % 360) as f32 / 60.0;
+    let x = 1.0 - (hue % 2.0 - 1.0).abs();
+    let (r, g, b) = match hue as u32 {
+        0 => (1.0, x, 0.0),
+        1 => (x, 1.0, 0.0),
+        2 => (0.0, 1.0, x),
+        3 => (0.0, x, 1.0),
+        4 => (x, 0.0, 1.0),
+        _ => (1.0, 0.0, x),
+    };
+    Vec4::new(0.3 + r * 0.5, 0.3 + g * 0.5, 0.3 + b * 0.5, 0.9)
+}
+
+impl Drop for ProfilerOverlay {
+    fn drop(&mut self) {
+        unsafe {
+            gl_state::delete_buffers(1, &self.vertex_buffer);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profiler(history: usize) -> Profiler {
+        Profiler::new(&ProfilerSettings { history, ..Default::default() })
+    }
+
+    #[test]
+    fn keeps_the_latest_frames() {
+        let mut profiler = profiler(3);
+        for _ in 0..5 {
+            profiler.begin_frame();
+            profiler.begin_scope("update");
+            profiler.end_scope();
+            profiler.end_frame();
+        }
+        let indices: Vec<u64> = profiler.frames().iter().map(|frame| frame.index).collect();
+        assert_eq!(indices, [2, 3, 4]);
+        assert!(profiler.frames().iter().all(|frame| frame.cpu.len() == 1));
+    }
+
+    #[test]
+    fn nests_and_closes_scopes() {
+        let mut profiler = profiler(8);
+        //Scopes outside a frame are ignored
+        profiler.begin_scope("loading");
+        profiler.begin_frame();
+        profiler.begin_scope("render");
+        profiler.begin_scope("shadows");
+        profiler.end_scope();
+        profiler.begin_scope("opaque");
+        profiler.end_frame();
+        let frame = profiler.frames().back().unwrap();
+        let scopes: Vec<(&str, u32)> = frame.cpu.iter().map(|scope| (scope.name.as_str(), scope.depth)).collect();
+        assert_eq!(scopes, [("render", 0), ("shadows", 1), ("opaque", 1)]);
+    }
+
+    #[test]
+    fn writes_complete_trace_events() {
+        let mut profiler = profiler(8);
+        profiler.frames.push_back(FrameProfile {
+            index: 7,
+            start: 2.0,
+            duration: 1.5,
+            cpu: vec![Scope { name: "say \"hi\"\n".to_string(), depth: 0, start: 0.5, duration: 0.25 }],
+            gpu: vec![Scope { name: "shadows".to_string(), depth: 0, start: 0.0, duration: 1.0 }],
+            gpu_ready: true,
+        });
+        let trace = profiler.chrome_trace();
+        assert!(trace.starts_with("{\"traceEvents\":[\n"));
+        assert!(trace.ends_with("\n]}\n"));
+        assert_eq!(trace.matches("\"ph\":\"M\"").count(), 2);
+        assert_eq!(trace.matches("\"ph\":\"X\"").count(), 2);
+        //Timestamps are in microseconds from the profiler epoch
+        assert!(trace.contains("{\"name\":\"say \\\"hi\\\"\\u000a\",\"cat\":\"cpu\",\"ph\":\"X\",\"ts\":2500.000,\"dur\":250.000,\"pid\":1,\"tid\":1,\"args\":{\"frame\":7}}"));
+        assert!(trace.contains("{\"name\":\"shadows\",\"cat\":\"gpu\",\"ph\":\"X\",\"ts\":2000.000,\"dur\":1000.000,\"pid\":1,\"tid\":2,\"args\":{\"frame\":7}}"));
+    }
+}
@@ -0,0 +1,555 @@
+use std::{collections::HashMap, error::Error, fmt::Write, fs, ptr};
+
+use bevy_ecs::prelude::*;
+use bevy_ecs::system::BoxedSystem;
+use simple_error::SimpleError;
+
+use crate::{framebuffer::Framebuffer, gl_state, profiler, view::{self, RenderView}};
+
+//Built in insertion points in execution order, plugins can add their own with add_insertion_point
+pub const SETUP: &str = "setup";
+pub const SHADOWS: &str = "shadows";
+pub const PREPASS: &str = "prepass";
+pub const OPAQUE: &str = "opaque";
+pub const TRANSPARENT: &str = "transparent";
+pub const POST_PROCESS: &str = "post_process";
+pub const OVERLAY: &str = "overlay";
+
+//Resources owned outside the graph, passes declare them only for ordering
+pub const BACKBUFFER: &str = "backbuffer";
+pub const SHADOW_ATLAS: &str = "shadow_atlas";
+//Whichever target the scene is being drawn into, the window or one bound by an earlier pass
+pub const SCENE: &str = "scene";
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum AttachmentFormat {
+    Rgba8,
+    Rgba16F,
+    R32F,
+    Depth32F,
+}
+
+impl AttachmentFormat {
+    //Internal format, format and data type for glTexImage2D
+    fn gl_formats(&self) -> (u32, u32, u32) {
+        match self {
+            AttachmentFormat::Rgba8 => (gl::RGBA8, gl::RGBA, gl::UNSIGNED_BYTE),
+            AttachmentFormat::Rgba16F => (gl::RGBA16F, gl::RGBA, gl::FLOAT),
+            AttachmentFormat::R32F => (gl::R32F, gl::RED, gl::FLOAT),
+            AttachmentFormat::Depth32F => (gl::DEPTH_COMPONENT32F, gl::DEPTH_COMPONENT, gl::FLOAT),
+        }
+    }
+    pub fn is_depth(&self) -> bool {
+        *self == AttachmentFormat::Depth32F
+    }
+}
+
+//Transient attachments are sized relative to the view being drawn
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct AttachmentDescriptor {
+    pub format: AttachmentFormat,
+    pub scale: f32,
+}
+
+impl AttachmentDescriptor {
+    pub fn new(format: AttachmentFormat) -> AttachmentDescriptor {
+        AttachmentDescriptor { format, scale: 1.0 }
+    }
+    fn size(&self, width: u32, height: u32) -> (i32, i32) {
+        (((width as f32 * self.scale) as i32).max(1), ((height as f32 * self.scale) as i32).max(1))
+    }
+}
+
+//A node of the graph, resources are plain names so passes can depend on targets they own themselves
+pub struct RenderPass {
+    name: String,
+    insertion_point: String,
+    reads: Vec<String>,
+    writes: Vec<String>,
+    creates: Vec<(String, AttachmentDescriptor)>,
+    framebuffers: Vec<(String, Vec<String>)>,
+    system: BoxedSystem,
+    initialized: bool,
+    once_per_frame: bool,
+}
+
+impl RenderPass {
+    pub fn new<M>(name: &str, insertion_point: &str, system: impl IntoSystem<(), (), M>) -> RenderPass {
+        RenderPass {
+            name: name.to_string(),
+            insertion_point: insertion_point.to_string(),
+            reads: Vec::new(),
+            writes: Vec::new(),
+            creates: Vec::new(),
+            framebuffers: Vec::new(),
+            system: Box::new(IntoSystem::into_system(system)),
+            initialized: false,
+            once_per_frame: false,
+        }
+    }
+    //Runs once before any view instead of once per camera, for work shared by every view such as shadows
+    pub fn once_per_frame(mut self) -> RenderPass {
+        self.once_per_frame = true;
+        self
+    }
+    pub fn reads(mut self, resource: &str) -> RenderPass {
+        self.reads.push(resource.to_string());
+        self
+    }
+    pub fn writes(mut self, resource: &str) -> RenderPass {
+        self.writes.push(resource.to_string());
+        self
+    }
+    //Transient attachment allocated by the graph, its contents only live until the last pass reading it
+    pub fn creates(mut self, attachment: &str, descriptor: AttachmentDescriptor) -> RenderPass {
+        self.creates.push((attachment.to_string(), descriptor));
+        self.writes.push(attachment.to_string());
+        self
+    }
+    //Framebuffer built from transient attachments, color attachments in order and at most one depth
+    pub fn framebuffer(mut self, name: &str, attachments: &[&str]) -> RenderPass {
+        self.framebuffers.push((name.to_string(), attachments.iter().map(|a| a.to_string()).collect()));
+        self
+    }
+}
+
+//Transient textures and framebuffers of the compiled graph, passes look their framebuffers up by name
+#[derive(Resource, Default)]
+pub struct RenderTargets {
+    textures: HashMap<String, u32>,
+    framebuffers: HashMap<String, Framebuffer>,
+    //Physical textures, several attachments share one when their lifetimes don't overlap
+    physical: Vec<u32>,
+}
+
+impl RenderTargets {
+    pub fn framebuffer(&self, name: &str) -> Option<&Framebuffer> {
+        self.framebuffers.get(name)
+    }
+}
+
+impl Drop for RenderTargets {
+    fn drop(&mut self) {
+        self.framebuffers.clear();
+        unsafe {
+            gl_state::delete_textures(self.physical.len() as i32, self.physical.as_ptr());
+        }
+    }
+}
+
+#[derive(Resource)]
+pub struct RenderGraph {
+    insertion_points: Vec<String>,
+    passes: Vec<RenderPass>,
+    //Indices into passes in execution order
+    order: Vec<usize>,
+    //Physical texture index of every transient attachment, used for the DOT dump
+    aliases: HashMap<String, usize>,
+    //Transients of every view size drawn last frame
+    targets: HashMap<(u32, u32), RenderTargets>,
+    dirty: bool,
+    dump_path: Option<String>,
+}
+
+impl RenderGraph {
+    pub fn new() -> RenderGraph {
+        RenderGraph {
+            insertion_points: [SETUP, SHADOWS, PREPASS, OPAQUE, TRANSPARENT, POST_PROCESS, OVERLAY].iter().map(|p| p.to_string()).collect(),
+            passes: Vec::new(),
+            order: Vec::new(),
+            aliases: HashMap::new(),
+            targets: HashMap::new(),
+            dirty: true,
+            dump_path: None,
+        }
+    }
+
+    pub fn add_insertion_point(&mut self, name: &str, after: &str) -> Result<(), Box<dyn Error>> {
+        if self.insertion_points.iter().any(|p| p == name) {
+            return Err(Box::new(SimpleError::new(format!("Insertion point {} already exists", name))));
+        }
+        let index = self.insertion_point_index(after)?;
+        self.insertion_points.insert(index + 1, name.to_string());
+        self.dirty = true;
+        Ok(())
+    }
+
+    pub fn add_pass(&mut self, pass: RenderPass) -> Result<(), Box<dyn Error>> {
+        self.insertion_point_index(&pass.insertion_point)?;
+        if self.passes.iter().any(|p| p.name == pass.name) {
+            return Err(Box::new(SimpleError::new(format!("Render pass {} already exists", pass.name))));
+        }
+        for (attachment, _) in &pass.creates {
+            if self.passes.iter().any(|p| p.creates.iter().any(|(a, _)| a == attachment)) {
+                return Err(Box::new(SimpleError::new(format!("Attachment {} is created by more than one pass", attachment))));
+            }
+        }
+        self.passes.push(pass);
+        self.dirty = true;
+        Ok(())
+    }
+
+    //Writes the graph as DOT every time it is recompiled
+    pub fn dump_to(&mut self, path: &str) {
+        self.dump_path = Some(path.to_string());
+        self.dirty = true;
+    }
+
+    fn insertion_point_index(&self, name: &str) -> Result<usize, Box<dyn Error>> {
+        self.insertion_points.iter().position(|p| p == name)
+            .ok_or_else(|| Box::new(SimpleError::new(format!("Unknown insertion point {}", name))) as Box<dyn Error>)
+    }
+
+    //Insertion points run in order, inside one the writers of a resource run before its readers
+    //Passes writing the same resource keep the order they were added in
+    pub fn compile(&mut self) -> Result<(), Box<dyn Error>> {
+        let count = self.passes.len();
+        let stages: Vec<usize> = self.passes.iter().map(|p| self.insertion_point_index(&p.insertion_point)).collect::<Result<_, _>>()?;
+        let key = |index: usize| (stages[index], index);
+
+        let mut edges: Vec<Vec<usize>> = vec![Vec::new(); count];
+        let mut incoming = vec![0; count];
+        let mut add_edge = |from: usize, to: usize, edges: &mut Vec<Vec<usize>>| {
+            if from != to && !edges[from].contains(&to) {
+                edges[from].push(to);
+                incoming[to] += 1;
+            }
+        };
+        for a in 0..count {
+            for b in 0..count {
+                if stages[a] < stages[b] {
+                    add_edge(a, b, &mut edges);
+                }
+                for resource in &self.passes[a].writes {
+                    let b_writes = self.passes[b].writes.contains(resource);
+                    if (b_writes && key(a) < key(b)) || (!b_writes && self.passes[b].reads.contains(resource)) {
+                        add_edge(a, b, &mut edges);
+                    }
+                }
+            }
+        }
+        for pass in &self.passes {
+            for resource in &pass.reads {
+                if !self.passes.iter().any(|p| p.writes.contains(resource)) {
+                    return Err(Box::new(SimpleError::new(format!("Render pass {} reads {} which nothing writes", pass.name, resource))));
+                }
+            }
+        }
+
+        //Kahn's algorithm, ties go to the earliest insertion point and then the earliest added pass
+        let mut order = Vec::with_capacity(count);
+        let mut ready: Vec<usize> = (0..count).filter(|i| incoming[*i] == 0).collect();
+        while !ready.is_empty() {
+            let (position, _) = ready.iter().enumerate().min_by_key(|(_, i)| key(**i)).unwrap();
+            let index = ready.swap_remove(position);
+            order.push(index);
+            for next in &edges[index] {
+                incoming[*next] -= 1;
+                if incoming[*next] == 0 {
+                    ready.push(*next);
+                }
+            }
+        }
+        if order.len() != count {
+            let stuck: Vec<&str> = (0..count).filter(|i| !order.contains(i)).map(|i| self.passes[i].name.as_str()).collect();
+            return Err(Box::new(SimpleError::new(format!("Render graph has a cycle between {}", stuck.join(", ")))));
+        }
+
+        self.order = order;
+        Ok(())
+    }
+
+    //Every transient lives from the pass creating it to the last pass reading it, slots are reused once free.
+    //Returns the descriptor of every slot and the slot of every attachment in the order they are first written.
+    fn plan_aliases(&self) -> (Vec<AttachmentDescriptor>, Vec<(String, usize)>) {
+        let position: HashMap<usize, usize> = self.order.iter().enumerate().map(|(position, index)| (*index, position)).collect();
+        let mut lifetimes: Vec<(String, AttachmentDescriptor, usize, usize)> = Vec::new();
+        for (index, pass) in self.passes.iter().enumerate() {
+            for (attachment, descriptor) in &pass.creates {
+                let first = position[&index];
+                let last = self.passes.iter().enumerate()
+                    .filter(|(_, p)| p.reads.contains(attachment))
+                    .map(|(i, _)| position[&i])
+                    .fold(first, usize::max);
+                lifetimes.push((attachment.clone(), *descriptor, first, last));
+            }
+        }
+        lifetimes.sort_by_key(|(_, _, first, _)| *first);
+
+        let mut pool: Vec<(AttachmentDescriptor, usize)> = Vec::new(); //descriptor, last use
+        let mut slots = Vec::with_capacity(lifetimes.len());
+        for (attachment, descriptor, first, last) in lifetimes {
+            let slot = match pool.iter().position(|(d, free_after)| *d == descriptor && *free_after < first) {
+                Some(slot) => {
+                    pool[slot].1 = last;
+                    slot
+                }
+                None => {
+                    pool.push((descriptor, last));
+                    pool.len() - 1
+                }
+            };
+            slots.push((attachment, slot));
+        }
+        (pool.into_iter().map(|(descriptor, _)| descriptor).collect(), slots)
+    }
+
+    fn allocate(&mut self, width: u32, height: u32) -> RenderTargets {
+        let (descriptors, slots) = self.plan_aliases();
+        let mut targets = RenderTargets::default();
+        for descriptor in &descriptors {
+            let (w, h) = descriptor.size(width, height);
+            targets.physical.push(create_texture(descriptor.format, w, h));
+        }
+        let mut slot_names: Vec<Vec<String>> = vec![Vec::new(); descriptors.len()];
+        self.aliases.clear();
+        for (attachment, slot) in slots {
+            targets.textures.insert(attachment.clone(), targets.physical[slot]);
+            slot_names[slot].push(attachment.clone());
+            self.aliases.insert(attachment, slot);
+        }
+        //Aliased textures carry the names of every attachment sharing them
+        for (texture, names) in targets.physical.iter().zip(&slot_names) {
+            gl_state::label(gl::TEXTURE, *texture, &names.join(", "));
+        }
+
+        for pass in &self.passes {
+            for (name, attachments) in &pass.framebuffers {
+                let descriptors: Vec<AttachmentDescriptor> = attachments.iter()
+                    .filter_map(|a| pass.creates.iter().find(|(c, _)| c == a).map(|(_, d)| *d))
+                    .collect();
+                if descriptors.len() != attachments.len() {
+                    println!("Framebuffer {} of render pass {} uses attachments the pass doesn't create", name, pass.name);
+                    continue;
+                }
+                let colors: Vec<u32> = attachments.iter().zip(&descriptors)
+                    .filter(|(_, d)| !d.format.is_depth())
+                    .map(|(a, _)| targets.textures[a])
+                    .collect();
+                let depth = attachments.iter().zip(&descriptors)
+                    .find(|(_, d)| d.format.is_depth())
+                    .map(|(a, _)| targets.textures[a]);
+                let (w, h) = descriptors.first().map_or((1, 1), |d| d.size(width, height));
+                let framebuffer = Framebuffer::from_textures(w, h, &colors, depth);
+                framebuffer.is_complete();
+                framebuffer.label(name);
+                targets.framebuffers.insert(name.clone(), framebuffer);
+            }
+        }
+        targets
+    }
+
+    //Recompiles when passes changed, then runs the per frame passes and every other pass once per camera view.
+    //Views of the same size share transients, sizes no view used this frame are freed.
+    pub fn run(&mut self, world: &mut World) {
+        let recompiled = self.dirty;
+        if self.dirty {
+            if let Err(error) = self.compile() {
+                println!("Unable to compile render graph: {}", error);
+                return;
+            }
+            self.targets.clear();
+            self.dirty = false;
+        }
+
+        profiler::begin_scope(world, "render", true);
+        self.run_passes(world, true);
+        let mut used = Vec::new();
+        for (index, view) in view::collect_views(world).into_iter().enumerate() {
+            profiler::begin_scope(world, &format!("view {}", index), true);
+            gl_state::push_group(&format!("view {}", index));
+            let size = view.size();
+            let targets = match self.targets.remove(&size) {
+                Some(targets) => targets,
+                None => self.allocate(size.0, size.1),
+            };
+            world.insert_resource(targets);
+            world.insert_resource(view);
+            self.run_passes(world, false);
+            if let Some(targets) = world.remove_resource::<RenderTargets>() {
+                self.targets.insert(size, targets);
+            }
+            used.push(size);
+            gl_state::pop_group();
+            profiler::end_scope(world, true);
+        }
+        world.remove_resource::<RenderView>();
+        profiler::end_scope(world, true);
+        self.targets.retain(|size, _| used.contains(size));
+
+        //Written after allocating so the dump shows which attachments share textures
+        if let Some(path) = self.dump_path.as_ref().filter(|_| recompiled) {
+            if let Err(error) = fs::write(path, self.to_dot()) {
+                println!("Unable to write render graph to {}: {}", path, error);
+            }
+        }
+    }
+
+    fn run_passes(&mut self, world: &mut World, once_per_frame: bool) {
+        let change_tick = world.change_tick();
+        for index in &self.order {
+            let pass = &mut self.passes[*index];
+            if pass.once_per_frame != once_per_frame {
+                continue;
+            }
+            if !pass.initialized {
+                pass.system.initialize(world);
+                pass.initialized = true;
+            }
+            profiler::begin_scope(world, &pass.name, true);
+            gl_state::push_group(&pass.name);
+            pass.system.check_change_tick(change_tick);
+            pass.system.run((), world);
+            pass.system.apply_buffers(world);
+            gl_state::pop_group();
+            profiler::end_scope(world, true);
+        }
+    }
+
+    //Passes are boxes grouped by insertion point and numbered in execution order, resources are ellipses
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::new();
+        let _ = writeln!(dot, "digraph render_graph {{");
+        let _ = writeln!(dot, "    rankdir=LR;");
+        let _ = writeln!(dot, "    node [shape=box];");
+        for (stage, point) in self.insertion_points.iter().enumerate() {
+            let _ = writeln!(dot, "    subgraph cluster_{} {{", stage);
+            let _ = writeln!(dot, "        label=\"{}\";", point);
+            for (index, pass) in self.passes.iter().enumerate().filter(|(_, p)| &p.insertion_point == point) {
+                let position = self.order.iter().position(|i| *i == index).map_or("?".to_string(), |p| p.to_string());
+                let frequency = if pass.once_per_frame { "\\n(per frame)" } else { "" };
+                let _ = writeln!(dot, "        \"pass:{}\" [label=\"{}: {}{}\"];", pass.name, position, pass.name, frequency);
+            }
+            let _ = writeln!(dot, "    }}");
+        }
+
+        let mut resources: Vec<&String> = self.passes.iter().flat_map(|p| p.reads.iter().chain(p.writes.iter())).collect();
+        resources.sort();
+        resources.dedup();
+        for resource in resources {
+            let descriptor = self.passes.iter().find_map(|p| p.creates.iter().find(|(a, _)| a == resource).map(|(_, d)| *d));
+            let label = match (descriptor, self.aliases.get(resource)) {
+                (Some(d), Some(slot)) => format!("{}\\n{:?} x{}\\ntexture #{}", resource, d.format, d.scale, slot),
+                _ => resource.clone(),
+            };
+            let style = if descriptor.is_some() { "ellipse" } else { "octagon" };
+            let _ = writeln!(dot, "    \"res:{}\" [shape={}, label=\"{}\"];", resource, style, label);
+        }
+
+        for pass in &self.passes {
+            for resource in &pass.writes {
+                let _ = writeln!(dot, "    \"pass:{}\" -> \"res:{}\";", pass.name, resource);
+            }
+            for resource in &pass.reads {
+                let _ = writeln!(dot, "    \"res:{}\" -> \"pass:{}\" [style=dashed];", resource, pass.name);
+            }
+        }
+        let _ = writeln!(dot, "}}");
+        dot
+    }
+}
+
+fn create_texture(format: AttachmentFormat, width: i32, height: i32) -> u32 {
+    let (internal_format, pixel_format, data_type) = format.gl_formats();
+    let filter = if format.is_depth() { gl::NEAREST } else { gl::LINEAR };
+    let mut texture = 0;
+    unsafe {
+        gl::GenTextures(1, &mut texture);
+        gl_state::bind_texture(gl::TEXTURE_2D, texture);
+        gl::TexImage2D(gl::TEXTURE_2D, 0, internal_format as i32, width, height, 0, pixel_format, data_type, ptr::null());
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, filter as i32);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, filter as i32);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as i32);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as i32);
+        gl_state::bind_texture(gl::TEXTURE_2D, 0);
+    }
+    texture
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pass() {}
+
+    fn names(graph: &RenderGraph) -> Vec<&str> {
+        graph.order.iter().map(|index| graph.passes[*index].name.as_str()).collect()
+    }
+
+    #[test]
+    fn runs_insertion_points_in_order() {
+        let mut graph = RenderGraph::new();
+        graph.add_pass(RenderPass::new("overlay", OVERLAY, pass)).unwrap();
+        graph.add_pass(RenderPass::new("opaque", OPAQUE, pass)).unwrap();
+        graph.add_pass(RenderPass::new("setup", SETUP, pass)).unwrap();
+        graph.add_insertion_point("outline", OPAQUE).unwrap();
+        graph.add_pass(RenderPass::new("outline", "outline", pass)).unwrap();
+        graph.compile().unwrap();
+        assert_eq!(names(&graph), ["setup", "opaque", "outline", "overlay"]);
+    }
+
+    #[test]
+    fn runs_writers_before_readers() {
+        let mut graph = RenderGraph::new();
+        graph.add_pass(RenderPass::new("composite", OPAQUE, pass).reads("lighting").writes(SCENE)).unwrap();
+        graph.add_pass(RenderPass::new("lighting", OPAQUE, pass).reads("gbuffer").writes("lighting")).unwrap();
+        graph.add_pass(RenderPass::new("gbuffer", OPAQUE, pass).writes("gbuffer")).unwrap();
+        graph.add_pass(RenderPass::new("sky", OPAQUE, pass).writes(SCENE)).unwrap();
+        graph.compile().unwrap();
+        //Both write the scene so they keep the order they were added in
+        assert_eq!(names(&graph), ["gbuffer", "lighting", "composite", "sky"]);
+    }
+
+    #[test]
+    fn rejects_invalid_graphs() {
+        let mut graph = RenderGraph::new();
+        assert!(graph.add_pass(RenderPass::new("lost", "nowhere", pass)).is_err());
+        graph.add_pass(RenderPass::new("a", OPAQUE, pass).creates("color", AttachmentDescriptor::new(AttachmentFormat::Rgba8))).unwrap();
+        assert!(graph.add_pass(RenderPass::new("a", OPAQUE, pass)).is_err());
+        assert!(graph.add_pass(RenderPass::new("b", OPAQUE, pass).creates("color", AttachmentDescriptor::new(AttachmentFormat::Rgba8))).is_err());
+        assert!(graph.add_insertion_point(OPAQUE, SETUP).is_err());
+
+        graph.add_pass(RenderPass::new("reader", OPAQUE, pass).reads("missing")).unwrap();
+        assert!(graph.compile().is_err());
+    }
+
+    #[test]
+    fn reports_cycles() {
+        let mut graph = RenderGraph::new();
+        graph.add_pass(RenderPass::new("a", OPAQUE, pass).reads("y").writes("x")).unwrap();
+        graph.add_pass(RenderPass::new("b", OPAQUE, pass).reads("x").writes("y")).unwrap();
+        let error = graph.compile().unwrap_err().to_string();
+        assert!(error.contains("cycle"), "{}", error);
+    }
+
+    #[test]
+    fn aliases_transients_whose_lifetimes_dont_overlap() {
+        let color = AttachmentDescriptor::new(AttachmentFormat::Rgba16F);
+        let mut graph = RenderGraph::new();
+        graph.add_pass(RenderPass::new("bloom", POST_PROCESS, pass).creates("bright", color)).unwrap();
+        graph.add_pass(RenderPass::new("blur", POST_PROCESS, pass).reads("bright").creates("blurred", color)).unwrap();
+        graph.add_pass(RenderPass::new("tonemap", POST_PROCESS, pass).reads("blurred").creates("graded", color)).unwrap();
+        graph.add_pass(RenderPass::new("present", POST_PROCESS, pass).reads("graded").writes(BACKBUFFER)).unwrap();
+        graph.compile().unwrap();
+
+        let (descriptors, slots) = graph.plan_aliases();
+        //Bright is dead once blurred is written, so graded takes its texture
+        assert_eq!(descriptors, [color, color]);
+        assert_eq!(slots, [("bright".to_string(), 0), ("blurred".to_string(), 1), ("graded".to_string(), 0)]);
+    }
+
+    #[test]
+    fn keeps_different_descriptors_apart() {
+        let mut graph = RenderGraph::new();
+        let depth = AttachmentDescriptor::new(AttachmentFormat::Depth32F);
+        let half = AttachmentDescriptor { scale: 0.5, ..AttachmentDescriptor::new(AttachmentFormat::Rgba8) };
+        graph.add_pass(RenderPass::new("prepass", PREPASS, pass).creates("depth", depth)).unwrap();
+        graph.add_pass(RenderPass::new("ao", OPAQUE, pass).reads("depth").creates("occlusion", half)).unwrap();
+        graph.add_pass(RenderPass::new("shade", OPAQUE, pass).reads("occlusion").writes(SCENE)).unwrap();
+        graph.compile().unwrap();
+
+        let (descriptors, slots) = graph.plan_aliases();
+        assert_eq!(descriptors, [depth, half]);
+        assert_eq!(slots, [("depth".to_string(), 0), ("occlusion".to_string(), 1)]);
+    }
+}
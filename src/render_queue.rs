@@ -0,0 +1,130 @@
+use std::cmp::Ordering;
+
+use glam::{Vec3, Vec4Swizzles};
+
+use crate::{mesh::Mesh, lightmap::Lightmap, renderer::Instance, resources::AssetPool};
+
+//A visible mesh collected for this frame
+pub struct DrawItem<'a> {
+    pub mesh: &'a Mesh,
+    pub instance: Instance,
+    pub lightmap: Option<&'a Lightmap>,
+}
+
+//Instances that share mesh, material and lightmap, drawn with a single instanced call
+pub struct Batch<'a> {
+    pub mesh: &'a Mesh,
+    pub lightmap: Option<&'a Lightmap>,
+    pub instances: Vec<Instance>,
+}
+
+#[derive(Default)]
+pub struct RenderQueue<'a> {
+    items: Vec<DrawItem<'a>>,
+}
+
+impl<'a> RenderQueue<'a> {
+    pub fn new() -> RenderQueue<'a> {
+        RenderQueue { items: Vec::new() }
+    }
+
+    pub fn push(&mut self, mesh: &'a Mesh, instance: Instance, lightmap: Option<&'a Lightmap>) {
+        self.items.push(DrawItem { mesh, instance, lightmap });
+    }
+
+    //Sorted by shader, then material, then mesh so consecutive batches share as much GL state as possible
+    pub fn batches(mut self, assets: &AssetPool) -> Vec<Batch<'a>> {
+        let shader = |item: &DrawItem| assets.get_material(&item.mesh.material).map(|material| material.shader.clone()).unwrap_or_default();
+        self.items.sort_by_cached_key(|item| (shader(item), item.mesh.material.clone(), item.mesh.id(), lightmap_id(item)));
+        merge(self.items, |a, b| a.mesh.id() == b.mesh.id() && a.mesh.material == b.mesh.material && lightmap_id(a) == lightmap_id(b))
+    }
+
+    //Depth only passes don't care about materials, every instance of a mesh ends up in one batch
+    pub fn depth_batches(mut self) -> Vec<Batch<'a>> {
+        self.items.sort_by_key(|item| item.mesh.id());
+        merge(self.items, |a, b| a.mesh.id() == b.mesh.id())
+    }
+
+    //Blended meshes can't be merged, they are drawn one at a time furthest from the eye first
+    pub fn back_to_front(mut self, eye: Vec3) -> Vec<Batch<'a>> {
+        let distance = |item: &DrawItem| item.instance.model.w_axis.xyz().distance_squared(eye);
+        self.items.sort_by(|a, b| distance(b).partial_cmp(&distance(a)).unwrap_or(Ordering::Equal));
+        merge(self.items, |_, _| false)
+    }
+}
+
+fn lightmap_id(item: &DrawItem) -> u32 {
+    item.lightmap.map_or(0, |lightmap| lightmap.texture.handle())
+}
+
+//Folds runs of sorted items into batches while `same` holds between neighbours
+fn merge<'a>(items: Vec<DrawItem<'a>>, same: impl Fn(&DrawItem, &DrawItem) -> bool) -> Vec<Batch<'a>> {
+    let mut batches: Vec<Batch<'a>> = Vec::new();
+    let mut previous: Option<DrawItem<'a>> = None;
+    for item in items {
+        match (&previous, batches.last_mut()) {
+            (Some(last), Some(batch)) if same(last, &item) => batch.instances.push(item.instance),
+            _ => batches.push(Batch { mesh: item.mesh, lightmap: item.lightmap, instances: vec![item.instance] }),
+        }
+        previous = Some(item);
+    }
+    batches
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Mat4;
+
+    use super::*;
+    use crate::{backend, mesh::{self, MeshData}, null_backend::NullBackend};
+
+    fn triangle(material: &str) -> Mesh {
+        Mesh::from_data(&MeshData::new(vec![0, 1, 2], material)
+            .with_buffer(vec![0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0], mesh::POSITION_LOCATION, 3))
+    }
+
+    fn at(z: f32) -> Instance {
+        Instance::new(Mat4::from_translation(Vec3::Z * z))
+    }
+
+    #[test]
+    fn batches_instances_of_the_same_mesh() {
+        backend::install(Box::new(NullBackend::new()));
+        let (wood, stone) = (triangle("wood"), triangle("stone"));
+        let other_wood = triangle("wood");
+        let mut queue = RenderQueue::new();
+        for (mesh, z) in [(&wood, 0.0), (&stone, 1.0), (&other_wood, 2.0), (&wood, 3.0)] {
+            queue.push(mesh, at(z), None);
+        }
+        let batches = queue.batches(&AssetPool::default());
+        let materials: Vec<(&str, usize)> = batches.iter().map(|batch| (batch.mesh.material.as_str(), batch.instances.len())).collect();
+        assert_eq!(materials.len(), 3);
+        assert_eq!(materials[0], ("stone", 1));
+        assert!(batches.iter().any(|batch| batch.mesh.id() == wood.id() && batch.instances.len() == 2));
+    }
+
+    #[test]
+    fn ignores_materials_for_depth_batches() {
+        backend::install(Box::new(NullBackend::new()));
+        let (wood, stone) = (triangle("wood"), triangle("stone"));
+        let mut queue = RenderQueue::new();
+        for (mesh, z) in [(&wood, 0.0), (&stone, 1.0), (&wood, 2.0)] {
+            queue.push(mesh, at(z), None);
+        }
+        let counts: Vec<usize> = queue.depth_batches().iter().map(|batch| batch.instances.len()).collect();
+        assert_eq!(counts.len(), 2);
+        assert_eq!(counts.iter().sum::<usize>(), 3);
+    }
+
+    #[test]
+    fn draws_blended_meshes_furthest_first() {
+        backend::install(Box::new(NullBackend::new()));
+        let glass = triangle("glass");
+        let mut queue = RenderQueue::new();
+        for z in [-1.0, -5.0, -3.0] {
+            queue.push(&glass, at(z), None);
+        }
+        let depths: Vec<f32> = queue.back_to_front(Vec3::ZERO).iter().map(|batch| batch.instances[0].model.w_axis.z).collect();
+        assert_eq!(depths, [-5.0, -3.0, -1.0]);
+    }
+}
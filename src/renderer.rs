@@ -1,161 +1,201 @@
 #![allow(dead_code)]
 
-use gl::types::*;
+use glam::{Mat4, Vec4};
 use std::mem;
-use std::os::raw::c_void;
-use std::ptr;
+
+use crate::backend::{self, BufferKind, BufferUsage, DrawCommand, Handle, Primitive, Resource, VertexAttribute};
 
 pub struct VAO {
-    id: u32,
+    id: Handle,
 }
 
 impl VAO {
     pub fn new() -> VAO {
-        let mut vao = 0;
-        unsafe {
-            gl::GenVertexArrays(1, &mut vao);
-        }
+        let vao = backend::with(|backend| backend.create_vertex_array());
         return VAO { id: vao };
     }
+    pub fn handle(&self) -> Handle {
+        self.id
+    }
+    //Names it in debuggers, a vertex array only exists once it has been bound
+    pub fn label(&self, name: &str) {
+        backend::with(|backend| backend.label(Resource::VertexArray(self.id), name));
+    }
 }
 
 impl GPUObject for VAO {
     fn bind(&self) {
-        unsafe {
-            gl::BindVertexArray(self.id);
-        }
+        backend::with(|backend| backend.bind(Resource::VertexArray(self.id)));
     }
 
     fn unbind(&self) {
-        unsafe {
-            gl::BindVertexArray(0);
-        }
+        backend::with(|backend| backend.unbind(Resource::VertexArray(self.id)));
     }
 }
 
 impl Drop for VAO {
     fn drop(&mut self) {
-        unsafe {
-            gl::DeleteVertexArrays(1, &self.id);
-        }
+        backend::release(Resource::VertexArray(self.id));
     }
 }
 pub struct VBO {
-    id: u32,
+    id: Handle,
     data: Vec<f32>,
 }
 
 impl VBO {
     pub fn new(data: Vec<f32>, index: u32, size: i32, vao: &VAO) -> VBO {
-        let mut vbo: VBO = VBO { id: 0, data: data };
-        unsafe {
-            gl::GenBuffers(1, &mut vbo.id);
-        }
-        // bind the Vertex Array Object first, then bind and set vertex buffer(s), and then configure vertex attributes(s).
-        vao.bind();
-        vbo.bind();
-        unsafe {
-            gl::BufferData(
-                gl::ARRAY_BUFFER,
-                (vbo.data.len() * mem::size_of::<GLfloat>()) as GLsizeiptr,
-                vbo.data.get(0).unwrap() as *const f32 as *const c_void,
-                gl::STATIC_DRAW,
-            );
-
-            gl::VertexAttribPointer(
-                index,
-                size,
-                gl::FLOAT,
-                gl::FALSE,
-                0,
-                //size * mem::size_of::<GLfloat>() as GLsizei,
-                ptr::null(),
-            );
-            gl::EnableVertexAttribArray(index);
-        }
-        vbo.unbind();
-        vao.unbind();
-
-        return vbo;
+        let id = backend::with(|backend| {
+            let buffer = backend.create_buffer(BufferKind::Vertex);
+            backend.upload_buffer(buffer, BufferUsage::Static, backend::as_bytes(&data));
+            backend.set_vertex_layout(vao.handle(), buffer, &[VertexAttribute::new(index, size)]);
+            buffer
+        });
+        return VBO { id, data };
     }
     pub fn get_data(&self) -> &Vec<f32> {
         &self.data
     }
+    pub fn label(&self, name: &str) {
+        backend::with(|backend| backend.label(Resource::Buffer(self.id), name));
+    }
 }
 
 impl GPUObject for VBO {
     fn bind(&self) {
-        unsafe {
-            gl::BindBuffer(gl::ARRAY_BUFFER, self.id);
-        }
+        backend::with(|backend| backend.bind(Resource::Buffer(self.id)));
     }
 
     fn unbind(&self) {
-        unsafe {
-            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
-        }
+        backend::with(|backend| backend.unbind(Resource::Buffer(self.id)));
     }
 }
 
 impl Drop for VBO {
     fn drop(&mut self) {
-        unsafe {
-            gl::DeleteBuffers(1, &self.id);
-        }
+        backend::release(Resource::Buffer(self.id));
     }
 }
 
 pub struct IBO {
-    id: u32,
+    id: Handle,
     indices: Vec<i32>,
 }
 
 impl IBO {
     pub fn new(indices: Vec<i32>, vao: &VAO) -> IBO {
-        let mut ibo: IBO = IBO { id: 0, indices };
-        unsafe {
-            gl::GenBuffers(1, &mut ibo.id);
-        }
-        vao.bind();
-        ibo.bind();
-        unsafe {
-            gl::BufferData(
-                gl::ELEMENT_ARRAY_BUFFER,
-                (ibo.indices.len() * mem::size_of::<GLfloat>()) as GLsizeiptr,
-                ibo.indices.get(0).unwrap() as *const i32 as *const c_void,
-                gl::STATIC_DRAW,
-            );
-        }
-        //never unbind
-        //ibo.unbind();
-        vao.unbind();
-
-        return ibo;
+        let id = backend::with(|backend| {
+            let buffer = backend.create_buffer(BufferKind::Index);
+            backend.upload_buffer(buffer, BufferUsage::Static, backend::as_bytes(&indices));
+            backend.set_index_buffer(vao.handle(), buffer);
+            buffer
+        });
+        return IBO { id, indices };
     }
     pub fn get_indices(&self) -> &Vec<i32> {
         &self.indices
     }
+    pub fn label(&self, name: &str) {
+        backend::with(|backend| backend.label(Resource::Buffer(self.id), name));
+    }
 }
 
 impl GPUObject for IBO {
     fn bind(&self) {
-        unsafe {
-            gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, self.id);
-        }
+        backend::with(|backend| backend.bind(Resource::Buffer(self.id)));
     }
 
     fn unbind(&self) {
-        unsafe {
-            gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, 0);
-        }
+        backend::with(|backend| backend.unbind(Resource::Buffer(self.id)));
     }
 }
 
 impl Drop for IBO {
     fn drop(&mut self) {
-        unsafe {
-            gl::DeleteBuffers(1, &self.id);
-        }
+        backend::release(Resource::Buffer(self.id));
+    }
+}
+
+//Per-instance vertex data. A non-zero fade discards part of the pixels in a dither pattern during LOD
+//transitions, positive for the level fading in and negative for the one fading out (see dither.glsl).
+//Skinned meshes find their joint matrices at skin_offset in the palette, it is negative for the rest (see skinning.glsl).
+//Morph weights are found the same way at morph_offset (see morph.glsl).
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct Instance {
+    pub model: Mat4,
+    pub fade: f32,
+    pub skin_offset: f32,
+    pub morph_offset: f32,
+}
+
+impl Instance {
+    pub fn new(model: Mat4) -> Instance {
+        Instance { model, fade: 0.0, skin_offset: -1.0, morph_offset: -1.0 }
+    }
+}
+
+//The model matrix takes four consecutive locations starting at `model_location`
+pub struct InstanceBuffer {
+    id: Handle,
+}
+
+impl InstanceBuffer {
+    pub fn new(model_location: u32, fade_location: u32, skin_offset_location: u32, morph_offset_location: u32, vao: &VAO) -> InstanceBuffer {
+        let stride = mem::size_of::<Instance>() as i32;
+        let per_instance = |location: u32, components: i32, offset: usize| VertexAttribute { location, components, stride, offset, divisor: 1 };
+        let mut attributes: Vec<VertexAttribute> = (0..4).map(|column| per_instance(model_location + column, 4, column as usize * mem::size_of::<Vec4>())).collect();
+        attributes.push(per_instance(fade_location, 1, mem::size_of::<Mat4>()));
+        attributes.push(per_instance(skin_offset_location, 1, mem::size_of::<Mat4>() + mem::size_of::<f32>()));
+        attributes.push(per_instance(morph_offset_location, 1, mem::size_of::<Mat4>() + 2 * mem::size_of::<f32>()));
+        let id = backend::with(|backend| {
+            let buffer = backend.create_buffer(BufferKind::Vertex);
+            backend.set_vertex_layout(vao.handle(), buffer, &attributes);
+            buffer
+        });
+        InstanceBuffer { id }
+    }
+    //Orphans the previous contents so batches of the same mesh can be drawn back to back
+    pub fn upload(&self, instances: &[Instance]) {
+        backend::with(|backend| backend.upload_buffer(self.id, BufferUsage::Stream, backend::as_bytes(instances)));
+    }
+    pub fn label(&self, name: &str) {
+        backend::with(|backend| backend.label(Resource::Buffer(self.id), name));
+    }
+}
+
+impl GPUObject for InstanceBuffer {
+    fn bind(&self) {
+        backend::with(|backend| backend.bind(Resource::Buffer(self.id)));
+    }
+
+    fn unbind(&self) {
+        backend::with(|backend| backend.unbind(Resource::Buffer(self.id)));
+    }
+}
+
+impl Drop for InstanceBuffer {
+    fn drop(&mut self) {
+        backend::release(Resource::Buffer(self.id));
+    }
+}
+
+//Draws a single triangle covering the screen, positions are generated from gl_VertexID in fullscreen.vs
+pub struct FullscreenTriangle {
+    vao: VAO,
+}
+
+impl FullscreenTriangle {
+    pub fn new() -> FullscreenTriangle {
+        let vao = VAO::new();
+        vao.bind();
+        vao.label("fullscreen triangle");
+        vao.unbind();
+        FullscreenTriangle { vao }
+    }
+    pub fn render(&self) {
+        let draw = DrawCommand { vertex_array: self.vao.handle(), primitive: Primitive::Triangles, first: 0, count: 3, instances: 1, indexed: false };
+        backend::with(|backend| backend.submit(&[draw]));
     }
 }
 
@@ -6,7 +6,7 @@ use std::{collections::HashMap, error::Error, sync::Arc, time::*};
 use bevy_ecs::system::Resource;
 use winit::event::MouseButton;
 
-use crate::{texture::{Texture}, shader::{Shader}, material::{Material, MagnificationFilter, self}, settings::Settings};
+use crate::{texture::{Texture}, shader::{Shader}, material::{Material, TextureOptions}, settings::Settings, sprite::TextureAtlas, text::Font, particles::ParticleEffect};
 
 //TODO: Fix accesses
 #[derive(Resource)]
@@ -227,6 +227,9 @@ pub struct AssetPool {
     materials: HashMap<String, Arc<Material>>,
     textures: HashMap<String, Arc<Texture>>,
     shaders: HashMap<String, Arc<Shader>>,
+    atlases: HashMap<String, Arc<TextureAtlas>>,
+    fonts: HashMap<String, Arc<Font>>,
+    particle_effects: HashMap<String, Arc<ParticleEffect>>,
 }
 
 impl AssetPool {
@@ -256,15 +259,26 @@ impl AssetPool {
         self.materials.get(name)
     }
 
-    pub fn load_texture(&mut self, name: &str, filter: &MagnificationFilter, aniso_level: f32) -> Result<Arc<Texture>, Box<dyn Error>> {
+    pub fn load_texture(&mut self, name: &str, options: &TextureOptions, aniso_level: f32) -> Result<Arc<Texture>, Box<dyn Error>> {
         if self.get_texture(name).is_some() {
             return Ok(self.get_texture(name).unwrap().clone());
         }
 
-        let texture = Texture::new(name, material::to_gl_filter(filter), aniso_level)?;
+        let texture = if options.layers.is_empty() {
+            Texture::new(name, options, aniso_level)?
+        } else {
+            let names: Vec<&str> = std::iter::once(name).chain(options.layers.iter().map(|layer| layer.as_str())).collect();
+            Texture::array(&names, options, aniso_level)?
+        };
         self.textures.insert(name.to_string(), Arc::new(texture));
         Ok(self.get_texture(name).unwrap().clone())
     }
+    //Textures created at runtime, such as camera render targets, replacing any texture of the same name
+    pub fn insert_texture(&mut self, name: &str, texture: Texture) -> Arc<Texture> {
+        let texture = Arc::new(texture);
+        self.textures.insert(name.to_string(), texture.clone());
+        texture
+    }
     pub fn unload_texture(&mut self, name: &str) -> Option<Box<dyn Error>> {
         let texture = self.get_texture(name)?;
         if Arc::strong_count(texture) > 1 {
@@ -277,6 +291,49 @@ impl AssetPool {
         self.textures.get(name)
     }
 
+    //Sprite atlases and the texture they cut up, plain textures become single frame atlases
+    pub fn load_atlas(&mut self, name: &str, settings: &Settings) -> Result<Arc<TextureAtlas>, Box<dyn Error>> {
+        if self.get_atlas(name).is_some() {
+            return Ok(self.get_atlas(name).unwrap().clone());
+        }
+        let atlas = TextureAtlas::load(name, |texture, options| Ok(self.load_texture(texture, options, settings.aniso_level)?.size()))?;
+        self.atlases.insert(name.to_string(), Arc::new(atlas));
+        Ok(self.get_atlas(name).unwrap().clone())
+    }
+    pub fn get_atlas(&self, name: &str) -> Option<&Arc<TextureAtlas>> {
+        self.atlases.get(name)
+    }
+
+    pub fn load_font(&mut self, name: &str) -> Result<Arc<Font>, Box<dyn Error>> {
+        if self.get_font(name).is_some() {
+            return Ok(self.get_font(name).unwrap().clone());
+        }
+
+        let font = Font::load(name)?;
+        self.fonts.insert(name.to_string(), Arc::new(font));
+        Ok(self.get_font(name).unwrap().clone())
+    }
+    pub fn get_font(&self, name: &str) -> Option<&Arc<Font>> {
+        self.fonts.get(name)
+    }
+
+    //Effect definitions and the texture they draw with
+    pub fn load_particle_effect(&mut self, name: &str, settings: &Settings) -> Result<Arc<ParticleEffect>, Box<dyn Error>> {
+        if self.get_particle_effect(name).is_some() {
+            return Ok(self.get_particle_effect(name).unwrap().clone());
+        }
+
+        let effect = ParticleEffect::load(name)?;
+        if !effect.texture.is_empty() {
+            self.load_texture(&effect.texture, &effect.texture_options, settings.aniso_level)?;
+        }
+        self.particle_effects.insert(name.to_string(), Arc::new(effect));
+        Ok(self.get_particle_effect(name).unwrap().clone())
+    }
+    pub fn get_particle_effect(&self, name: &str) -> Option<&Arc<ParticleEffect>> {
+        self.particle_effects.get(name)
+    }
+
     pub fn load_shader(&mut self, name: &str) -> Result<Arc<Shader>, Box<dyn Error>> {
         if self.get_shader(name).is_some() {
             return Ok(self.get_shader(name).unwrap().clone());
@@ -302,5 +359,8 @@ impl AssetPool {
         self.materials.clear();
         self.textures.clear();
         self.shaders.clear();
+        self.atlases.clear();
+        self.fonts.clear();
+        self.particle_effects.clear();
     }
 }
\ No newline at end of file
@@ -2,7 +2,10 @@ use std::fs;
 use bevy_ecs::system::Resource;
 use serde::{Serialize, Deserialize};
 
+use crate::{deferred::RenderPath, environment::SkyboxSettings, lightmap::BakeSettings, lod::LodSettings, profiler::ProfilerSettings, sprite::SpriteSettings, ssr::SsrQuality, text::TextSettings};
+
 #[derive(Resource, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Settings {
     pub width: u32,
     pub height: u32,
@@ -10,8 +13,24 @@ pub struct Settings {
     //TODO: Add fullscreen
     pub swap_interval: i32,
     pub is_wireframe: bool,
+    //Shows the DebugDraw shapes, toggled with F9
+    pub debug_draw: bool,
     pub fov: f32,
     pub aniso_level: f32,
+    pub render_path: RenderPath,
+    //Weighted blended OIT instead of sorting blended meshes, only when rendering into an offscreen target
+    pub order_independent_transparency: bool,
+    pub max_shadow_lights: u32,
+    pub shadow_atlas_size: u32,
+    pub shadow_max_tile_size: u32,
+    pub shadow_min_tile_size: u32,
+    pub ssr_quality: SsrQuality,
+    pub bake: BakeSettings,
+    pub lod: LodSettings,
+    pub skybox: SkyboxSettings,
+    pub sprites: SpriteSettings,
+    pub text: TextSettings,
+    pub profiler: ProfilerSettings,
 }
 
 pub const SETTINGS_LOCATION: &str = "resources/settings.toml";
@@ -24,8 +43,22 @@ impl Default for Settings {
             title: "Game - Butter Engine".to_string(),
             swap_interval: 60,
             is_wireframe: false,
+            debug_draw: false,
             fov: 90.0,
             aniso_level: 4.0,
+            render_path: RenderPath::Forward,
+            order_independent_transparency: false,
+            max_shadow_lights: 4,
+            shadow_atlas_size: 4096,
+            shadow_max_tile_size: 1024,
+            shadow_min_tile_size: 128,
+            ssr_quality: SsrQuality::Medium,
+            bake: BakeSettings::default(),
+            lod: LodSettings::default(),
+            skybox: SkyboxSettings::default(),
+            sprites: SpriteSettings::default(),
+            text: TextSettings::default(),
+            profiler: ProfilerSettings::default(),
         }
     }
 }
@@ -1,291 +1,157 @@
 use glam::*;
-use gl::types::*;
 use std::error::Error;
-use std::ffi::CString;
 use std::fs;
-use std::ptr;
-use std::str;
 
-use crate::renderer;
+use crate::{backend::{self, Handle, Resource, ShaderStage, Uniform}, renderer};
 
 pub struct Shader {
-    program: u32,
+    program: Handle,
 }
 
 impl renderer::GPUObject for Shader {
     fn bind(&self) {
-        unsafe {
-            gl::UseProgram(self.program);
-        }
+        backend::with(|backend| backend.bind(Resource::Shader(self.program)));
     }
 
     fn unbind(&self) {
-        unsafe {
-            gl::UseProgram(0);
-        }
+        backend::with(|backend| backend.unbind(Resource::Shader(self.program)));
     }
 }
 
 impl Drop for Shader {
     fn drop(&mut self) {
-        unsafe {
-            gl::DeleteProgram(self.program);
-        }
+        backend::release(Resource::Shader(self.program));
     }
 }
 
 impl Shader {
     pub fn new(name: &str) -> Result<Shader, Box<dyn Error>> {
-        let vertex_src: String = fs::read_to_string(format!("resources/shaders/{}.vs", name))?;
-        let fragment_src: String = fs::read_to_string(format!("resources/shaders/{}.fs", name))?;
+        Shader::from_files(name, name)
+    }
 
-        let program = unsafe {
-            // build and compile our shader program
-            // ------------------------------------
-            // vertex shader
-            let vertex_shader = gl::CreateShader(gl::VERTEX_SHADER);
-            let c_str_vert = CString::new(vertex_src.as_bytes()).unwrap();
-            gl::ShaderSource(vertex_shader, 1, &c_str_vert.as_ptr(), ptr::null());
-            gl::CompileShader(vertex_shader);
+    //Lets passes share a vertex shader, e.g. every fullscreen pass uses fullscreen.vs
+    pub fn from_files(vertex_name: &str, fragment_name: &str) -> Result<Shader, Box<dyn Error>> {
+        let vertex_src: String = read_source(&format!("{}.vs", vertex_name))?;
+        let fragment_src: String = read_source(&format!("{}.fs", fragment_name))?;
 
-            // check for shader compile errors
-            let mut success = gl::FALSE as GLint;
-            let mut info_log = Vec::with_capacity(512);
-            info_log.set_len(512 - 1); // subtract 1 to skip the trailing null character
-            gl::GetShaderiv(vertex_shader, gl::COMPILE_STATUS, &mut success);
-            if success != gl::TRUE as GLint {
-                gl::GetShaderInfoLog(
-                    vertex_shader,
-                    512,
-                    ptr::null_mut(),
-                    info_log.as_mut_ptr() as *mut GLchar,
-                );
-                println!(
-                    "ERROR::SHADER::VERTEX::COMPILATION_FAILED\n{}",
-                    str::from_utf8(&info_log).unwrap()
-                );
-            }
+        let name = if vertex_name == fragment_name { vertex_name.to_string() } else { format!("{}+{}", vertex_name, fragment_name) };
+        let stages = [(ShaderStage::Vertex, vertex_src.as_str()), (ShaderStage::Fragment, fragment_src.as_str())];
+        let program = backend::with(|backend| backend.create_shader(&name, &stages));
 
-            // fragment shader
-            let fragment_shader = gl::CreateShader(gl::FRAGMENT_SHADER);
-            let c_str_frag = CString::new(fragment_src.as_bytes()).unwrap();
-            gl::ShaderSource(fragment_shader, 1, &c_str_frag.as_ptr(), ptr::null());
-            gl::CompileShader(fragment_shader);
-            // check for shader compile errors
-            gl::GetShaderiv(fragment_shader, gl::COMPILE_STATUS, &mut success);
-            if success != gl::TRUE as GLint {
-                gl::GetShaderInfoLog(
-                    fragment_shader,
-                    512,
-                    ptr::null_mut(),
-                    info_log.as_mut_ptr() as *mut GLchar,
-                );
-                println!(
-                    "ERROR::SHADER::FRAGMENT::COMPILATION_FAILED\n{}",
-                    str::from_utf8(&info_log).unwrap()
-                );
-            }
+        Ok(Shader { program })
+    }
 
-            // link shaders
-            let shader_program = gl::CreateProgram();
-            gl::AttachShader(shader_program, vertex_shader);
-            gl::AttachShader(shader_program, fragment_shader);
-            gl::LinkProgram(shader_program);
-            // check for linking errors
-            gl::GetProgramiv(shader_program, gl::LINK_STATUS, &mut success);
-            if success != gl::TRUE as GLint {
-                gl::GetProgramInfoLog(
-                    shader_program,
-                    512,
-                    ptr::null_mut(),
-                    info_log.as_mut_ptr() as *mut GLchar,
-                );
-                println!(
-                    "ERROR::SHADER::PROGRAM::COMPILATION_FAILED\n{}",
-                    str::from_utf8(&info_log).unwrap()
-                );
-            }
-            gl::DeleteShader(vertex_shader);
-            gl::DeleteShader(fragment_shader);
+    //Compute shaders live in resources/shaders/{name}.cs
+    pub fn compute(name: &str) -> Result<Shader, Box<dyn Error>> {
+        let compute_src: String = read_source(&format!("{}.cs", name))?;
 
-            shader_program
-        };
+        let program = backend::with(|backend| backend.create_shader(name, &[(ShaderStage::Compute, compute_src.as_str())]));
+
+        Ok(Shader { program })
+    }
+
+    pub fn dispatch(&self, groups_x: u32, groups_y: u32, groups_z: u32) {
+        backend::with(|backend| backend.dispatch(self.program, UVec3::new(groups_x, groups_y, groups_z)));
+    }
 
-        Ok(Shader { program: program })
+    fn set_uniform(&self, label: &str, value: Uniform) {
+        backend::with(|backend| backend.set_uniform(self.program, label, value));
     }
 
     //Bools
     pub fn set_uniform_bool(&self, label: String, value: &bool) {
-        let c_label = CString::new(label).unwrap();
-        unsafe {
-            let location = gl::GetUniformLocation(self.program, c_label.as_ptr());
-            gl::Uniform1i(location, *value as i32);
-        }
+        self.set_uniform(&label, Uniform::Bool(*value));
     }
 
     //Floats
     pub fn set_uniform_4f(&self, label: String, value: &Vec4) {
-        let c_label = CString::new(label).unwrap();
-        unsafe {
-            let location = gl::GetUniformLocation(self.program, c_label.as_ptr());
-            gl::Uniform4f(location, value.x, value.y, value.z, value.w);
-        }
+        self.set_uniform(&label, Uniform::Vec4(*value));
     }
     pub fn set_uniform_3f(&self, label: String, value: &Vec3) {
-        let c_label = CString::new(label).unwrap();
-        unsafe {
-            let location = gl::GetUniformLocation(self.program, c_label.as_ptr());
-            gl::Uniform3f(location, value.x, value.y, value.z);
-        }
+        self.set_uniform(&label, Uniform::Vec3(*value));
     }
     pub fn set_uniform_2f(&self, label: String, value: &Vec2) {
-        let c_label = CString::new(label).unwrap();
-        unsafe {
-            let location = gl::GetUniformLocation(self.program, c_label.as_ptr());
-            gl::Uniform2f(location, value.x, value.y);
-        }
+        self.set_uniform(&label, Uniform::Vec2(*value));
     }
     pub fn set_uniform_1f(&self, label: String, value: &f32) {
-        let c_label = CString::new(label).unwrap();
-        unsafe {
-            let location = gl::GetUniformLocation(self.program, c_label.as_ptr());
-            gl::Uniform1f(location, *value);
-        }
+        self.set_uniform(&label, Uniform::Float(*value));
     }
 
     //Signed Integers
     pub fn set_uniform_4i(&self, label: String, value: &IVec4) {
-        let c_label = CString::new(label).unwrap();
-        unsafe {
-            let location = gl::GetUniformLocation(self.program, c_label.as_ptr());
-            gl::Uniform4i(location, value.x, value.y, value.z, value.w);
-        }
+        self.set_uniform(&label, Uniform::IVec4(*value));
     }
     pub fn set_uniform_3i(&self, label: String, value: &IVec3) {
-        let c_label = CString::new(label).unwrap();
-        unsafe {
-            let location = gl::GetUniformLocation(self.program, c_label.as_ptr());
-            gl::Uniform3i(location, value.x, value.y, value.z);
-        }
+        self.set_uniform(&label, Uniform::IVec3(*value));
     }
     pub fn set_uniform_2i(&self, label: String, value: &IVec2) {
-        let c_label = CString::new(label).unwrap();
-        unsafe {
-            let location = gl::GetUniformLocation(self.program, c_label.as_ptr());
-            gl::Uniform2i(location, value.x, value.y);
-        }
+        self.set_uniform(&label, Uniform::IVec2(*value));
     }
     pub fn set_uniform_i32(&self, label: String, value: &i32) {
-        let c_label = CString::new(label).unwrap();
-        unsafe {
-            let location = gl::GetUniformLocation(self.program, c_label.as_ptr());
-            gl::Uniform1i(location, *value);
-        }
+        self.set_uniform(&label, Uniform::Int(*value));
     }
 
     //Unsigned Integers
     pub fn set_uniform_4ui(&self, label: String, value: &UVec4) {
-        let c_label = CString::new(label).unwrap();
-        unsafe {
-            let location = gl::GetUniformLocation(self.program, c_label.as_ptr());
-            gl::Uniform4ui(location, value.x, value.y, value.z, value.w);
-        }
+        self.set_uniform(&label, Uniform::UVec4(*value));
     }
     pub fn set_uniform_3ui(&self, label: String, value: &UVec3) {
-        let c_label = CString::new(label).unwrap();
-        unsafe {
-            let location = gl::GetUniformLocation(self.program, c_label.as_ptr());
-            gl::Uniform3ui(location, value.x, value.y, value.z);
-        }
+        self.set_uniform(&label, Uniform::UVec3(*value));
     }
     pub fn set_uniform_2ui(&self, label: String, value: &UVec2) {
-        let c_label = CString::new(label).unwrap();
-        unsafe {
-            let location = gl::GetUniformLocation(self.program, c_label.as_ptr());
-            gl::Uniform2ui(location, value.x, value.y);
-        }
+        self.set_uniform(&label, Uniform::UVec2(*value));
     }
     pub fn set_uniform_u32(&self, label: String, value: &u32) {
-        let c_label = CString::new(label).unwrap();
-        unsafe {
-            let location = gl::GetUniformLocation(self.program, c_label.as_ptr());
-            gl::Uniform1ui(location, *value);
-        }
+        self.set_uniform(&label, Uniform::UInt(*value));
     }
 
     //Doubles
     pub fn set_uniform_4d(&self, label: String, value: &DVec4) {
-        let c_label = CString::new(label).unwrap();
-        unsafe {
-            let location = gl::GetUniformLocation(self.program, c_label.as_ptr());
-            gl::Uniform4d(location, value.x, value.y, value.z, value.w);
-        }
+        self.set_uniform(&label, Uniform::DVec4(*value));
     }
     pub fn set_uniform_3d(&self, label: String, value: &DVec3) {
-        let c_label = CString::new(label).unwrap();
-        unsafe {
-            let location = gl::GetUniformLocation(self.program, c_label.as_ptr());
-            gl::Uniform3d(location, value.x, value.y, value.z);
-        }
+        self.set_uniform(&label, Uniform::DVec3(*value));
     }
     pub fn set_uniform_2d(&self, label: String, value: &DVec2) {
-        let c_label = CString::new(label).unwrap();
-        unsafe {
-            let location = gl::GetUniformLocation(self.program, c_label.as_ptr());
-            gl::Uniform2d(location, value.x, value.y);
-        }
+        self.set_uniform(&label, Uniform::DVec2(*value));
     }
     pub fn set_uniform_d64(&self, label: String, value: &f64) {
-        let c_label = CString::new(label).unwrap();
-        unsafe {
-            let location = gl::GetUniformLocation(self.program, c_label.as_ptr());
-            gl::Uniform1d(location, *value);
-        }
+        self.set_uniform(&label, Uniform::Double(*value));
     }
 
     //Matrices Floats
     pub fn set_uniform_2x2f(&self, label: String, count: Option<i32>, value: &Mat2) {
-        let c_label = CString::new(label).unwrap();
-        unsafe {
-            let location = gl::GetUniformLocation(self.program, c_label.as_ptr());
-            gl::UniformMatrix2fv(location, count.unwrap_or(1), gl::FALSE, value.as_ref() as *const f32);
-        }
+        self.set_uniform(&label, Uniform::Mat2(*value, count.unwrap_or(1)));
     }
     pub fn set_uniform_3x3f(&self, label: String, count: Option<i32>, value: &Mat3) {
-        let c_label = CString::new(label).unwrap();
-        unsafe {
-            let location = gl::GetUniformLocation(self.program, c_label.as_ptr());
-            gl::UniformMatrix3fv(location, count.unwrap_or(1), gl::FALSE, value.as_ref() as *const f32);
-        }
+        self.set_uniform(&label, Uniform::Mat3(*value, count.unwrap_or(1)));
     }
     pub fn set_uniform_4x4f(&self, label: String, count: Option<i32>, value: &Mat4) {
-        let c_label = CString::new(label).unwrap();
-        unsafe {
-            let location = gl::GetUniformLocation(self.program, c_label.as_ptr());
-            gl::UniformMatrix4fv(location, count.unwrap_or(1), gl::FALSE, value.as_ref() as *const f32);
-        }
+        self.set_uniform(&label, Uniform::Mat4(*value, count.unwrap_or(1)));
     }
 
     //Matrices Doubles
     pub fn set_uniform_2x2d(&self, label: String, count: Option<i32>, value: &DMat2) {
-        let c_label = CString::new(label).unwrap();
-        unsafe {
-            let location = gl::GetUniformLocation(self.program, c_label.as_ptr());
-            gl::UniformMatrix2dv(location, count.unwrap_or(1), gl::FALSE, value.as_ref() as *const f64);
-        }
+        self.set_uniform(&label, Uniform::DMat2(*value, count.unwrap_or(1)));
     }
     pub fn set_uniform_3x3d(&self, label: String, count: Option<i32>, value: &DMat3) {
-        let c_label = CString::new(label).unwrap();
-        unsafe {
-            let location = gl::GetUniformLocation(self.program, c_label.as_ptr());
-            gl::UniformMatrix3dv(location, count.unwrap_or(1), gl::FALSE, value.as_ref() as *const f64);
-        }
+        self.set_uniform(&label, Uniform::DMat3(*value, count.unwrap_or(1)));
     }
     pub fn set_uniform_4x4d(&self, label: String, count: Option<i32>, value: &DMat4) {
-        let c_label = CString::new(label).unwrap();
-        unsafe {
-            let location = gl::GetUniformLocation(self.program, c_label.as_ptr());
-            gl::UniformMatrix4dv(location, count.unwrap_or(1), gl::FALSE, value.as_ref() as *const f64);
+        self.set_uniform(&label, Uniform::DMat4(*value, count.unwrap_or(1)));
+    }
+}
+
+//Reads a shader source, replacing #include "file" lines with the contents of resources/shaders/file
+fn read_source(file: &str) -> Result<String, Box<dyn Error>> {
+    let source = fs::read_to_string(format!("resources/shaders/{}", file))?;
+    let mut output = String::with_capacity(source.len());
+    for line in source.lines() {
+        match line.trim().strip_prefix("#include") {
+            Some(include) => output.push_str(&read_source(include.trim().trim_matches('"'))?),
+            None => output.push_str(line),
         }
+        output.push('\n');
     }
+    Ok(output)
 }
@@ -0,0 +1,311 @@
+use std::collections::HashMap;
+
+use bevy_ecs::prelude::*;
+use glam::*;
+
+use crate::{framebuffer::{self, Framebuffer}, shader::Shader};
+
+//Must match MAX_SHADOW_VIEWS in the lit shaders
+pub const MAX_SHADOW_VIEWS: usize = 48;
+pub const SHADOW_ATLAS_UNIT: u32 = 1;
+const SHADOW_NEAR_PLANE: f32 = 0.05;
+//Smaller atlases are raised to this, it still fits every view at a few texels each
+const MIN_ATLAS_SIZE: u32 = 256;
+
+//One rendered depth tile inside the atlas
+pub struct ShadowView {
+    pub view_projection: Mat4,
+    pub viewport: UVec4, //x, y, width, height in texels
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum ShadowCaster {
+    Point { position: Vec3, range: f32 },
+    Spot { position: Vec3, direction: Vec3, range: f32, outer_angle: f32 },
+}
+
+pub struct ShadowRequest {
+    pub entity: Entity,
+    pub caster: ShadowCaster,
+    pub coverage: f32,
+}
+
+#[derive(Resource)]
+pub struct ShadowAtlas {
+    framebuffer: Framebuffer,
+    size: u32,
+    views: Vec<ShadowView>,
+    //Index of the first view owned by a light, point lights own six consecutive views
+    first_views: HashMap<Entity, usize>,
+}
+
+impl ShadowAtlas {
+    pub fn new(size: u32) -> ShadowAtlas {
+        let size = ShadowAtlas::size_for(size);
+        let mut framebuffer = Framebuffer::new(size as i32, size as i32);
+        framebuffer.add_depth_attachment(true);
+        framebuffer.is_complete();
+        framebuffer.label("shadow atlas");
+
+        ShadowAtlas { framebuffer, size, views: Vec::new(), first_views: HashMap::new() }
+    }
+
+    //Power of two side of the atlas created for the requested size
+    pub fn size_for(requested: u32) -> u32 {
+        requested.max(MIN_ATLAS_SIZE).next_power_of_two()
+    }
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+    pub fn framebuffer(&self) -> &Framebuffer {
+        &self.framebuffer
+    }
+    pub fn views(&self) -> &Vec<ShadowView> {
+        &self.views
+    }
+    pub fn first_view(&self, entity: Entity) -> Option<usize> {
+        self.first_views.get(&entity).copied()
+    }
+
+    //Picks which lights get shadows this frame and where their tiles live in the atlas, see plan_views
+    pub fn plan(&mut self, requests: Vec<ShadowRequest>, max_lights: usize, max_tile: u32, min_tile: u32) {
+        (self.views, self.first_views) = plan_views(requests, self.size, max_lights, max_tile, min_tile);
+    }
+
+    pub fn bind_for_sampling(&self) {
+        framebuffer::bind_attachment(self.framebuffer.depth_attachment().unwrap(), SHADOW_ATLAS_UNIT);
+    }
+    pub fn unbind_for_sampling(&self) {
+        framebuffer::unbind_attachment(SHADOW_ATLAS_UNIT);
+    }
+
+    pub fn upload(&self, shader: &Shader) {
+        shader.set_uniform_i32("shadowAtlas".to_string(), &(SHADOW_ATLAS_UNIT as i32));
+        shader.set_uniform_1f("shadowTexelSize".to_string(), &(1.0 / self.size as f32));
+        for (index, view) in self.views.iter().enumerate() {
+            let rect = view.viewport.as_vec4() / self.size as f32;
+            shader.set_uniform_4x4f(format!("shadowViews[{}].matrix", index), None, &view.view_projection);
+            shader.set_uniform_4f(format!("shadowViews[{}].rect", index), &rect);
+        }
+    }
+}
+
+//Lights covering more of the screen win when there are more requests than max_lights, and every tile is halved
+//until the whole set fits. Once every tile is down to a single texel the lights covering the least are dropped.
+pub fn plan_views(mut requests: Vec<ShadowRequest>, atlas_size: u32, max_lights: usize, max_tile: u32, min_tile: u32) -> (Vec<ShadowView>, HashMap<Entity, usize>) {
+    requests.sort_by(|a, b| b.coverage.total_cmp(&a.coverage));
+    requests.truncate(max_lights);
+
+    let max_tile = max_tile.next_power_of_two().min(atlas_size);
+    let min_tile = min_tile.next_power_of_two().min(max_tile);
+    let mut tiles: Vec<(usize, u32, u32)> = Vec::new(); //request, face, size
+    for (index, request) in requests.iter().enumerate() {
+        let size = tile_size_for_coverage(request.coverage, max_tile, min_tile);
+        let needed = match request.caster {
+            ShadowCaster::Point { .. } => 6,
+            ShadowCaster::Spot { .. } => 1,
+        };
+        if tiles.len() + needed > MAX_SHADOW_VIEWS {
+            break;
+        }
+        match request.caster {
+            ShadowCaster::Point { .. } => {
+                for face in 0..6 {
+                    tiles.push((index, face, (size / 2).max(min_tile)));
+                }
+            },
+            ShadowCaster::Spot { .. } => tiles.push((index, 0, size)),
+        }
+    }
+
+    let mut sizes: Vec<u32> = tiles.iter().map(|t| t.2).collect();
+    let positions = loop {
+        let mut order: Vec<usize> = (0..sizes.len()).collect();
+        order.sort_by(|a, b| sizes[*b].cmp(&sizes[*a]));
+        let sorted: Vec<u32> = order.iter().map(|i| sizes[*i]).collect();
+        if let Some(packed) = pack_tiles(&sorted, atlas_size) {
+            let mut positions = vec![UVec2::ZERO; sizes.len()];
+            for (slot, index) in order.iter().enumerate() {
+                positions[*index] = packed[slot];
+            }
+            break positions;
+        }
+        if sizes.iter().all(|size| *size == 1) {
+            //Every face of a point light goes with it, the shaders expect six consecutive views
+            let last = tiles.last().map(|tile| tile.0);
+            while tiles.last().map(|tile| tile.0) == last {
+                tiles.pop();
+                sizes.pop();
+            }
+        } else {
+            sizes.iter_mut().for_each(|size| *size = (*size / 2).max(1));
+        }
+    };
+
+    let mut views = Vec::with_capacity(tiles.len());
+    let mut first_views = HashMap::new();
+    for (tile, (request_index, face, _)) in tiles.iter().enumerate() {
+        let request = &requests[*request_index];
+        if *face == 0 {
+            first_views.insert(request.entity, views.len());
+        }
+        views.push(ShadowView {
+            view_projection: view_projection(&request.caster, *face),
+            viewport: UVec4::new(positions[tile].x, positions[tile].y, sizes[tile], sizes[tile]),
+        });
+    }
+    (views, first_views)
+}
+
+//Rough fraction of the screen height covered by a light's bounding sphere
+pub fn screen_coverage(light_position: Vec3, range: f32, camera_position: Vec3, fov: f32) -> f32 {
+    let distance = light_position.distance(camera_position);
+    if distance <= range {
+        return 1.0;
+    }
+    let half_height = distance * (fov.to_radians() * 0.5).tan();
+    (range / half_height).clamp(0.0, 1.0)
+}
+
+//Full coverage gets max_tile, every halving of coverage halves the resolution
+pub fn tile_size_for_coverage(coverage: f32, max_tile: u32, min_tile: u32) -> u32 {
+    let mut size = max_tile;
+    let mut coverage = coverage.max(0.0);
+    while size > min_tile && coverage < 0.5 {
+        size /= 2;
+        coverage *= 2.0;
+    }
+    size
+}
+
+//Packs power of two squares (sorted largest first) into a power of two atlas by walking it in
+//Morton order, every tile lands on a boundary aligned to its own size so nothing overlaps
+pub fn pack_tiles(sorted_sizes: &[u32], atlas_size: u32) -> Option<Vec<UVec2>> {
+    let unit = match sorted_sizes.last() {
+        Some(unit) => *unit,
+        None => return Some(Vec::new()),
+    };
+    let total: u64 = sorted_sizes.iter().map(|s| *s as u64 * *s as u64).sum();
+    if sorted_sizes[0] > atlas_size || total > atlas_size as u64 * atlas_size as u64 {
+        return None;
+    }
+
+    let mut cursor: u64 = 0;
+    let mut positions = Vec::with_capacity(sorted_sizes.len());
+    for size in sorted_sizes {
+        let cell = cursor / (unit as u64 * unit as u64);
+        positions.push(UVec2::new(deinterleave(cell), deinterleave(cell >> 1)) * unit);
+        cursor += *size as u64 * *size as u64;
+    }
+    Some(positions)
+}
+
+fn deinterleave(mut bits: u64) -> u32 {
+    bits &= 0x5555555555555555;
+    bits = (bits | (bits >> 1)) & 0x3333333333333333;
+    bits = (bits | (bits >> 2)) & 0x0F0F0F0F0F0F0F0F;
+    bits = (bits | (bits >> 4)) & 0x00FF00FF00FF00FF;
+    bits = (bits | (bits >> 8)) & 0x0000FFFF0000FFFF;
+    bits = (bits | (bits >> 16)) & 0x00000000FFFFFFFF;
+    bits as u32
+}
+
+//Face order is +X, -X, +Y, -Y, +Z, -Z, the lit shaders pick faces in the same order
+pub fn view_projection(caster: &ShadowCaster, face: u32) -> Mat4 {
+    match *caster {
+        ShadowCaster::Point { position, range } => {
+            let (forward, up) = match face {
+                0 => (Vec3::X, Vec3::NEG_Y),
+                1 => (Vec3::NEG_X, Vec3::NEG_Y),
+                2 => (Vec3::Y, Vec3::Z),
+                3 => (Vec3::NEG_Y, Vec3::NEG_Z),
+                4 => (Vec3::Z, Vec3::NEG_Y),
+                _ => (Vec3::NEG_Z, Vec3::NEG_Y),
+            };
+            let projection = Mat4::perspective_rh_gl(90.0_f32.to_radians(), 1.0, SHADOW_NEAR_PLANE, range);
+            projection * Mat4::look_at_rh(position, position + forward, up)
+        },
+        ShadowCaster::Spot { position, direction, range, outer_angle } => {
+            let direction = direction.normalize();
+            let up = if direction.y.abs() > 0.99 { Vec3::Z } else { Vec3::Y };
+            let projection = Mat4::perspective_rh_gl((outer_angle * 2.0).to_radians().min(170.0_f32.to_radians()), 1.0, SHADOW_NEAR_PLANE, range);
+            projection * Mat4::look_at_rh(position, position + direction, up)
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn overlaps(a: &UVec4, b: &UVec4) -> bool {
+        a.x < b.x + b.z && b.x < a.x + a.z && a.y < b.y + b.w && b.y < a.y + a.w
+    }
+
+    fn spot(entity: u32, coverage: f32) -> ShadowRequest {
+        let caster = ShadowCaster::Spot { position: Vec3::ZERO, direction: Vec3::NEG_Y, range: 10.0, outer_angle: 35.0 };
+        ShadowRequest { entity: Entity::from_raw(entity), caster, coverage }
+    }
+
+    fn point(entity: u32, coverage: f32) -> ShadowRequest {
+        ShadowRequest { entity: Entity::from_raw(entity), caster: ShadowCaster::Point { position: Vec3::ZERO, range: 10.0 }, coverage }
+    }
+
+    #[test]
+    fn packs_aligned_tiles_without_overlaps() {
+        let sizes = [512, 256, 256, 128, 128, 128, 64];
+        let positions = pack_tiles(&sizes, 1024).unwrap();
+        let rects: Vec<UVec4> = positions.iter().zip(sizes).map(|(position, size)| UVec4::new(position.x, position.y, size, size)).collect();
+        for (index, rect) in rects.iter().enumerate() {
+            assert!(rect.x % rect.z == 0 && rect.y % rect.w == 0, "{:?} is not aligned to its size", rect);
+            assert!(rect.x + rect.z <= 1024 && rect.y + rect.w <= 1024);
+            assert!(rects[index + 1..].iter().all(|other| !overlaps(rect, other)));
+        }
+        assert_eq!(pack_tiles(&[], 16), Some(Vec::new()));
+    }
+
+    #[test]
+    fn refuses_tiles_that_dont_fit() {
+        assert_eq!(pack_tiles(&[2048], 1024), None);
+        assert_eq!(pack_tiles(&[512, 512, 512, 512, 256], 1024), None);
+        assert!(pack_tiles(&[512, 512, 512, 512], 1024).is_some());
+    }
+
+    #[test]
+    fn keeps_the_lights_covering_the_most() {
+        let requests = vec![spot(1, 0.1), spot(2, 0.9), spot(3, 0.5)];
+        let (views, first_views) = plan_views(requests, 1024, 2, 512, 64);
+        assert_eq!(views.len(), 2);
+        assert_eq!(first_views.get(&Entity::from_raw(2)), Some(&0));
+        assert_eq!(first_views.get(&Entity::from_raw(3)), Some(&1));
+        assert!(!first_views.contains_key(&Entity::from_raw(1)));
+        assert_eq!(views[0].viewport.z, 512);
+    }
+
+    #[test]
+    fn gives_point_lights_six_faces() {
+        let (views, first_views) = plan_views(vec![spot(1, 0.2), point(2, 1.0)], 1024, 4, 512, 64);
+        assert_eq!(views.len(), 7);
+        assert_eq!(first_views[&Entity::from_raw(2)], 0);
+        assert_eq!(first_views[&Entity::from_raw(1)], 6);
+        //Faces are half the light's tile and differ only in direction
+        assert!(views[..6].iter().all(|view| view.viewport.z == 256));
+        assert_ne!(views[0].view_projection, views[1].view_projection);
+    }
+
+    #[test]
+    fn shrinks_and_drops_tiles_to_fit_tiny_atlases() {
+        let requests: Vec<ShadowRequest> = (0..8).map(|index| point(index, 1.0 - index as f32 * 0.1)).collect();
+        let (views, first_views) = plan_views(requests, 4, 8, 512, 64);
+        //Sixteen texels hold two point lights at one texel a face
+        assert_eq!(views.len(), 12);
+        assert!(views.iter().all(|view| view.viewport.z == 1));
+        assert!(first_views.contains_key(&Entity::from_raw(0)) && first_views.contains_key(&Entity::from_raw(1)));
+
+        let (views, first_views) = plan_views(vec![point(0, 1.0)], 2, 1, 512, 64);
+        assert!(views.is_empty() && first_views.is_empty());
+        assert_eq!(ShadowAtlas::size_for(0), MIN_ATLAS_SIZE);
+        assert_eq!(ShadowAtlas::size_for(3000), 4096);
+    }
+}
+
@@ -0,0 +1,376 @@
+use std::{cmp::Ordering, collections::{BinaryHeap, HashMap}};
+
+use glam::{DVec3, Vec3};
+
+use crate::mesh::{MeshData, POSITION_LOCATION};
+
+//Symmetric 4x4 error quadric of Garland & Heckbert, upper triangle stored row by row
+#[derive(Clone, Copy, Default)]
+struct Quadric([f64; 10]);
+
+impl Quadric {
+    fn from_plane(normal: DVec3, d: f64, weight: f64) -> Quadric {
+        let (a, b, c) = (normal.x, normal.y, normal.z);
+        Quadric([a * a, a * b, a * c, a * d, b * b, b * c, b * d, c * c, c * d, d * d].map(|value| value * weight))
+    }
+
+    fn add(&mut self, other: &Quadric) {
+        for (value, other) in self.0.iter_mut().zip(other.0.iter()) {
+            *value += other;
+        }
+    }
+
+    fn error(&self, p: DVec3) -> f64 {
+        let q = &self.0;
+        q[0] * p.x * p.x + 2.0 * q[1] * p.x * p.y + 2.0 * q[2] * p.x * p.z + 2.0 * q[3] * p.x
+            + q[4] * p.y * p.y + 2.0 * q[5] * p.y * p.z + 2.0 * q[6] * p.y
+            + q[7] * p.z * p.z + 2.0 * q[8] * p.z
+            + q[9]
+    }
+}
+
+//Collapse of one vertex onto a neighbour, stamps detect entries made stale by later collapses
+struct Collapse {
+    cost: f64,
+    from: usize,
+    to: usize,
+    stamps: (u32, u32),
+}
+
+impl PartialEq for Collapse {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+impl Eq for Collapse {}
+impl PartialOrd for Collapse {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+//Reversed so the BinaryHeap pops the cheapest collapse first
+impl Ord for Collapse {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.cost.total_cmp(&self.cost)
+    }
+}
+
+//Works on position groups, the vertices split along UV or normal seams that share a position. A group collapses
+//as a whole, each of its vertices onto the vertex of the other group on its own side of the seam, so seams stay
+//closed without being locked. Groups are indexed by their first vertex.
+struct Simplifier {
+    positions: Vec<DVec3>,
+    group: Vec<usize>,
+    members: Vec<Vec<usize>>,
+    quadrics: Vec<Quadric>,
+    triangles: Vec<[usize; 3]>,
+    alive: Vec<bool>,
+    vertex_triangles: Vec<Vec<usize>>,
+    locked: Vec<bool>,
+    removed: Vec<bool>,
+    stamps: Vec<u32>,
+    heap: BinaryHeap<Collapse>,
+}
+
+impl Simplifier {
+    fn new(positions: Vec<DVec3>, triangles: Vec<[usize; 3]>) -> Simplifier {
+        let count = positions.len();
+        let group = weld(&positions);
+        let mut members = vec![Vec::new(); count];
+        for (vertex, group) in group.iter().enumerate() {
+            members[*group].push(vertex);
+        }
+        let mut quadrics = vec![Quadric::default(); count];
+        let mut vertex_triangles = vec![Vec::new(); count];
+        for (index, triangle) in triangles.iter().enumerate() {
+            let [a, b, c] = triangle.map(|vertex| positions[vertex]);
+            let cross = (b - a).cross(c - a);
+            let area = cross.length() * 0.5;
+            if area > 0.0 {
+                let normal = cross.normalize();
+                let quadric = Quadric::from_plane(normal, -normal.dot(a), area);
+                for vertex in triangle {
+                    quadrics[group[*vertex]].add(&quadric);
+                }
+            }
+            for vertex in triangle {
+                vertex_triangles[*vertex].push(index);
+            }
+        }
+
+        let alive = vec![true; triangles.len()];
+        let locked = lock_borders(&group, &triangles);
+        Simplifier {
+            positions, group, members, quadrics, triangles, alive, vertex_triangles, locked,
+            removed: vec![false; count],
+            stamps: vec![0; count],
+            heap: BinaryHeap::new(),
+        }
+    }
+
+    fn live_triangles(&self, vertex: usize) -> impl Iterator<Item = &[usize; 3]> {
+        self.vertex_triangles[vertex].iter().filter(|triangle| self.alive[**triangle]).map(|triangle| &self.triangles[*triangle])
+    }
+
+    fn neighbours(&self, group: usize) -> Vec<usize> {
+        let mut neighbours: Vec<usize> = self.members[group].iter()
+            .flat_map(|vertex| self.live_triangles(*vertex))
+            .flat_map(|triangle| triangle.map(|vertex| self.group[vertex]))
+            .filter(|other| *other != group)
+            .collect();
+        neighbours.sort_unstable();
+        neighbours.dedup();
+        neighbours
+    }
+
+    //Only unlocked groups may move. Both directions are queued, a seam can leave only one of them possible.
+    fn push_edge(&mut self, a: usize, b: usize) {
+        let mut quadric = self.quadrics[a];
+        quadric.add(&self.quadrics[b]);
+        for (from, to) in [(a, b), (b, a)] {
+            if !self.locked[from] {
+                let cost = quadric.error(self.positions[to]);
+                self.heap.push(Collapse { cost, from, to, stamps: (self.stamps[from], self.stamps[to]) });
+            }
+        }
+    }
+
+    //Where every used vertex of `from` goes, the one vertex of `to` it shares a triangle with. None when a vertex
+    //shares none or several, its attributes would have to be made up.
+    fn targets(&self, from: usize, to: usize) -> Option<Vec<(usize, usize)>> {
+        let mut targets = Vec::new();
+        for vertex in &self.members[from] {
+            let mut shared: Vec<usize> = self.live_triangles(*vertex).flatten().copied().filter(|other| self.group[*other] == to).collect();
+            shared.sort_unstable();
+            shared.dedup();
+            match shared[..] {
+                [target] => targets.push((*vertex, target)),
+                [] if self.live_triangles(*vertex).next().is_none() => {}
+                _ => return None,
+            }
+        }
+        Some(targets)
+    }
+
+    //Rejects collapses that would flip or squash one of the triangles that survive them
+    fn flips(&self, from: usize, to: usize) -> bool {
+        self.members[from].iter()
+            .flat_map(|vertex| self.live_triangles(*vertex))
+            .filter(|triangle| !triangle.iter().any(|vertex| self.group[*vertex] == to))
+            .any(|triangle| {
+                let before = triangle.map(|vertex| self.positions[vertex]);
+                let after = triangle.map(|vertex| self.positions[if self.group[vertex] == from { to } else { vertex }]);
+                let normal_before = (before[1] - before[0]).cross(before[2] - before[0]);
+                let normal_after = (after[1] - after[0]).cross(after[2] - after[0]);
+                normal_after.length_squared() <= f64::EPSILON || normal_before.dot(normal_after) <= 0.0
+            })
+    }
+
+    fn run(&mut self, target_triangles: usize) {
+        let mut edges: Vec<(usize, usize)> = self.triangles.iter()
+            .flat_map(|[a, b, c]| [(*a, *b), (*b, *c), (*c, *a)])
+            .map(|(x, y)| (self.group[x].min(self.group[y]), self.group[x].max(self.group[y])))
+            .filter(|(x, y)| x != y)
+            .collect();
+        edges.sort_unstable();
+        edges.dedup();
+        for (a, b) in edges {
+            self.push_edge(a, b);
+        }
+
+        let mut triangle_count = self.triangles.len();
+        while triangle_count > target_triangles {
+            let collapse = match self.heap.pop() {
+                Some(collapse) => collapse,
+                None => break,
+            };
+            let (from, to) = (collapse.from, collapse.to);
+            if self.removed[from] || self.removed[to] || collapse.stamps != (self.stamps[from], self.stamps[to]) || self.flips(from, to) {
+                continue;
+            }
+            let targets = match self.targets(from, to) {
+                Some(targets) => targets,
+                None => continue,
+            };
+
+            for (vertex, target) in targets {
+                for triangle in std::mem::take(&mut self.vertex_triangles[vertex]) {
+                    if !self.alive[triangle] {
+                        continue;
+                    }
+                    for corner in self.triangles[triangle].iter_mut().filter(|corner| **corner == vertex) {
+                        *corner = target;
+                    }
+                    //Triangles along the collapsed edge end up with two corners in one group
+                    let [a, b, c] = self.triangles[triangle].map(|corner| self.group[corner]);
+                    if a == b || b == c || c == a {
+                        self.alive[triangle] = false;
+                        triangle_count -= 1;
+                    } else {
+                        self.vertex_triangles[target].push(triangle);
+                    }
+                }
+            }
+            let quadric = self.quadrics[from];
+            self.quadrics[to].add(&quadric);
+            self.removed[from] = true;
+            self.stamps[to] += 1;
+            for neighbour in self.neighbours(to) {
+                self.push_edge(to, neighbour);
+            }
+        }
+    }
+}
+
+//The group of every vertex, the first vertex with exactly the same position
+fn weld(positions: &[DVec3]) -> Vec<usize> {
+    let mut welded: HashMap<[u64; 3], usize> = HashMap::new();
+    positions.iter().enumerate().map(|(index, position)| {
+        let key = position.to_array().map(f64::to_bits);
+        *welded.entry(key).or_insert(index)
+    }).collect()
+}
+
+//Groups on open borders are kept so the silhouette of a mesh with holes doesn't shrink
+fn lock_borders(group: &[usize], triangles: &[[usize; 3]]) -> Vec<bool> {
+    let mut edges: HashMap<(usize, usize), u32> = HashMap::new();
+    for triangle in triangles {
+        let [a, b, c] = triangle.map(|vertex| group[vertex]);
+        for (x, y) in [(a, b), (b, c), (c, a)] {
+            *edges.entry((x.min(y), x.max(y))).or_insert(0) += 1;
+        }
+    }
+    let mut border = vec![false; group.len()];
+    for ((x, y), count) in edges {
+        if count == 1 {
+            border[x] = true;
+            border[y] = true;
+        }
+    }
+    border
+}
+
+//Reduces the triangle count to roughly `target_ratio` of the original using quadric error metrics.
+//Vertices only ever collapse onto existing neighbours, so every attribute buffer stays valid and is just re-indexed.
+//Vertices split along seams move together, keeping the seam closed.
+pub fn simplify(data: &MeshData, target_ratio: f32) -> MeshData {
+    let (size, buffer) = match data.buffer(POSITION_LOCATION) {
+        Some((size, buffer)) if size >= 3 => (size as usize, buffer),
+        _ => return data.clone(),
+    };
+    let triangle_count = data.indices.len() / 3;
+    let target_triangles = ((triangle_count as f32 * target_ratio.clamp(0.0, 1.0)) as usize).max(1);
+    if target_triangles >= triangle_count {
+        return data.clone();
+    }
+
+    let positions: Vec<DVec3> = buffer.chunks_exact(size).map(|vertex| Vec3::new(vertex[0], vertex[1], vertex[2]).as_dvec3()).collect();
+    let triangles: Vec<[usize; 3]> = data.indices.chunks_exact(3).map(|triangle| [triangle[0] as usize, triangle[1] as usize, triangle[2] as usize]).collect();
+    let mut simplifier = Simplifier::new(positions, triangles);
+    simplifier.run(target_triangles);
+
+    //Compact the vertices still referenced by a triangle
+    let mut remap: Vec<Option<usize>> = vec![None; simplifier.positions.len()];
+    let mut kept: Vec<usize> = Vec::new();
+    let mut indices: Vec<i32> = Vec::new();
+    for (triangle, alive) in simplifier.triangles.iter().zip(simplifier.alive.iter()) {
+        if !alive {
+            continue;
+        }
+        for vertex in triangle {
+            let index = *remap[*vertex].get_or_insert_with(|| {
+                kept.push(*vertex);
+                kept.len() - 1
+            });
+            indices.push(index as i32);
+        }
+    }
+
+    let mut simplified = MeshData::new(indices, &data.material);
+    for (location, size, buffer) in &data.buffers {
+        let size = *size as usize;
+        let attribute: Vec<f32> = kept.iter().flat_map(|vertex| buffer[vertex * size..(vertex + 1) * size].iter().copied()).collect();
+        simplified.set_buffer(attribute, *location, size as i32);
+    }
+    simplified
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{UVec2, Vec2};
+
+    use super::*;
+    use crate::{mesh::NORMAL_LOCATION, mesh_builder::MeshBuilder};
+
+    fn triangle_count(data: &MeshData) -> usize {
+        data.indices.len() / 3
+    }
+
+    fn positions(data: &MeshData) -> Vec<Vec3> {
+        data.buffer(POSITION_LOCATION).unwrap().1.chunks_exact(3).map(Vec3::from_slice).collect()
+    }
+
+    //Every edge between positions is shared by exactly two triangles
+    fn is_closed(data: &MeshData) -> bool {
+        let positions = positions(data);
+        let key = |index: i32| positions[index as usize].to_array().map(f32::to_bits);
+        let mut edges: HashMap<([u32; 3], [u32; 3]), u32> = HashMap::new();
+        for triangle in data.indices.chunks_exact(3) {
+            for (a, b) in [(triangle[0], triangle[1]), (triangle[1], triangle[2]), (triangle[2], triangle[0])] {
+                let (a, b) = (key(a), key(b));
+                *edges.entry((a.min(b), a.max(b))).or_insert(0) += 1;
+            }
+        }
+        edges.values().all(|count| *count == 2)
+    }
+
+    #[test]
+    fn collapses_uv_split_meshes() {
+        //Every face has its own vertices, so each edge between faces is a seam
+        let cube = MeshBuilder::cube(Vec3::ONE, 4).build("wood");
+        assert!(is_closed(&cube));
+        let simplified = simplify(&cube, 0.1);
+        assert!(triangle_count(&simplified) < triangle_count(&cube) / 4, "{} of {} triangles left", triangle_count(&simplified), triangle_count(&cube));
+        assert!(triangle_count(&simplified) >= 12);
+        assert!(is_closed(&simplified));
+        //Corners stay on their own side of the seam, each triangle still belongs to a single face
+        let normals = simplified.buffer(NORMAL_LOCATION).unwrap().1;
+        for triangle in simplified.indices.chunks_exact(3) {
+            let normal = |index: i32| Vec3::from_slice(&normals[index as usize * 3..]);
+            assert_eq!(normal(triangle[0]), normal(triangle[1]));
+            assert_eq!(normal(triangle[0]), normal(triangle[2]));
+        }
+        //Flat faces collapse without moving the surface
+        for position in positions(&simplified) {
+            assert!((position.abs().max_element() - 0.5).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn simplifies_curved_meshes_with_seams() {
+        let sphere = MeshBuilder::icosphere(1.0, 3).build("wood");
+        let simplified = simplify(&sphere, 0.25);
+        let target = triangle_count(&sphere) / 4;
+        assert!(triangle_count(&simplified) <= target + 2, "{} triangles left, wanted {}", triangle_count(&simplified), target);
+        assert!(is_closed(&simplified));
+        assert!(positions(&simplified).iter().all(|position| (position.length() - 1.0).abs() < 1e-5));
+    }
+
+    #[test]
+    fn keeps_open_borders() {
+        let plane = MeshBuilder::plane(Vec2::splat(2.0), UVec2::splat(8)).build("wood");
+        let border = |position: &Vec3| position.x.abs() == 1.0 || position.z.abs() == 1.0;
+        let border_before = positions(&plane).iter().filter(|position| border(position)).count();
+        let simplified = simplify(&plane, 0.05);
+        assert!(triangle_count(&simplified) < triangle_count(&plane));
+        assert_eq!(positions(&simplified).iter().filter(|position| border(position)).count(), border_before);
+    }
+
+    #[test]
+    fn leaves_meshes_alone_at_full_ratio() {
+        let cube = MeshBuilder::cube(Vec3::ONE, 2).build("wood");
+        assert!(simplify(&cube, 1.0) == cube);
+        let empty = MeshData::new(Vec::new(), "wood");
+        assert!(simplify(&empty, 0.5) == empty);
+    }
+}
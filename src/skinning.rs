@@ -0,0 +1,67 @@
+use std::sync::Arc;
+
+use bevy_ecs::prelude::*;
+use glam::*;
+
+use crate::{animation::{Skeleton, Transform}, backend::BufferUsage, shader::Shader, texture::BufferTexture};
+
+//Texture unit of the joint matrix buffer sampled by skinning.glsl
+pub const SKINNING_UNIT: u32 = 13;
+
+//Mesh whose vertices follow the joints of a skeleton, its JOINTS and WEIGHTS attributes index into joint_matrices
+#[derive(Component, Clone)]
+pub struct SkinnedMesh {
+    pub skeleton: Arc<Skeleton>,
+    pub joint_matrices: Vec<Mat4>,
+    //Where joint_matrices start in this frame's palette, None until it is first uploaded
+    palette_offset: Option<u32>,
+}
+
+impl SkinnedMesh {
+    //Starts out in the rest pose
+    pub fn new(skeleton: Arc<Skeleton>) -> SkinnedMesh {
+        let joint_matrices = skeleton.joint_matrices(&skeleton.rest_pose());
+        SkinnedMesh { skeleton, joint_matrices, palette_offset: None }
+    }
+    pub fn set_pose(&mut self, pose: &[Transform]) {
+        self.joint_matrices = self.skeleton.joint_matrices(pose);
+    }
+    //Per-instance value telling the vertex shader where the joint matrices are, negative for meshes that aren't skinned
+    pub fn instance_offset(skinned: Option<&SkinnedMesh>) -> f32 {
+        skinned.and_then(|skinned| skinned.palette_offset).map_or(-1.0, |offset| offset as f32)
+    }
+}
+
+//Joint matrices of every skinned mesh, packed into one texture buffer each frame so instances of the same mesh
+//in different poses still draw together. Every matrix takes four RGBA32F texels.
+#[derive(Resource)]
+pub struct SkinningPalette {
+    texture: BufferTexture,
+    matrices: Vec<Mat4>,
+}
+
+impl SkinningPalette {
+    pub fn new() -> SkinningPalette {
+        let texture = BufferTexture::new(gl::RGBA32F, "joint matrices");
+        texture.update(BufferUsage::Stream, &[Mat4::IDENTITY]);
+        SkinningPalette { texture, matrices: Vec::new() }
+    }
+    //Appends the mesh's matrices to the frame being built and remembers where they went
+    pub fn push(&mut self, skinned: &mut SkinnedMesh) {
+        skinned.palette_offset = Some(self.matrices.len() as u32);
+        self.matrices.extend_from_slice(&skinned.joint_matrices);
+    }
+    //Replaces last frame's matrices with the ones pushed since, the texture stays bound to SKINNING_UNIT
+    pub fn upload(&mut self) {
+        if !self.matrices.is_empty() {
+            self.texture.update(BufferUsage::Stream, &self.matrices);
+        }
+        self.texture.bind_to_unit(SKINNING_UNIT);
+        self.matrices.clear();
+    }
+}
+
+//Points the jointMatrices sampler of a shader that includes skinning.glsl at the palette
+pub fn bind_palette(shader: &Shader) {
+    shader.set_uniform_i32("jointMatrices".to_string(), &(SKINNING_UNIT as i32));
+}
@@ -0,0 +1,352 @@
+use std::{collections::HashMap, error::Error, fs, mem, os::raw::c_void, path::Path};
+
+use bevy_ecs::prelude::*;
+use gl::types::*;
+use glam::*;
+use serde::{Serialize, Deserialize};
+use simple_error::SimpleError;
+
+use crate::{components::Camera, gl_state, material::{MagnificationFilter, MinificationFilter, TextureOptions, WrapMode}, renderer::{GPUObject, VAO}, mesh, shader::Shader};
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct SpriteSettings {
+    //Texture pixels per world unit for sprites without an explicit size
+    pub pixels_per_unit: f32,
+}
+
+impl Default for SpriteSettings {
+    fn default() -> Self {
+        Self { pixels_per_unit: 16.0 }
+    }
+}
+
+//Textured quad in the entity's XY plane, drawn after the 3D scene in ascending order
+#[derive(Component, Clone)]
+pub struct Sprite {
+    //resources/atlases/{texture}.toml when it exists, otherwise a plain texture used whole
+    pub texture: String,
+    pub frame: AtlasFrame,
+    pub color: Vec4,
+    //World units, None sizes the sprite from its pixels and SpriteSettings::pixels_per_unit
+    pub size: Option<Vec2>,
+    //Pivot the sprite is placed and rotated around, (0.5, 0.5) is the center
+    pub anchor: Vec2,
+    pub flip_x: bool,
+    pub flip_y: bool,
+    //Lower orders are drawn first, equal orders are drawn furthest first
+    pub order: i32,
+}
+
+impl Sprite {
+    pub fn new(texture: &str) -> Sprite {
+        Sprite { texture: texture.to_string(), ..Default::default() }
+    }
+}
+
+impl Default for Sprite {
+    fn default() -> Self {
+        Self {
+            texture: String::new(),
+            frame: AtlasFrame::Index(0),
+            color: Vec4::ONE,
+            size: None,
+            anchor: Vec2::splat(0.5),
+            flip_x: false,
+            flip_y: false,
+            order: 0,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum AtlasFrame {
+    //Grid cells row by row from the top left, then the named regions in file order
+    Index(usize),
+    Name(String),
+}
+
+//Layout of resources/atlases/{name}.toml, pixel rectangles are x, y, width and height from the top left
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default)]
+struct AtlasFile {
+    texture: String,
+    #[serde(default = "sprite_texture_options")]
+    options: TextureOptions,
+    //Uniform sprite sheet grid, 0 when the atlas only has named regions
+    columns: u32,
+    rows: u32,
+    regions: Vec<(String, [u32; 4])>,
+}
+
+#[derive(Clone, Copy)]
+pub struct AtlasRegion {
+    pub uv_min: Vec2,
+    pub uv_max: Vec2,
+    //Pixels, used for sizing sprites
+    pub size: Vec2,
+}
+
+pub struct TextureAtlas {
+    pub texture: String,
+    frames: Vec<AtlasRegion>,
+    names: HashMap<String, usize>,
+}
+
+impl TextureAtlas {
+    //Loads the atlas description and its texture through `load_texture`, or treats a plain texture as a single frame atlas
+    pub fn load(name: &str, mut load_texture: impl FnMut(&str, &TextureOptions) -> Result<(i32, i32), Box<dyn Error>>) -> Result<TextureAtlas, Box<dyn Error>> {
+        let path = format!("resources/atlases/{}.toml", name);
+        if !Path::new(&path).exists() {
+            let (width, height) = load_texture(name, &sprite_texture_options())?;
+            return Ok(TextureAtlas {
+                texture: name.to_string(),
+                frames: vec![AtlasRegion { uv_min: Vec2::ZERO, uv_max: Vec2::ONE, size: Vec2::new(width as f32, height as f32) }],
+                names: HashMap::new(),
+            });
+        }
+
+        let file: AtlasFile = toml::from_str(&fs::read_to_string(&path)?)?;
+        let (width, height) = load_texture(&file.texture, &file.options)?;
+        let (width, height) = (width as u32, height as u32);
+        let mut rectangles = Vec::new();
+        if file.columns > 0 && file.rows > 0 {
+            let (cell_width, cell_height) = (width / file.columns, height / file.rows);
+            for row in 0..file.rows {
+                for column in 0..file.columns {
+                    rectangles.push([column * cell_width, row * cell_height, cell_width, cell_height]);
+                }
+            }
+        }
+        let mut names = HashMap::new();
+        for (region, rectangle) in &file.regions {
+            names.insert(region.clone(), rectangles.len());
+            rectangles.push(*rectangle);
+        }
+        if rectangles.is_empty() {
+            return Err(Box::new(SimpleError::new(format!("Atlas {} has no grid or regions", name))));
+        }
+
+        //Textures are stored bottom row first, so rows counted from the top are flipped
+        let texture_size = Vec2::new(width as f32, height as f32);
+        let frames = rectangles.iter().map(|[x, y, w, h]| {
+            let min = Vec2::new(*x as f32, height.saturating_sub(y + h) as f32);
+            let size = Vec2::new(*w as f32, *h as f32);
+            AtlasRegion { uv_min: min / texture_size, uv_max: (min + size) / texture_size, size }
+        }).collect();
+        Ok(TextureAtlas { texture: file.texture, frames, names })
+    }
+
+    pub fn frame(&self, frame: &AtlasFrame) -> Option<&AtlasRegion> {
+        match frame {
+            AtlasFrame::Index(index) => self.frames.get(*index),
+            AtlasFrame::Name(name) => self.names.get(name).and_then(|index| self.frames.get(*index)),
+        }
+    }
+}
+
+//Pixel art stays crisp, sheets don't bleed into neighbouring cells at the edges
+fn sprite_texture_options() -> TextureOptions {
+    TextureOptions {
+        mag_filter: MagnificationFilter::Nearest,
+        min_filter: MinificationFilter::Nearest,
+        wrap: WrapMode::ClampToEdge,
+        ..Default::default()
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct SpriteVertex {
+    position: [f32; 3],
+    color: [f32; 4],
+    uv: [f32; 2],
+}
+
+struct QueuedSprite {
+    texture: String,
+    order: i32,
+    depth: f32,
+    vertices: [SpriteVertex; 4],
+}
+
+//Batches every sprite of a view into one vertex buffer, consecutive sprites sharing a texture are one draw call
+#[derive(Resource)]
+pub struct SpriteRenderer {
+    shader: Shader,
+    vao: VAO,
+    vertex_buffer: u32,
+    index_buffer: u32,
+    //Quads the index buffer currently covers
+    index_capacity: usize,
+    queue: Vec<QueuedSprite>,
+}
+
+impl SpriteRenderer {
+    pub fn new() -> Result<SpriteRenderer, Box<dyn Error>> {
+        let vao = VAO::new();
+        let (mut vertex_buffer, mut index_buffer) = (0, 0);
+        unsafe {
+            gl::GenBuffers(1, &mut vertex_buffer);
+            gl::GenBuffers(1, &mut index_buffer);
+            vao.bind();
+            gl_state::bind_buffer(gl::ARRAY_BUFFER, vertex_buffer);
+            let stride = mem::size_of::<SpriteVertex>() as GLsizei;
+            let attributes = [(mesh::POSITION_LOCATION, 3, 0), (mesh::COLOR_LOCATION, 4, 3), (mesh::TEXCOORD_LOCATION, 2, 7)];
+            for (location, size, offset) in attributes {
+                gl::VertexAttribPointer(location, size, gl::FLOAT, gl::FALSE, stride, (offset * mem::size_of::<f32>()) as *const c_void);
+                gl::EnableVertexAttribArray(location);
+            }
+            gl_state::bind_buffer(gl::ELEMENT_ARRAY_BUFFER, index_buffer);
+            vao.label("sprites");
+            gl_state::label(gl::BUFFER, vertex_buffer, "sprite vertices");
+            gl_state::label(gl::BUFFER, index_buffer, "sprite indices");
+            vao.unbind();
+            gl_state::bind_buffer(gl::ARRAY_BUFFER, 0);
+        }
+        Ok(SpriteRenderer {
+            shader: Shader::new("sprite")?,
+            vao,
+            vertex_buffer,
+            index_buffer,
+            index_capacity: 0,
+            queue: Vec::new(),
+        })
+    }
+
+    //Sprite corners are moved into view space here, pixel perfect views snap the pivot to the pixel grid first
+    pub fn push(&mut self, sprite: &Sprite, atlas: &TextureAtlas, model: &Mat4, view: &Mat4, snap: Option<f32>, pixels_per_unit: f32) {
+        let region = match atlas.frame(&sprite.frame) {
+            Some(region) => region,
+            None => return,
+        };
+        let size = sprite.size.unwrap_or(region.size / pixels_per_unit);
+        let model_view = *view * *model;
+        let pivot = model_view.transform_point3(Vec3::ZERO);
+        let offset = snap.map_or(Vec3::ZERO, |units| {
+            let snapped = (pivot.truncate() / units).round() * units;
+            (snapped - pivot.truncate()).extend(0.0)
+        });
+
+        let color = sprite.color.to_array();
+        let corners = [Vec2::new(0.0, 0.0), Vec2::new(1.0, 0.0), Vec2::new(1.0, 1.0), Vec2::new(0.0, 1.0)];
+        let vertices = corners.map(|corner| {
+            let local = ((corner - sprite.anchor) * size).extend(0.0);
+            let u = if sprite.flip_x { 1.0 - corner.x } else { corner.x };
+            let v = if sprite.flip_y { 1.0 - corner.y } else { corner.y };
+            let uv = region.uv_min + (region.uv_max - region.uv_min) * Vec2::new(u, v);
+            SpriteVertex { position: (model_view.transform_point3(local) + offset).to_array(), color, uv: uv.to_array() }
+        });
+        self.queue.push(QueuedSprite { texture: atlas.texture.clone(), order: sprite.order, depth: -pivot.z, vertices });
+    }
+
+    //Draws and clears the queue, blended over the bound target and depth tested against the scene without writing depth
+    pub fn flush(&mut self, camera: &Camera, textures: impl Fn(&str) -> Option<u32>, is_wireframe: bool) {
+        if self.queue.is_empty() {
+            return;
+        }
+        let mut queue = mem::take(&mut self.queue);
+        queue.sort_by(|a, b| a.order.cmp(&b.order).then(b.depth.total_cmp(&a.depth)));
+
+        let vertices: Vec<SpriteVertex> = queue.iter().flat_map(|sprite| sprite.vertices).collect();
+        self.reserve_indices(queue.len());
+        unsafe {
+            gl_state::bind_buffer(gl::ARRAY_BUFFER, self.vertex_buffer);
+            gl::BufferData(gl::ARRAY_BUFFER, (vertices.len() * mem::size_of::<SpriteVertex>()) as GLsizeiptr, vertices.as_ptr() as *const c_void, gl::STREAM_DRAW);
+            gl_state::bind_buffer(gl::ARRAY_BUFFER, 0);
+
+            gl::PolygonMode(gl::FRONT_AND_BACK, gl::FILL);
+            gl_state::enable(gl::BLEND);
+            gl_state::blend_func(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+            gl_state::depth_mask(gl::FALSE);
+            gl_state::depth_func(gl::LEQUAL);
+        }
+        let shader = &self.shader;
+        shader.bind();
+        shader.set_uniform_4x4f("projection".to_string(), None, &camera.projection);
+        shader.set_uniform_i32("sprite".to_string(), &0);
+        self.vao.bind();
+        let mut start = 0;
+        while start < queue.len() {
+            let texture = &queue[start].texture;
+            let end = queue[start..].iter().position(|sprite| &sprite.texture != texture).map_or(queue.len(), |count| start + count);
+            if let Some(handle) = textures(texture) {
+                unsafe {
+                    gl_state::bind_texture(gl::TEXTURE_2D, handle);
+                    gl::DrawElements(gl::TRIANGLES, ((end - start) * 6) as i32, gl::UNSIGNED_INT, (start * 6 * mem::size_of::<u32>()) as *const c_void);
+                }
+            }
+            start = end;
+        }
+        self.vao.unbind();
+        shader.unbind();
+        unsafe {
+            gl_state::bind_texture(gl::TEXTURE_2D, 0);
+            gl_state::disable(gl::BLEND);
+            gl_state::depth_mask(gl::TRUE);
+            gl_state::depth_func(gl::LESS);
+        }
+        crate::renderer::update_wireframe(&is_wireframe);
+
+        //Keeps the allocation for the next view
+        queue.clear();
+        self.queue = queue;
+    }
+
+    fn reserve_indices(&mut self, quads: usize) {
+        if quads <= self.index_capacity {
+            return;
+        }
+        let capacity = quads.next_power_of_two();
+        let indices: Vec<u32> = (0..capacity as u32).flat_map(|quad| [0, 1, 2, 0, 2, 3].map(|i| quad * 4 + i)).collect();
+        unsafe {
+            self.vao.bind();
+            gl_state::bind_buffer(gl::ELEMENT_ARRAY_BUFFER, self.index_buffer);
+            gl::BufferData(gl::ELEMENT_ARRAY_BUFFER, (indices.len() * mem::size_of::<u32>()) as GLsizeiptr, indices.as_ptr() as *const c_void, gl::STATIC_DRAW);
+            self.vao.unbind();
+        }
+        self.index_capacity = capacity;
+    }
+}
+
+impl Drop for SpriteRenderer {
+    fn drop(&mut self) {
+        unsafe {
+            gl_state::delete_buffers(1, &self.vertex_buffer);
+            gl_state::delete_buffers(1, &self.index_buffer);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_grid_cells_and_named_regions() {
+        let mut loaded = Vec::new();
+        let atlas = TextureAtlas::load("planks", |name, options| {
+            loaded.push((name.to_string(), options.mag_filter));
+            Ok((16, 16))
+        }).unwrap();
+        assert_eq!(loaded, [("planks_oak".to_string(), MagnificationFilter::Nearest)]);
+        assert_eq!(atlas.texture, "planks_oak");
+
+        //Top right cell, rows count from the top while UVs start at the bottom
+        let cell = atlas.frame(&AtlasFrame::Index(1)).unwrap();
+        assert_eq!((cell.uv_min, cell.uv_max, cell.size), (Vec2::splat(0.5), Vec2::ONE, Vec2::splat(8.0)));
+        let center = atlas.frame(&AtlasFrame::Name("center".to_string())).unwrap();
+        assert_eq!((center.uv_min, center.uv_max), (Vec2::splat(0.25), Vec2::splat(0.75)));
+        assert!(atlas.frame(&AtlasFrame::Index(4)).is_some());
+        assert!(atlas.frame(&AtlasFrame::Index(5)).is_none());
+        assert!(atlas.frame(&AtlasFrame::Name("missing".to_string())).is_none());
+    }
+
+    #[test]
+    fn uses_plain_textures_whole() {
+        let atlas = TextureAtlas::load("planks_oak", |_, _| Ok((20, 10))).unwrap();
+        let frame = atlas.frame(&AtlasFrame::Index(0)).unwrap();
+        assert_eq!((frame.uv_min, frame.uv_max, frame.size), (Vec2::ZERO, Vec2::ONE, Vec2::new(20.0, 10.0)));
+        assert!(TextureAtlas::load("planks_oak", |_, _| Err("missing".into())).is_err());
+    }
+}
@@ -0,0 +1,393 @@
+use std::{error::Error, mem};
+
+use bevy_ecs::prelude::*;
+use glam::*;
+use serde::{Serialize, Deserialize};
+
+use crate::{framebuffer::{self, Framebuffer}, gl_state, renderer::{FullscreenTriangle, GPUObject}, shader::Shader};
+
+#[derive(Serialize, Deserialize, Default, Clone, Copy, PartialEq)]
+pub enum SsrQuality {
+    Off,
+    Low,
+    #[default]
+    Medium,
+    High,
+}
+
+pub struct SsrParameters {
+    pub max_steps: i32,
+    pub resolution_scale: f32,
+    pub max_distance: f32,
+    pub thickness: f32,
+    pub edge_fade: f32,
+    pub max_roughness: f32,
+}
+
+impl SsrQuality {
+    pub fn parameters(&self) -> SsrParameters {
+        match self {
+            SsrQuality::Off | SsrQuality::Low => SsrParameters {
+                max_steps: 24,
+                resolution_scale: 0.5,
+                max_distance: 8.0,
+                thickness: 0.5,
+                edge_fade: 0.15,
+                max_roughness: 0.5,
+            },
+            SsrQuality::Medium => SsrParameters {
+                max_steps: 48,
+                resolution_scale: 0.75,
+                max_distance: 16.0,
+                thickness: 0.35,
+                edge_fade: 0.1,
+                max_roughness: 0.7,
+            },
+            SsrQuality::High => SsrParameters {
+                max_steps: 96,
+                resolution_scale: 1.0,
+                max_distance: 32.0,
+                thickness: 0.25,
+                edge_fade: 0.075,
+                max_roughness: 0.85,
+            },
+        }
+    }
+}
+
+//Depth pyramid where every texel holds the closest depth of the 2x2 texels below it
+pub struct HiZBuffer {
+    texture: u32,
+    framebuffer: u32,
+    width: i32,
+    height: i32,
+    levels: i32,
+}
+
+impl HiZBuffer {
+    pub fn new(width: i32, height: i32) -> HiZBuffer {
+        let levels = mip_levels(width, height);
+        let mut hiz = HiZBuffer { texture: 0, framebuffer: 0, width, height, levels };
+        unsafe {
+            gl::GenTextures(1, &mut hiz.texture);
+            gl_state::bind_texture(gl::TEXTURE_2D, hiz.texture);
+            gl::TexStorage2D(gl::TEXTURE_2D, levels, gl::R32F, width, height);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST_MIPMAP_NEAREST as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as i32);
+            gl_state::bind_texture(gl::TEXTURE_2D, 0);
+            gl::GenFramebuffers(1, &mut hiz.framebuffer);
+        }
+        gl_state::label(gl::TEXTURE, hiz.texture, "hi-z");
+        hiz
+    }
+
+    pub fn texture(&self) -> u32 {
+        self.texture
+    }
+    pub fn levels(&self) -> i32 {
+        self.levels
+    }
+
+    //Level 0 is a copy of the depth texture, every level after reduces the previous one
+    pub fn build(&self, depth_texture: u32, shader: &Shader, triangle: &FullscreenTriangle) {
+        shader.bind();
+        shader.set_uniform_i32("depthTexture".to_string(), &0);
+        shader.set_uniform_i32("source".to_string(), &1);
+        unsafe {
+            gl_state::bind_framebuffer(gl::FRAMEBUFFER, self.framebuffer);
+        }
+        framebuffer::bind_attachment(depth_texture, 0);
+
+        for level in 0..self.levels {
+            let (width, height) = self.level_size(level);
+            let (source_width, source_height) = self.level_size((level - 1).max(0));
+            unsafe {
+                //Only the source level is visible to the sampler, so reading and writing never overlap
+                gl_state::active_texture(gl::TEXTURE1);
+                if level == 0 {
+                    gl_state::bind_texture(gl::TEXTURE_2D, 0);
+                } else {
+                    gl_state::bind_texture(gl::TEXTURE_2D, self.texture);
+                    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_BASE_LEVEL, level - 1);
+                    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAX_LEVEL, level - 1);
+                }
+                gl_state::active_texture(gl::TEXTURE0);
+
+                gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::COLOR_ATTACHMENT0, gl::TEXTURE_2D, self.texture, level);
+                gl::Viewport(0, 0, width, height);
+            }
+            shader.set_uniform_bool("copyDepth".to_string(), &(level == 0));
+            shader.set_uniform_2i("sourceSize".to_string(), &IVec2::new(source_width, source_height));
+            triangle.render();
+        }
+
+        unsafe {
+            gl_state::active_texture(gl::TEXTURE1);
+            gl_state::bind_texture(gl::TEXTURE_2D, self.texture);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_BASE_LEVEL, 0);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAX_LEVEL, self.levels - 1);
+            gl_state::active_texture(gl::TEXTURE0);
+            gl_state::bind_framebuffer(gl::FRAMEBUFFER, 0);
+        }
+        framebuffer::unbind_attachment(1);
+        framebuffer::unbind_attachment(0);
+        shader.unbind();
+    }
+
+    fn level_size(&self, level: i32) -> (i32, i32) {
+        level_size(self.width, self.height, level)
+    }
+}
+
+//Levels of a full mip chain down to 1x1
+fn mip_levels(width: i32, height: i32) -> i32 {
+    32 - (width.max(height).max(1) as u32).leading_zeros() as i32
+}
+
+//Odd sizes round down, no level is ever empty
+fn level_size(width: i32, height: i32, level: i32) -> (i32, i32) {
+    ((width >> level).max(1), (height >> level).max(1))
+}
+
+//Reflections are traced at the preset's fraction of the view
+fn reflection_size(width: u32, height: u32, quality: SsrQuality) -> (i32, i32) {
+    let scale = quality.parameters().resolution_scale;
+    (((width.max(1) as f32 * scale) as i32).max(1), ((height.max(1) as f32 * scale) as i32).max(1))
+}
+
+impl Drop for HiZBuffer {
+    fn drop(&mut self) {
+        unsafe {
+            gl_state::delete_framebuffers(1, &self.framebuffer);
+            gl_state::delete_textures(1, &self.texture);
+        }
+    }
+}
+
+//Render graph attachments and framebuffers, written by the prepass and read back when resolving
+pub const PREPASS: &str = "ssr_prepass";
+//View space normal in rgb, material roughness in alpha
+pub const NORMALS: &str = "ssr_normals";
+pub const PREPASS_DEPTH: &str = "ssr_prepass_depth";
+//Scene color goes to an offscreen target so the trace can read it back
+pub const SCENE: &str = "ssr_scene";
+pub const SCENE_COLOR: &str = "ssr_scene_color";
+pub const SCENE_DEPTH: &str = "ssr_scene_depth";
+
+struct SsrTargets {
+    width: u32,
+    height: u32,
+    quality: SsrQuality,
+    reflections: Framebuffer,
+    reflection_levels: i32,
+    hiz: HiZBuffer,
+}
+
+impl SsrTargets {
+    fn new(width: u32, height: u32, quality: SsrQuality) -> SsrTargets {
+        let (w, h) = (width.max(1) as i32, height.max(1) as i32);
+
+        let (rw, rh) = reflection_size(width, height, quality);
+        let mut reflections = Framebuffer::new(rw, rh);
+        reflections.add_color_attachment(gl::RGBA16F, gl::RGBA, gl::FLOAT, gl::LINEAR);
+        reflections.is_complete();
+        reflections.label("reflections");
+        let reflection_levels = mip_levels(rw, rh);
+        unsafe {
+            gl_state::bind_texture(gl::TEXTURE_2D, reflections.color_attachment(0).unwrap());
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR_MIPMAP_LINEAR as i32);
+            gl::GenerateMipmap(gl::TEXTURE_2D);
+            gl_state::bind_texture(gl::TEXTURE_2D, 0);
+        }
+
+        SsrTargets { width, height, quality, reflections, reflection_levels, hiz: HiZBuffer::new(w, h) }
+    }
+}
+
+//Targets of recently used view sizes are kept, so split screen views don't reallocate every frame
+const CACHED_TARGETS: usize = 4;
+
+#[derive(Resource)]
+pub struct ScreenSpaceReflections {
+    targets: SsrTargets,
+    cached_targets: Vec<SsrTargets>,
+    triangle: FullscreenTriangle,
+    prepass_shader: Shader,
+    hiz_shader: Shader,
+    trace_shader: Shader,
+    composite_shader: Shader,
+    //Target and viewport bound before the prepass, reflections are composited back into them
+    previous_framebuffer: i32,
+    previous_viewport: [i32; 4],
+}
+
+impl ScreenSpaceReflections {
+    pub fn new(width: u32, height: u32, quality: SsrQuality) -> Result<ScreenSpaceReflections, Box<dyn Error>> {
+        Ok(ScreenSpaceReflections {
+            targets: SsrTargets::new(width, height, quality),
+            cached_targets: Vec::new(),
+            triangle: FullscreenTriangle::new(),
+            prepass_shader: Shader::new("ssr_prepass")?,
+            hiz_shader: Shader::from_files("fullscreen", "hiz")?,
+            trace_shader: Shader::from_files("fullscreen", "ssr_trace")?,
+            composite_shader: Shader::from_files("fullscreen", "ssr_composite")?,
+            previous_framebuffer: 0,
+            previous_viewport: [0; 4],
+        })
+    }
+
+    //Recreates every target when the view size or the quality preset changes
+    pub fn resize(&mut self, width: u32, height: u32, quality: SsrQuality) {
+        let matches = |targets: &SsrTargets| targets.width == width && targets.height == height && targets.quality == quality;
+        if matches(&self.targets) {
+            return;
+        }
+        let targets = match self.cached_targets.iter().position(matches) {
+            Some(index) => self.cached_targets.remove(index),
+            None => SsrTargets::new(width, height, quality),
+        };
+        self.cached_targets.push(mem::replace(&mut self.targets, targets));
+        if self.cached_targets.len() > CACHED_TARGETS {
+            self.cached_targets.remove(0);
+        }
+    }
+
+    pub fn prepass_shader(&self) -> &Shader {
+        &self.prepass_shader
+    }
+
+    pub fn begin_prepass(&mut self, prepass: &Framebuffer) {
+        unsafe {
+            gl::GetIntegerv(gl::DRAW_FRAMEBUFFER_BINDING, &mut self.previous_framebuffer);
+            gl::GetIntegerv(gl::VIEWPORT, self.previous_viewport.as_mut_ptr());
+        }
+        prepass.bind();
+        unsafe {
+            gl::Viewport(0, 0, prepass.width(), prepass.height());
+            gl::ClearBufferfv(gl::COLOR, 0, [0.0f32; 4].as_ptr());
+            gl::Clear(gl::DEPTH_BUFFER_BIT);
+        }
+        self.prepass_shader.bind();
+    }
+
+    pub fn end_prepass(&self, prepass: &Framebuffer) {
+        self.prepass_shader.unbind();
+        prepass.unbind();
+    }
+
+    pub fn begin_scene(&self, scene: &Framebuffer) {
+        scene.bind();
+        unsafe {
+            gl::Viewport(0, 0, scene.width(), scene.height());
+            gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
+        }
+    }
+
+    pub fn resolve(&self, prepass: &Framebuffer, scene: &Framebuffer, projection: &Mat4, quality: SsrQuality, is_wireframe: bool) {
+        let targets = &self.targets;
+        let parameters = quality.parameters();
+        scene.unbind();
+        unsafe {
+            gl::PolygonMode(gl::FRONT_AND_BACK, gl::FILL);
+            gl_state::disable(gl::DEPTH_TEST);
+        }
+
+        targets.hiz.build(prepass.depth_attachment().unwrap(), &self.hiz_shader, &self.triangle);
+
+        //Trace
+        targets.reflections.bind();
+        unsafe {
+            gl::Viewport(0, 0, targets.reflections.width(), targets.reflections.height());
+            gl::ClearBufferfv(gl::COLOR, 0, [0.0f32; 4].as_ptr());
+        }
+        let shader = &self.trace_shader;
+        shader.bind();
+        shader.set_uniform_i32("hiz".to_string(), &0);
+        shader.set_uniform_i32("normals".to_string(), &1);
+        shader.set_uniform_i32("sceneColor".to_string(), &2);
+        shader.set_uniform_i32("hizLevels".to_string(), &targets.hiz.levels());
+        shader.set_uniform_4x4f("projection".to_string(), None, projection);
+        shader.set_uniform_4x4f("invProjection".to_string(), None, &projection.inverse());
+        shader.set_uniform_i32("maxSteps".to_string(), &parameters.max_steps);
+        shader.set_uniform_1f("maxDistance".to_string(), &parameters.max_distance);
+        shader.set_uniform_1f("thickness".to_string(), &parameters.thickness);
+        shader.set_uniform_1f("edgeFade".to_string(), &parameters.edge_fade);
+        shader.set_uniform_1f("maxRoughness".to_string(), &parameters.max_roughness);
+        framebuffer::bind_attachment(targets.hiz.texture(), 0);
+        framebuffer::bind_attachment(prepass.color_attachment(0).unwrap(), 1);
+        framebuffer::bind_attachment(scene.color_attachment(0).unwrap(), 2);
+        self.triangle.render();
+        shader.unbind();
+        targets.reflections.unbind();
+
+        //Rough surfaces read blurrier mips of the reflections
+        unsafe {
+            gl_state::bind_texture(gl::TEXTURE_2D, targets.reflections.color_attachment(0).unwrap());
+            gl::GenerateMipmap(gl::TEXTURE_2D);
+            gl_state::bind_texture(gl::TEXTURE_2D, 0);
+        }
+
+        //Composite into the previous target, depth is carried over so later passes still depth test
+        unsafe {
+            gl_state::bind_framebuffer(gl::FRAMEBUFFER, self.previous_framebuffer as u32);
+            let viewport = self.previous_viewport;
+            gl::Viewport(viewport[0], viewport[1], viewport[2], viewport[3]);
+            gl_state::enable(gl::DEPTH_TEST);
+            gl_state::depth_func(gl::ALWAYS);
+        }
+        let shader = &self.composite_shader;
+        shader.bind();
+        shader.set_uniform_i32("sceneColor".to_string(), &0);
+        shader.set_uniform_i32("sceneDepth".to_string(), &1);
+        shader.set_uniform_i32("normals".to_string(), &2);
+        shader.set_uniform_i32("reflections".to_string(), &3);
+        shader.set_uniform_4x4f("invProjection".to_string(), None, &projection.inverse());
+        shader.set_uniform_1f("maxLod".to_string(), &((targets.reflection_levels - 1) as f32));
+        shader.set_uniform_1f("maxRoughness".to_string(), &parameters.max_roughness);
+        framebuffer::bind_attachment(scene.color_attachment(0).unwrap(), 0);
+        framebuffer::bind_attachment(scene.depth_attachment().unwrap(), 1);
+        framebuffer::bind_attachment(prepass.color_attachment(0).unwrap(), 2);
+        framebuffer::bind_attachment(targets.reflections.color_attachment(0).unwrap(), 3);
+        self.triangle.render();
+        for unit in 0..4 {
+            framebuffer::unbind_attachment(unit);
+        }
+        shader.unbind();
+
+        unsafe {
+            gl_state::depth_func(gl::LESS);
+        }
+        crate::renderer::update_wireframe(&is_wireframe);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sizes_hiz_mip_chains() {
+        assert_eq!(mip_levels(1, 1), 1);
+        assert_eq!(mip_levels(0, 0), 1);
+        assert_eq!(mip_levels(1024, 1024), 11);
+        assert_eq!(mip_levels(1280, 720), 11);
+        assert_eq!(level_size(1280, 720, 0), (1280, 720));
+        assert_eq!(level_size(1280, 720, 3), (160, 90));
+        //The shorter side stops at one texel while the longer keeps halving
+        assert_eq!(level_size(1280, 720, 10), (1, 1));
+        assert_eq!(level_size(1280, 3, 4), (80, 1));
+    }
+
+    #[test]
+    fn scales_reflections_by_quality() {
+        assert_eq!(reflection_size(1280, 720, SsrQuality::High), (1280, 720));
+        assert_eq!(reflection_size(1280, 720, SsrQuality::Medium), (960, 540));
+        assert_eq!(reflection_size(1280, 720, SsrQuality::Low), (640, 360));
+        assert_eq!(reflection_size(0, 1, SsrQuality::Low), (1, 1));
+        let presets = [SsrQuality::Low, SsrQuality::Medium, SsrQuality::High].map(|quality| quality.parameters());
+        assert!(presets.windows(2).all(|pair| pair[0].max_steps < pair[1].max_steps && pair[0].max_distance < pair[1].max_distance));
+    }
+}
+
@@ -1,10 +1,13 @@
-use crate::{components::*, resources::*, settings::Settings, mesh::Mesh, renderer::GPUObject, window::Window};
-use bevy_ecs::prelude::*;
+use crate::{components::*, resources::*, settings::Settings, shader::Shader, mesh::Mesh, renderer::GPUObject, window::Window, lighting::SceneLights, shadow::*, ssr::{self, ScreenSpaceReflections, SsrQuality}, lightmap::*, deferred::{self, DeferredRenderer, RenderPath}, postprocess::{self, PostProcess, PostProcessing}, render_graph::RenderTargets, culling::{CullingStats, Frustum}, render_queue::{Batch, RenderQueue}, renderer::Instance, lod::{self, MeshLod}, material::{BlendMode, Material, MATERIAL_TEXTURE_UNIT, MAX_MATERIAL_TEXTURES}, oit::{self, OrderIndependentTransparency}, environment::{self, Skybox}, framebuffer, view::{self, RenderTextures, RenderView}, sprite::{Sprite, SpriteRenderer}, debug_draw::{DebugDraw, DebugOptions, DebugRenderer}, text::{Font, GlyphAtlas, Text, TextLayout, TextRenderer, TextSpace}, particles::{ParticleEmitter, ParticleRenderer, RestartWhenFinished}, profiler::{Profiler, ProfilerOverlay}, terrain::{Terrain, TerrainChunk}, animation::{AnimationClips, AnimationPlayer}, animation_graph::AnimationGraphPlayer, skinning::{self, SkinnedMesh, SkinningPalette}, morph::{self, MorphPalette, MorphWeights}, gl_state};
+use std::sync::Arc;
+use bevy_ecs::{prelude::*, system::SystemParam};
+use glam::{EulerRot, Mat4, Quat, Vec2, Vec3, Vec4, Vec4Swizzles};
 use winit::{keyboard::KeyCode, window::CursorGrabMode};
 use winit::event::MouseButton;
 
 const CAMERA_SPEED: f32 = 1.0; // adjust accordingly
 const SENSITIVITY: f32 = 0.1;
+const ANIMATION_STEP: f32 = 1.0 / 30.0; //Seconds a paused clip moves per F7 or F8 press
 
 pub fn move_camera(
     mut query: Query<(&mut Position, &mut Rotation, &mut Camera)>,
@@ -13,6 +16,11 @@ pub fn move_camera(
     time: Res<Time>,
 ) {
     for (mut position, mut direction, mut camera) in &mut query {
+        //Orthographic cameras are 2D and HUD views, they keep where they were placed
+        if camera.is_orthographic() {
+            camera.view = glam::Mat4::look_at_rh(position.d, position.d + camera.front, camera.up);
+            continue;
+        }
         if input.mouse_just_pressed(MouseButton::Left) {
             window.set_cursor_grab(CursorGrabMode::Confined);
         }
@@ -75,30 +83,1009 @@ pub fn update_wireframe(input: Res<Input>, mut settings: ResMut<Settings>) {
     }
 }
 
+pub fn update_debug_draw(input: Res<Input>, mut settings: ResMut<Settings>) {
+    if input.keyboard_just_pressed(KeyCode::F9) {
+        settings.debug_draw = !settings.debug_draw;
+    }
+}
+
+//F10 shows the profiler overlay, F11 writes the recorded frames as a Chrome trace
+pub fn update_profiler(input: Res<Input>, profiler: Res<Profiler>, mut settings: ResMut<Settings>) {
+    if input.keyboard_just_pressed(KeyCode::F10) {
+        settings.profiler.overlay = !settings.profiler.overlay;
+    }
+    if input.keyboard_just_pressed(KeyCode::F11) {
+        match profiler.export_chrome_trace(&settings.profiler.trace_path) {
+            Ok(_) => println!("Wrote {} frames to {}", profiler.frames().len(), settings.profiler.trace_path),
+            Err(error) => println!("Unable to write profile to {}: {}", settings.profiler.trace_path, error),
+        }
+    }
+}
+
+pub fn render_profiler_overlay(
+    profiler: Res<Profiler>,
+    mut overlay: ResMut<ProfilerOverlay>,
+    mut text: ResMut<TextRenderer>,
+    mut atlas: ResMut<GlyphAtlas>,
+    mut assets: ResMut<AssetPool>,
+    window: Res<Window>,
+    settings: Res<Settings>,
+) {
+    if !settings.profiler.overlay {
+        return;
+    }
+    let font_name = &settings.profiler.overlay_font;
+    let font = match assets.load_font(font_name) {
+        Ok(font) => Some(font),
+        Err(error) => {
+            atlas.report_missing(font_name, error.as_ref());
+            None
+        },
+    };
+    let size = window.size();
+    overlay.render(&profiler, (size.width, size.height), &mut text, &mut atlas, font.as_deref().map(|font| (font_name.as_str(), font)), &settings);
+}
+
+//Light ranges, directions and the shadow views planned for this frame
+pub fn draw_debug_lights(
+    query_directional: Query<&DirectionalLight>,
+    query_point: Query<(&Position, &PointLight)>,
+    query_spot: Query<(&Position, &SpotLight)>,
+    shadows: Res<ShadowAtlas>,
+    mut debug: ResMut<DebugDraw>,
+    settings: Res<Settings>,
+) {
+    if !settings.debug_draw {
+        return;
+    }
+    let options = DebugOptions::default();
+    debug.grid(Vec3::ZERO, 10.0, 10, Vec4::new(0.5, 0.5, 0.5, 0.5), options);
+    for light in &query_directional {
+        debug.arrow(Vec3::Y * 3.0, Vec3::Y * 3.0 + light.direction.normalize(), light.color.extend(1.0), options.on_top());
+    }
+    for (position, light) in &query_point {
+        debug.sphere(position.d, light.range, light.color.extend(0.5), options);
+        debug.text(position.d + Vec3::Y * 0.2, "point", 0.1, light.color.extend(1.0), options.on_top());
+    }
+    for (position, light) in &query_spot {
+        debug.arrow(position.d, position.d + light.direction.normalize() * light.range, light.color.extend(0.5), options);
+        debug.text(position.d + Vec3::Y * 0.2, "spot", 0.1, light.color.extend(1.0), options.on_top());
+    }
+    for view in shadows.views() {
+        debug.frustum(&view.view_projection, Vec4::new(1.0, 0.5, 0.0, 0.5), options);
+    }
+}
+
+type PlacedMesh<'a> = (&'a Mesh, Option<&'a Position>, Option<&'a Rotation>, Option<&'a Scale>);
+
+//World space boxes around every mesh, the volumes frustum culling tests, and the block of every world text
+pub fn draw_debug_bounds(query_mesh: Query<PlacedMesh>, query_text: Query<SceneText>, mut debug: ResMut<DebugDraw>, settings: Res<Settings>) {
+    if !settings.debug_draw {
+        return;
+    }
+    for (mesh, position, rotation, scale) in &query_mesh {
+        let bounds = mesh.bounds().transformed(&model_matrix(position, rotation, scale));
+        debug.aabb(&bounds, Vec4::new(0.2, 1.0, 0.2, 0.5), DebugOptions::default());
+    }
+    for (text, layout, position, rotation, scale, _) in query_text.iter().filter(|(text, ..)| text.space == TextSpace::World) {
+        let model = model_matrix(position, rotation, scale);
+        let min = -text.anchor * layout.size;
+        let corners = [min, min + layout.size * Vec2::X, min + layout.size, min + layout.size * Vec2::Y]
+            .map(|corner| model.transform_point3(corner.extend(0.0)));
+        for (index, corner) in corners.iter().enumerate() {
+            debug.line(*corner, corners[(index + 1) % 4], Vec4::new(1.0, 1.0, 0.2, 0.5), DebugOptions::default());
+        }
+    }
+}
+
+pub fn update_render_path(input: Res<Input>, mut settings: ResMut<Settings>) {
+    if input.keyboard_just_pressed(KeyCode::F6) {
+        settings.render_path = match settings.render_path {
+            RenderPath::Forward => RenderPath::Deferred,
+            RenderPath::Deferred => RenderPath::Forward,
+        };
+    }
+}
+
+pub fn update_post_processing(input: Res<Input>, mut query: Query<&mut PostProcess>) {
+    for mut post_process in &mut query {
+        if input.keyboard_just_pressed(KeyCode::F7) {
+            post_process.tone_mapping = post_process.tone_mapping.next();
+        }
+        if input.keyboard_just_pressed(KeyCode::F8) {
+            post_process.bloom.enabled = !post_process.bloom.enabled;
+        }
+    }
+}
+
+type LodMesh<'a> = (&'a mut MeshLod, &'a mut Mesh, Option<&'a Position>, Option<&'a Rotation>, Option<&'a Scale>);
+
+//Levels are picked for the main view, the minimap and monitor cameras show whatever it selected
+pub fn update_lods(
+    mut query: Query<LodMesh>,
+    query_camera: Query<(Entity, &Camera)>,
+    time: Res<Time>,
+    settings: Res<Settings>,
+) {
+    let camera = match view::main_camera(query_camera.iter()) {
+        Some(camera) => camera,
+        None => return,
+    };
+    let eye = eye(camera);
+    for (mut lod, mut mesh, position, rotation, scale) in &mut query {
+        let (center, radius) = mesh.bounds().world_sphere(&model_matrix(position, rotation, scale));
+        let screen_size = lod::screen_size(center, radius, eye, &camera.projection);
+        if lod.update(screen_size, time.delta_seconds(), settings.lod.fade_duration) {
+            *mesh = lod.current().clone();
+        }
+    }
+}
+
+//Every camera follows the shape of its own viewport, so split screen halves aren't stretched
 pub fn update_projection(mut query: Query<&mut Camera>, window: Res<Window>, settings: Res<Settings>) {
-    if window.is_changed() || settings.is_changed() {
-        for mut camera in &mut query {
-            camera.set_projection(settings.fov, window.aspect_ratio(), 0.01, 100.0);
+    let size = window.size();
+    for mut camera in &mut query {
+        let (width, height) = match &camera.target {
+            RenderTarget::Window => (size.width, size.height),
+            RenderTarget::Texture { width, height, .. } => (*width, *height),
+        };
+        let [_, _, width, height] = camera.viewport.pixels(width, height);
+        let (width, height) = (width.max(1) as f32, height.max(1) as f32);
+        match camera.projection_mode {
+            ProjectionMode::Perspective => camera.set_projection(settings.fov, width / height, 0.01, 100.0),
+            ProjectionMode::Orthographic { scaling, .. } => camera.set_orthographic(scaling, width, height, 0.01, 100.0),
+        }
+    }
+}
+
+type CasterMesh<'a> = (&'a Mesh, Option<&'a Position>, Option<&'a Rotation>, Option<&'a Scale>, Option<&'a SkinnedMesh>, Option<&'a MorphWeights>);
+
+//Lights that can cast shadows, each asks for a tile as large as it appears from the camera
+#[derive(SystemParam)]
+pub struct ShadowLights<'w, 's> {
+    point: Query<'w, 's, (Entity, &'static Position, &'static PointLight)>,
+    spot: Query<'w, 's, (Entity, &'static Position, &'static SpotLight)>,
+}
+
+impl ShadowLights<'_, '_> {
+    fn requests(&self, camera_position: Vec3, fov: f32) -> Vec<ShadowRequest> {
+        let mut requests = Vec::new();
+        for (entity, position, light) in &self.point {
+            if light.casts_shadows {
+                requests.push(ShadowRequest {
+                    entity,
+                    caster: ShadowCaster::Point { position: position.d, range: light.range },
+                    coverage: screen_coverage(position.d, light.range, camera_position, fov),
+                });
+            }
+        }
+        for (entity, position, light) in &self.spot {
+            if light.casts_shadows {
+                requests.push(ShadowRequest {
+                    entity,
+                    caster: ShadowCaster::Spot { position: position.d, direction: light.direction, range: light.range, outer_angle: light.outer_angle },
+                    coverage: screen_coverage(position.d, light.range, camera_position, fov),
+                });
+            }
+        }
+        requests
+    }
+}
+
+//Render textures are created as cameras ask for them and recreated when their size changes
+pub fn update_render_textures(query: Query<&Camera>, mut textures: ResMut<RenderTextures>, mut assets: ResMut<AssetPool>) {
+    textures.update(query.iter(), &mut assets);
+}
+
+pub fn render_shadows(
+    query_mesh: Query<CasterMesh>,
+    query_camera: Query<&Position, With<Camera>>,
+    lights: ShadowLights,
+    mut shadows: ResMut<ShadowAtlas>,
+    assets: Res<AssetPool>,
+    settings: Res<Settings>,
+    window: Res<Window>,
+) {
+    if shadows.size() != ShadowAtlas::size_for(settings.shadow_atlas_size) {
+        *shadows = ShadowAtlas::new(settings.shadow_atlas_size);
+    }
+
+    //TODO: Coverage only considers the first camera
+    let camera_position = query_camera.iter().next().map_or(Vec3::ZERO, |p| p.d);
+    let requests = lights.requests(camera_position, settings.fov);
+    shadows.plan(requests, settings.max_shadow_lights as usize, settings.shadow_max_tile_size, settings.shadow_min_tile_size);
+
+    let shader = match assets.get_shader("shadow") {
+        Some(shader) => shader,
+        None => return,
+    };
+
+    shadows.framebuffer().bind();
+    shader.bind();
+    skinning::bind_palette(shader);
+    morph::bind_targets(shader);
+    unsafe {
+        gl::PolygonMode(gl::FRONT_AND_BACK, gl::FILL);
+        gl_state::enable(gl::SCISSOR_TEST);
+        gl_state::enable(gl::POLYGON_OFFSET_FILL);
+        gl::PolygonOffset(2.0, 4.0);
+    }
+    for view in shadows.views() {
+        let viewport = view.viewport.as_ivec4();
+        unsafe {
+            gl::Viewport(viewport.x, viewport.y, viewport.z, viewport.w);
+            gl::Scissor(viewport.x, viewport.y, viewport.z, viewport.w);
+            gl::Clear(gl::DEPTH_BUFFER_BIT);
+        }
+        shader.set_uniform_4x4f("lightMatrix".to_string(), None, &view.view_projection);
+        let frustum = Frustum::from_matrix(&view.view_projection);
+        let mut queue = RenderQueue::new();
+        for (mesh, position, rotation, scale, skinned, morph) in &query_mesh {
+            let model = model_matrix(position, rotation, scale);
+            if is_visible(&frustum, mesh, &model, skinned) {
+                queue.push(mesh, animated_instance(mesh, model, skinned, morph), None);
+            }
+        }
+        for batch in queue.depth_batches() {
+            batch.mesh.render(&batch.instances);
+        }
+    }
+    unsafe {
+        gl_state::disable(gl::POLYGON_OFFSET_FILL);
+        gl_state::disable(gl::SCISSOR_TEST);
+        let size = window.size();
+        gl::Viewport(0, 0, size.width as i32, size.height as i32);
+    }
+    crate::renderer::update_wireframe(&settings.is_wireframe);
+    shader.unbind();
+    shadows.framebuffer().unbind();
+}
+
+type SceneMesh<'a> = (&'a Mesh, Option<&'a Position>, Option<&'a Rotation>, Option<&'a Scale>, Option<&'a Lightmap>, Option<&'a MeshLod>, Option<&'a RenderLayers>, Option<&'a SkinnedMesh>, Option<&'a MorphWeights>);
+
+//The view being rendered and the meshes it may see, culling results are counted in stats
+#[derive(SystemParam)]
+pub struct SceneView<'w, 's> {
+    meshes: Query<'w, 's, SceneMesh<'static>>,
+    cameras: Query<'w, 's, &'static Camera>,
+    view: Res<'w, RenderView>,
+    stats: ResMut<'w, CullingStats>,
+}
+
+//Lights of the scene along with the shadow atlas and environment they are sampled with
+#[derive(SystemParam)]
+pub struct SceneLightQueries<'w, 's> {
+    directional: Query<'w, 's, (&'static DirectionalLight, Option<&'static Static>)>,
+    point: Query<'w, 's, (Entity, &'static Position, &'static PointLight, Option<&'static Static>)>,
+    spot: Query<'w, 's, (Entity, &'static Position, &'static SpotLight, Option<&'static Static>)>,
+    shadows: Res<'w, ShadowAtlas>,
+    skybox: Option<Res<'w, Skybox>>,
+}
+
+impl SceneLightQueries<'_, '_> {
+    fn collect(&self) -> SceneLights<'_> {
+        SceneLights {
+            directional: self.directional.iter().map(|(l, s)| (l, s.is_some())).collect(),
+            point: self.point.iter().map(|(e, p, l, s)| (e, p, l, s.is_some())).collect(),
+            spot: self.spot.iter().map(|(e, p, l, s)| (e, p, l, s.is_some())).collect(),
+            environment: self.skybox.as_deref(),
+        }
+    }
+}
+
+pub fn render_scene(mut scene: SceneView, scene_lights: SceneLightQueries, assets: Res<AssetPool>, settings: Res<Settings>) {
+    if settings.render_path != RenderPath::Forward {
+        return;
+    }
+    let camera = match scene.cameras.get(scene.view.camera) {
+        Ok(camera) => camera,
+        Err(_) => return,
+    };
+    let (lights, shadows) = (scene_lights.collect(), &scene_lights.shadows);
+
+    lights.bind_for_sampling(shadows);
+    let opaque = queue_scene(&scene.meshes, camera, &scene.view.layers, &mut scene.stats, &assets, false);
+    draw_batches(&opaque.batches(&assets), camera, &lights, shadows, &assets, false);
+    lights.unbind_for_sampling(shadows);
+}
+
+//Opaque meshes go through the G-buffer regardless of their material shader, transparent ones are drawn forward by render_transparent
+pub fn render_deferred(
+    mut scene: SceneView,
+    scene_lights: SceneLightQueries,
+    mut deferred: ResMut<DeferredRenderer>,
+    targets: Res<RenderTargets>,
+    assets: Res<AssetPool>,
+    settings: Res<Settings>,
+) {
+    if settings.render_path != RenderPath::Deferred {
+        return;
+    }
+    let camera = match scene.cameras.get(scene.view.camera) {
+        Ok(camera) => camera,
+        Err(_) => return,
+    };
+    let (geometry, lit) = match (targets.framebuffer(deferred::GBUFFER), targets.framebuffer(deferred::LIT)) {
+        (Some(geometry), Some(lit)) => (geometry, lit),
+        _ => return,
+    };
+    let (lights, shadows) = (scene_lights.collect(), &scene_lights.shadows);
+
+    lights.bind_for_sampling(shadows);
+    let opaque = queue_scene(&scene.meshes, camera, &scene.view.layers, &mut scene.stats, &assets, false);
+    deferred.begin_geometry(geometry);
+    let shader = deferred.geometry_shader();
+    shader.set_uniform_4x4f("camMatrix".to_string(), None, &camera.get_calculation());
+    skinning::bind_palette(shader);
+    morph::bind_targets(shader);
+    shader.set_uniform_i32("lightmap".to_string(), &(LIGHTMAP_UNIT as i32));
+    //Environment irradiance replaces the constant ambient written into the G-buffer
+    environment::upload(shader, lights.environment);
+    let mut bound_material = None;
+    for batch in opaque.batches(&assets) {
+        let material = match assets.get_material(&batch.mesh.material) {
+            Some(material) => material,
+            None => continue,
+        };
+        if bound_material != Some(&batch.mesh.material) {
+            bind_material_textures(material, shader, &assets);
+            shader.set_uniform_1f("roughness".to_string(), &material.roughness);
+            shader.set_uniform_1f("alphaCutoff".to_string(), &material.cutoff());
+            bound_material = Some(&batch.mesh.material);
+        }
+        shader.set_uniform_bool("hasLightmap".to_string(), &batch.lightmap.is_some());
+        if let Some(lightmap) = batch.lightmap {
+            framebuffer::bind_attachment(lightmap.texture.handle(), LIGHTMAP_UNIT);
+        }
+
+        batch.mesh.render(&batch.instances);
+
+        if batch.lightmap.is_some() {
+            framebuffer::unbind_attachment(LIGHTMAP_UNIT);
+        }
+    }
+    unsafe {
+        gl_state::bind_texture(gl::TEXTURE_2D, 0);
+    }
+    deferred.end_geometry(geometry);
+
+    deferred.light(geometry, lit, camera, &lights, shadows);
+    deferred.present(geometry, lit, settings.is_wireframe);
+    lights.unbind_for_sampling(shadows);
+}
+
+//Drawn after the opaque meshes of either path so only the uncovered pixels run the sky shader
+pub fn render_skybox(query_camera: Query<&Camera>, skybox: Option<Res<Skybox>>, view: Res<RenderView>, settings: Res<Settings>) {
+    if let (Some(skybox), Ok(camera)) = (skybox, query_camera.get(view.camera)) {
+        skybox.render(camera, settings.is_wireframe);
+    }
+}
+
+//Atlases and textures of newly added sprites, failures are reported once per sprite
+pub fn load_sprites(query: Query<&Sprite, Added<Sprite>>, mut assets: ResMut<AssetPool>, settings: Res<Settings>) {
+    for sprite in &query {
+        if let Err(error) = assets.load_atlas(&sprite.texture, &settings) {
+            println!("Unable to load sprite {}: {}", sprite.texture, error);
+        }
+    }
+}
+
+type SceneSprite<'a> = (&'a Sprite, Option<&'a Position>, Option<&'a Rotation>, Option<&'a Scale>, Option<&'a RenderLayers>);
+
+//Sprites on the view's layers, batched by texture after sorting by order and depth
+pub fn render_sprites(
+    query_sprite: Query<SceneSprite>,
+    query_camera: Query<&Camera>,
+    mut renderer: ResMut<SpriteRenderer>,
+    view: Res<RenderView>,
+    assets: Res<AssetPool>,
+    settings: Res<Settings>,
+) {
+    let camera = match query_camera.get(view.camera) {
+        Ok(camera) => camera,
+        Err(_) => return,
+    };
+    let snap = match camera.projection_mode {
+        ProjectionMode::Orthographic { pixel_perfect: true, .. } => Some(camera.units_per_pixel(view.viewport[3] as f32)),
+        _ => None,
+    };
+    for (sprite, position, rotation, scale, layers) in &query_sprite {
+        if !layers.copied().unwrap_or_default().intersects(&view.layers) {
+            continue;
+        }
+        if let Some(atlas) = assets.get_atlas(&sprite.texture) {
+            renderer.push(sprite, atlas, &model_matrix(position, rotation, scale), &camera.view, snap, settings.sprites.pixels_per_unit);
+        }
+    }
+    renderer.flush(camera, |texture| assets.get_texture(texture).map(|texture| texture.handle()), settings.is_wireframe);
+}
+
+//Lays out text again when it changes, fonts load with the first text using them
+pub fn layout_text(
+    query: Query<(Entity, &Text), Changed<Text>>,
+    mut commands: Commands,
+    mut atlas: ResMut<GlyphAtlas>,
+    mut assets: ResMut<AssetPool>,
+    settings: Res<Settings>,
+) {
+    for (entity, text) in &query {
+        let mut loaded = Vec::new();
+        for name in std::iter::once(&text.font).chain(&settings.text.fallback_fonts) {
+            match assets.load_font(name) {
+                Ok(font) => loaded.push((name.as_str(), font)),
+                Err(error) => atlas.report_missing(name, error.as_ref()),
+            }
+        }
+        let fonts: Vec<(&str, &Font)> = loaded.iter().map(|(name, font)| (*name, font.as_ref())).collect();
+        commands.entity(entity).insert(atlas.layout(text, &fonts));
+    }
+}
+
+type SceneText<'a> = (&'a Text, &'a TextLayout, Option<&'a Position>, Option<&'a Rotation>, Option<&'a Scale>, Option<&'a RenderLayers>);
+
+//World text is depth tested against the view's scene, screen text goes on top in pixels from the viewport's bottom left
+pub fn render_text(
+    query_text: Query<SceneText>,
+    query_camera: Query<&Camera>,
+    mut renderer: ResMut<TextRenderer>,
+    atlas: Res<GlyphAtlas>,
+    view: Res<RenderView>,
+    settings: Res<Settings>,
+) {
+    let camera = match query_camera.get(view.camera) {
+        Ok(camera) => camera,
+        Err(_) => return,
+    };
+    for space in [TextSpace::World, TextSpace::Screen] {
+        for (text, layout, position, rotation, scale, layers) in &query_text {
+            if text.space != space || !layers.copied().unwrap_or_default().intersects(&view.layers) {
+                continue;
+            }
+            let transform = match space {
+                TextSpace::World => camera.view * model_matrix(position, rotation, scale),
+                TextSpace::Screen => glam::Mat4::from_translation(position.map_or(Vec3::ZERO, |position| position.d * Vec3::new(1.0, 1.0, 0.0))),
+            };
+            renderer.push(layout, &transform);
+        }
+        let projection = match space {
+            TextSpace::World => camera.projection,
+            TextSpace::Screen => {
+                let (width, height) = view.size();
+                glam::Mat4::orthographic_rh_gl(0.0, width as f32, 0.0, height as f32, -1.0, 1.0)
+            },
+        };
+        renderer.flush(&projection, &atlas, space == TextSpace::World, settings.is_wireframe);
+    }
+}
+
+pub fn load_particle_effects(query: Query<&ParticleEmitter, Added<ParticleEmitter>>, mut assets: ResMut<AssetPool>, settings: Res<Settings>) {
+    for emitter in &query {
+        if let Err(error) = assets.load_particle_effect(&emitter.effect, &settings) {
+            println!("Unable to load particle effect {}: {}", emitter.effect, error);
+        }
+    }
+}
+
+//Materials of newly spawned meshes, such as the ones glTF models name after the file's materials. Batches whose
+//material failed to load are reported here and skipped when drawing.
+pub fn load_mesh_materials(query: Query<&Mesh, Added<Mesh>>, mut assets: ResMut<AssetPool>, settings: Res<Settings>) {
+    for mesh in &query {
+        if let Err(error) = assets.load_material(&mesh.material, &settings) {
+            println!("Unable to load mesh material {}: {}", mesh.material, error);
+        }
+    }
+}
+
+pub fn load_terrain_materials(query: Query<&Terrain, Added<Terrain>>, mut assets: ResMut<AssetPool>, settings: Res<Settings>) {
+    for terrain in &query {
+        if let Err(error) = assets.load_material(&terrain.descriptor.material, &settings) {
+            println!("Unable to load terrain material {}: {}", terrain.descriptor.material, error);
         }
     }
 }
 
-pub fn render_scene(mut query_mesh: Query<&Mesh>, mut query_camera: Query<&Camera>, assets: Res<AssetPool>) {
-    for camera in &mut query_camera {
-        for mesh in &mut query_mesh {
-            //TODO: Support multiple textures
-            let material = assets.get_material(&mesh.material).unwrap();
-            let shader = assets.get_shader(&material.shader).unwrap();
-            let texture = assets.get_texture(&material.textures.get(0).unwrap().0).unwrap();
-    
+type PlacedTerrain<'a> = (Entity, &'a mut Terrain, Option<&'a Position>, Option<&'a RenderLayers>);
+
+//Picks each terrain's chunks around the main view's camera, respawning the ones whose level or stitching changed.
+//Chunks follow the terrain's Position and RenderLayers and go away with it.
+pub fn update_terrain(
+    mut commands: Commands,
+    mut query_terrain: Query<PlacedTerrain, Without<TerrainChunk>>,
+    mut query_chunk: Query<(Entity, &TerrainChunk, &mut Position, Option<&RenderLayers>)>,
+    query_camera: Query<(Entity, &Camera)>,
+    assets: Res<AssetPool>,
+) {
+    for (entity, chunk, _, _) in &query_chunk {
+        if !query_terrain.contains(chunk.terrain) {
+            commands.entity(entity).despawn();
+        }
+    }
+    let camera = match view::main_camera(query_camera.iter()) {
+        Some(camera) => camera,
+        None => return,
+    };
+    let eye = eye(camera);
+    for (terrain_entity, mut terrain, position, layers) in &mut query_terrain {
+        //Chunks can't be drawn without their material
+        if assets.get_material(&terrain.descriptor.material).is_none() {
+            continue;
+        }
+        let origin = position.map_or(Vec3::ZERO, |position| position.d);
+        let layers = layers.copied().unwrap_or_default();
+        let mut selected = terrain.select(eye - origin);
+        for (entity, chunk, mut position, chunk_layers) in &mut query_chunk {
+            if chunk.terrain != terrain_entity {
+                continue;
+            }
+            match selected.remove(&chunk.node) {
+                Some(stitches) if stitches == chunk.stitches => {
+                    position.d = origin;
+                    if chunk_layers.copied().unwrap_or_default() != layers {
+                        commands.entity(entity).insert(layers);
+                    }
+                }
+                Some(stitches) => {
+                    position.d = origin;
+                    let mesh = terrain.chunk_mesh(chunk.node, stitches);
+                    commands.entity(entity).insert((mesh, TerrainChunk { stitches, ..*chunk }, layers));
+                }
+                None => commands.entity(entity).despawn(),
+            }
+        }
+        for (node, stitches) in selected {
+            let mesh = terrain.chunk_mesh(node, stitches);
+            commands.spawn((mesh, TerrainChunk { terrain: terrain_entity, node, stitches }, Position { d: origin }, layers));
+        }
+    }
+}
+
+//Advances animation players, posing the skeletons of their skinned meshes and setting their morph weights
+pub fn animate(mut query: Query<(&mut AnimationPlayer, Option<&mut SkinnedMesh>, Option<&mut MorphWeights>)>, time: Res<Time>) {
+    for (mut player, skinned, morph) in &mut query {
+        player.advance(time.delta_seconds());
+        if let Some(mut skinned) = skinned {
+            let pose = player.pose(&skinned.skeleton);
+            skinned.set_pose(&pose);
+        }
+        if let Some(mut morph) = morph {
+            if let Some(node) = morph.node {
+                player.weights(node, &mut morph.weights);
+            }
+        }
+    }
+}
+
+type AnimatedGraph<'a> = (&'a mut AnimationGraphPlayer, &'a mut SkinnedMesh, Option<&'a mut Position>, Option<&'a mut Rotation>, Option<&'a Scale>);
+
+//Evaluates animation graphs into the skeletons of their skinned meshes, root motion moves and turns the entity
+pub fn animate_graphs(mut query: Query<AnimatedGraph>, time: Res<Time>) {
+    for (mut player, mut skinned, position, rotation, scale) in &mut query {
+        let (pose, root_motion) = player.update(time.delta_seconds(), &skinned.skeleton);
+        skinned.set_pose(&pose);
+        let facing = rotation.as_ref().map_or(Quat::IDENTITY, |r| Quat::from_euler(EulerRot::XYZ, r.d.x, r.d.y, r.d.z));
+        if let Some(mut position) = position {
+            position.d += facing * (root_motion.translation * scale.map_or(Vec3::ONE, |s| s.d));
+        }
+        if let Some(mut rotation) = rotation {
+            if root_motion.yaw != 0.0 {
+                let (x, y, z) = (facing * Quat::from_rotation_y(root_motion.yaw)).to_euler(EulerRot::XYZ);
+                rotation.d = Vec3::new(x, y, z);
+            }
+        }
+    }
+}
+
+//F4 flips the excited parameter of every animation graph, for trying out transitions, and excited ones twitch
+//their tip harder. F5 pauses and resumes them and F6 restarts the base layer's state, clip players have their own keys.
+pub fn update_animation_parameters(input: Res<Input>, mut query: Query<&mut AnimationGraphPlayer>) {
+    for mut player in &mut query {
+        if input.keyboard_just_pressed(KeyCode::F4) {
+            let excited = player.parameter("excited") != 0.0;
+            player.set_bool("excited", !excited);
+            player.set_layer_weight("tip", if excited { 0.5 } else { 1.0 });
+        }
+        if input.keyboard_just_pressed(KeyCode::F5) {
+            if player.is_paused() {
+                player.resume();
+            } else {
+                player.pause();
+            }
+        }
+        if input.keyboard_just_pressed(KeyCode::F6) {
+            if let Some(state) = player.state("base").map(str::to_string) {
+                player.transition_to("base", &state, 0.2);
+            }
+        }
+    }
+}
+
+//F1 fades to the next clip of every AnimationClips and back to the first once it ends, F2 pauses and resumes,
+//F3 replays the current clip on a loop, F7 and F8 step a paused clip back and forward and F12 stops
+pub fn update_animation_controls(input: Res<Input>, mut query: Query<(&mut AnimationPlayer, Option<&AnimationClips>)>) {
+    for (mut player, clips) in &mut query {
+        if input.keyboard_just_pressed(KeyCode::F2) {
+            if player.is_paused() {
+                player.resume();
+            } else {
+                player.pause();
+            }
+        }
+        if player.is_paused() {
+            for (key, step) in [(KeyCode::F7, -ANIMATION_STEP), (KeyCode::F8, ANIMATION_STEP)] {
+                if input.keyboard_just_pressed(key) {
+                    let time = (player.time() + step).max(0.0);
+                    player.seek(time);
+                }
+            }
+        }
+        if input.keyboard_just_pressed(KeyCode::F3) {
+            player.seek(0.0);
+            player.set_looping(true);
+        }
+        if input.keyboard_just_pressed(KeyCode::F12) {
+            player.stop();
+        }
+        let clips = match clips.filter(|clips| !clips.clips.is_empty()) {
+            Some(clips) => &clips.clips,
+            None => continue,
+        };
+        if input.keyboard_just_pressed(KeyCode::F1) {
+            let current = player.clip().and_then(|clip| clips.iter().position(|other| Arc::ptr_eq(other, clip)));
+            let next = current.map_or(0, |current| (current + 1) % clips.len());
+            player.crossfade(clips[next].clone(), 0.3, next == 0);
+        } else if player.clip().is_some() && player.is_finished() {
+            player.crossfade(clips[0].clone(), 0.3, true);
+        }
+    }
+}
+
+//Packs the joint matrices of every skinned mesh into this frame's palette
+pub fn upload_skinning(mut query: Query<&mut SkinnedMesh>, mut palette: ResMut<SkinningPalette>) {
+    for mut skinned in &mut query {
+        palette.push(&mut skinned);
+    }
+    palette.upload();
+}
+
+//Packs the weights of every morphed mesh into this frame's palette
+pub fn upload_morph_weights(mut query: Query<&mut MorphWeights>, mut palette: ResMut<MorphPalette>) {
+    for mut morph in &mut query {
+        palette.push(&mut morph);
+    }
+    palette.upload();
+}
+
+type PlacedEmitter<'a> = (&'a mut ParticleEmitter, Option<&'a Position>, Option<&'a Rotation>, Option<&'a Scale>, Option<&'a RestartWhenFinished>);
+
+pub fn simulate_particles(
+    mut query: Query<PlacedEmitter>,
+    assets: Res<AssetPool>,
+    time: Res<Time>,
+    mut debug: ResMut<DebugDraw>,
+    settings: Res<Settings>,
+) {
+    for (mut emitter, position, rotation, scale, restart) in &mut query {
+        if let Some(effect) = assets.get_particle_effect(&emitter.effect) {
+            if restart.is_some() && emitter.is_finished(effect) {
+                emitter.restart();
+                //Flashes where the effect started over for half a second
+                if settings.debug_draw {
+                    debug.sphere(emitter.origin(), 0.3, Vec4::new(1.0, 0.6, 0.1, 1.0), DebugOptions::duration(0.5));
+                }
+            }
+            emitter.update(effect, model_matrix(position, rotation, scale), time.delta_seconds());
+        }
+    }
+}
+
+//Emitters on the view's layers, furthest first so blended effects overlap correctly
+pub fn render_particles(
+    query_emitter: Query<(&ParticleEmitter, Option<&RenderLayers>)>,
+    query_camera: Query<&Camera>,
+    mut renderer: ResMut<ParticleRenderer>,
+    view: Res<RenderView>,
+    assets: Res<AssetPool>,
+    settings: Res<Settings>,
+) {
+    let camera = match query_camera.get(view.camera) {
+        Ok(camera) => camera,
+        Err(_) => return,
+    };
+    let mut emitters: Vec<(&ParticleEmitter, f32)> = query_emitter.iter()
+        .filter(|(emitter, layers)| emitter.particle_count() > 0 && layers.copied().unwrap_or_default().intersects(&view.layers))
+        .map(|(emitter, _)| (emitter, camera.view.transform_point3(emitter.origin()).z))
+        .collect();
+    emitters.sort_by(|a, b| a.1.total_cmp(&b.1));
+    for (emitter, _) in emitters {
+        if let Some(effect) = assets.get_particle_effect(&emitter.effect) {
+            let texture = assets.get_texture(&effect.texture).map(|texture| texture.handle());
+            renderer.render(emitter, effect, camera, texture, settings.is_wireframe);
+        }
+    }
+}
+
+//Blended meshes for both render paths, drawn on top of the opaque scene in whatever target is bound.
+//Weighted blended OIT replaces sorting when enabled, additive meshes don't depend on order and skip it.
+pub fn render_transparent(
+    mut scene: SceneView,
+    scene_lights: SceneLightQueries,
+    mut oit: ResMut<OrderIndependentTransparency>,
+    targets: Res<RenderTargets>,
+    assets: Res<AssetPool>,
+    settings: Res<Settings>,
+) {
+    let camera = match scene.cameras.get(scene.view.camera) {
+        Ok(camera) => camera,
+        Err(_) => return,
+    };
+    let batches = queue_scene(&scene.meshes, camera, &scene.view.layers, &mut scene.stats, &assets, true).back_to_front(eye(camera));
+    if batches.is_empty() {
+        return;
+    }
+    let (lights, shadows) = (scene_lights.collect(), &scene_lights.shadows);
+
+    lights.bind_for_sampling(shadows);
+    match targets.framebuffer(oit::TARGET).filter(|_| settings.order_independent_transparency) {
+        Some(target) if oit.begin(target) => {
+            let (additive, weighted): (Vec<Batch>, Vec<Batch>) = batches.into_iter()
+                .partition(|batch| blend_mode(batch, &assets) == BlendMode::Additive);
+            draw_batches(&weighted, camera, &lights, shadows, &assets, true);
+            oit.resolve(target, settings.is_wireframe);
+            draw_transparent(&additive, camera, &lights, shadows, &assets);
+        }
+        _ => draw_transparent(&batches, camera, &lights, shadows, &assets),
+    }
+    lights.unbind_for_sampling(shadows);
+}
+
+//The first texture goes to unit 0 as tex0, the rest to the material units. Every tex sampler is pointed at its
+//unit even when the material has no texture there, so samplers of different types never share unit 0.
+fn bind_material_textures(material: &Material, shader: &Shader, assets: &AssetPool) {
+    for (index, (name, _)) in material.textures.iter().enumerate().take(MAX_MATERIAL_TEXTURES) {
+        match (index, assets.get_texture(name)) {
+            (0, Some(texture)) => texture.bind(),
+            (_, Some(texture)) => texture.bind_to_unit(MATERIAL_TEXTURE_UNIT + index as u32 - 1),
+            (_, None) => {},
+        }
+    }
+    for index in 1..MAX_MATERIAL_TEXTURES as u32 {
+        shader.set_uniform_i32(format!("tex{}", index), &((MATERIAL_TEXTURE_UNIT + index - 1) as i32));
+    }
+    shader.set_uniform_bool("splatMapped".to_string(), &material.splat_tiling.is_some());
+    shader.set_uniform_1f("splatTiling".to_string(), &material.splat_tiling.unwrap_or(1.0));
+}
+
+fn is_transparent(mesh: &Mesh, assets: &AssetPool) -> bool {
+    assets.get_material(&mesh.material).is_some_and(|material| material.blend_mode.is_transparent())
+}
+
+fn blend_mode(batch: &Batch, assets: &AssetPool) -> BlendMode {
+    assets.get_material(&batch.mesh.material).map_or(BlendMode::Opaque, |material| material.blend_mode)
+}
+
+fn eye(camera: &Camera) -> Vec3 {
+    camera.view.inverse().w_axis.xyz()
+}
+
+//Queues either the opaque or the blended meshes on the view's layers the camera can see, counting the ones it culled
+fn queue_scene<'a>(query_mesh: &'a Query<SceneMesh>, camera: &Camera, layers: &RenderLayers, stats: &mut CullingStats, assets: &AssetPool, transparent: bool) -> RenderQueue<'a> {
+    let frustum = Frustum::from_matrix(&camera.get_calculation());
+    let mut queue = RenderQueue::new();
+    for (mesh, position, rotation, scale, lightmap, lod, mesh_layers, skinned, morph) in query_mesh {
+        if is_transparent(mesh, assets) != transparent || !mesh_layers.copied().unwrap_or_default().intersects(layers) {
+            continue;
+        }
+        let model = model_matrix(position, rotation, scale);
+        if !stats.record(is_visible(&frustum, mesh, &model, skinned)) {
+            continue;
+        }
+        //Both levels are drawn while a LOD transition dithers between them
+        let (fade, fading_out) = lod.map_or((0.0, None), |lod| lod.fades());
+        queue.push(mesh, Instance { fade, ..animated_instance(mesh, model, skinned, morph) }, lightmap);
+        if let Some((previous, fade)) = fading_out {
+            queue.push(previous, Instance { fade, ..animated_instance(previous, model, skinned, morph) }, lightmap);
+        }
+    }
+    queue
+}
+
+//Skinned meshes are never culled, their bounds only cover the bind pose
+fn is_visible(frustum: &Frustum, mesh: &Mesh, model: &Mat4, skinned: Option<&SkinnedMesh>) -> bool {
+    skinned.is_some() || frustum.is_visible(mesh.bounds(), model)
+}
+
+fn animated_instance(mesh: &Mesh, model: Mat4, skinned: Option<&SkinnedMesh>, morph: Option<&MorphWeights>) -> Instance {
+    Instance { skin_offset: SkinnedMesh::instance_offset(skinned), morph_offset: MorphWeights::instance_offset(morph, mesh), ..Instance::new(model) }
+}
+
+//Blended on top of whatever target is bound with each material's blend mode, depth is tested but not written
+fn draw_transparent(batches: &[Batch], camera: &Camera, lights: &SceneLights, shadows: &ShadowAtlas, assets: &AssetPool) {
+    unsafe {
+        gl_state::depth_mask(gl::FALSE);
+    }
+    draw_batches(batches, camera, lights, shadows, assets, false);
+    unsafe {
+        gl_state::depth_mask(gl::TRUE);
+        gl_state::disable(gl::BLEND);
+    }
+}
+
+//Shader uniforms, textures and blend state are only set up again when a batch changes shader or material.
+//Blend state is left alone while accumulating weighted OIT layers.
+fn draw_batches(batches: &[Batch], camera: &Camera, lights: &SceneLights, shadows: &ShadowAtlas, assets: &AssetPool, weighted_oit: bool) {
+    let mut bound_shader = None;
+    let mut bound_material = None;
+    for batch in batches {
+        let (material, shader) = match assets.get_material(&batch.mesh.material).and_then(|material| Some((material, assets.get_shader(&material.shader)?))) {
+            Some(loaded) => loaded,
+            None => continue,
+        };
+        if bound_shader != Some(&material.shader) {
             shader.bind();
             shader.set_uniform_4x4f("camMatrix".to_string(), None, &camera.get_calculation());
-            texture.bind();
-    
-            mesh.render();
-    
-            texture.unbind();
-            shader.unbind();
+            shader.set_uniform_3f("cameraPosition".to_string(), &eye(camera));
+            lights.upload(shader, shadows);
+            shader.set_uniform_i32("lightmap".to_string(), &(LIGHTMAP_UNIT as i32));
+            shader.set_uniform_bool("weightedOit".to_string(), &weighted_oit);
+            shader.set_uniform_2f("oitWeightRange".to_string(), &oit::WEIGHT_RANGE);
+            skinning::bind_palette(shader);
+            morph::bind_targets(shader);
+            bound_shader = Some(&material.shader);
+        }
+        if bound_material != Some(&batch.mesh.material) {
+            bind_material_textures(material, shader, assets);
+            shader.set_uniform_1f("roughness".to_string(), &material.roughness);
+            shader.set_uniform_1f("alphaCutoff".to_string(), &material.cutoff());
+            shader.set_uniform_bool("premultipliedAlpha".to_string(), &(material.blend_mode == BlendMode::Premultiplied));
+            if !weighted_oit {
+                material.blend_mode.apply();
+            }
+            bound_material = Some(&batch.mesh.material);
+        }
+        shader.set_uniform_bool("hasLightmap".to_string(), &batch.lightmap.is_some());
+        if let Some(lightmap) = batch.lightmap {
+            framebuffer::bind_attachment(lightmap.texture.handle(), LIGHTMAP_UNIT);
         }
+
+        batch.mesh.render(&batch.instances);
+
+        if batch.lightmap.is_some() {
+            framebuffer::unbind_attachment(LIGHTMAP_UNIT);
+        }
+    }
+    unsafe {
+        gl_state::bind_texture(gl::TEXTURE_2D, 0);
+        gl_state::use_program(0);
     }
-}
\ No newline at end of file
+}
+
+//Shapes from DebugDraw on top of the finished view, skipped by cameras that don't see layer 0
+pub fn render_debug_draw(
+    query_camera: Query<&Camera>,
+    renderer: Res<DebugRenderer>,
+    debug: Res<DebugDraw>,
+    view: Res<RenderView>,
+    settings: Res<Settings>,
+) {
+    if !settings.debug_draw || !view.layers.intersects(&RenderLayers::default()) {
+        return;
+    }
+    if let Ok(camera) = query_camera.get(view.camera) {
+        renderer.render(&debug, &camera.view, &camera.projection, settings.is_wireframe);
+    }
+}
+
+//Starts the frame, culling counters only cover what is drawn from here on.
+//Clears the whole window so areas no camera covers don't keep old frames.
+pub fn clear_backbuffer(mut stats: ResMut<CullingStats>, window: Res<Window>) {
+    stats.reset();
+    let size = window.size();
+    let color = ClearSettings::DEFAULT_COLOR;
+    unsafe {
+        gl_state::bind_framebuffer(gl::FRAMEBUFFER, 0);
+        gl::Viewport(0, 0, size.width as i32, size.height as i32);
+        gl::ClearColor(color.x, color.y, color.z, color.w);
+        gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
+    }
+}
+
+//Binds the camera's target and clears its viewport the way the camera asks
+pub fn begin_view(view: Res<RenderView>) {
+    view.begin();
+}
+
+type PrepassMesh<'a> = (&'a Mesh, Option<&'a Position>, Option<&'a Rotation>, Option<&'a Scale>, Option<&'a RenderLayers>, Option<&'a SkinnedMesh>, Option<&'a MorphWeights>);
+
+pub fn render_ssr_prepass(
+    query_mesh: Query<PrepassMesh>,
+    query_camera: Query<&Camera>,
+    mut ssr: ResMut<ScreenSpaceReflections>,
+    targets: Res<RenderTargets>,
+    view: Res<RenderView>,
+    assets: Res<AssetPool>,
+    settings: Res<Settings>,
+) {
+    if settings.ssr_quality == SsrQuality::Off {
+        return;
+    }
+    let camera = match query_camera.get(view.camera) {
+        Ok(camera) => camera,
+        Err(_) => return,
+    };
+    let (width, height) = view.size();
+    ssr.resize(width, height, settings.ssr_quality);
+
+    let (prepass, scene) = match (targets.framebuffer(ssr::PREPASS), targets.framebuffer(ssr::SCENE)) {
+        (Some(prepass), Some(scene)) => (prepass, scene),
+        _ => return,
+    };
+
+    ssr.begin_prepass(prepass);
+    let shader = ssr.prepass_shader();
+    shader.set_uniform_4x4f("camMatrix".to_string(), None, &camera.get_calculation());
+    shader.set_uniform_4x4f("view".to_string(), None, &camera.view);
+    skinning::bind_palette(shader);
+    morph::bind_targets(shader);
+    let frustum = Frustum::from_matrix(&camera.get_calculation());
+    let mut queue = RenderQueue::new();
+    for (mesh, position, rotation, scale, layers, skinned, morph) in &query_mesh {
+        let model = model_matrix(position, rotation, scale);
+        if layers.copied().unwrap_or_default().intersects(&view.layers) && is_visible(&frustum, mesh, &model, skinned) {
+            queue.push(mesh, animated_instance(mesh, model, skinned, morph), None);
+        }
+    }
+    for batch in queue.batches(&assets) {
+        let roughness = assets.get_material(&batch.mesh.material).map_or(1.0, |material| material.roughness);
+        shader.set_uniform_1f("roughness".to_string(), &roughness);
+        batch.mesh.render(&batch.instances);
+    }
+    ssr.end_prepass(prepass);
+    ssr.begin_scene(scene);
+}
+
+pub fn render_ssr(query_camera: Query<&Camera>, ssr: Res<ScreenSpaceReflections>, targets: Res<RenderTargets>, view: Res<RenderView>, settings: Res<Settings>) {
+    if settings.ssr_quality == SsrQuality::Off {
+        return;
+    }
+    let (prepass, scene) = match (targets.framebuffer(ssr::PREPASS), targets.framebuffer(ssr::SCENE)) {
+        (Some(prepass), Some(scene)) => (prepass, scene),
+        _ => return,
+    };
+    if let Ok(camera) = query_camera.get(view.camera) {
+        ssr.resolve(prepass, scene, &camera.projection, settings.ssr_quality, settings.is_wireframe);
+    }
+}
+
+pub fn begin_post_processing(
+    query_camera: Query<&PostProcess, With<Camera>>,
+    mut post: ResMut<PostProcessing>,
+    targets: Res<RenderTargets>,
+    view: Res<RenderView>,
+) {
+    //Only cameras with a PostProcess component render through the HDR target
+    let (post_process, hdr) = match (query_camera.get(view.camera), targets.framebuffer(postprocess::HDR)) {
+        (Ok(post_process), Some(hdr)) => (post_process, hdr),
+        _ => return,
+    };
+    let (width, height) = view.size();
+    post.resize(width, height, post_process.bloom.mip_count);
+    post.begin(hdr);
+}
+
+pub fn render_post_processing(
+    query_camera: Query<&PostProcess, With<Camera>>,
+    mut post: ResMut<PostProcessing>,
+    targets: Res<RenderTargets>,
+    view: Res<RenderView>,
+    time: Res<Time>,
+    settings: Res<Settings>,
+) {
+    if !post.is_active() {
+        return;
+    }
+    let (post_process, hdr) = match (query_camera.get(view.camera), targets.framebuffer(postprocess::HDR)) {
+        (Ok(post_process), Some(hdr)) => (post_process, hdr),
+        _ => return,
+    };
+    post.resolve(hdr, post_process, time.delta_seconds(), settings.is_wireframe);
+}
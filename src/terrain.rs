@@ -0,0 +1,440 @@
+use std::{collections::{HashMap, HashSet}, error::Error, fs};
+
+use bevy_ecs::prelude::*;
+use glam::*;
+use serde::{Serialize, Deserialize};
+use simple_error::SimpleError;
+
+use crate::{mesh::Mesh, mesh_builder::MeshBuilder};
+
+//Edges of a chunk that border a coarser one, their odd vertices are skipped so both sides line up
+pub const STITCH_WEST: u8 = 1;
+pub const STITCH_EAST: u8 = 2;
+pub const STITCH_NORTH: u8 = 4;
+pub const STITCH_SOUTH: u8 = 8;
+
+//Loaded from resources/terrains/{name}.toml
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct TerrainDescriptor {
+    //16 bit grayscale resources/textures/{heightmap}.png, or raw little endian samples of a square map in .r16
+    pub heightmap: String,
+    //Splat mapped materials (see Material::splat_tiling) get UVs covering the whole terrain once
+    pub material: String,
+    //Extent along X and Z, the terrain is centered on its Position
+    pub size: [f32; 2],
+    //Height of a white sample, black ones sit at 0
+    pub height: f32,
+    //Quads along each side of a chunk, a power of two. Coarser chunks have as many quads covering more ground.
+    pub chunk_quads: u32,
+    //Chunks closer to the camera than this many times their own width split into four finer ones
+    pub lod_distance: f32,
+}
+
+impl Default for TerrainDescriptor {
+    fn default() -> Self {
+        Self {
+            heightmap: String::new(),
+            material: String::new(),
+            size: [64.0, 64.0],
+            height: 8.0,
+            chunk_quads: 32,
+            lod_distance: 2.0,
+        }
+    }
+}
+
+//Quadtree node, level 0 chunks cover `chunk_quads` heightmap quads and every level up doubles that
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct TerrainNode {
+    pub level: u32,
+    pub x: u32,
+    pub z: u32,
+}
+
+impl TerrainNode {
+    fn children(&self) -> [TerrainNode; 4] {
+        let (level, x, z) = (self.level - 1, self.x * 2, self.z * 2);
+        [
+            TerrainNode { level, x, z },
+            TerrainNode { level, x: x + 1, z },
+            TerrainNode { level, x, z: z + 1 },
+            TerrainNode { level, x: x + 1, z: z + 1 },
+        ]
+    }
+}
+
+//Heightmap terrain drawn as chunk entities that update_terrain keeps in step with the camera. Queries take
+//coordinates relative to the entity's Position, rotating or scaling a terrain isn't supported.
+#[derive(Component)]
+pub struct Terrain {
+    pub descriptor: TerrainDescriptor,
+    //Samples per row and rows, row 0 lies at -Z like the top of the image
+    width: usize,
+    depth: usize,
+    heights: Vec<f32>,
+    spacing: Vec2,
+    height_range: (f32, f32),
+    root_level: u32,
+    meshes: HashMap<(TerrainNode, u8), Mesh>,
+}
+
+//Spawned by update_terrain, one per selected node
+#[derive(Component)]
+pub struct TerrainChunk {
+    pub terrain: Entity,
+    pub node: TerrainNode,
+    pub stitches: u8,
+}
+
+impl Terrain {
+    pub fn load(name: &str) -> Result<Terrain, Box<dyn Error>> {
+        let descriptor: TerrainDescriptor = toml::from_str(&fs::read_to_string(format!("resources/terrains/{}.toml", name))?)?;
+        let (width, depth, samples) = load_heightmap(&descriptor.heightmap)?;
+        Terrain::from_samples(descriptor, width, depth, &samples)
+    }
+
+    pub fn from_samples(descriptor: TerrainDescriptor, width: usize, depth: usize, samples: &[u16]) -> Result<Terrain, Box<dyn Error>> {
+        if width < 2 || depth < 2 || samples.len() != width * depth {
+            return Err(SimpleError::new(format!("Heightmap {} has {} samples for {}x{}", descriptor.heightmap, samples.len(), width, depth)).into());
+        }
+        if !descriptor.chunk_quads.is_power_of_two() || descriptor.chunk_quads < 2 {
+            return Err(SimpleError::new(format!("Terrain chunks need a power of two quads of at least 2, not {}", descriptor.chunk_quads)).into());
+        }
+        let heights: Vec<f32> = samples.iter().map(|sample| *sample as f32 / u16::MAX as f32 * descriptor.height).collect();
+        let height_range = heights.iter().fold((f32::MAX, f32::MIN), |(low, high), height| (low.min(*height), high.max(*height)));
+        let size = Vec2::from_array(descriptor.size);
+        let spacing = size / Vec2::new((width - 1) as f32, (depth - 1) as f32);
+        //Enough levels for a single root node to cover every quad
+        let leaves = (width - 1).max(depth - 1).div_ceil(descriptor.chunk_quads as usize);
+        let root_level = leaves.next_power_of_two().trailing_zeros();
+        Ok(Terrain { descriptor, width, depth, heights, spacing, height_range, root_level, meshes: HashMap::new() })
+    }
+
+    pub fn size(&self) -> Vec2 {
+        Vec2::from_array(self.descriptor.size)
+    }
+
+    //Lowest and highest point of the surface
+    pub fn height_range(&self) -> (f32, f32) {
+        self.height_range
+    }
+
+    //Height of the surface above (x, z), following the triangles of the finest chunks. None outside the terrain.
+    pub fn height_at(&self, x: f32, z: f32) -> Option<f32> {
+        let (cell, fraction) = self.cell(x, z)?;
+        let [h00, h10, h01, h11] = self.corners(cell).map(|(x, z)| self.sample(x, z));
+        //Quads are split along their diagonal from -X -Z to +X +Z
+        Some(if fraction.x >= fraction.y {
+            h00 + fraction.x * (h10 - h00) + fraction.y * (h11 - h10)
+        } else {
+            h00 + fraction.y * (h01 - h00) + fraction.x * (h11 - h01)
+        })
+    }
+
+    //Smooth surface normal at (x, z), blended between the vertex normals the chunks are lit with
+    pub fn normal_at(&self, x: f32, z: f32) -> Option<Vec3> {
+        let (cell, fraction) = self.cell(x, z)?;
+        let [n00, n10, n01, n11] = self.corners(cell).map(|(x, z)| self.normal(x, z));
+        Some(n00.lerp(n10, fraction.x).lerp(n01.lerp(n11, fraction.x), fraction.y).normalize())
+    }
+
+    //Distance along a normalized `direction` to where a ray starting above the surface first hits it
+    pub fn raycast(&self, origin: Vec3, direction: Vec3, max_distance: f32) -> Option<f32> {
+        let above = |distance: f32| {
+            let point = origin + direction * distance;
+            self.height_at(point.x, point.z).map(|height| point.y - height)
+        };
+        //Half a sample apart so the ray can't step over a ridge, then halved down to the crossing
+        let step = self.spacing.min_element() * 0.5;
+        let (mut previous, mut previous_above) = (0.0, above(0.0));
+        while previous < max_distance {
+            let distance = (previous + step).min(max_distance);
+            let current_above = above(distance);
+            if let (Some(before), Some(after)) = (previous_above, current_above) {
+                if before >= 0.0 && after < 0.0 {
+                    let (mut low, mut high) = (previous, distance);
+                    for _ in 0..16 {
+                        let middle = (low + high) * 0.5;
+                        if above(middle).is_some_and(|height| height >= 0.0) { low = middle } else { high = middle }
+                    }
+                    return Some(high);
+                }
+            }
+            (previous, previous_above) = (distance, current_above);
+        }
+        None
+    }
+
+    //Chunks to draw for an eye in the terrain's space with the edges each has to stitch. Nearby nodes are split
+    //until neighbours differ by one level at most, which is all the stitching can join without cracks.
+    //Meshes cached for nodes no longer selected are dropped.
+    pub fn select(&mut self, eye: Vec3) -> HashMap<TerrainNode, u8> {
+        let mut selected = HashSet::new();
+        let mut pending = vec![TerrainNode { level: self.root_level, x: 0, z: 0 }];
+        while let Some(node) = pending.pop() {
+            if node.level > 0 && self.distance(node, eye) < self.node_width(node) * self.descriptor.lod_distance {
+                pending.extend(node.children().into_iter().filter(|child| self.exists(*child)));
+            } else {
+                selected.insert(node);
+            }
+        }
+
+        loop {
+            let too_coarse: HashSet<TerrainNode> = selected.iter()
+                .flat_map(|node| self.neighbours(node, &selected).into_iter().flatten().filter(move |neighbour| neighbour.level > node.level + 1))
+                .collect();
+            if too_coarse.is_empty() {
+                break;
+            }
+            for node in too_coarse {
+                selected.remove(&node);
+                selected.extend(node.children().into_iter().filter(|child| self.exists(*child)));
+            }
+        }
+
+        let chunks: HashMap<TerrainNode, u8> = selected.iter().map(|node| {
+            let stitches = self.neighbours(node, &selected).iter().zip([STITCH_WEST, STITCH_EAST, STITCH_NORTH, STITCH_SOUTH])
+                .filter(|(neighbour, _)| neighbour.is_some_and(|neighbour| neighbour.level > node.level))
+                .fold(0, |stitches, (_, edge)| stitches | edge);
+            (*node, stitches)
+        }).collect();
+        self.meshes.retain(|(node, _), _| chunks.contains_key(node));
+        chunks
+    }
+
+    //Uploads the chunk the first time it is asked for, needs a GL context
+    pub fn chunk_mesh(&mut self, node: TerrainNode, stitches: u8) -> Mesh {
+        if let Some(mesh) = self.meshes.get(&(node, stitches)) {
+            return mesh.clone();
+        }
+        let mesh = self.build_chunk(node, stitches).upload(&self.descriptor.material);
+        self.meshes.insert((node, stitches), mesh.clone());
+        mesh
+    }
+
+    //(chunk_quads + 1)² vertices sampled every 2^level samples, clamped to the last row and column for nodes
+    //hanging over the edge of the heightmap. Stitched edges reuse the even vertex before each odd one.
+    fn build_chunk(&self, node: TerrainNode, stitches: u8) -> MeshBuilder {
+        let quads = self.descriptor.chunk_quads;
+        let step = 1 << node.level;
+        let (first_x, first_z) = (node.x * quads * step, node.z * quads * step);
+        let mut builder = MeshBuilder::new();
+        let mut positions = Vec::new();
+        for j in 0..=quads {
+            for i in 0..=quads {
+                let x = ((first_x + i * step) as usize).min(self.width - 1);
+                let z = ((first_z + j * step) as usize).min(self.depth - 1);
+                let position = self.position(x, z);
+                let uv = Vec2::new(x as f32 / (self.width - 1) as f32, 1.0 - z as f32 / (self.depth - 1) as f32);
+                builder.add_vertex(position, self.normal(x, z), uv);
+                positions.push(position);
+            }
+        }
+
+        let vertex = |i: u32, j: u32| {
+            let stitched_column = (i == 0 && stitches & STITCH_WEST != 0) || (i == quads && stitches & STITCH_EAST != 0);
+            let j = if stitched_column { j & !1 } else { j };
+            let stitched_row = (j == 0 && stitches & STITCH_NORTH != 0) || (j == quads && stitches & STITCH_SOUTH != 0);
+            let i = if stitched_row { i & !1 } else { i };
+            j * (quads + 1) + i
+        };
+        for j in 0..quads {
+            for i in 0..quads {
+                let (p00, p10, p01, p11) = (vertex(i, j), vertex(i + 1, j), vertex(i, j + 1), vertex(i + 1, j + 1));
+                //Wound to face +Y with rows running along +Z
+                for [a, b, c] in [[p00, p11, p10], [p00, p01, p11]] {
+                    let [pa, pb, pc] = [a, b, c].map(|index| positions[index as usize]);
+                    if (pb - pa).cross(pc - pa).length_squared() > 1e-12 {
+                        builder.add_triangle(a, b, c);
+                    }
+                }
+            }
+        }
+        builder
+    }
+
+    fn sample(&self, x: usize, z: usize) -> f32 {
+        self.heights[z.min(self.depth - 1) * self.width + x.min(self.width - 1)]
+    }
+
+    fn position(&self, x: usize, z: usize) -> Vec3 {
+        let corner = -self.size() / 2.0;
+        Vec3::new(corner.x + x as f32 * self.spacing.x, self.sample(x, z), corner.y + z as f32 * self.spacing.y)
+    }
+
+    //Central differences over the finest samples, so coarse chunks are lit like the fine ones they replace
+    fn normal(&self, x: usize, z: usize) -> Vec3 {
+        let (left, right) = (x.saturating_sub(1), (x + 1).min(self.width - 1));
+        let (up, down) = (z.saturating_sub(1), (z + 1).min(self.depth - 1));
+        let slope_x = (self.sample(right, z) - self.sample(left, z)) / ((right - left) as f32 * self.spacing.x);
+        let slope_z = (self.sample(x, down) - self.sample(x, up)) / ((down - up) as f32 * self.spacing.y);
+        Vec3::new(-slope_x, 1.0, -slope_z).normalize()
+    }
+
+    //Sample at the -X -Z corner of the quad under (x, z) and how far across it the point is
+    fn cell(&self, x: f32, z: f32) -> Option<(UVec2, Vec2)> {
+        let grid = (Vec2::new(x, z) + self.size() / 2.0) / self.spacing;
+        let last = Vec2::new((self.width - 1) as f32, (self.depth - 1) as f32);
+        if grid.cmplt(Vec2::ZERO).any() || grid.cmpgt(last).any() {
+            return None;
+        }
+        let cell = grid.floor().min(last - 1.0);
+        Some((cell.as_uvec2(), grid - cell))
+    }
+
+    fn corners(&self, cell: UVec2) -> [(usize, usize); 4] {
+        let (x, z) = (cell.x as usize, cell.y as usize);
+        [(x, z), (x + 1, z), (x, z + 1), (x + 1, z + 1)]
+    }
+
+    //Quads along a side of the node
+    fn span(&self, level: u32) -> u32 {
+        self.descriptor.chunk_quads << level
+    }
+
+    fn exists(&self, node: TerrainNode) -> bool {
+        let span = self.span(node.level) as usize;
+        (node.x as usize * span) < self.width - 1 && (node.z as usize * span) < self.depth - 1
+    }
+
+    fn node_width(&self, node: TerrainNode) -> f32 {
+        self.span(node.level) as f32 * self.spacing.max_element()
+    }
+
+    //From the eye to the closest point of the node's box, which spans every height of the terrain
+    fn distance(&self, node: TerrainNode, eye: Vec3) -> f32 {
+        let span = self.span(node.level) as usize;
+        let min = self.position(node.x as usize * span, node.z as usize * span);
+        let max = self.position((node.x as usize + 1) * span, (node.z as usize + 1) * span);
+        let min = Vec3::new(min.x, self.height_range.0, min.z);
+        let max = Vec3::new(max.x, self.height_range.1, max.z);
+        eye.distance(eye.clamp(min, max))
+    }
+
+    //Selected nodes across the west, east, north and south edges, checked halfway along each. A coarser
+    //neighbour covers the whole edge, so one point is enough to find it.
+    fn neighbours(&self, node: &TerrainNode, selected: &HashSet<TerrainNode>) -> [Option<TerrainNode>; 4] {
+        let span = self.span(node.level);
+        let (first_x, first_z) = (node.x * span, node.z * span);
+        let (last_x, last_z) = (self.width as u32 - 2, self.depth as u32 - 2);
+        let (middle_x, middle_z) = ((first_x + span / 2).min(last_x), (first_z + span / 2).min(last_z));
+        let points = [
+            first_x.checked_sub(1).map(|x| (x, middle_z)),
+            Some((first_x + span, middle_z)).filter(|(x, _)| *x <= last_x),
+            first_z.checked_sub(1).map(|z| (middle_x, z)),
+            Some((middle_x, first_z + span)).filter(|(_, z)| *z <= last_z),
+        ];
+        points.map(|point| point.and_then(|(x, z)| self.covering(x, z, selected)))
+    }
+
+    //The selected node containing quad (x, z)
+    fn covering(&self, x: u32, z: u32, selected: &HashSet<TerrainNode>) -> Option<TerrainNode> {
+        (0..=self.root_level)
+            .map(|level| TerrainNode { level, x: x / self.span(level), z: z / self.span(level) })
+            .find(|node| selected.contains(node))
+    }
+}
+
+//Width, depth and samples, rows from the top of the image
+fn load_heightmap(name: &str) -> Result<(usize, usize, Vec<u16>), Box<dyn Error>> {
+    if let Ok(bytes) = fs::read(format!("resources/textures/{}.r16", name)) {
+        let samples: Vec<u16> = bytes.chunks_exact(2).map(|pair| u16::from_le_bytes([pair[0], pair[1]])).collect();
+        let side = (samples.len() as f64).sqrt() as usize;
+        if side * side != samples.len() {
+            return Err(SimpleError::new(format!("Raw heightmap {} isn't square", name)).into());
+        }
+        return Ok((side, side, samples));
+    }
+
+    let mut contents = fs::read(format!("resources/textures/{}.png", name))?;
+    let (mut width, mut height, mut components) = (0, 0, 0);
+    unsafe {
+        //8 bit images are widened, colors are turned into gray
+        stb_image_rust::stbi_set_flip_vertically_on_load(0);
+        let data = stb_image_rust::stbi_load_16_from_memory(contents.as_mut_ptr(), contents.len() as i32, &mut width, &mut height, &mut components, 1);
+        if data.is_null() {
+            return Err(SimpleError::new(format!("Unable to decode heightmap {}", name)).into());
+        }
+        let samples = std::slice::from_raw_parts(data, width as usize * height as usize).to_vec();
+        stb_image_rust::c_runtime::free(data);
+        Ok((width as usize, height as usize, samples))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //5x5 samples over 8x8 units, rising 1 unit per sample along +X
+    fn ramp() -> Terrain {
+        let descriptor = TerrainDescriptor { size: [8.0, 8.0], height: 4.0, chunk_quads: 2, ..Default::default() };
+        let samples: Vec<u16> = (0..25).map(|i| (i % 5) as u16 * (u16::MAX / 4)).collect();
+        Terrain::from_samples(descriptor, 5, 5, &samples).unwrap()
+    }
+
+    //A single raised sample in the middle of a flat map
+    fn peak() -> Terrain {
+        let descriptor = TerrainDescriptor { size: [4.0, 4.0], height: 1.0, chunk_quads: 2, ..Default::default() };
+        let mut samples = vec![0; 9];
+        samples[4] = u16::MAX;
+        Terrain::from_samples(descriptor, 3, 3, &samples).unwrap()
+    }
+
+    #[test]
+    fn rejects_bad_heightmaps() {
+        assert!(Terrain::from_samples(TerrainDescriptor::default(), 3, 3, &[0; 8]).is_err());
+        assert!(Terrain::from_samples(TerrainDescriptor::default(), 1, 1, &[0]).is_err());
+        let descriptor = TerrainDescriptor { chunk_quads: 3, ..Default::default() };
+        assert!(Terrain::from_samples(descriptor, 3, 3, &[0; 9]).is_err());
+    }
+
+    #[test]
+    fn interpolates_heights() {
+        let terrain = ramp();
+        assert_eq!(terrain.height_range(), (0.0, terrain.sample(4, 0)));
+        assert!((terrain.height_at(-4.0, 0.0).unwrap() - 0.0).abs() < 1e-3);
+        assert!((terrain.height_at(0.0, 0.0).unwrap() - 2.0).abs() < 1e-3);
+        assert!((terrain.height_at(1.0, -3.0).unwrap() - 2.5).abs() < 1e-3);
+        assert!((terrain.height_at(4.0, 4.0).unwrap() - 4.0).abs() < 1e-3);
+        assert_eq!(terrain.height_at(4.1, 0.0), None);
+        assert_eq!(terrain.height_at(0.0, -4.1), None);
+    }
+
+    #[test]
+    fn follows_the_chunk_triangles() {
+        //The diagonal runs from -X -Z to +X +Z, so the point left of it is lerped towards the peak from +Z
+        let terrain = peak();
+        let right = terrain.height_at(-0.5, -1.5).unwrap();
+        let left = terrain.height_at(-1.5, -0.5).unwrap();
+        assert!((right - 0.25).abs() < 1e-3, "{}", right);
+        assert!((left - 0.25).abs() < 1e-3, "{}", left);
+        assert!((terrain.height_at(-1.0, -1.0).unwrap() - 0.5).abs() < 1e-3);
+        assert!((terrain.height_at(0.0, 0.0).unwrap() - 1.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn tilts_normals_against_the_slope() {
+        let terrain = ramp();
+        let normal = terrain.normal_at(0.0, 0.0).unwrap();
+        let expected = Vec3::new(-0.5, 1.0, 0.0).normalize();
+        assert!(normal.abs_diff_eq(expected, 1e-3), "{}", normal);
+        assert_eq!(terrain.normal_at(5.0, 0.0), None);
+    }
+
+    #[test]
+    fn raycasts_onto_the_surface() {
+        let terrain = ramp();
+        let distance = terrain.raycast(Vec3::new(0.0, 10.0, 0.0), Vec3::NEG_Y, 20.0).unwrap();
+        assert!((distance - 8.0).abs() < 1e-3, "{}", distance);
+
+        //Along the ramp towards the rising side
+        let direction = Vec3::new(1.0, -0.25, 0.0).normalize();
+        let distance = terrain.raycast(Vec3::new(-4.0, 1.0, 0.0), direction, 20.0).unwrap();
+        let hit = Vec3::new(-4.0, 1.0, 0.0) + direction * distance;
+        assert!((hit.y - terrain.height_at(hit.x, hit.z).unwrap()).abs() < 1e-3);
+
+        assert_eq!(terrain.raycast(Vec3::new(0.0, 10.0, 0.0), Vec3::Y, 20.0), None);
+        assert_eq!(terrain.raycast(Vec3::new(0.0, 10.0, 0.0), Vec3::NEG_Y, 5.0), None);
+    }
+}
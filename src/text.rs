@@ -0,0 +1,604 @@
+use std::{collections::{HashMap, HashSet}, error::Error, fs, mem, os::raw::c_void, path::Path, ptr};
+
+use bevy_ecs::prelude::*;
+use gl::types::*;
+use glam::*;
+use serde::{Serialize, Deserialize};
+use simple_error::SimpleError;
+use ttf_parser::{Face, GlyphId, OutlineBuilder};
+
+use crate::{gl_state, renderer::{GPUObject, VAO}, mesh, shader::Shader};
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct TextSettings {
+    //Searched in order when a text's font has no glyph for a character
+    pub fallback_fonts: Vec<String>,
+    //Pixels per em glyphs are rasterised at, the distance field keeps them sharp when drawn larger
+    pub glyph_size: u32,
+    //Atlas pixels the distance field reaches outside of an outline
+    pub sdf_spread: u32,
+    pub atlas_size: u32,
+}
+
+impl Default for TextSettings {
+    fn default() -> Self {
+        Self { fallback_fonts: Vec::new(), glyph_size: 48, sdf_spread: 6, atlas_size: 1024 }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum TextSpace {
+    //Position is in pixels from the bottom left of the view, size is in pixels
+    #[default]
+    Screen,
+    //Laid out in the entity's XY plane, size is in world units
+    World,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum TextAlignment {
+    #[default]
+    Left,
+    Center,
+    Right,
+}
+
+#[derive(Clone, Debug)]
+pub struct TextSpan {
+    pub text: String,
+    pub color: Vec4,
+}
+
+//Laid out again whenever it changes, the result is kept in a TextLayout on the same entity
+#[derive(Component, Clone)]
+pub struct Text {
+    //Consecutive runs of text, each with its own color
+    pub spans: Vec<TextSpan>,
+    //resources/fonts/{font}.ttf or .otf, TextSettings::fallback_fonts are tried for missing glyphs
+    pub font: String,
+    //Em size, pixels or world units depending on the space
+    pub size: f32,
+    pub space: TextSpace,
+    //Lines wrap between words past this width, same units as size
+    pub max_width: Option<f32>,
+    pub alignment: TextAlignment,
+    //Multiplier of the font's line height
+    pub line_spacing: f32,
+    //Point of the text block placed at the position, (0, 1) is the top left and (0.5, 0.5) the center
+    pub anchor: Vec2,
+}
+
+impl Text {
+    pub fn new(text: &str, font: &str, size: f32) -> Text {
+        Text { font: font.to_string(), size, ..Default::default() }.with_span(text, Vec4::ONE)
+    }
+
+    pub fn with_span(mut self, text: &str, color: Vec4) -> Text {
+        self.spans.push(TextSpan { text: text.to_string(), color });
+        self
+    }
+}
+
+impl Default for Text {
+    fn default() -> Self {
+        Self {
+            spans: Vec::new(),
+            font: String::new(),
+            size: 16.0,
+            space: TextSpace::Screen,
+            max_width: None,
+            alignment: TextAlignment::Left,
+            line_spacing: 1.0,
+            anchor: Vec2::new(0.0, 1.0),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct GlyphQuad {
+    pub min: Vec2,
+    pub max: Vec2,
+    pub uv_min: Vec2,
+    pub uv_max: Vec2,
+    pub color: Vec4,
+}
+
+//Glyph quads relative to the anchor, in the text's units
+#[derive(Component, Clone, Default)]
+pub struct TextLayout {
+    pub quads: Vec<GlyphQuad>,
+    pub size: Vec2,
+}
+
+//Font file kept in memory, faces are parsed from it when glyphs are needed
+pub struct Font {
+    data: Vec<u8>,
+    units_per_em: f32,
+    //Em units
+    ascender: f32,
+    line_height: f32,
+}
+
+impl Font {
+    pub fn load(name: &str) -> Result<Font, Box<dyn Error>> {
+        let path = ["ttf", "otf"].iter()
+            .map(|extension| format!("resources/fonts/{}.{}", name, extension))
+            .find(|path| Path::new(path).exists())
+            .ok_or_else(|| SimpleError::new(format!("No ttf or otf file for font {}", name)))?;
+        let data = fs::read(&path)?;
+        let face = Face::parse(&data, 0)?;
+        let units_per_em = face.units_per_em() as f32;
+        let ascender = face.ascender() as f32 / units_per_em;
+        let line_height = (face.ascender() - face.descender() + face.line_gap()) as f32 / units_per_em;
+        Ok(Font { data, units_per_em, ascender, line_height })
+    }
+
+    fn face(&self) -> Option<Face<'_>> {
+        Face::parse(&self.data, 0).ok()
+    }
+}
+
+//Outline flattened into line segments, curves are split evenly
+#[derive(Default)]
+struct Outline {
+    segments: Vec<(Vec2, Vec2)>,
+    start: Vec2,
+    current: Vec2,
+}
+
+const CURVE_STEPS: usize = 8;
+
+impl OutlineBuilder for Outline {
+    fn move_to(&mut self, x: f32, y: f32) {
+        self.start = Vec2::new(x, y);
+        self.current = self.start;
+    }
+
+    fn line_to(&mut self, x: f32, y: f32) {
+        let point = Vec2::new(x, y);
+        self.segments.push((self.current, point));
+        self.current = point;
+    }
+
+    fn quad_to(&mut self, x1: f32, y1: f32, x: f32, y: f32) {
+        let (p0, p1, p2) = (self.current, Vec2::new(x1, y1), Vec2::new(x, y));
+        for step in 1..=CURVE_STEPS {
+            let t = step as f32 / CURVE_STEPS as f32;
+            let point = p0.lerp(p1, t).lerp(p1.lerp(p2, t), t);
+            self.line_to(point.x, point.y);
+        }
+    }
+
+    fn curve_to(&mut self, x1: f32, y1: f32, x2: f32, y2: f32, x: f32, y: f32) {
+        let (p0, p1, p2, p3) = (self.current, Vec2::new(x1, y1), Vec2::new(x2, y2), Vec2::new(x, y));
+        for step in 1..=CURVE_STEPS {
+            let t = step as f32 / CURVE_STEPS as f32;
+            let (a, b, c) = (p0.lerp(p1, t), p1.lerp(p2, t), p2.lerp(p3, t));
+            let point = a.lerp(b, t).lerp(b.lerp(c, t), t);
+            self.line_to(point.x, point.y);
+        }
+    }
+
+    fn close(&mut self) {
+        if self.current != self.start {
+            let start = self.start;
+            self.line_to(start.x, start.y);
+        }
+    }
+}
+
+impl Outline {
+    fn distance(&self, point: Vec2) -> f32 {
+        self.segments.iter().map(|(a, b)| {
+            let edge = *b - *a;
+            let t = ((point - *a).dot(edge) / edge.length_squared().max(f32::EPSILON)).clamp(0.0, 1.0);
+            point.distance(*a + edge * t)
+        }).fold(f32::MAX, f32::min)
+    }
+
+    //Non-zero winding, TrueType and CFF contours wind in opposite directions so only the count matters
+    fn is_inside(&self, point: Vec2) -> bool {
+        let mut winding = 0;
+        for (a, b) in &self.segments {
+            let side = (b.x - a.x) * (point.y - a.y) - (point.x - a.x) * (b.y - a.y);
+            if a.y <= point.y && b.y > point.y && side > 0.0 {
+                winding += 1;
+            } else if b.y <= point.y && a.y > point.y && side < 0.0 {
+                winding -= 1;
+            }
+        }
+        winding != 0
+    }
+}
+
+#[derive(Clone, Copy)]
+struct GlyphRegion {
+    uv_min: Vec2,
+    uv_max: Vec2,
+    //Em units from the pen position on the baseline
+    plane_min: Vec2,
+    plane_max: Vec2,
+}
+
+//Single channel distance fields of every glyph used so far, shared by all fonts and packed in shelves
+#[derive(Resource)]
+pub struct GlyphAtlas {
+    texture: u32,
+    size: u32,
+    glyph_size: u32,
+    spread: u32,
+    //Font name to glyph id, None for glyphs without an outline like spaces
+    glyphs: HashMap<String, HashMap<u16, Option<GlyphRegion>>>,
+    shelf_x: u32,
+    shelf_y: u32,
+    shelf_height: u32,
+    is_full: bool,
+    missing_fonts: HashSet<String>,
+}
+
+impl GlyphAtlas {
+    pub fn new(settings: &TextSettings) -> GlyphAtlas {
+        let mut texture = 0;
+        unsafe {
+            gl::GenTextures(1, &mut texture);
+            gl_state::bind_texture(gl::TEXTURE_2D, texture);
+            gl::TexImage2D(gl::TEXTURE_2D, 0, gl::R8 as i32, settings.atlas_size as i32, settings.atlas_size as i32, 0, gl::RED, gl::UNSIGNED_BYTE, ptr::null());
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as i32);
+            gl_state::bind_texture(gl::TEXTURE_2D, 0);
+        }
+        gl_state::label(gl::TEXTURE, texture, "glyph atlas");
+        GlyphAtlas {
+            texture,
+            size: settings.atlas_size,
+            glyph_size: settings.glyph_size,
+            spread: settings.sdf_spread,
+            glyphs: HashMap::new(),
+            shelf_x: 0,
+            shelf_y: 0,
+            shelf_height: 0,
+            is_full: false,
+            missing_fonts: HashSet::new(),
+        }
+    }
+
+    pub fn handle(&self) -> u32 {
+        self.texture
+    }
+
+    //Fonts that failed to load are only reported once
+    pub fn report_missing(&mut self, font: &str, error: &dyn Error) {
+        if self.missing_fonts.insert(font.to_string()) {
+            println!("Unable to load font {}: {}", font, error);
+        }
+    }
+
+    fn glyph(&mut self, font_name: &str, font: &Font, face: &Face, glyph: GlyphId) -> Option<GlyphRegion> {
+        if let Some(region) = self.glyphs.get(font_name).and_then(|glyphs| glyphs.get(&glyph.0)) {
+            return *region;
+        }
+        let region = self.rasterise(font, face, glyph);
+        //A full atlas retries later glyphs in case the text changes, the rest are cached even when empty
+        if region.is_some() || !self.is_full {
+            self.glyphs.entry(font_name.to_string()).or_default().insert(glyph.0, region);
+        }
+        region
+    }
+
+    fn rasterise(&mut self, font: &Font, face: &Face, glyph: GlyphId) -> Option<GlyphRegion> {
+        let mut outline = Outline::default();
+        let bounds = face.outline_glyph(glyph, &mut outline)?;
+        if outline.segments.is_empty() {
+            return None;
+        }
+        let scale = self.glyph_size as f32 / font.units_per_em;
+        let spread = self.spread as f32;
+        let min = Vec2::new(bounds.x_min as f32, bounds.y_min as f32) * scale - spread;
+        let max = Vec2::new(bounds.x_max as f32, bounds.y_max as f32) * scale + spread;
+        let (width, height) = ((max.x - min.x).ceil() as u32, (max.y - min.y).ceil() as u32);
+        let (x, y) = self.allocate(width, height)?;
+
+        //Rows start at the bottom like the rest of the atlas, 0.5 lies on the outline
+        let mut pixels = Vec::with_capacity((width * height) as usize);
+        for row in 0..height {
+            for column in 0..width {
+                let point = (min + Vec2::new(column as f32 + 0.5, row as f32 + 0.5)) / scale;
+                let distance = outline.distance(point) * scale;
+                let signed = if outline.is_inside(point) { distance } else { -distance };
+                pixels.push(((0.5 + signed / (2.0 * spread)).clamp(0.0, 1.0) * 255.0) as u8);
+            }
+        }
+        unsafe {
+            gl_state::bind_texture(gl::TEXTURE_2D, self.texture);
+            gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
+            gl::TexSubImage2D(gl::TEXTURE_2D, 0, x as i32, y as i32, width as i32, height as i32, gl::RED, gl::UNSIGNED_BYTE, pixels.as_ptr() as *const c_void);
+            gl::PixelStorei(gl::UNPACK_ALIGNMENT, 4);
+            gl_state::bind_texture(gl::TEXTURE_2D, 0);
+        }
+
+        let atlas_size = self.size as f32;
+        let size = Vec2::new(width as f32, height as f32);
+        let uv_min = Vec2::new(x as f32, y as f32) / atlas_size;
+        let plane_min = min / self.glyph_size as f32;
+        Some(GlyphRegion { uv_min, uv_max: uv_min + size / atlas_size, plane_min, plane_max: plane_min + size / self.glyph_size as f32 })
+    }
+
+    //Shelf packing with a pixel of padding so linear filtering doesn't pick up neighbours
+    fn allocate(&mut self, width: u32, height: u32) -> Option<(u32, u32)> {
+        let (padded_width, padded_height) = (width + 1, height + 1);
+        if self.shelf_x + padded_width > self.size {
+            self.shelf_y += self.shelf_height;
+            self.shelf_x = 0;
+            self.shelf_height = 0;
+        }
+        if padded_width > self.size || self.shelf_y + padded_height > self.size {
+            if !self.is_full {
+                println!("Glyph atlas is full, increase TextSettings::atlas_size");
+                self.is_full = true;
+            }
+            return None;
+        }
+        let position = (self.shelf_x, self.shelf_y);
+        self.shelf_x += padded_width;
+        self.shelf_height = self.shelf_height.max(padded_height);
+        Some(position)
+    }
+
+    //Shapes the spans with the font and its fallbacks, kerning from the kern table, then wraps and aligns the lines
+    pub fn layout(&mut self, text: &Text, fonts: &[(&str, &Font)]) -> TextLayout {
+        let faces: Vec<(&str, &Font, Face)> = fonts.iter()
+            .filter_map(|(name, font)| font.face().map(|face| (*name, *font, face)))
+            .collect();
+        let primary = match faces.first() {
+            Some((_, font, _)) => *font,
+            None => return TextLayout::default(),
+        };
+
+        struct Shaped {
+            font: usize,
+            glyph: GlyphId,
+            advance: f32,
+            color: Vec4,
+            is_whitespace: bool,
+            is_newline: bool,
+        }
+        let mut shaped: Vec<Shaped> = Vec::new();
+        for span in &text.spans {
+            for character in span.text.chars() {
+                if character == '\n' {
+                    shaped.push(Shaped { font: 0, glyph: GlyphId(0), advance: 0.0, color: span.color, is_whitespace: false, is_newline: true });
+                    continue;
+                }
+                if character.is_control() {
+                    continue;
+                }
+                //Characters no font covers use the primary font's missing glyph box
+                let (font, glyph) = faces.iter().enumerate()
+                    .find_map(|(index, (_, _, face))| face.glyph_index(character).map(|glyph| (index, glyph)))
+                    .unwrap_or((0, GlyphId(0)));
+                let (_, font_data, face) = &faces[font];
+                let advance = face.glyph_hor_advance(glyph).unwrap_or(0) as f32 / font_data.units_per_em;
+                if let Some(previous) = shaped.last_mut().filter(|previous| previous.font == font && !previous.is_newline) {
+                    previous.advance += kerning(face, previous.glyph, glyph) / font_data.units_per_em;
+                }
+                shaped.push(Shaped { font, glyph, advance, color: span.color, is_whitespace: character.is_whitespace(), is_newline: false });
+            }
+        }
+
+        //Greedy wrapping after the last whitespace that fits, words longer than a line are split between characters
+        let max_width = text.max_width.map(|width| width / text.size);
+        let mut lines = Vec::new();
+        let (mut start, mut index, mut x, mut break_at) = (0, 0, 0.0, None);
+        while index < shaped.len() {
+            let character = &shaped[index];
+            if character.is_newline {
+                lines.push(start..index);
+                (start, index, x, break_at) = (index + 1, index + 1, 0.0, None);
+                continue;
+            }
+            if max_width.is_some_and(|limit| x + character.advance > limit) && !character.is_whitespace && index > start {
+                let end = break_at.unwrap_or(index);
+                lines.push(start..end);
+                start = end;
+                while start < shaped.len() && shaped[start].is_whitespace {
+                    start += 1;
+                }
+                (index, x, break_at) = (start, 0.0, None);
+                continue;
+            }
+            x += character.advance;
+            if character.is_whitespace {
+                break_at = Some(index + 1);
+            }
+            index += 1;
+        }
+        lines.push(start..shaped.len());
+
+        //Trailing whitespace doesn't count towards alignment
+        let widths: Vec<f32> = lines.iter().map(|line| {
+            let end = shaped[line.clone()].iter().rposition(|character| !character.is_whitespace).map_or(line.start, |last| line.start + last + 1);
+            shaped[line.start..end].iter().map(|character| character.advance).sum()
+        }).collect();
+        let block_width = max_width.unwrap_or_else(|| widths.iter().cloned().fold(0.0, f32::max));
+        let line_height = primary.line_height * text.line_spacing;
+        let block_height = line_height * (lines.len() - 1) as f32 + primary.line_height;
+        let alignment = match text.alignment {
+            TextAlignment::Left => 0.0,
+            TextAlignment::Center => 0.5,
+            TextAlignment::Right => 1.0,
+        };
+
+        //The block spans x 0 to width and y -height to 0 before moving the anchor to the origin
+        let origin = Vec2::new(text.anchor.x * block_width, (text.anchor.y - 1.0) * block_height);
+        let mut quads = Vec::new();
+        for (line_index, line) in lines.iter().enumerate() {
+            let mut pen = Vec2::new((block_width - widths[line_index]) * alignment, -primary.ascender - line_height * line_index as f32);
+            for character in &shaped[line.clone()] {
+                let (name, font, face) = &faces[character.font];
+                if !character.is_whitespace {
+                    if let Some(region) = self.glyph(name, font, face, character.glyph) {
+                        quads.push(GlyphQuad {
+                            min: (pen + region.plane_min - origin) * text.size,
+                            max: (pen + region.plane_max - origin) * text.size,
+                            uv_min: region.uv_min,
+                            uv_max: region.uv_max,
+                            color: character.color,
+                        });
+                    }
+                }
+                pen.x += character.advance;
+            }
+        }
+        TextLayout { quads, size: Vec2::new(block_width, block_height) * text.size }
+    }
+}
+
+//First horizontal kern subtable with the pair, fonts with only GPOS kerning are left unkerned
+fn kerning(face: &Face, left: GlyphId, right: GlyphId) -> f32 {
+    face.tables().kern.and_then(|kern| {
+        kern.subtables.into_iter()
+            .filter(|subtable| subtable.horizontal && !subtable.variable)
+            .find_map(|subtable| subtable.glyphs_kerning(left, right))
+    }).unwrap_or(0) as f32
+}
+
+impl Drop for GlyphAtlas {
+    fn drop(&mut self) {
+        unsafe {
+            gl_state::delete_textures(1, &self.texture);
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct TextVertex {
+    position: [f32; 3],
+    color: [f32; 4],
+    uv: [f32; 2],
+}
+
+//Batches laid out text into one draw call per space, glyph edges are rebuilt from the distance field in the shader
+#[derive(Resource)]
+pub struct TextRenderer {
+    shader: Shader,
+    vao: VAO,
+    vertex_buffer: u32,
+    index_buffer: u32,
+    //Quads the index buffer currently covers
+    index_capacity: usize,
+    vertices: Vec<TextVertex>,
+}
+
+impl TextRenderer {
+    pub fn new() -> Result<TextRenderer, Box<dyn Error>> {
+        let vao = VAO::new();
+        let (mut vertex_buffer, mut index_buffer) = (0, 0);
+        unsafe {
+            gl::GenBuffers(1, &mut vertex_buffer);
+            gl::GenBuffers(1, &mut index_buffer);
+            vao.bind();
+            gl_state::bind_buffer(gl::ARRAY_BUFFER, vertex_buffer);
+            let stride = mem::size_of::<TextVertex>() as GLsizei;
+            let attributes = [(mesh::POSITION_LOCATION, 3, 0), (mesh::COLOR_LOCATION, 4, 3), (mesh::TEXCOORD_LOCATION, 2, 7)];
+            for (location, size, offset) in attributes {
+                gl::VertexAttribPointer(location, size, gl::FLOAT, gl::FALSE, stride, (offset * mem::size_of::<f32>()) as *const c_void);
+                gl::EnableVertexAttribArray(location);
+            }
+            gl_state::bind_buffer(gl::ELEMENT_ARRAY_BUFFER, index_buffer);
+            vao.label("text");
+            gl_state::label(gl::BUFFER, vertex_buffer, "text vertices");
+            gl_state::label(gl::BUFFER, index_buffer, "text indices");
+            vao.unbind();
+            gl_state::bind_buffer(gl::ARRAY_BUFFER, 0);
+        }
+        Ok(TextRenderer {
+            shader: Shader::new("text")?,
+            vao,
+            vertex_buffer,
+            index_buffer,
+            index_capacity: 0,
+            vertices: Vec::new(),
+        })
+    }
+
+    //Quads are moved by `transform` here, into view space for world text or pixels for screen text
+    pub fn push(&mut self, layout: &TextLayout, transform: &Mat4) {
+        for quad in &layout.quads {
+            let color = quad.color.to_array();
+            let corners = [Vec2::new(0.0, 0.0), Vec2::new(1.0, 0.0), Vec2::new(1.0, 1.0), Vec2::new(0.0, 1.0)];
+            self.vertices.extend(corners.map(|corner| TextVertex {
+                position: transform.transform_point3((quad.min + (quad.max - quad.min) * corner).extend(0.0)).to_array(),
+                color,
+                uv: (quad.uv_min + (quad.uv_max - quad.uv_min) * corner).to_array(),
+            }));
+        }
+    }
+
+    //Draws and clears the queue blended over the bound target, depth tested for world text
+    pub fn flush(&mut self, projection: &Mat4, atlas: &GlyphAtlas, depth_test: bool, is_wireframe: bool) {
+        if self.vertices.is_empty() {
+            return;
+        }
+        let quads = self.vertices.len() / 4;
+        self.reserve_indices(quads);
+        unsafe {
+            gl_state::bind_buffer(gl::ARRAY_BUFFER, self.vertex_buffer);
+            gl::BufferData(gl::ARRAY_BUFFER, (self.vertices.len() * mem::size_of::<TextVertex>()) as GLsizeiptr, self.vertices.as_ptr() as *const c_void, gl::STREAM_DRAW);
+            gl_state::bind_buffer(gl::ARRAY_BUFFER, 0);
+
+            gl::PolygonMode(gl::FRONT_AND_BACK, gl::FILL);
+            gl_state::enable(gl::BLEND);
+            gl_state::blend_func(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+            gl_state::depth_mask(gl::FALSE);
+            if !depth_test {
+                gl_state::disable(gl::DEPTH_TEST);
+            }
+            gl_state::bind_texture(gl::TEXTURE_2D, atlas.handle());
+        }
+        self.shader.bind();
+        self.shader.set_uniform_4x4f("projection".to_string(), None, projection);
+        self.shader.set_uniform_i32("glyphs".to_string(), &0);
+        self.vao.bind();
+        unsafe {
+            gl::DrawElements(gl::TRIANGLES, (quads * 6) as i32, gl::UNSIGNED_INT, ptr::null());
+        }
+        self.vao.unbind();
+        self.shader.unbind();
+        unsafe {
+            gl_state::bind_texture(gl::TEXTURE_2D, 0);
+            gl_state::disable(gl::BLEND);
+            gl_state::depth_mask(gl::TRUE);
+            gl_state::enable(gl::DEPTH_TEST);
+        }
+        crate::renderer::update_wireframe(&is_wireframe);
+        self.vertices.clear();
+    }
+
+    fn reserve_indices(&mut self, quads: usize) {
+        if quads <= self.index_capacity {
+            return;
+        }
+        let capacity = quads.next_power_of_two();
+        let indices: Vec<u32> = (0..capacity as u32).flat_map(|quad| [0, 1, 2, 0, 2, 3].map(|i| quad * 4 + i)).collect();
+        unsafe {
+            self.vao.bind();
+            gl_state::bind_buffer(gl::ELEMENT_ARRAY_BUFFER, self.index_buffer);
+            gl::BufferData(gl::ELEMENT_ARRAY_BUFFER, (indices.len() * mem::size_of::<u32>()) as GLsizeiptr, indices.as_ptr() as *const c_void, gl::STATIC_DRAW);
+            self.vao.unbind();
+        }
+        self.index_capacity = capacity;
+    }
+}
+
+impl Drop for TextRenderer {
+    fn drop(&mut self) {
+        unsafe {
+            gl_state::delete_buffers(1, &self.vertex_buffer);
+            gl_state::delete_buffers(1, &self.index_buffer);
+        }
+    }
+}
@@ -1,72 +1,338 @@
-use std::{os::raw::c_void, fs::File, io::{Error, Read}};
+use std::{fs::{self, File}, io::{Error, ErrorKind, Read}};
 
-use crate::{renderer::{self, GPUObject}};
+use std::f32::consts::PI;
+
+use glam::Vec3;
+
+use crate::{backend::{self, BufferUsage, Handle, Resource, SamplerDescriptor, TextureDescriptor, TextureKind, TextureRegion}, gl_state, material::{ColorSpace, MinificationFilter, TextureOptions}, renderer, texture_formats::{self, PixelFormat, TextureData}};
 
 pub struct Texture {
-    handle: u32,
+    handle: Handle,
+    width: i32,
+    height: i32,
 }
 
 impl Texture {
-    pub fn new(name: &str, mag_filter: u32, aniso_level: f32) -> Result<Texture, Error> {
-        let mut texture: Texture = Texture { handle: 0 };
-        let image = Image::new(&name);
-        if image.is_err() {
-            return Err(image.err().unwrap());
+    //Loads resources/textures/{name} from the first of .ktx2, .dds, .exr, .hdr or .png that exists.
+    //Containers keep their pre-baked mips and are uploaded as stored, so they must be exported bottom row first.
+    pub fn new(name: &str, options: &TextureOptions, aniso_level: f32) -> Result<Texture, Error> {
+        let texture = Texture::from_data(&load_data(name)?, options, aniso_level)?;
+        texture.label(name);
+        Ok(texture)
+    }
+
+    //Same sized layers of the same format stacked into a TEXTURE_2D_ARRAY, sampled with a sampler2DArray
+    pub fn array(names: &[&str], options: &TextureOptions, aniso_level: f32) -> Result<Texture, Error> {
+        let layers = names.iter().map(|name| load_data(name)).collect::<Result<Vec<TextureData>, Error>>()?;
+        let first = layers.first().ok_or_else(|| Error::new(ErrorKind::InvalidInput, "texture arrays need at least one layer"))?;
+        for (name, layer) in names.iter().zip(&layers) {
+            let matches = layer.kind() == TextureKind::Texture2D && layer.format == first.format && (layer.width, layer.height) == (first.width, first.height);
+            if !matches || layer.levels.len() != first.levels.len() {
+                return Err(Error::new(ErrorKind::InvalidData, format!("layer {} doesn't match the size or format of {}", name, names[0])));
+            }
         }
-        let image = image.unwrap();
+        let levels = (0..first.levels.len())
+            .map(|level| layers.iter().flat_map(|layer| layer.levels[level].iter().copied()).collect())
+            .collect();
+        let data = TextureData { levels, layers: layers.len(), ..*first };
+        let texture = Texture::from_data(&data, options, aniso_level)?;
+        texture.label(&names.join(", "));
+        Ok(texture)
+    }
 
-        unsafe {
-            gl::GenTextures(1, &mut texture.handle);
+    //Any shape of texture data. Mips missing from uncompressed data are generated when the filter samples them.
+    pub fn from_data(data: &TextureData, options: &TextureOptions, aniso_level: f32) -> Result<Texture, Error> {
+        data.validate()?;
+        let format = if options.color_space == ColorSpace::Srgb { data.format.srgb() } else { data.format };
+        let kind = data.kind();
+        let generate_mipmaps = data.levels.len() == 1 && options.min_filter.uses_mipmaps() && !format.is_compressed();
+        let level_count = if generate_mipmaps {
+            let largest = if kind == TextureKind::Texture3D { data.width.max(data.height).max(data.depth) } else { data.width.max(data.height) };
+            largest.ilog2() as usize + 1
+        } else {
+            data.levels.len()
+        };
+        let depth = match kind {
+            TextureKind::Texture3D => data.depth,
+            TextureKind::Texture2DArray => data.layers,
+            _ => 1,
+        };
+        let descriptor = TextureDescriptor {
+            kind,
+            internal_format: format.internal_format(),
+            width: data.width as i32,
+            height: data.height as i32,
+            depth: depth as i32,
+            levels: level_count as i32,
+        };
+        let sampler = SamplerDescriptor { mag_filter: options.mag_filter, min_filter: options.min_filter, wrap: options.wrap, anisotropy: aniso_level };
+
+        let texture = Texture::allocate(&descriptor);
+        backend::with(|backend| {
+            for (level, pixels) in data.levels.iter().enumerate() {
+                let (width, height, depth) = data.level_size(level);
+                let depth = if kind == TextureKind::Texture2DArray { data.layers } else { depth };
+                let region = |face| TextureRegion { level: level as i32, face, width: width as i32, height: height as i32, depth: depth as i32, format };
+                if kind == TextureKind::Cubemap {
+                    let face_size = data.image_size(level);
+                    for (face, face_pixels) in pixels.chunks_exact(face_size).take(6).enumerate() {
+                        backend.upload_texture(texture.handle, &region(Some(face as u32)), face_pixels);
+                    }
+                } else {
+                    backend.upload_texture(texture.handle, &region(None), pixels);
+                }
+            }
+            if generate_mipmaps {
+                backend.generate_mipmaps(texture.handle);
+            }
+            backend.set_sampler(texture.handle, &sampler);
+        });
+
+        Ok(texture)
+    }
+}
+
+fn load_data(name: &str) -> Result<TextureData, Error> {
+    match texture_formats::load(name)? {
+        Some(data) => Ok(data),
+        None => Ok(Image::new(name)?.to_data()),
+    }
+}
+
+impl Texture {
+    //Linear float RGB data straight from memory, used for baked lightmaps
+    pub fn from_hdr_image(image: &HdrImage) -> Texture {
+        let (width, height) = (image.width as i32, image.height as i32);
+        let texture = Texture::allocate(&TextureDescriptor { kind: TextureKind::Texture2D, internal_format: gl::RGB16F, width, height, depth: 1, levels: 1 });
+        let region = TextureRegion { level: 0, face: None, width, height, depth: 1, format: RGB_FLOAT };
+        backend::with(|backend| {
+            backend.upload_texture(texture.handle, &region, backend::as_bytes(&image.data));
+            backend.set_sampler(texture.handle, &SamplerDescriptor::clamped(MinificationFilter::Linear));
+        });
+
+        texture
+    }
+
+    //Color grading LUT stored as a horizontal strip of size x size slices, blue picks the slice
+    pub fn lut(name: &str) -> Result<Texture, Error> {
+        let image = Image::new(name)?;
+        let channels = image.componenets as usize;
+        if image.data.is_null() || !(1..=4).contains(&channels) {
+            return Err(Error::new(ErrorKind::InvalidData, format!("LUT {} could not be decoded", name)));
         }
-        texture.bind();
-        unsafe {
-            // set the texture wrapping parameters
-            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::REPEAT as i32); // set texture wrapping to gl::REPEAT (default wrapping method)
-            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::REPEAT as i32);
-            // set texture filtering parameters
-            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR_MIPMAP_LINEAR as i32);
-            gl::TexParameterf(gl::TEXTURE_2D, gl::TEXTURE_MAX_ANISOTROPY_EXT, aniso_level);
-
-            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, mag_filter as i32);
- 
-            gl::TexImage2D(
-                gl::TEXTURE_2D,
-                0,
-                image.componenets,
-                image.width,
-                image.height,
-                0,
-                image.opengl_load_type,
-                gl::UNSIGNED_BYTE,
-                image.data as *const u8 as *const c_void,
-            );
-            gl::GenerateMipmap(gl::TEXTURE_2D);
+        //The strip is as tall as one slice and as wide as all of them
+        let size = image.height as usize;
+        if size == 0 || image.width as usize != size * size {
+            return Err(Error::new(ErrorKind::InvalidData, format!("LUT {} must be {} pixels wide", name, size * size)));
         }
-        texture.unbind();
 
-        return Ok(texture);
+        let pixels = unsafe { std::slice::from_raw_parts(image.data, image.width as usize * size * channels) };
+        let data = lut_volume(pixels, size, channels);
+
+        let size = size as i32;
+        let texture = Texture::allocate(&TextureDescriptor { kind: TextureKind::Texture3D, internal_format: gl::RGB8, width: size, height: size, depth: size, levels: 1 });
+        let format = PixelFormat::Uncompressed { internal_format: gl::RGB8, format: gl::RGB, data_type: gl::UNSIGNED_BYTE, bytes_per_pixel: 3 };
+        let region = TextureRegion { level: 0, face: None, width: size, height: size, depth: size, format };
+        backend::with(|backend| {
+            backend.upload_texture(texture.handle, &region, &data);
+            backend.set_sampler(texture.handle, &SamplerDescriptor::clamped(MinificationFilter::Linear));
+        });
+        texture.label(name);
+
+        Ok(texture)
+    }
+}
+
+//Reorders a flipped strip of size x size slices into RGB texels of a size^3 volume, red varies fastest and blue
+//slowest. Grey strips repeat their only channel.
+fn lut_volume(pixels: &[u8], size: usize, channels: usize) -> Vec<u8> {
+    let mut data: Vec<u8> = Vec::with_capacity(size * size * size * 3);
+    for blue in 0..size {
+        for green in 0..size {
+            for red in 0..size {
+                //Rows were flipped on load, green grows down the image
+                let row = size - 1 - green;
+                let index = (row * size * size + blue * size + red) * channels;
+                for channel in 0..3 {
+                    data.push(pixels[index + channel.min(channels - 1)]);
+                }
+            }
+        }
+    }
+    data
+}
+
+//Cubemaps, faces are in GL order +X, -X, +Y, -Y, +Z, -Z
+impl Texture {
+    //Six faces from resources/textures/{name}/, named px, nx, py, ny, pz and nz
+    pub fn cubemap(name: &str) -> Result<Texture, Error> {
+        //Cubemap faces are stored top row first, unlike every other texture
+        let faces = CUBE_FACES.iter().map(|suffix| Image::load(&format!("{}/{}", name, suffix), false)).collect::<Result<Vec<Image>, Error>>()?;
+        let size = faces[0].width;
+        if faces.iter().any(|face| face.width != size || face.height != size) {
+            return Err(Error::new(ErrorKind::InvalidData, format!("the faces of cubemap {} must be square and the same size", name)));
+        }
+        let texture = Texture::allocate(&TextureDescriptor { kind: TextureKind::Cubemap, internal_format: gl::RGB8, width: size, height: size, depth: 1, levels: mip_count(size) });
+        backend::with(|backend| {
+            for (face, image) in faces.iter().enumerate() {
+                let data = image.to_data();
+                let region = TextureRegion { level: 0, face: Some(face as u32), width: size, height: size, depth: 1, format: data.format };
+                backend.upload_texture(texture.handle, &region, &data.levels[0]);
+            }
+        });
+        texture.finish_cubemap();
+        texture.label(name);
+        Ok(texture)
+    }
+
+    //Resamples an equirectangular panorama onto the six faces
+    pub fn cubemap_from_equirectangular(image: &HdrImage, size: usize) -> Texture {
+        let texture = Texture::allocate(&TextureDescriptor {
+            kind: TextureKind::Cubemap,
+            internal_format: gl::RGB16F,
+            width: size as i32,
+            height: size as i32,
+            depth: 1,
+            levels: mip_count(size as i32),
+        });
+        let mut data = vec![0.0f32; size * size * 3];
+        for face in 0..6 {
+            for y in 0..size {
+                for x in 0..size {
+                    let u = (x as f32 + 0.5) / size as f32 * 2.0 - 1.0;
+                    let v = (y as f32 + 0.5) / size as f32 * 2.0 - 1.0;
+                    let color = image.sample_direction(cube_direction(face, u, v));
+                    data[(y * size + x) * 3..(y * size + x) * 3 + 3].copy_from_slice(&color);
+                }
+            }
+            let region = TextureRegion { level: 0, face: Some(face as u32), width: size as i32, height: size as i32, depth: 1, format: RGB_FLOAT };
+            backend::with(|backend| backend.upload_texture(texture.handle, &region, backend::as_bytes(&data)));
+        }
+        texture.finish_cubemap();
+        texture
+    }
+
+    //Immutable storage that compute shaders can write into as an image, levels include the base level
+    pub fn empty_cubemap(size: i32, levels: i32, internal_format: u32) -> Texture {
+        let texture = Texture::allocate(&TextureDescriptor { kind: TextureKind::Cubemap, internal_format, width: size, height: size, depth: 1, levels });
+        let min_filter = if levels > 1 { MinificationFilter::LinearMipmapLinear } else { MinificationFilter::Linear };
+        backend::with(|backend| backend.set_sampler(texture.handle, &SamplerDescriptor::clamped(min_filter)));
+        texture
+    }
+
+    //2D counterpart of empty_cubemap without mipmaps
+    pub fn empty(width: i32, height: i32, internal_format: u32) -> Texture {
+        let texture = Texture::allocate(&TextureDescriptor { kind: TextureKind::Texture2D, internal_format, width, height, depth: 1, levels: 1 });
+        backend::with(|backend| backend.set_sampler(texture.handle, &SamplerDescriptor::clamped(MinificationFilter::Linear)));
+        texture
+    }
+
+    fn allocate(descriptor: &TextureDescriptor) -> Texture {
+        let handle = backend::with(|backend| backend.create_texture(descriptor));
+        Texture { handle, width: descriptor.width, height: descriptor.height }
+    }
+
+    //Mipmaps let convolution read a blurred environment instead of taking thousands of samples
+    fn finish_cubemap(&self) {
+        backend::with(|backend| {
+            backend.set_sampler(self.handle, &SamplerDescriptor::clamped(MinificationFilter::LinearMipmapLinear));
+            backend.generate_mipmaps(self.handle);
+        });
+    }
+}
+
+//Every level down to 1x1
+fn mip_count(size: i32) -> i32 {
+    (size.max(1) as u32).ilog2() as i32 + 1
+}
+
+const RGB_FLOAT: PixelFormat = PixelFormat::Uncompressed { internal_format: gl::RGB16F, format: gl::RGB, data_type: gl::FLOAT, bytes_per_pixel: 12 };
+
+const CUBE_FACES: [&str; 6] = ["px", "nx", "py", "ny", "pz", "nz"];
+
+//Direction through a face at u, v in -1..1 with v growing down the face, following the GL cubemap layout
+pub fn cube_direction(face: usize, u: f32, v: f32) -> Vec3 {
+    let direction = match face {
+        0 => Vec3::new(1.0, -v, -u),
+        1 => Vec3::new(-1.0, -v, u),
+        2 => Vec3::new(u, 1.0, v),
+        3 => Vec3::new(u, -1.0, -v),
+        4 => Vec3::new(u, -v, 1.0),
+        _ => Vec3::new(-u, -v, -1.0),
+    };
+    direction.normalize()
+}
+
+impl Texture {
+    pub fn handle(&self) -> u32 {
+        self.handle
+    }
+    //Names it in debuggers like RenderDoc
+    pub fn label(&self, name: &str) {
+        backend::with(|backend| backend.label(Resource::Texture(self.handle), name));
+    }
+    //Width and height of level 0
+    pub fn size(&self) -> (i32, i32) {
+        (self.width, self.height)
+    }
+    pub fn bind_to_unit(&self, unit: u32) {
+        bind_to_unit(self.handle, unit);
+    }
+}
+
+//Leaves unit 0 active afterwards like the rest of the renderer expects
+fn bind_to_unit(texture: Handle, unit: u32) {
+    unsafe {
+        gl_state::active_texture(gl::TEXTURE0 + unit);
+    }
+    backend::with(|backend| backend.bind(Resource::Texture(texture)));
+    unsafe {
+        gl_state::active_texture(gl::TEXTURE0);
+    }
+}
+
+//Plain data a shader reads with texelFetch through a samplerBuffer, like the joint matrices of every skinned mesh
+pub struct BufferTexture {
+    handle: Handle,
+}
+
+impl BufferTexture {
+    //internal_format decides how the data is split into texels, e.g. RGBA32F reads four floats at a time
+    pub fn new(internal_format: u32, name: &str) -> BufferTexture {
+        let handle = backend::with(|backend| {
+            let handle = backend.create_buffer_texture(internal_format);
+            backend.label(Resource::Texture(handle), name);
+            handle
+        });
+        BufferTexture { handle }
+    }
+    pub fn update<T: Copy>(&self, usage: BufferUsage, data: &[T]) {
+        backend::with(|backend| backend.update_buffer_texture(self.handle, usage, backend::as_bytes(data)));
+    }
+    pub fn bind_to_unit(&self, unit: u32) {
+        bind_to_unit(self.handle, unit);
+    }
+}
+
+impl Drop for BufferTexture {
+    fn drop(&mut self) {
+        backend::release(Resource::Texture(self.handle));
     }
 }
 
 impl renderer::GPUObject for Texture {
     fn bind(&self) {
-        unsafe {
-            gl::BindTexture(gl::TEXTURE_2D, self.handle);
-        }
+        backend::with(|backend| backend.bind(Resource::Texture(self.handle)));
     }
 
     fn unbind(&self) {
-        unsafe {
-            gl::BindTexture(gl::TEXTURE_2D, 0);
-        }
+        backend::with(|backend| backend.unbind(Resource::Texture(self.handle)));
     }
 }
 
 impl Drop for Texture {
     fn drop(&mut self) {
-        unsafe {
-            gl::DeleteTextures(1, &self.handle);
-        }
+        backend::release(Resource::Texture(self.handle));
     }
 }
 
@@ -80,6 +346,11 @@ struct Image {
 
 impl Image {
     pub fn new(name: &str) -> Result<Image, Error> {
+        Image::load(name, true)
+    }
+
+    //Flipped images start at the bottom row like GL expects for 2D textures
+    pub fn load(name: &str, flip: bool) -> Result<Image, Error> {
         let mut image: Image = Image { width: 0, height: 0, componenets: 0, opengl_load_type: gl::RGB, data: 0 as *mut u8 };
 
         // Load file into memory
@@ -89,11 +360,11 @@ impl Image {
         }
         let mut f = file.unwrap();
         let mut contents = vec![];
-        f.read_to_end(&mut contents).expect("Failed to put data into Vec<u8> in texture!");
+        f.read_to_end(&mut contents)?;
 
         unsafe {
             // load image, create texture and generate mipmaps
-            stb_image_rust::stbi_set_flip_vertically_on_load(true as i32);
+            stb_image_rust::stbi_set_flip_vertically_on_load(flip as i32);
             image.data = stb_image_rust::stbi_load_from_memory(
                 contents.as_mut_ptr(),
                 contents.len() as i32,
@@ -103,6 +374,10 @@ impl Image {
                 0,
             );
         }
+        //stb_image hands back null for files it can't decode
+        if image.data.is_null() {
+            return Err(Error::new(ErrorKind::InvalidData, format!("{}.png is corrupt or not a supported image", name)));
+        }
         image.opengl_load_type = match image.componenets {
             1 => gl::RED,
             2 => gl::RG,
@@ -114,10 +389,235 @@ impl Image {
     }
 }
 
+impl Image {
+    fn to_data(&self) -> TextureData {
+        let (internal_format, bytes_per_pixel) = match self.componenets {
+            1 => (gl::R8, 1),
+            2 => (gl::RG8, 2),
+            4 => (gl::RGBA8, 4),
+            _ => (gl::RGB8, 3),
+        };
+        let size = self.width as usize * self.height as usize * bytes_per_pixel;
+        let pixels = unsafe { std::slice::from_raw_parts(self.data, size) };
+        TextureData {
+            format: PixelFormat::Uncompressed { internal_format, format: self.opengl_load_type, data_type: gl::UNSIGNED_BYTE, bytes_per_pixel },
+            width: self.width as usize,
+            height: self.height as usize,
+            depth: 1,
+            layers: 0,
+            faces: 1,
+            levels: vec![pixels.to_vec()],
+        }
+    }
+}
+
 impl Drop for Image {
     fn drop(&mut self) {
-        unsafe {
-            stb_image_rust::c_runtime::free(self.data);
+        if !self.data.is_null() {
+            unsafe {
+                stb_image_rust::c_runtime::free(self.data);
+            }
+        }
+    }
+}
+
+//Linear RGB float image stored as Radiance RGBE (.hdr), rows go bottom to top like GL textures
+pub struct HdrImage {
+    pub width: usize,
+    pub height: usize,
+    pub data: Vec<f32>,
+}
+
+impl HdrImage {
+    pub fn new(width: usize, height: usize) -> HdrImage {
+        HdrImage { width, height, data: vec![0.0; width * height * 3] }
+    }
+
+    pub fn load(path: &str) -> Result<HdrImage, Error> {
+        let bytes = fs::read(path)?;
+        HdrImage::parse(&bytes).map_err(|error| Error::new(error.kind(), format!("{}: {}", path, error)))
+    }
+
+    pub fn parse(bytes: &[u8]) -> Result<HdrImage, Error> {
+        let invalid = |message: &str| Error::new(ErrorKind::InvalidData, message.to_string());
+
+        //Header lines end with an empty line, followed by the resolution line
+        let mut cursor = 0;
+        let next_line = |cursor: &mut usize| -> Option<String> {
+            let start = *cursor;
+            let end = start + bytes[start..].iter().position(|b| *b == b'\n')?;
+            *cursor = end + 1;
+            Some(String::from_utf8_lossy(&bytes[start..end]).to_string())
+        };
+        let magic = next_line(&mut cursor).ok_or_else(|| invalid("missing header"))?;
+        if !magic.starts_with("#?") {
+            return Err(invalid("not a radiance file"));
+        }
+        loop {
+            let line = next_line(&mut cursor).ok_or_else(|| invalid("unterminated header"))?;
+            if line.is_empty() {
+                break;
+            }
+            if line.starts_with("FORMAT=") && line != "FORMAT=32-bit_rle_rgbe" {
+                return Err(invalid("only rgbe is supported"));
+            }
         }
+        let resolution = next_line(&mut cursor).ok_or_else(|| invalid("missing resolution"))?;
+        let parts: Vec<&str> = resolution.split_whitespace().collect();
+        if parts.len() != 4 || parts[0] != "-Y" || parts[2] != "+X" {
+            return Err(invalid("unsupported orientation"));
+        }
+        let height: usize = parts[1].parse().map_err(|_| invalid("bad height"))?;
+        let width: usize = parts[3].parse().map_err(|_| invalid("bad width"))?;
+        if width == 0 || height == 0 || width > texture_formats::MAX_SIZE || height > texture_formats::MAX_SIZE {
+            return Err(invalid("unsupported size"));
+        }
+        //Run length encoded scanlines still take two bytes per run of every channel, so a short file can't claim a huge image
+        let smallest_scanline = if width < 8 { 4 * width } else { 4 + 8 * width.div_ceil(127) };
+        let texels = width.checked_mul(height).and_then(|texels| texels.checked_mul(3)).ok_or_else(|| invalid("image too large"))?;
+        if height.checked_mul(smallest_scanline).is_none_or(|needed| needed > bytes.len() - cursor) {
+            return Err(invalid("truncated pixel data"));
+        }
+
+        let mut image = HdrImage { width, height, data: vec![0.0; texels] };
+        let mut scanline = vec![[0u8; 4]; width];
+        for row in 0..height {
+            cursor = read_scanline(bytes, cursor, &mut scanline).ok_or_else(|| invalid("truncated pixel data"))?;
+            let y = height - 1 - row;
+            for (x, rgbe) in scanline.iter().enumerate() {
+                let color = rgbe_to_float(*rgbe);
+                image.data[(y * width + x) * 3..(y * width + x) * 3 + 3].copy_from_slice(&color);
+            }
+        }
+        Ok(image)
+    }
+
+    //Writes flat (uncompressed) scanlines, every reader supports them
+    pub fn save(&self, path: &str) -> Result<(), Error> {
+        let mut bytes = format!("#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {} +X {}\n", self.height, self.width).into_bytes();
+        bytes.reserve(self.width * self.height * 4);
+        for row in 0..self.height {
+            let y = self.height - 1 - row;
+            for x in 0..self.width {
+                let i = (y * self.width + x) * 3;
+                bytes.extend_from_slice(&float_to_rgbe([self.data[i], self.data[i + 1], self.data[i + 2]]));
+            }
+        }
+        fs::write(path, bytes)
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, color: [f32; 3]) {
+        let i = (y * self.width + x) * 3;
+        self.data[i..i + 3].copy_from_slice(&color);
     }
-}
\ No newline at end of file
+
+    //Bilinear lookup treating the image as an equirectangular panorama, rows run bottom to top so +Y is the last row
+    pub fn sample_direction(&self, direction: Vec3) -> [f32; 3] {
+        let u = direction.z.atan2(direction.x) / (2.0 * PI) + 0.5;
+        let v = direction.y.clamp(-1.0, 1.0).asin() / PI + 0.5;
+        let x = u * self.width as f32 - 0.5;
+        let y = (v * self.height as f32 - 0.5).clamp(0.0, self.height as f32 - 1.0);
+        let (x0, y0) = (x.floor(), y.floor());
+        let (tx, ty) = (x - x0, y - y0);
+        //Wraps horizontally around the seam, clamps at the poles
+        let texel = |x: f32, y: f32| {
+            let x = (x as i64).rem_euclid(self.width as i64) as usize;
+            let y = (y as usize).min(self.height - 1);
+            let i = (y * self.width + x) * 3;
+            Vec3::new(self.data[i], self.data[i + 1], self.data[i + 2])
+        };
+        let below = texel(x0, y0).lerp(texel(x0 + 1.0, y0), tx);
+        let above = texel(x0, y0 + 1.0).lerp(texel(x0 + 1.0, y0 + 1.0), tx);
+        below.lerp(above, ty).to_array()
+    }
+}
+
+fn read_scanline(bytes: &[u8], mut cursor: usize, scanline: &mut [[u8; 4]]) -> Option<usize> {
+    let width = scanline.len();
+    let header = bytes.get(cursor..cursor + 4)?;
+    let is_rle = (8..0x8000).contains(&width) && header[0] == 2 && header[1] == 2 && header[2] & 0x80 == 0
+        && ((header[2] as usize) << 8 | header[3] as usize) == width;
+    if !is_rle {
+        for pixel in scanline.iter_mut() {
+            pixel.copy_from_slice(bytes.get(cursor..cursor + 4)?);
+            cursor += 4;
+        }
+        return Some(cursor);
+    }
+
+    //New style run length encoding stores each channel separately
+    cursor += 4;
+    for channel in 0..4 {
+        let mut x = 0;
+        while x < width {
+            let count = *bytes.get(cursor)? as usize;
+            cursor += 1;
+            if count > 128 {
+                let value = *bytes.get(cursor)?;
+                cursor += 1;
+                for _ in 0..count - 128 {
+                    scanline.get_mut(x)?[channel] = value;
+                    x += 1;
+                }
+            } else {
+                for _ in 0..count {
+                    scanline.get_mut(x)?[channel] = *bytes.get(cursor)?;
+                    cursor += 1;
+                    x += 1;
+                }
+            }
+        }
+    }
+    Some(cursor)
+}
+
+fn float_to_rgbe(color: [f32; 3]) -> [u8; 4] {
+    let max = color[0].max(color[1]).max(color[2]);
+    if max < 1e-32 {
+        return [0, 0, 0, 0];
+    }
+    let (mantissa, exponent) = libm::frexpf(max);
+    let scale = mantissa * 256.0 / max;
+    [
+        (color[0].max(0.0) * scale) as u8,
+        (color[1].max(0.0) * scale) as u8,
+        (color[2].max(0.0) * scale) as u8,
+        (exponent + 128) as u8,
+    ]
+}
+
+fn rgbe_to_float(rgbe: [u8; 4]) -> [f32; 3] {
+    if rgbe[3] == 0 {
+        return [0.0; 3];
+    }
+    let scale = libm::ldexpf(1.0, rgbe[3] as i32 - (128 + 8));
+    [(rgbe[0] as f32 + 0.5) * scale, (rgbe[1] as f32 + 0.5) * scale, (rgbe[2] as f32 + 0.5) * scale]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unpacks_lut_strips_into_volumes() {
+        //2x2x2 strip of RGBA pixels holding their own coordinates, bottom row first like after the flip on load
+        let size = 2;
+        let mut pixels = vec![0; size * size * size * 4];
+        for row in 0..size {
+            for x in 0..size * size {
+                let (green, blue, red) = (size - 1 - row, x / size, x % size);
+                let index = (row * size * size + x) * 4;
+                pixels[index..index + 4].copy_from_slice(&[red as u8, green as u8, blue as u8, 255]);
+            }
+        }
+        let volume = lut_volume(&pixels, size, 4);
+        let texels: Vec<&[u8]> = volume.chunks_exact(3).collect();
+        assert_eq!(texels.len(), 8);
+        for (index, texel) in texels.iter().enumerate() {
+            assert_eq!(*texel, [index % 2, index / 2 % 2, index / 4].map(|value| value as u8));
+        }
+
+        assert_eq!(lut_volume(&[9], 1, 1), [9, 9, 9]);
+    }
+}
+
@@ -0,0 +1,705 @@
+use std::{fs, io::{Error, ErrorKind}, path::Path};
+
+use crate::{backend::TextureKind, texture::HdrImage};
+
+//GL upload parameters of a texel format
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum PixelFormat {
+    Uncompressed { internal_format: u32, format: u32, data_type: u32, bytes_per_pixel: usize },
+    //Block sizes are in texels
+    Compressed { internal_format: u32, block_width: usize, block_height: usize, block_bytes: usize },
+}
+
+const fn uncompressed(internal_format: u32, format: u32, data_type: u32, bytes_per_pixel: usize) -> PixelFormat {
+    PixelFormat::Uncompressed { internal_format, format, data_type, bytes_per_pixel }
+}
+
+//Every BC format uses 4x4 blocks
+const fn bc(internal_format: u32, block_bytes: usize) -> PixelFormat {
+    PixelFormat::Compressed { internal_format, block_width: 4, block_height: 4, block_bytes }
+}
+
+//Footprints in the order of the GL and Vulkan ASTC enums
+const ASTC_BLOCKS: [(usize, usize); 14] = [
+    (4, 4), (5, 4), (5, 5), (6, 5), (6, 6), (8, 5), (8, 6), (8, 8), (10, 5), (10, 6), (10, 8), (10, 10), (12, 10), (12, 12),
+];
+
+fn astc(block: usize, srgb: bool) -> Option<PixelFormat> {
+    let (block_width, block_height) = *ASTC_BLOCKS.get(block)?;
+    let base = if srgb { gl::COMPRESSED_SRGB8_ALPHA8_ASTC_4x4_KHR } else { gl::COMPRESSED_RGBA_ASTC_4x4_KHR };
+    Some(PixelFormat::Compressed { internal_format: base + block as u32, block_width, block_height, block_bytes: 16 })
+}
+
+impl PixelFormat {
+    pub fn internal_format(&self) -> u32 {
+        match *self {
+            PixelFormat::Uncompressed { internal_format, .. } | PixelFormat::Compressed { internal_format, .. } => internal_format,
+        }
+    }
+
+    pub fn is_compressed(&self) -> bool {
+        matches!(self, PixelFormat::Compressed { .. })
+    }
+
+    //Bytes of a single width x height image
+    pub fn image_size(&self, width: usize, height: usize) -> usize {
+        match *self {
+            PixelFormat::Uncompressed { bytes_per_pixel, .. } => width * height * bytes_per_pixel,
+            PixelFormat::Compressed { block_width, block_height, block_bytes, .. } => {
+                width.div_ceil(block_width) * height.div_ceil(block_height) * block_bytes
+            }
+        }
+    }
+
+    //The variant that decodes sRGB when sampled, formats without one are returned unchanged
+    pub fn srgb(self) -> PixelFormat {
+        let srgb = match self.internal_format() {
+            gl::RGB8 => gl::SRGB8,
+            gl::RGBA8 => gl::SRGB8_ALPHA8,
+            gl::COMPRESSED_RGB_S3TC_DXT1_EXT => gl::COMPRESSED_SRGB_S3TC_DXT1_EXT,
+            gl::COMPRESSED_RGBA_S3TC_DXT1_EXT => gl::COMPRESSED_SRGB_ALPHA_S3TC_DXT1_EXT,
+            gl::COMPRESSED_RGBA_S3TC_DXT3_EXT => gl::COMPRESSED_SRGB_ALPHA_S3TC_DXT3_EXT,
+            gl::COMPRESSED_RGBA_S3TC_DXT5_EXT => gl::COMPRESSED_SRGB_ALPHA_S3TC_DXT5_EXT,
+            gl::COMPRESSED_RGBA_BPTC_UNORM => gl::COMPRESSED_SRGB_ALPHA_BPTC_UNORM,
+            format @ gl::COMPRESSED_RGBA_ASTC_4x4_KHR..=gl::COMPRESSED_RGBA_ASTC_12x12_KHR => {
+                format - gl::COMPRESSED_RGBA_ASTC_4x4_KHR + gl::COMPRESSED_SRGB8_ALPHA8_ASTC_4x4_KHR
+            }
+            other => other,
+        };
+        match self {
+            PixelFormat::Uncompressed { format, data_type, bytes_per_pixel, .. } => uncompressed(srgb, format, data_type, bytes_per_pixel),
+            PixelFormat::Compressed { block_width, block_height, block_bytes, .. } => {
+                PixelFormat::Compressed { internal_format: srgb, block_width, block_height, block_bytes }
+            }
+        }
+    }
+}
+
+//Anything larger is treated as a corrupt header rather than allocated
+pub const MAX_SIZE: usize = 16384;
+const MAX_LAYERS: usize = 2048;
+const MAX_LEVELS: usize = 15;
+
+//Texels of any texture shape as read from disk
+pub struct TextureData {
+    pub format: PixelFormat,
+    pub width: usize,
+    pub height: usize,
+    //1 unless this is a 3D texture
+    pub depth: usize,
+    //0 unless this is an array texture
+    pub layers: usize,
+    //6 for cubemaps, 1 otherwise
+    pub faces: usize,
+    //Finest level first, each level holds every layer, then every face, then every depth slice
+    pub levels: Vec<Vec<u8>>,
+}
+
+impl TextureData {
+    pub fn kind(&self) -> TextureKind {
+        match (self.faces, self.layers, self.depth) {
+            (6, _, _) => TextureKind::Cubemap,
+            (_, 0, 1) => TextureKind::Texture2D,
+            (_, 0, _) => TextureKind::Texture3D,
+            _ => TextureKind::Texture2DArray,
+        }
+    }
+
+    //Width, height and depth of a mip level, array layers don't shrink
+    pub fn level_size(&self, level: usize) -> (usize, usize, usize) {
+        ((self.width >> level).max(1), (self.height >> level).max(1), (self.depth >> level).max(1))
+    }
+
+    //Bytes of one face or layer of a level, including every depth slice
+    pub fn image_size(&self, level: usize) -> usize {
+        let (width, height, depth) = self.level_size(level);
+        self.format.image_size(width, height) * depth
+    }
+
+    //Keeps the size arithmetic below from overflowing on corrupt headers
+    fn check_limits(&self, level_count: usize) -> Result<(), Error> {
+        if self.width > MAX_SIZE || self.height > MAX_SIZE || self.depth > MAX_SIZE || self.layers > MAX_LAYERS || level_count > MAX_LEVELS {
+            return Err(invalid(&format!("{}x{}x{} texture with {} layers and {} levels is too large", self.width, self.height, self.depth, self.layers, level_count)));
+        }
+        Ok(())
+    }
+
+    //Catches truncated files and shapes GL can't represent before anything is uploaded
+    pub fn validate(&self) -> Result<(), Error> {
+        self.check_limits(self.levels.len())?;
+        if self.width == 0 || self.height == 0 || self.levels.is_empty() {
+            return Err(invalid("empty texture"));
+        }
+        if self.faces != 1 && self.faces != 6 {
+            return Err(invalid("cubemaps need six faces"));
+        }
+        if self.faces == 6 && self.layers > 0 {
+            return Err(invalid("cubemap arrays are not supported"));
+        }
+        if self.layers > 0 && self.depth > 1 {
+            return Err(invalid("arrays of 3D textures are not supported"));
+        }
+        for (level, pixels) in self.levels.iter().enumerate() {
+            if pixels.len() < self.image_size(level) * self.layers.max(1) * self.faces {
+                return Err(invalid(&format!("mip level {} is truncated", level)));
+            }
+        }
+        Ok(())
+    }
+}
+
+//Formats tried for resources/textures/{name}, containers with pre-baked mips first. PNGs are left to stb_image.
+const EXTENSIONS: [&str; 4] = ["ktx2", "dds", "exr", "hdr"];
+
+//Returns None when the texture only exists as a PNG
+pub fn load(name: &str) -> Result<Option<TextureData>, Error> {
+    for extension in EXTENSIONS {
+        let path = format!("resources/textures/{}.{}", name, extension);
+        if !Path::new(&path).exists() {
+            continue;
+        }
+        let data = match extension {
+            "ktx2" => parse_ktx2(&fs::read(&path)?),
+            "dds" => parse_dds(&fs::read(&path)?),
+            "exr" => parse_exr(&fs::read(&path)?),
+            _ => Ok(from_hdr_image(&HdrImage::load(&path)?)),
+        };
+        return data.map(Some).map_err(|error| Error::new(error.kind(), format!("{}: {}", path, error)));
+    }
+    Ok(None)
+}
+
+fn invalid(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message.to_string())
+}
+
+//Offsets and lengths come from the file, so the end is computed without overflowing
+fn slice(bytes: &[u8], offset: usize, length: usize) -> Result<&[u8], Error> {
+    offset.checked_add(length).and_then(|end| bytes.get(offset..end)).ok_or_else(|| invalid("unexpected end of file"))
+}
+
+fn read<const N: usize>(bytes: &[u8], offset: usize) -> Result<[u8; N], Error> {
+    slice(bytes, offset, N).map(|slice| slice.try_into().unwrap())
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> Result<u32, Error> {
+    Ok(u32::from_le_bytes(read(bytes, offset)?))
+}
+
+fn u64_at(bytes: &[u8], offset: usize) -> Result<u64, Error> {
+    Ok(u64::from_le_bytes(read(bytes, offset)?))
+}
+
+fn i32_at(bytes: &[u8], offset: usize) -> Result<i32, Error> {
+    Ok(i32::from_le_bytes(read(bytes, offset)?))
+}
+
+fn floats_to_bytes(data: &[f32]) -> Vec<u8> {
+    data.iter().flat_map(|value| value.to_le_bytes()).collect()
+}
+
+fn from_hdr_image(image: &HdrImage) -> TextureData {
+    TextureData {
+        format: uncompressed(gl::RGB16F, gl::RGB, gl::FLOAT, 12),
+        width: image.width,
+        height: image.height,
+        depth: 1,
+        layers: 0,
+        faces: 1,
+        levels: vec![floats_to_bytes(&image.data)],
+    }
+}
+
+const KTX2_IDENTIFIER: [u8; 12] = [0xAB, b'K', b'T', b'X', b' ', b'2', b'0', 0xBB, b'\r', b'\n', 0x1A, b'\n'];
+
+//KTX2 without supercompression. Levels are already stored layer, face, slice in order.
+fn parse_ktx2(bytes: &[u8]) -> Result<TextureData, Error> {
+    if read::<12>(bytes, 0)? != KTX2_IDENTIFIER {
+        return Err(invalid("not a KTX2 file"));
+    }
+    let vk_format = u32_at(bytes, 12)?;
+    let format = vulkan_format(vk_format).ok_or_else(|| invalid(&format!("unsupported vkFormat {}", vk_format)))?;
+    if u32_at(bytes, 44)? != 0 {
+        return Err(invalid("supercompressed KTX2 files are not supported"));
+    }
+    //A level count of 0 asks the loader to generate mipmaps, only the base level is stored
+    let level_count = (u32_at(bytes, 40)? as usize).max(1);
+    let mut data = TextureData {
+        format,
+        width: u32_at(bytes, 20)? as usize,
+        height: (u32_at(bytes, 24)? as usize).max(1),
+        depth: (u32_at(bytes, 28)? as usize).max(1),
+        layers: u32_at(bytes, 32)? as usize,
+        faces: u32_at(bytes, 36)? as usize,
+        levels: Vec::new(),
+    };
+    data.check_limits(level_count)?;
+    data.levels.reserve(level_count);
+    for level in 0..level_count {
+        let entry = 80 + level * 24;
+        let offset = u64_at(bytes, entry)?;
+        let length = u64_at(bytes, entry + 8)?;
+        let pixels = usize::try_from(offset).ok().zip(usize::try_from(length).ok())
+            .and_then(|(offset, length)| slice(bytes, offset, length).ok())
+            .ok_or_else(|| invalid("level data past the end of file"))?;
+        data.levels.push(pixels.to_vec());
+    }
+    Ok(data)
+}
+
+fn vulkan_format(vk_format: u32) -> Option<PixelFormat> {
+    Some(match vk_format {
+        9 => uncompressed(gl::R8, gl::RED, gl::UNSIGNED_BYTE, 1),
+        16 => uncompressed(gl::RG8, gl::RG, gl::UNSIGNED_BYTE, 2),
+        23 => uncompressed(gl::RGB8, gl::RGB, gl::UNSIGNED_BYTE, 3),
+        29 => uncompressed(gl::SRGB8, gl::RGB, gl::UNSIGNED_BYTE, 3),
+        37 => uncompressed(gl::RGBA8, gl::RGBA, gl::UNSIGNED_BYTE, 4),
+        43 => uncompressed(gl::SRGB8_ALPHA8, gl::RGBA, gl::UNSIGNED_BYTE, 4),
+        97 => uncompressed(gl::RGBA16F, gl::RGBA, gl::HALF_FLOAT, 8),
+        109 => uncompressed(gl::RGBA32F, gl::RGBA, gl::FLOAT, 16),
+        131 => bc(gl::COMPRESSED_RGB_S3TC_DXT1_EXT, 8),
+        132 => bc(gl::COMPRESSED_SRGB_S3TC_DXT1_EXT, 8),
+        133 => bc(gl::COMPRESSED_RGBA_S3TC_DXT1_EXT, 8),
+        134 => bc(gl::COMPRESSED_SRGB_ALPHA_S3TC_DXT1_EXT, 8),
+        135 => bc(gl::COMPRESSED_RGBA_S3TC_DXT3_EXT, 16),
+        136 => bc(gl::COMPRESSED_SRGB_ALPHA_S3TC_DXT3_EXT, 16),
+        137 => bc(gl::COMPRESSED_RGBA_S3TC_DXT5_EXT, 16),
+        138 => bc(gl::COMPRESSED_SRGB_ALPHA_S3TC_DXT5_EXT, 16),
+        139 => bc(gl::COMPRESSED_RED_RGTC1, 8),
+        140 => bc(gl::COMPRESSED_SIGNED_RED_RGTC1, 8),
+        141 => bc(gl::COMPRESSED_RG_RGTC2, 16),
+        142 => bc(gl::COMPRESSED_SIGNED_RG_RGTC2, 16),
+        143 => bc(gl::COMPRESSED_RGB_BPTC_UNSIGNED_FLOAT, 16),
+        144 => bc(gl::COMPRESSED_RGB_BPTC_SIGNED_FLOAT, 16),
+        145 => bc(gl::COMPRESSED_RGBA_BPTC_UNORM, 16),
+        146 => bc(gl::COMPRESSED_SRGB_ALPHA_BPTC_UNORM, 16),
+        //UNORM and SRGB alternate for every block footprint
+        157..=184 => return astc((vk_format - 157) as usize / 2, vk_format.is_multiple_of(2)),
+        _ => return None,
+    })
+}
+
+const DDSD_DEPTH: u32 = 0x800000;
+const DDPF_FOURCC: u32 = 0x4;
+const DDPF_RGB: u32 = 0x40;
+const DDSCAPS2_CUBEMAP: u32 = 0x200;
+const DDS_RESOURCE_MISC_TEXTURECUBE: u32 = 0x4;
+
+//DDS stores every mip of an image before the next layer or face, so levels are regathered to match KTX2
+fn parse_dds(bytes: &[u8]) -> Result<TextureData, Error> {
+    if read::<4>(bytes, 0)? != *b"DDS " {
+        return Err(invalid("not a DDS file"));
+    }
+    let flags = u32_at(bytes, 8)?;
+    let height = u32_at(bytes, 12)? as usize;
+    let width = u32_at(bytes, 16)? as usize;
+    let depth = if flags & DDSD_DEPTH != 0 { (u32_at(bytes, 24)? as usize).max(1) } else { 1 };
+    let level_count = (u32_at(bytes, 28)? as usize).max(1);
+    let four_cc = read::<4>(bytes, 84)?;
+
+    let (format, layers, faces, mut offset) = if &four_cc == b"DX10" {
+        let dxgi_format = u32_at(bytes, 128)?;
+        let format = dxgi_format_of(dxgi_format).ok_or_else(|| invalid(&format!("unsupported DXGI format {}", dxgi_format)))?;
+        let faces = if u32_at(bytes, 136)? & DDS_RESOURCE_MISC_TEXTURECUBE != 0 { 6 } else { 1 };
+        let array_size = u32_at(bytes, 140)? as usize;
+        (format, if array_size > 1 { array_size } else { 0 }, faces, 148)
+    } else {
+        let format = legacy_dds_format(u32_at(bytes, 80)?, &four_cc, u32_at(bytes, 88)?, u32_at(bytes, 92)?)
+            .ok_or_else(|| invalid("unsupported pixel format"))?;
+        let faces = if u32_at(bytes, 112)? & DDSCAPS2_CUBEMAP != 0 { 6 } else { 1 };
+        (format, 0, faces, 128)
+    };
+
+    let mut data = TextureData { format, width, height, depth, layers, faces, levels: Vec::new() };
+    data.check_limits(level_count)?;
+    data.levels = vec![Vec::new(); level_count];
+    for _ in 0..layers.max(1) * faces {
+        for level in 0..level_count {
+            let size = data.image_size(level);
+            let pixels = slice(bytes, offset, size)?;
+            data.levels[level].extend_from_slice(pixels);
+            offset += size;
+        }
+    }
+    Ok(data)
+}
+
+fn legacy_dds_format(flags: u32, four_cc: &[u8; 4], bit_count: u32, red_mask: u32) -> Option<PixelFormat> {
+    if flags & DDPF_FOURCC != 0 {
+        return Some(match four_cc {
+            //DXT1 may carry 1 bit alpha, the RGBA variant decodes both
+            b"DXT1" => bc(gl::COMPRESSED_RGBA_S3TC_DXT1_EXT, 8),
+            b"DXT3" => bc(gl::COMPRESSED_RGBA_S3TC_DXT3_EXT, 16),
+            b"DXT5" => bc(gl::COMPRESSED_RGBA_S3TC_DXT5_EXT, 16),
+            b"ATI1" | b"BC4U" => bc(gl::COMPRESSED_RED_RGTC1, 8),
+            b"ATI2" | b"BC5U" => bc(gl::COMPRESSED_RG_RGTC2, 16),
+            _ => return None,
+        });
+    }
+    match (flags & DDPF_RGB != 0, bit_count, red_mask) {
+        (true, 32, 0x000000ff) => Some(uncompressed(gl::RGBA8, gl::RGBA, gl::UNSIGNED_BYTE, 4)),
+        (true, 32, 0x00ff0000) => Some(uncompressed(gl::RGBA8, gl::BGRA, gl::UNSIGNED_BYTE, 4)),
+        _ => None,
+    }
+}
+
+fn dxgi_format_of(dxgi_format: u32) -> Option<PixelFormat> {
+    Some(match dxgi_format {
+        2 => uncompressed(gl::RGBA32F, gl::RGBA, gl::FLOAT, 16),
+        10 => uncompressed(gl::RGBA16F, gl::RGBA, gl::HALF_FLOAT, 8),
+        28 => uncompressed(gl::RGBA8, gl::RGBA, gl::UNSIGNED_BYTE, 4),
+        29 => uncompressed(gl::SRGB8_ALPHA8, gl::RGBA, gl::UNSIGNED_BYTE, 4),
+        87 => uncompressed(gl::RGBA8, gl::BGRA, gl::UNSIGNED_BYTE, 4),
+        91 => uncompressed(gl::SRGB8_ALPHA8, gl::BGRA, gl::UNSIGNED_BYTE, 4),
+        71 => bc(gl::COMPRESSED_RGBA_S3TC_DXT1_EXT, 8),
+        72 => bc(gl::COMPRESSED_SRGB_ALPHA_S3TC_DXT1_EXT, 8),
+        74 => bc(gl::COMPRESSED_RGBA_S3TC_DXT3_EXT, 16),
+        75 => bc(gl::COMPRESSED_SRGB_ALPHA_S3TC_DXT3_EXT, 16),
+        77 => bc(gl::COMPRESSED_RGBA_S3TC_DXT5_EXT, 16),
+        78 => bc(gl::COMPRESSED_SRGB_ALPHA_S3TC_DXT5_EXT, 16),
+        80 => bc(gl::COMPRESSED_RED_RGTC1, 8),
+        81 => bc(gl::COMPRESSED_SIGNED_RED_RGTC1, 8),
+        83 => bc(gl::COMPRESSED_RG_RGTC2, 16),
+        84 => bc(gl::COMPRESSED_SIGNED_RG_RGTC2, 16),
+        95 => bc(gl::COMPRESSED_RGB_BPTC_UNSIGNED_FLOAT, 16),
+        96 => bc(gl::COMPRESSED_RGB_BPTC_SIGNED_FLOAT, 16),
+        98 => bc(gl::COMPRESSED_RGBA_BPTC_UNORM, 16),
+        99 => bc(gl::COMPRESSED_SRGB_ALPHA_BPTC_UNORM, 16),
+        _ => return None,
+    })
+}
+
+const EXR_MAGIC: [u8; 4] = [0x76, 0x2f, 0x31, 0x01];
+const EXR_TILED: u32 = 0x200;
+const EXR_MULTI_PART: u32 = 0x1000;
+
+//Single part scanline OpenEXR without compression, read into RGBA floats. Missing channels read 0, alpha 1.
+fn parse_exr(bytes: &[u8]) -> Result<TextureData, Error> {
+    if read::<4>(bytes, 0)? != EXR_MAGIC {
+        return Err(invalid("not an OpenEXR file"));
+    }
+    if u32_at(bytes, 4)? & (EXR_TILED | EXR_MULTI_PART) != 0 {
+        return Err(invalid("only single part scanline images are supported"));
+    }
+
+    //Attributes are name, type, size and value until an empty name
+    let mut cursor = 8;
+    let mut channels: Vec<(String, u32)> = Vec::new();
+    let mut compression = None;
+    let mut window = None;
+    loop {
+        let name = read_string(bytes, &mut cursor)?;
+        if name.is_empty() {
+            break;
+        }
+        read_string(bytes, &mut cursor)?;
+        let size = u32_at(bytes, cursor)? as usize;
+        let value = slice(bytes, cursor + 4, size)?;
+        cursor += 4 + size;
+        match name.as_str() {
+            "channels" => {
+                let mut offset = 0;
+                loop {
+                    let channel = read_string(value, &mut offset)?;
+                    if channel.is_empty() {
+                        break;
+                    }
+                    channels.push((channel, u32_at(value, offset)?));
+                    offset += 16;
+                }
+            }
+            "compression" => compression = value.first().copied(),
+            "dataWindow" => window = Some([i32_at(value, 0)?, i32_at(value, 4)?, i32_at(value, 8)?, i32_at(value, 12)?]),
+            _ => {}
+        }
+    }
+    if compression != Some(0) {
+        return Err(invalid("only uncompressed images are supported"));
+    }
+    if channels.is_empty() {
+        return Err(invalid("no channels"));
+    }
+    let [x_min, y_min, x_max, y_max] = window.ok_or_else(|| invalid("missing dataWindow"))?;
+    let extent = |min: i32, max: i32| (max as i64 - min as i64 + 1).clamp(0, MAX_SIZE as i64 + 1) as usize;
+    let (width, height) = (extent(x_min, x_max), extent(y_min, y_max));
+    if width > MAX_SIZE || height > MAX_SIZE {
+        return Err(invalid(&format!("{}x{} image is too large", width, height)));
+    }
+
+    //Uncompressed files store one scanline per chunk, each listed in the offset table after the header
+    let mut pixels = vec![0.0f32; width * height * 4];
+    for pixel in pixels.chunks_exact_mut(4) {
+        pixel[3] = 1.0;
+    }
+    for line in 0..height {
+        let chunk = usize::try_from(u64_at(bytes, cursor + line * 8)?).map_err(|_| invalid("unexpected end of file"))?;
+        let y = (i32_at(bytes, chunk)? as i64 - y_min as i64) as usize;
+        if y >= height {
+            return Err(invalid("scanline outside the data window"));
+        }
+        //EXR rows go top to bottom, GL textures bottom to top
+        let row = height - 1 - y;
+        let mut offset = chunk.checked_add(8).ok_or_else(|| invalid("unexpected end of file"))?;
+        for (name, pixel_type) in &channels {
+            let channel = ["R", "G", "B", "A"].iter().position(|component| component == name);
+            for x in 0..width {
+                let value = match pixel_type {
+                    0 => u32_at(bytes, offset)? as f32,
+                    1 => half_to_f32(u16::from_le_bytes(read(bytes, offset)?)),
+                    _ => f32::from_le_bytes(read(bytes, offset)?),
+                };
+                offset += if *pixel_type == 1 { 2 } else { 4 };
+                if let Some(channel) = channel {
+                    pixels[(row * width + x) * 4 + channel] = value;
+                }
+            }
+        }
+    }
+
+    Ok(TextureData {
+        format: uncompressed(gl::RGBA16F, gl::RGBA, gl::FLOAT, 16),
+        width,
+        height,
+        depth: 1,
+        layers: 0,
+        faces: 1,
+        levels: vec![floats_to_bytes(&pixels)],
+    })
+}
+
+fn read_string(bytes: &[u8], cursor: &mut usize) -> Result<String, Error> {
+    let start = *cursor;
+    let end = start + bytes.get(start..).and_then(|rest| rest.iter().position(|b| *b == 0)).ok_or_else(|| invalid("unterminated string"))?;
+    *cursor = end + 1;
+    Ok(String::from_utf8_lossy(&bytes[start..end]).to_string())
+}
+
+fn half_to_f32(half: u16) -> f32 {
+    let sign = if half & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = ((half >> 10) & 0x1f) as i32;
+    let mantissa = (half & 0x3ff) as f32;
+    sign * match exponent {
+        0 => mantissa * 2f32.powi(-24),
+        31 if mantissa == 0.0 => f32::INFINITY,
+        31 => f32::NAN,
+        _ => (1.0 + mantissa / 1024.0) * 2f32.powi(exponent - 15),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn put(bytes: &mut Vec<u8>, offset: usize, value: &[u8]) {
+        if bytes.len() < offset + value.len() {
+            bytes.resize(offset + value.len(), 0);
+        }
+        bytes[offset..offset + value.len()].copy_from_slice(value);
+    }
+
+    fn floats(data: &TextureData) -> Vec<f32> {
+        data.levels[0].chunks_exact(4).map(|bytes| f32::from_le_bytes(bytes.try_into().unwrap())).collect()
+    }
+
+    //Single level 2x2 RGBA8 image
+    fn ktx2() -> Vec<u8> {
+        let mut bytes = KTX2_IDENTIFIER.to_vec();
+        for (offset, value) in [(12, 37), (20, 2), (24, 2), (36, 1), (40, 1)] {
+            put(&mut bytes, offset, &u32::to_le_bytes(value));
+        }
+        put(&mut bytes, 80, &u64::to_le_bytes(104));
+        put(&mut bytes, 88, &u64::to_le_bytes(16));
+        put(&mut bytes, 104, &[7; 16]);
+        bytes
+    }
+
+    //4x4 DXT1 image with its full mip chain
+    fn dds() -> Vec<u8> {
+        let mut bytes = b"DDS ".to_vec();
+        for (offset, value) in [(4, 124), (12, 4), (16, 4), (28, 3), (76, 32), (80, DDPF_FOURCC)] {
+            put(&mut bytes, offset, &u32::to_le_bytes(value));
+        }
+        put(&mut bytes, 84, b"DXT1");
+        put(&mut bytes, 128, &[1; 8]);
+        put(&mut bytes, 136, &[2; 8]);
+        put(&mut bytes, 144, &[3; 8]);
+        bytes
+    }
+
+    fn attribute(bytes: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+        for string in [name, kind] {
+            bytes.extend_from_slice(string.as_bytes());
+            bytes.push(0);
+        }
+        bytes.extend_from_slice(&(value.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(value);
+    }
+
+    //2x2 image with float G and R channels, G holds the pixel index and R ten times it
+    fn exr() -> Vec<u8> {
+        let mut bytes = EXR_MAGIC.to_vec();
+        bytes.extend_from_slice(&2u32.to_le_bytes());
+        let mut channels = Vec::new();
+        for name in ["G", "R"] {
+            channels.extend_from_slice(name.as_bytes());
+            channels.push(0);
+            channels.extend_from_slice(&2u32.to_le_bytes());
+            channels.extend_from_slice(&[0; 4]);
+            channels.extend_from_slice(&1i32.to_le_bytes());
+            channels.extend_from_slice(&1i32.to_le_bytes());
+        }
+        channels.push(0);
+        attribute(&mut bytes, "channels", "chlist", &channels);
+        attribute(&mut bytes, "compression", "compression", &[0]);
+        let window: Vec<u8> = [0i32, 0, 1, 1].iter().flat_map(|value| value.to_le_bytes()).collect();
+        attribute(&mut bytes, "dataWindow", "box2i", &window);
+        bytes.push(0);
+
+        let table = bytes.len();
+        bytes.resize(table + 16, 0);
+        for y in 0..2 {
+            let chunk = bytes.len();
+            put(&mut bytes, table + y * 8, &(chunk as u64).to_le_bytes());
+            bytes.extend_from_slice(&(y as i32).to_le_bytes());
+            bytes.extend_from_slice(&16i32.to_le_bytes());
+            for scale in [1.0, 10.0] {
+                for x in 0..2 {
+                    bytes.extend_from_slice(&((y * 2 + x) as f32 * scale).to_le_bytes());
+                }
+            }
+        }
+        bytes
+    }
+
+    #[test]
+    fn parses_ktx2() {
+        let data = parse_ktx2(&ktx2()).unwrap();
+        assert_eq!(data.format.internal_format(), gl::RGBA8);
+        assert_eq!((data.width, data.height, data.depth, data.layers, data.faces), (2, 2, 1, 0, 1));
+        assert_eq!(data.levels, [vec![7; 16]]);
+        assert_eq!(data.kind(), TextureKind::Texture2D);
+        data.validate().unwrap();
+    }
+
+    #[test]
+    fn parses_dds_mip_chains() {
+        let data = parse_dds(&dds()).unwrap();
+        assert!(data.format.is_compressed());
+        assert_eq!((data.width, data.height), (4, 4));
+        assert_eq!(data.levels, [vec![1; 8], vec![2; 8], vec![3; 8]]);
+        data.validate().unwrap();
+    }
+
+    #[test]
+    fn gathers_dds_cubemap_faces_per_level() {
+        let mut bytes = dds();
+        bytes.truncate(128);
+        for (offset, value) in [(12, 1), (16, 1), (28, 1), (80, DDPF_RGB), (88, 32), (92, 0xff), (112, DDSCAPS2_CUBEMAP)] {
+            put(&mut bytes, offset, &u32::to_le_bytes(value));
+        }
+        for face in 0..6 {
+            bytes.extend_from_slice(&[face; 4]);
+        }
+        let data = parse_dds(&bytes).unwrap();
+        assert_eq!(data.kind(), TextureKind::Cubemap);
+        assert_eq!(data.levels[0].len(), 24);
+        assert_eq!(data.levels[0][20..], [5; 4]);
+        data.validate().unwrap();
+    }
+
+    #[test]
+    fn parses_exr_bottom_row_first() {
+        let data = parse_exr(&exr()).unwrap();
+        assert_eq!((data.width, data.height), (2, 2));
+        assert_eq!(floats(&data), [
+            20.0, 2.0, 0.0, 1.0, 30.0, 3.0, 0.0, 1.0,
+            0.0, 0.0, 0.0, 1.0, 10.0, 1.0, 0.0, 1.0,
+        ]);
+        data.validate().unwrap();
+    }
+
+    #[test]
+    fn rejects_every_truncation() {
+        for (name, bytes, parse) in [
+            ("ktx2", ktx2(), parse_ktx2 as fn(&[u8]) -> Result<TextureData, Error>),
+            ("dds", dds(), parse_dds),
+            ("exr", exr(), parse_exr),
+        ] {
+            for length in 0..bytes.len() {
+                assert!(parse(&bytes[..length]).is_err(), "{} truncated to {} bytes", name, length);
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_offsets_past_the_end() {
+        let mut bytes = ktx2();
+        put(&mut bytes, 80, &u64::to_le_bytes(u64::MAX - 4));
+        assert!(parse_ktx2(&bytes).is_err());
+
+        let mut bytes = exr();
+        let table = bytes.len() - 2 * 24 - 16;
+        put(&mut bytes, table, &u64::to_le_bytes(u64::MAX));
+        assert!(parse_exr(&bytes).is_err());
+    }
+
+    #[test]
+    fn rejects_absurd_sizes() {
+        let mut bytes = ktx2();
+        put(&mut bytes, 40, &u32::MAX.to_le_bytes());
+        assert!(parse_ktx2(&bytes).is_err());
+
+        let mut bytes = dds();
+        put(&mut bytes, 16, &u32::MAX.to_le_bytes());
+        assert!(parse_dds(&bytes).is_err());
+        let mut bytes = dds();
+        put(&mut bytes, 28, &u32::MAX.to_le_bytes());
+        assert!(parse_dds(&bytes).is_err());
+
+        let mut bytes = exr();
+        let window = bytes.len() - 2 * 24 - 16 - 1 - 16;
+        put(&mut bytes, window, &i32::MIN.to_le_bytes());
+        put(&mut bytes, window + 8, &i32::MAX.to_le_bytes());
+        assert!(parse_exr(&bytes).is_err());
+    }
+
+    //1x2 image, red in the top row and green below it
+    fn hdr(resolution: &str) -> Vec<u8> {
+        let mut bytes = format!("#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n{}\n", resolution).into_bytes();
+        bytes.extend_from_slice(&[128, 0, 0, 129, 0, 128, 0, 129]);
+        bytes
+    }
+
+    #[test]
+    fn parses_hdr_bottom_row_first() {
+        let image = HdrImage::parse(&hdr("-Y 2 +X 1")).unwrap();
+        assert_eq!((image.width, image.height), (1, 2));
+        //RGBE decodes to the middle of each mantissa step
+        let rounded: Vec<f32> = image.data.iter().map(|value| value.round()).collect();
+        assert_eq!(rounded, [0.0, 1.0, 0.0, 1.0, 0.0, 0.0]);
+
+        let bytes = hdr("-Y 2 +X 1");
+        for length in 0..bytes.len() {
+            assert!(HdrImage::parse(&bytes[..length]).is_err(), "hdr truncated to {} bytes", length);
+        }
+    }
+
+    #[test]
+    fn rejects_oversized_hdr_headers() {
+        for resolution in ["-Y 100000 +X 1", "-Y 1 +X 100000", "-Y 0 +X 1", "-Y 99999999999999999999999 +X 1"] {
+            let error = HdrImage::parse(&hdr(resolution)).err().unwrap();
+            assert_eq!(error.kind(), ErrorKind::InvalidData, "{}", resolution);
+        }
+        //Allowed sizes still need the pixel data to back them before anything is allocated
+        assert!(HdrImage::parse(&hdr(&format!("-Y {} +X {}", MAX_SIZE, MAX_SIZE))).is_err());
+    }
+
+    #[test]
+    fn sizes_compressed_levels_in_blocks() {
+        let dxt1 = bc(gl::COMPRESSED_RGBA_S3TC_DXT1_EXT, 8);
+        assert_eq!(dxt1.image_size(1, 1), 8);
+        assert_eq!(dxt1.image_size(5, 4), 16);
+        assert_eq!(astc(2, true).unwrap().internal_format(), gl::COMPRESSED_SRGB8_ALPHA8_ASTC_5x5_KHR);
+        assert_eq!(dxt1.srgb().internal_format(), gl::COMPRESSED_SRGB_ALPHA_S3TC_DXT1_EXT);
+    }
+}
@@ -0,0 +1,142 @@
+use std::{collections::HashMap, sync::Arc};
+
+use bevy_ecs::prelude::*;
+
+use crate::{components::{Camera, ClearSettings, RenderLayers, RenderTarget}, framebuffer::Framebuffer, gl_state, resources::AssetPool, texture::Texture, window::Window};
+
+//Camera the per-view render passes draw, the render graph inserts one for every camera in priority order
+#[derive(Resource, Clone)]
+pub struct RenderView {
+    pub camera: Entity,
+    //Framebuffer the view ends up in, 0 for the window
+    pub framebuffer: u32,
+    //x, y, width and height in pixels of that framebuffer
+    pub viewport: [i32; 4],
+    pub layers: RenderLayers,
+    pub clear: ClearSettings,
+}
+
+impl RenderView {
+    pub fn size(&self) -> (u32, u32) {
+        (self.viewport[2] as u32, self.viewport[3] as u32)
+    }
+
+    //Binds the view's target and clears only inside its viewport, so other views sharing the target are kept
+    pub fn begin(&self) {
+        let [x, y, width, height] = self.viewport;
+        let color = self.clear.color.unwrap_or(ClearSettings::DEFAULT_COLOR);
+        let mut mask = 0;
+        if self.clear.color.is_some() {
+            mask |= gl::COLOR_BUFFER_BIT;
+        }
+        if self.clear.depth {
+            mask |= gl::DEPTH_BUFFER_BIT;
+        }
+        unsafe {
+            gl_state::bind_framebuffer(gl::FRAMEBUFFER, self.framebuffer);
+            gl::Viewport(x, y, width, height);
+            //Offscreen targets later passes clear use the view's color too
+            gl::ClearColor(color.x, color.y, color.z, color.w);
+            if mask != 0 {
+                gl_state::enable(gl::SCISSOR_TEST);
+                gl::Scissor(x, y, width, height);
+                gl::Clear(mask);
+                gl_state::disable(gl::SCISSOR_TEST);
+            }
+        }
+    }
+}
+
+//Cameras sorted by priority, ties keep spawn order. Cameras whose target doesn't exist yet or whose viewport is empty are skipped.
+pub fn collect_views(world: &mut World) -> Vec<RenderView> {
+    let mut query = world.query::<(Entity, &Camera, Option<&RenderLayers>)>();
+    let window = world.resource::<Window>().size();
+    let textures = world.get_resource::<RenderTextures>();
+    let mut views = Vec::new();
+    for (entity, camera, layers) in query.iter(world) {
+        let (framebuffer, width, height) = match &camera.target {
+            RenderTarget::Window => (0, window.width, window.height),
+            RenderTarget::Texture { name, .. } => match textures.and_then(|textures| textures.framebuffer(name)) {
+                Some(framebuffer) => (framebuffer.handle(), framebuffer.width() as u32, framebuffer.height() as u32),
+                None => continue,
+            },
+        };
+        let viewport = camera.viewport.pixels(width, height);
+        if viewport[2] <= 0 || viewport[3] <= 0 {
+            continue;
+        }
+        views.push((camera.priority, RenderView {
+            camera: entity,
+            framebuffer,
+            viewport,
+            layers: layers.copied().unwrap_or_default(),
+            clear: camera.clear,
+        }));
+    }
+    views.sort_by_key(|(priority, view)| (*priority, view.camera));
+    views.into_iter().map(|(_, view)| view).collect()
+}
+
+//The lowest priority camera drawing into the window, which everything else is layered over or shown inside of.
+//Per entity state that can only follow one camera, such as the LOD a mesh shows, follows this one.
+pub fn main_camera<'a>(cameras: impl Iterator<Item = (Entity, &'a Camera)>) -> Option<&'a Camera> {
+    cameras.min_by_key(|(entity, camera)| (camera.target != RenderTarget::Window, camera.priority, *entity))
+        .map(|(_, camera)| camera)
+}
+
+struct RenderTexture {
+    framebuffer: Framebuffer,
+    //Shared with the asset pool under the target's name
+    _color: Arc<Texture>,
+    _depth: Texture,
+}
+
+//Offscreen targets of cameras rendering into textures, for minimaps, monitors and mirrors
+#[derive(Resource, Default)]
+pub struct RenderTextures {
+    textures: HashMap<String, RenderTexture>,
+}
+
+impl RenderTextures {
+    pub fn framebuffer(&self, name: &str) -> Option<&Framebuffer> {
+        self.textures.get(name).map(|texture| &texture.framebuffer)
+    }
+
+    //Creates missing targets and recreates resized ones, call before loading materials that sample them
+    pub fn update<'a>(&mut self, cameras: impl Iterator<Item = &'a Camera>, assets: &mut AssetPool) {
+        for camera in cameras {
+            if let RenderTarget::Texture { name, width, height } = &camera.target {
+                let (width, height) = ((*width).max(1) as i32, (*height).max(1) as i32);
+                let existing = self.textures.get(name).map(|texture| (texture.framebuffer.width(), texture.framebuffer.height()));
+                if existing == Some((width, height)) {
+                    continue;
+                }
+                let color = assets.insert_texture(name, Texture::empty(width, height, gl::RGBA8));
+                let depth = Texture::empty(width, height, gl::DEPTH_COMPONENT32F);
+                let framebuffer = Framebuffer::from_textures(width, height, &[color.handle()], Some(depth.handle()));
+                framebuffer.is_complete();
+                framebuffer.label(name);
+                color.label(name);
+                depth.label(&format!("{} depth", name));
+                self.textures.insert(name.clone(), RenderTexture { framebuffer, _color: color, _depth: depth });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prefers_window_cameras_drawn_first() {
+        let camera = |priority: i32, target: RenderTarget| Camera { priority, target, ..Default::default() };
+        let monitor = camera(-1, RenderTarget::Texture { name: "monitor".to_string(), width: 256, height: 256 });
+        let (main, minimap, hud) = (camera(0, RenderTarget::Window), camera(1, RenderTarget::Window), camera(2, RenderTarget::Window));
+        let cameras = [(Entity::from_raw(0), &hud), (Entity::from_raw(1), &monitor), (Entity::from_raw(2), &minimap), (Entity::from_raw(3), &main)];
+        assert_eq!(main_camera(cameras.into_iter()).map(|camera| camera.priority), Some(0));
+        //Without a window camera the offscreen one is still followed
+        assert_eq!(main_camera(cameras[1..2].iter().copied()).map(|camera| camera.priority), Some(-1));
+        assert!(main_camera(std::iter::empty()).is_none());
+    }
+}
@@ -1,5 +1,5 @@
 use bevy_ecs::prelude::*;
-use raw_gl_context::{GlContext, GlConfig, Profile};
+use raw_gl_context::GlContext;
 
 use winit::{
     event_loop::EventLoop,
@@ -7,6 +7,8 @@ use winit::{
 };
 use winit::window::CursorGrabMode;
 
+use crate::{gl_state, opengl_backend::OpenGlBackend};
+
 #[derive(Resource)]
 pub struct Window {
     handle: winit::window::Window,
@@ -26,27 +28,14 @@ impl Window {
             .build(&event_loop).unwrap();
 
         let context = unsafe { 
-            GlContext::create(&window, GlConfig {
-                version: (4, 6),
-                profile: Profile::Core,
-                red_bits: 8,
-                blue_bits: 8,
-                green_bits: 8,
-                alpha_bits: 8,
-                depth_bits: 24,
-                stencil_bits: 8,
-                samples: None,
-                srgb: true,
-                double_buffer: true,
-                vsync: false,
-            }).unwrap()
+            GlContext::create(&window, OpenGlBackend::context_config()).unwrap()
         };
 
         unsafe {
             context.make_current();
         }
     
-        gl::load_with(|symbol| context.get_proc_address(symbol) as *const _);
+        OpenGlBackend::load_functions(&context);
 
         window.set_visible(true);
 
@@ -76,14 +65,21 @@ impl Window {
     }
 
     pub fn init_gl(&self) {
+        //Whatever the cache remembers belongs to an earlier context, a new one starts with nothing bound
+        gl_state::invalidate();
         unsafe {
             gl::ClearColor(0.2, 0.3, 0.3, 1.0);
-            gl::Enable(gl::DEPTH_TEST);
+            gl_state::enable(gl::DEPTH_TEST);
         }
+        gl_state::enable_debug_output();
         //TODO: More GL init please
     }
 
 
+    pub fn size(&self) -> PhysicalSize<u32> {
+        self.handle.inner_size()
+    }
+
     pub fn aspect_ratio(&self) -> f32 {
         let size = self.handle.inner_size();
         size.width as f32 / size.height as f32